@@ -0,0 +1,458 @@
+use crate::GCodePosition;
+
+/// Number of segments stored within a single leaf of the spatial index
+const LEAF_SIZE: usize = 4;
+
+/// Continuous run of moves of the same kind
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GCodeBackplotPolyline {
+    /// Whether the moves are rapid (G00) moves
+    pub rapid: bool,
+    pub points: Vec<[f64; 3]>,
+    /// Index of the command producing each segment, `commands[i]` is the
+    /// command moving from `points[i]` to `points[i + 1]`
+    pub commands: Vec<usize>,
+}
+
+/// Segment found by a backplot query
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeBackplotHit {
+    /// Index of the polyline within `GCodeBackplot::polylines()`
+    pub polyline: usize,
+    /// Index of the segment within the polyline
+    pub segment: usize,
+    /// Index of the command producing the segment
+    pub command: usize,
+    /// Distance between the query and the segment
+    pub distance: f64,
+}
+
+/// Accumulates moves into a `GCodeBackplot`
+#[derive(Clone, Debug, Default)]
+pub struct GCodeBackplotBuilder {
+    current: Option<[f64; 3]>,
+    polylines: Vec<GCodeBackplotPolyline>,
+}
+impl GCodeBackplotBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the starting position without adding a segment
+    pub fn start_at(&mut self, pos: GCodePosition) -> &mut Self {
+        self.current = Some(self.resolve(pos));
+        self
+    }
+
+    /// Adds a move to `pos`, produced by command `command`. Axes absent from
+    /// `pos` keep their previous value. Axes whose value was never set are
+    /// assumed to be 0.
+    pub fn move_to(&mut self, command: usize, pos: GCodePosition, rapid: bool) -> &mut Self {
+        let to = self.resolve(pos);
+        let from = match self.current.replace(to) {
+            Some(from) => from,
+            None => return self,
+        };
+        if from == to {
+            return self;
+        }
+
+        match self.polylines.last_mut() {
+            Some(line) if (line.rapid == rapid) && (line.points.last() == Some(&from)) => {
+                line.points.push(to);
+                line.commands.push(command);
+            }
+            _ => self.polylines.push(GCodeBackplotPolyline {
+                rapid,
+                points: vec![from, to],
+                commands: vec![command],
+            }),
+        }
+        self
+    }
+
+    /// Builds the spatial index over all moves added so far
+    pub fn build(self) -> GCodeBackplot {
+        let mut segments = vec![];
+        for (line_idx, line) in self.polylines.iter().enumerate() {
+            for seg_idx in 0..line.commands.len() {
+                segments.push(Segment {
+                    polyline: line_idx,
+                    segment: seg_idx,
+                    from: line.points[seg_idx],
+                    to: line.points[seg_idx + 1],
+                });
+            }
+        }
+
+        let mut backplot = GCodeBackplot {
+            polylines: self.polylines,
+            segments,
+            nodes: vec![],
+        };
+        if !backplot.segments.is_empty() {
+            let len = backplot.segments.len();
+            backplot.build_node(0, len);
+        }
+        backplot
+    }
+
+    fn resolve(&self, pos: GCodePosition) -> [f64; 3] {
+        let cur = self.current.unwrap_or_default();
+        let (x, y, z) = pos.as_f64();
+        [
+            x.unwrap_or(cur[0]),
+            y.unwrap_or(cur[1]),
+            z.unwrap_or(cur[2]),
+        ]
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    polyline: usize,
+    segment: usize,
+    from: [f64; 3],
+    to: [f64; 3],
+}
+
+#[derive(Clone, Copy, Debug)]
+enum NodeKind {
+    /// Range of segments
+    Leaf(usize, usize),
+    /// Indices of child nodes
+    Inner(usize, usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    min: [f64; 3],
+    max: [f64; 3],
+    kind: NodeKind,
+}
+
+/// Set of toolpath polylines, indexed for nearest-segment queries
+#[derive(Clone, Debug)]
+pub struct GCodeBackplot {
+    polylines: Vec<GCodeBackplotPolyline>,
+    segments: Vec<Segment>,
+    /// Bounding volume hierarchy over `segments`, root is the first node
+    nodes: Vec<Node>,
+}
+impl GCodeBackplot {
+    pub fn polylines(&self) -> &[GCodeBackplotPolyline] {
+        &self.polylines
+    }
+
+    /// Bounds of all segments, as (min, max)
+    pub fn bounds(&self) -> Option<([f64; 3], [f64; 3])> {
+        self.nodes.first().map(|node| (node.min, node.max))
+    }
+
+    /// Finds the segment closest to `point`
+    pub fn nearest_segment(&self, point: [f64; 3]) -> Option<GCodeBackplotHit> {
+        let mut best: Option<GCodeBackplotHit> = None;
+        self.search(
+            |min, max| {
+                let mut dist = 0.0;
+                for i in 0..3 {
+                    let d = (min[i] - point[i]).max(point[i] - max[i]).max(0.0);
+                    dist += d * d;
+                }
+                dist.sqrt()
+            },
+            |seg| point_segment_distance(point, seg.from, seg.to),
+            &mut best,
+        );
+        best
+    }
+
+    /// Finds the segment closest to the ray starting at `origin` heading in
+    /// `direction`, ignoring segments further than `max_distance` from the
+    /// ray. Intended for picking segments from a 3D view.
+    pub fn pick(
+        &self,
+        origin: [f64; 3],
+        direction: [f64; 3],
+        max_distance: f64,
+    ) -> Option<GCodeBackplotHit> {
+        if dot(direction, direction) == 0.0 {
+            return None;
+        }
+
+        let mut best: Option<GCodeBackplotHit> = None;
+        self.search(
+            |min, max| {
+                let min = [
+                    min[0] - max_distance,
+                    min[1] - max_distance,
+                    min[2] - max_distance,
+                ];
+                let max = [
+                    max[0] + max_distance,
+                    max[1] + max_distance,
+                    max[2] + max_distance,
+                ];
+                if ray_hits_box(origin, direction, min, max) {
+                    0.0
+                } else {
+                    f64::INFINITY
+                }
+            },
+            |seg| ray_segment_distance(origin, direction, seg.from, seg.to),
+            &mut best,
+        );
+        best.filter(|hit| hit.distance <= max_distance)
+    }
+
+    /// Depth-first branch and bound search. `node_dist` gives a lower bound of
+    /// the distance to anything within a node's bounds, `seg_dist` the actual
+    /// distance to a segment.
+    fn search(
+        &self,
+        node_dist: impl Fn([f64; 3], [f64; 3]) -> f64,
+        seg_dist: impl Fn(&Segment) -> f64,
+        best: &mut Option<GCodeBackplotHit>,
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![0];
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            let bound = node_dist(node.min, node.max);
+            if bound.is_infinite() || best.is_some_and(|b| bound >= b.distance) {
+                continue;
+            }
+
+            match node.kind {
+                NodeKind::Leaf(start, end) => {
+                    for seg in &self.segments[start..end] {
+                        let distance = seg_dist(seg);
+                        if best.is_none_or(|b| distance < b.distance) {
+                            *best = Some(GCodeBackplotHit {
+                                polyline: seg.polyline,
+                                segment: seg.segment,
+                                command: self.polylines[seg.polyline].commands[seg.segment],
+                                distance,
+                            });
+                        }
+                    }
+                }
+                NodeKind::Inner(left, right) => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
+        }
+    }
+
+    /// Recursively builds the node covering `segments[start..end]`, returning
+    /// its index
+    fn build_node(&mut self, start: usize, end: usize) -> usize {
+        let mut min = [f64::INFINITY; 3];
+        let mut max = [f64::NEG_INFINITY; 3];
+        for seg in &self.segments[start..end] {
+            for i in 0..3 {
+                min[i] = min[i].min(seg.from[i]).min(seg.to[i]);
+                max[i] = max[i].max(seg.from[i]).max(seg.to[i]);
+            }
+        }
+
+        let idx = self.nodes.len();
+        self.nodes.push(Node {
+            min,
+            max,
+            kind: NodeKind::Leaf(start, end),
+        });
+        if (end - start) <= LEAF_SIZE {
+            return idx;
+        }
+
+        /* Split at the median along the longest axis */
+        let axis = (0..3)
+            .max_by(|&a, &b| (max[a] - min[a]).total_cmp(&(max[b] - min[b])))
+            .unwrap_or(0);
+        let mid = (start + end) / 2;
+        let center = |seg: &Segment| seg.from[axis] + seg.to[axis];
+        self.segments[start..end]
+            .select_nth_unstable_by(mid - start, |a, b| center(a).total_cmp(&center(b)));
+
+        let left = self.build_node(start, mid);
+        let right = self.build_node(mid, end);
+        self.nodes[idx].kind = NodeKind::Inner(left, right);
+        idx
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn point_segment_distance(point: [f64; 3], from: [f64; 3], to: [f64; 3]) -> f64 {
+    let seg = sub(to, from);
+    let len = dot(seg, seg);
+    let t = if len > 0.0 {
+        (dot(sub(point, from), seg) / len).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let closest = [
+        from[0] + seg[0] * t,
+        from[1] + seg[1] * t,
+        from[2] + seg[2] * t,
+    ];
+    let diff = sub(point, closest);
+    dot(diff, diff).sqrt()
+}
+
+fn ray_segment_distance(origin: [f64; 3], dir: [f64; 3], from: [f64; 3], to: [f64; 3]) -> f64 {
+    let seg = sub(to, from);
+    let r = sub(from, origin);
+    let a = dot(seg, seg);
+    let b = dot(seg, dir);
+    let c = dot(seg, r);
+    let e = dot(dir, dir);
+    let f = dot(dir, r);
+
+    /* Parameters along the segment (s, 0..1) and ray (t, 0..) of the closest
+     * points between them */
+    let (s, t) = if a <= f64::EPSILON {
+        (0.0, (f / e).max(0.0))
+    } else {
+        let denom = a * e - b * b;
+        let s = if denom > 0.0 {
+            ((b * f - c * e) / denom).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let t = (b * s + f) / e;
+        if t < 0.0 {
+            ((-c / a).clamp(0.0, 1.0), 0.0)
+        } else {
+            (s, t)
+        }
+    };
+
+    let on_seg = [
+        from[0] + seg[0] * s,
+        from[1] + seg[1] * s,
+        from[2] + seg[2] * s,
+    ];
+    let on_ray = [
+        origin[0] + dir[0] * t,
+        origin[1] + dir[1] * t,
+        origin[2] + dir[2] * t,
+    ];
+    let diff = sub(on_seg, on_ray);
+    dot(diff, diff).sqrt()
+}
+
+fn ray_hits_box(origin: [f64; 3], dir: [f64; 3], min: [f64; 3], max: [f64; 3]) -> bool {
+    let mut t_min: f64 = 0.0;
+    let mut t_max = f64::INFINITY;
+    for i in 0..3 {
+        if dir[i] == 0.0 {
+            if (origin[i] < min[i]) || (origin[i] > max[i]) {
+                return false;
+            }
+        } else {
+            let t0 = (min[i] - origin[i]) / dir[i];
+            let t1 = (max[i] - origin[i]) / dir[i];
+            t_min = t_min.max(t0.min(t1));
+            t_max = t_max.min(t0.max(t1));
+        }
+    }
+    t_min <= t_max
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GCodeError;
+
+    fn backplot() -> Result<GCodeBackplot, GCodeError> {
+        let mut builder = GCodeBackplotBuilder::new();
+        builder
+            .start_at(GCodePosition::from_f64_full(0.0, 0.0, 5.0)?)
+            .move_to(1, GCodePosition::from_f64(None, None, Some(-1.0))?, false);
+        /* Grid of parallel lines, enough to produce several index levels */
+        for i in 0..20 {
+            let y = i as f64;
+            let x = if (i % 2) == 0 { 10.0 } else { 0.0 };
+            builder
+                .move_to(
+                    2 + 2 * i,
+                    GCodePosition::from_f64(Some(x), None, None)?,
+                    false,
+                )
+                .move_to(
+                    3 + 2 * i,
+                    GCodePosition::from_f64(None, Some(y + 1.0), None)?,
+                    false,
+                );
+        }
+        builder.move_to(100, GCodePosition::from_f64_full(0.0, 0.0, 5.0)?, true);
+        Ok(builder.build())
+    }
+
+    #[test]
+    fn backplot_polylines() -> Result<(), GCodeError> {
+        let backplot = backplot()?;
+        let lines = backplot.polylines();
+        assert_eq!(lines.len(), 2);
+        assert!(!lines[0].rapid);
+        assert_eq!(lines[0].points.len(), 42);
+        assert_eq!(lines[0].commands[..3], [1, 2, 3]);
+        assert!(lines[1].rapid);
+        assert_eq!(lines[1].commands, [100]);
+        assert_eq!(
+            backplot.bounds(),
+            Some(([0.0, 0.0, -1.0], [10.0, 20.0, 5.0]))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn backplot_nearest() -> Result<(), GCodeError> {
+        let backplot = backplot()?;
+
+        let hit = backplot.nearest_segment([5.0, 4.2, -1.0]).unwrap();
+        assert_eq!(hit.command, 10);
+        assert!((hit.distance - 0.2).abs() < 1e-9);
+
+        let hit = backplot.nearest_segment([10.5, 6.5, -1.0]).unwrap();
+        assert_eq!(hit.command, 15);
+        assert!((hit.distance - 0.5).abs() < 1e-9);
+
+        Ok(())
+    }
+
+    #[test]
+    fn backplot_pick() -> Result<(), GCodeError> {
+        let backplot = backplot()?;
+
+        /* Looking straight down */
+        let hit = backplot
+            .pick([5.0, 12.1, 50.0], [0.0, 0.0, -1.0], 0.5)
+            .unwrap();
+        assert_eq!(hit.command, 26);
+        assert!((hit.distance - 0.1).abs() < 1e-9);
+
+        /* Looking away from the toolpath */
+        assert_eq!(backplot.pick([5.0, 12.1, 50.0], [0.0, 0.0, 1.0], 0.5), None);
+        /* Outside of the toolpath */
+        assert_eq!(
+            backplot.pick([5.0, 30.0, 50.0], [0.0, 0.0, -1.0], 0.5),
+            None
+        );
+
+        Ok(())
+    }
+}
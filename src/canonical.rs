@@ -0,0 +1,421 @@
+use std::io::Write;
+
+use crate::{
+    GCodeCommand, GCodeError, GCodeFeedMode, GCodeHeater, GCodeOptions, GCodePosition,
+    GCodeRotaryAxis, GCodeSpindle, GCodeWriter,
+};
+
+/// Canonical machining functions, in the style of the RS274/NGC
+/// specification. Front-ends, e.g. generators or command streams, drive
+/// any back-end implementing these, e.g. a `GCodeWriter` for any dialect or
+/// a `GCodeCanonicalRecorder` for analysis.
+///
+/// Positions are absolute in the active work coordinate system, feed rates
+/// in units per minute. Axes absent from a position keep their value.
+pub trait GCodeCanonical {
+    /// Rapid linear move to `to`
+    fn straight_traverse(&mut self, to: GCodePosition) -> Result<(), GCodeError>;
+
+    /// Linear move to `to`, at `feed_rate` or the prior feed rate if None
+    fn straight_feed(
+        &mut self,
+        to: GCodePosition,
+        feed_rate: Option<f64>,
+    ) -> Result<(), GCodeError>;
+
+    /// Arc in the XY plane around `center` to `to`
+    fn arc_feed(
+        &mut self,
+        to: GCodePosition,
+        center: GCodePosition,
+        clockwise: bool,
+        feed_rate: Option<f64>,
+    ) -> Result<(), GCodeError>;
+
+    /// Move of a rotary axis to `angle`
+    fn rotary_motion(
+        &mut self,
+        axis: GCodeRotaryAxis,
+        angle: f64,
+        feed_rate: Option<f64>,
+        rapid: bool,
+    ) -> Result<(), GCodeError>;
+
+    fn dwell(&mut self, seconds: f64) -> Result<(), GCodeError>;
+
+    fn set_spindle(&mut self, spindle: GCodeSpindle) -> Result<(), GCodeError>;
+
+    fn set_temperature(
+        &mut self,
+        heater: GCodeHeater,
+        temp: f64,
+        wait: bool,
+    ) -> Result<(), GCodeError>;
+
+    /// Performs the canonical function corresponding to `command`
+    fn execute(&mut self, command: &GCodeCommand) -> Result<(), GCodeError> {
+        match *command {
+            GCodeCommand::Move {
+                to,
+                feed_rate,
+                rapid,
+                ..
+            } => match rapid {
+                true => self.straight_traverse(to),
+                false => self.straight_feed(to, feed_rate),
+            },
+            GCodeCommand::Arc {
+                to,
+                center,
+                clockwise,
+                feed_rate,
+                ..
+            } => self.arc_feed(to, center, clockwise, feed_rate),
+            GCodeCommand::Rotate {
+                axis,
+                angle,
+                feed_rate,
+                rapid,
+                ..
+            } => self.rotary_motion(axis, angle, feed_rate, rapid),
+            GCodeCommand::Dwell(seconds) => self.dwell(seconds),
+            GCodeCommand::Spindle(spindle) => self.set_spindle(spindle),
+            GCodeCommand::Temperature { heater, temp, wait } => {
+                self.set_temperature(heater, temp, wait)
+            }
+        }
+    }
+}
+
+/// Writes G-code in the writer's dialect. Feed rates are only written when
+/// they change, or with every move where the feed mode requires it.
+impl<W: Write> GCodeCanonical for GCodeWriter<'_, W> {
+    fn straight_traverse(&mut self, to: GCodePosition) -> Result<(), GCodeError> {
+        self.move_to(to, None, true)
+    }
+
+    fn straight_feed(
+        &mut self,
+        to: GCodePosition,
+        feed_rate: Option<f64>,
+    ) -> Result<(), GCodeError> {
+        let options = feed_options(self, feed_rate);
+        self.move_to(to, options, false)
+    }
+
+    fn arc_feed(
+        &mut self,
+        to: GCodePosition,
+        center: GCodePosition,
+        clockwise: bool,
+        feed_rate: Option<f64>,
+    ) -> Result<(), GCodeError> {
+        let options = feed_options(self, feed_rate);
+        self.arc_to(to, center, clockwise, options)
+    }
+
+    fn rotary_motion(
+        &mut self,
+        axis: GCodeRotaryAxis,
+        angle: f64,
+        feed_rate: Option<f64>,
+        rapid: bool,
+    ) -> Result<(), GCodeError> {
+        let options = feed_options(self, feed_rate);
+        self.rotate_to(axis, angle, options, rapid)
+    }
+
+    fn dwell(&mut self, seconds: f64) -> Result<(), GCodeError> {
+        GCodeWriter::dwell(self, seconds)
+    }
+
+    fn set_spindle(&mut self, spindle: GCodeSpindle) -> Result<(), GCodeError> {
+        GCodeWriter::set_spindle(self, spindle)
+    }
+
+    fn set_temperature(
+        &mut self,
+        heater: GCodeHeater,
+        temp: f64,
+        wait: bool,
+    ) -> Result<(), GCodeError> {
+        GCodeWriter::set_temperature(self, heater, temp, wait)
+    }
+}
+
+/// Options of a move at `feed_rate`, None where the F word can be omitted
+fn feed_options<W: Write>(
+    writer: &GCodeWriter<W>,
+    feed_rate: Option<f64>,
+) -> Option<GCodeOptions<'static>> {
+    let feed_rate = feed_rate.or(writer.state().feed_rate);
+    let unchanged = feed_rate == writer.state().feed_rate;
+    match writer.feed_mode() {
+        GCodeFeedMode::UnitsPerMinute if unchanged => None,
+        _ => feed_rate.map(|_| GCodeOptions {
+            feed_rate,
+            ..Default::default()
+        }),
+    }
+}
+
+/// Records canonical functions as `GCodeCommand`s, resolving start
+/// positions and distances, e.g. for simulation or analysis
+#[derive(Clone, Debug, PartialEq)]
+pub struct GCodeCanonicalRecorder {
+    pub commands: Vec<GCodeCommand>,
+    position: GCodePosition,
+    rotary: [Option<f64>; 3],
+    feed_rate: Option<f64>,
+}
+impl Default for GCodeCanonicalRecorder {
+    fn default() -> Self {
+        Self {
+            commands: vec![],
+            position: GCodePosition::from_raw(None, None, None),
+            rotary: [None; 3],
+            feed_rate: None,
+        }
+    }
+}
+impl GCodeCanonicalRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Position reached by all recorded motion
+    pub fn position(&self) -> GCodePosition {
+        self.position
+    }
+
+    /// Replaces the position, e.g. to make it unknown after coordinates are
+    /// set
+    pub(crate) fn set_position(&mut self, position: GCodePosition) {
+        self.position = position;
+    }
+
+    fn linear(&mut self, to: GCodePosition, feed_rate: Option<f64>, rapid: bool) {
+        let (from, to) = (self.position, to.merge(&self.position));
+        self.commands.push(GCodeCommand::Move {
+            from,
+            to,
+            distance: (from.is_complete() && to.is_complete())
+                .then(|| GCodePosition::fixed_to_f64(from.distance_to(&to))),
+            feed_rate,
+            rapid,
+        });
+        self.position = to;
+    }
+}
+impl GCodeCanonical for GCodeCanonicalRecorder {
+    fn straight_traverse(&mut self, to: GCodePosition) -> Result<(), GCodeError> {
+        self.linear(to, self.feed_rate, true);
+        Ok(())
+    }
+
+    fn straight_feed(
+        &mut self,
+        to: GCodePosition,
+        feed_rate: Option<f64>,
+    ) -> Result<(), GCodeError> {
+        self.feed_rate = feed_rate.or(self.feed_rate);
+        self.linear(to, self.feed_rate, false);
+        Ok(())
+    }
+
+    fn arc_feed(
+        &mut self,
+        to: GCodePosition,
+        center: GCodePosition,
+        clockwise: bool,
+        feed_rate: Option<f64>,
+    ) -> Result<(), GCodeError> {
+        let (from, to) = (self.position, to.merge(&self.position));
+        let arc = crate::fillet::arc_geometry(&from, &to, &center, clockwise)?;
+        self.feed_rate = feed_rate.or(self.feed_rate);
+        self.commands.push(GCodeCommand::Arc {
+            from,
+            to,
+            center,
+            clockwise,
+            distance: Some(arc.length()),
+            feed_rate: self.feed_rate,
+        });
+        self.position = to;
+        Ok(())
+    }
+
+    fn rotary_motion(
+        &mut self,
+        axis: GCodeRotaryAxis,
+        angle: f64,
+        feed_rate: Option<f64>,
+        rapid: bool,
+    ) -> Result<(), GCodeError> {
+        if !rapid {
+            self.feed_rate = feed_rate.or(self.feed_rate);
+        }
+        let current = self.rotary[axis.index()].replace(angle);
+        self.commands.push(GCodeCommand::Rotate {
+            axis,
+            angle,
+            travel: current.map(|cur| (angle - cur).abs()),
+            feed_rate: self.feed_rate,
+            rapid,
+        });
+        Ok(())
+    }
+
+    fn dwell(&mut self, seconds: f64) -> Result<(), GCodeError> {
+        self.commands.push(GCodeCommand::Dwell(seconds));
+        Ok(())
+    }
+
+    fn set_spindle(&mut self, spindle: GCodeSpindle) -> Result<(), GCodeError> {
+        self.commands.push(GCodeCommand::Spindle(spindle));
+        Ok(())
+    }
+
+    fn set_temperature(
+        &mut self,
+        heater: GCodeHeater,
+        temp: f64,
+        wait: bool,
+    ) -> Result<(), GCodeError> {
+        self.commands
+            .push(GCodeCommand::Temperature { heater, temp, wait });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diff_programs, GCodeDiffOptions};
+
+    /// Front-end driving any back-end
+    fn square(target: &mut impl GCodeCanonical) -> Result<(), GCodeError> {
+        let pos = |x, y| GCodePosition::from_f64(Some(x), Some(y), None);
+        target.straight_traverse(GCodePosition::from_f64_full(0.0, 0.0, 1.0)?)?;
+        target.set_spindle(GCodeSpindle::Clockwise(1000.0))?;
+        target.straight_feed(GCodePosition::from_f64(None, None, Some(0.0))?, Some(50.0))?;
+        target.straight_feed(pos(10.0, 0.0)?, Some(100.0))?;
+        target.arc_feed(pos(10.0, 10.0)?, pos(10.0, 5.0)?, false, None)?;
+        target.straight_feed(pos(0.0, 10.0)?, None)?;
+        target.straight_feed(pos(0.0, 0.0)?, Some(100.0))?;
+        target.straight_traverse(GCodePosition::from_f64(None, None, Some(1.0))?)
+    }
+
+    #[test]
+    fn canonical() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        square(&mut gcw)?;
+        gcw.writer()?;
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G00 X0.0000 Y0.0000 Z1.0000\n\
+             M03 S1000\n\
+             G01 Z0.0000 F50.00\n\
+             G01 X10.0000 Y0.0000 F100.00\n\
+             G03 X10.0000 Y10.0000 I0.0000 J5.0000\n\
+             G01 X0.0000 Y10.0000\n\
+             G01 X0.0000 Y0.0000\n\
+             G00 Z1.0000\n"
+        );
+
+        let mut recorder = GCodeCanonicalRecorder::new();
+        square(&mut recorder)?;
+        assert_eq!(recorder.commands.len(), 8);
+        assert_eq!(
+            recorder.position(),
+            GCodePosition::from_f64_full(0.0, 0.0, 1.0)?
+        );
+        let distance = match recorder.commands[4] {
+            GCodeCommand::Arc { distance, .. } => distance,
+            _ => None,
+        };
+        assert!((distance.unwrap_or(0.0) - 5.0 * std::f64::consts::PI).abs() < 1e-3);
+
+        /* Replaying the recording gives the same toolpath, with axes
+         * written out in full */
+        let mut replayed = vec![];
+        let mut gcw = GCodeWriter::builder()
+            .auto_newline(true)
+            .build(&mut replayed)?;
+        for command in &recorder.commands {
+            gcw.execute(command)?;
+        }
+        gcw.writer()?;
+        let (original, replayed) = (
+            String::from_utf8_lossy(&data),
+            String::from_utf8_lossy(&replayed),
+        );
+        assert_ne!(original, replayed);
+        assert!(diff_programs(&original, &replayed, &GCodeDiffOptions::default()).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn recorder_edge_cases() -> Result<(), GCodeError> {
+        let mut recorder = GCodeCanonicalRecorder::new();
+        let pos = |x, y| GCodePosition::from_f64(Some(x), Some(y), None);
+
+        /* Arcs need a known start */
+        assert_eq!(
+            recorder.arc_feed(pos(10.0, 0.0)?, pos(5.0, 0.0)?, true, Some(100.0)),
+            Err(GCodeError::StateError)
+        );
+        assert!(recorder.commands.is_empty());
+
+        /* Moves from an unknown position have no distance */
+        recorder.straight_feed(pos(10.0, 0.0)?, None)?;
+        recorder.rotary_motion(GCodeRotaryAxis::A, 90.0, Some(360.0), true)?;
+        recorder.rotary_motion(GCodeRotaryAxis::A, 45.0, None, false)?;
+        assert_eq!(
+            recorder.commands,
+            [
+                GCodeCommand::Move {
+                    from: GCodePosition::from_raw(None, None, None),
+                    to: pos(10.0, 0.0)?,
+                    distance: None,
+                    feed_rate: None,
+                    rapid: false,
+                },
+                GCodeCommand::Rotate {
+                    axis: GCodeRotaryAxis::A,
+                    angle: 90.0,
+                    travel: None,
+                    feed_rate: None,
+                    rapid: true,
+                },
+                /* Rapids leave the feed rate as-is */
+                GCodeCommand::Rotate {
+                    axis: GCodeRotaryAxis::A,
+                    angle: 45.0,
+                    travel: Some(45.0),
+                    feed_rate: None,
+                    rapid: false,
+                },
+            ]
+        );
+
+        /* Errors of the back-end are passed on */
+        let mut gcw = GCodeWriter::new(vec![])?;
+        assert_eq!(
+            gcw.execute(&GCodeCommand::Dwell(-1.0)),
+            Err(GCodeError::OutOfRangeError)
+        );
+        assert_eq!(
+            gcw.execute(&GCodeCommand::Temperature {
+                heater: GCodeHeater::Bed,
+                temp: 60.0,
+                wait: false,
+            }),
+            Err(GCodeError::UnsupportedError)
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use crate::fillet::arc_geometry;
+use crate::{reverse_path, GCodeError, GCodePathSegment, GCodePosition};
+
+/// Element of a 2D contour, each continuing from the end of the previous
+/// element
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GCodeContourSegment {
+    /// Line to the contained XY position
+    Line([f64; 2]),
+    /// Arc around `center` to `end`
+    Arc {
+        end: [f64; 2],
+        center: [f64; 2],
+        clockwise: bool,
+    },
+}
+impl GCodeContourSegment {
+    pub fn end(&self) -> [f64; 2] {
+        match self {
+            Self::Line(end) | Self::Arc { end, .. } => *end,
+        }
+    }
+}
+
+/// 2D geometry to be cut, e.g. imported from a drawing
+#[derive(Clone, Debug, PartialEq)]
+pub struct GCodeContour {
+    pub start: [f64; 2],
+    pub segments: Vec<GCodeContourSegment>,
+    /// Whether the contour returns to its start, a closing line is added by
+    /// `contour_passes()` where the last segment ends elsewhere
+    pub closed: bool,
+}
+impl GCodeContour {
+    /// Contour of lines through `points`, fails with `GeometryError` for
+    /// fewer than two points
+    pub fn from_points(points: &[[f64; 2]], closed: bool) -> Result<Self, GCodeError> {
+        match points {
+            [start, rest @ ..] if !rest.is_empty() => Ok(Self {
+                start: *start,
+                segments: rest.iter().map(|&p| GCodeContourSegment::Line(p)).collect(),
+                closed,
+            }),
+            _ => Err(GCodeError::GeometryError),
+        }
+    }
+
+    /// End of the last segment
+    pub fn end(&self) -> [f64; 2] {
+        self.segments.last().map_or(self.start, |seg| seg.end())
+    }
+}
+
+/// Converts a contour into a toolpath cutting it at each of `depths`, in
+/// order, to be written by `GCodeWriter::follow_path()`. The path starts
+/// with a line to the start of the contour at the first depth, i.e. a
+/// plunge from a position above it.
+///
+/// Closed contours are cut in the same direction on every pass, plunging at
+/// their start. Open contours are cut back and forth, plunging at whichever
+/// end the previous pass finished at.
+///
+/// Fails with `GeometryError` for contours without segments or with arcs
+/// whose center is not equidistant from both ends, and with
+/// `OutOfRangeError` for non-finite coordinates or depths.
+pub fn contour_passes(
+    contour: &GCodeContour,
+    depths: &[f64],
+) -> Result<Vec<GCodePathSegment>, GCodeError> {
+    if contour.segments.is_empty() {
+        return Err(GCodeError::GeometryError);
+    }
+
+    let mut path = vec![];
+    for (idx, &depth) in depths.iter().enumerate() {
+        let pass = contour_pass(contour, depth)?;
+        if !contour.closed && (idx % 2 == 1) {
+            path.extend(reverse_path(&pass));
+        } else {
+            path.extend(pass);
+        }
+    }
+
+    Ok(path)
+}
+
+/// Single pass over `contour` at Z `depth`, starting with a line to its
+/// start
+fn contour_pass(contour: &GCodeContour, depth: f64) -> Result<Vec<GCodePathSegment>, GCodeError> {
+    let finite = |p: [f64; 2]| match p.iter().chain([&depth]).all(|val| val.is_finite()) {
+        true => Ok(p),
+        false => Err(GCodeError::OutOfRangeError),
+    };
+    let pos = |p: [f64; 2]| {
+        let [x, y] = finite(p)?;
+        GCodePosition::from_f64_full(x, y, depth)
+    };
+
+    let mut cur = pos(contour.start)?;
+    let mut pass = vec![GCodePathSegment::Line(cur)];
+    for segment in &contour.segments {
+        let end = pos(segment.end())?;
+        pass.push(match *segment {
+            GCodeContourSegment::Line(_) => GCodePathSegment::Line(end),
+            GCodeContourSegment::Arc {
+                center, clockwise, ..
+            } => {
+                let [cx, cy] = finite(center)?;
+                let center = GCodePosition::from_f64(Some(cx), Some(cy), None)?;
+                arc_geometry(&cur, &end, &center, clockwise)?;
+                GCodePathSegment::Arc {
+                    end,
+                    center,
+                    clockwise,
+                }
+            }
+        });
+        cur = end;
+    }
+    if contour.closed && (contour.end() != contour.start) {
+        pass.push(GCodePathSegment::Line(pos(contour.start)?));
+    }
+
+    Ok(pass)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes() -> Result<(), GCodeError> {
+        let pos = |x, y, z| GCodePosition::from_f64_full(x, y, z);
+        let square =
+            GCodeContour::from_points(&[[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0]], true)?;
+        let path = contour_passes(&square, &[-1.0, -2.0])?;
+
+        assert_eq!(path.len(), 10);
+        assert_eq!(path[0], GCodePathSegment::Line(pos(0.0, 0.0, -1.0)?));
+        assert_eq!(path[4], GCodePathSegment::Line(pos(0.0, 0.0, -1.0)?));
+        assert_eq!(path[5], GCodePathSegment::Line(pos(0.0, 0.0, -2.0)?));
+        assert_eq!(path[9], GCodePathSegment::Line(pos(0.0, 0.0, -2.0)?));
+
+        let slot = GCodeContour {
+            start: [0.0, 0.0],
+            segments: vec![
+                GCodeContourSegment::Line([10.0, 0.0]),
+                GCodeContourSegment::Arc {
+                    end: [20.0, 0.0],
+                    center: [15.0, 0.0],
+                    clockwise: true,
+                },
+            ],
+            closed: false,
+        };
+        let path = contour_passes(&slot, &[-1.0, -2.0])?;
+
+        assert_eq!(path.len(), 6);
+        assert_eq!(path[3], GCodePathSegment::Line(pos(20.0, 0.0, -2.0)?));
+        assert_eq!(
+            path[4],
+            GCodePathSegment::Arc {
+                end: pos(10.0, 0.0, -2.0)?,
+                center: GCodePosition::from_f64(Some(15.0), Some(0.0), None)?,
+                clockwise: false,
+            }
+        );
+        assert_eq!(path[5], GCodePathSegment::Line(pos(0.0, 0.0, -2.0)?));
+
+        let bad = GCodeContour {
+            segments: vec![GCodeContourSegment::Arc {
+                end: [20.0, 0.0],
+                center: [12.0, 0.0],
+                clockwise: true,
+            }],
+            ..slot
+        };
+        assert_eq!(
+            contour_passes(&bad, &[-1.0]),
+            Err(GCodeError::GeometryError)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn passes_edge_cases() -> Result<(), GCodeError> {
+        assert_eq!(
+            GCodeContour::from_points(&[[0.0, 0.0]], false),
+            Err(GCodeError::GeometryError)
+        );
+        assert_eq!(
+            GCodeContour::from_points(&[], true),
+            Err(GCodeError::GeometryError)
+        );
+        let empty = GCodeContour {
+            start: [0.0, 0.0],
+            segments: vec![],
+            closed: true,
+        };
+        assert_eq!(
+            contour_passes(&empty, &[-1.0]),
+            Err(GCodeError::GeometryError)
+        );
+
+        let line = GCodeContour::from_points(&[[0.0, 0.0], [10.0, 0.0]], false)?;
+        assert!(contour_passes(&line, &[])?.is_empty());
+        assert_eq!(
+            contour_passes(&line, &[-1.0, f64::NAN]),
+            Err(GCodeError::OutOfRangeError)
+        );
+        let far = GCodeContour::from_points(&[[0.0, 0.0], [f64::INFINITY, 0.0]], false)?;
+        assert_eq!(
+            contour_passes(&far, &[-1.0]),
+            Err(GCodeError::OutOfRangeError)
+        );
+
+        /* Closed contours ending at their start get no closing line */
+        let triangle =
+            GCodeContour::from_points(&[[0.0, 0.0], [10.0, 0.0], [0.0, 10.0], [0.0, 0.0]], true)?;
+        assert_eq!(contour_passes(&triangle, &[-1.0])?.len(), 4);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{GCodeHeater, GCodePosition, GCodeRotaryAxis, GCodeSpindle, GCodeWriterState};
+
+/// Command written by a `GCodeWriter`, as seen by a cost model
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GCodeCommand {
+    /// Linear move. `distance` is None if the start or end is not fully
+    /// known, `feed_rate` is in units per minute.
+    Move {
+        from: GCodePosition,
+        to: GCodePosition,
+        distance: Option<f64>,
+        feed_rate: Option<f64>,
+        rapid: bool,
+    },
+    /// Arc in the XY plane around `center`, `distance` being the length
+    /// along the arc
+    Arc {
+        from: GCodePosition,
+        to: GCodePosition,
+        center: GCodePosition,
+        clockwise: bool,
+        distance: Option<f64>,
+        feed_rate: Option<f64>,
+    },
+    /// Rotary move to `angle`, `travel` is in the configured angle units
+    Rotate {
+        axis: GCodeRotaryAxis,
+        angle: f64,
+        travel: Option<f64>,
+        feed_rate: Option<f64>,
+        rapid: bool,
+    },
+    /// Dwell, in seconds
+    Dwell(f64),
+    Spindle(GCodeSpindle),
+    Temperature {
+        heater: GCodeHeater,
+        temp: f64,
+        wait: bool,
+    },
+}
+
+/// Estimates how long commands take to execute
+pub trait GCodeCostModel {
+    /// Duration of `command` in seconds, `state` being the modal state prior
+    /// to the command
+    fn duration(&self, command: &GCodeCommand, state: &GCodeWriterState) -> f64;
+}
+
+/// Cost model considering motion at the programmed feed rates and dwells,
+/// ignoring acceleration. All other commands take no time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeBasicCostModel {
+    /// Speed of rapid moves, in units per minute
+    pub rapid_rate: f64,
+}
+impl Default for GCodeBasicCostModel {
+    fn default() -> Self {
+        Self { rapid_rate: 5000.0 }
+    }
+}
+impl GCodeCostModel for GCodeBasicCostModel {
+    fn duration(&self, command: &GCodeCommand, _state: &GCodeWriterState) -> f64 {
+        let motion = |distance: Option<f64>, feed_rate: Option<f64>, rapid: bool| {
+            let rate = if rapid {
+                Some(self.rapid_rate)
+            } else {
+                feed_rate
+            };
+            match (distance, rate) {
+                (Some(distance), Some(rate)) if rate > 0.0 => distance / rate * 60.0,
+                _ => 0.0,
+            }
+        };
+
+        match *command {
+            GCodeCommand::Move {
+                distance,
+                feed_rate,
+                rapid,
+                ..
+            } => motion(distance, feed_rate, rapid),
+            GCodeCommand::Arc {
+                distance,
+                feed_rate,
+                ..
+            } => motion(distance, feed_rate, false),
+            GCodeCommand::Rotate {
+                travel,
+                feed_rate,
+                rapid,
+                ..
+            } => motion(travel, feed_rate, rapid),
+            GCodeCommand::Dwell(seconds) => seconds,
+            GCodeCommand::Spindle(_) | GCodeCommand::Temperature { .. } => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GCodeError, GCodeWriter};
+
+    #[test]
+    fn basic() -> Result<(), GCodeError> {
+        let model = GCodeBasicCostModel::default();
+        let state = GCodeWriterState::default();
+        let from = GCodePosition::from_f64_full(0.0, 0.0, 0.0)?;
+        let to = GCodePosition::from_f64_full(100.0, 0.0, 0.0)?;
+        let line = |distance, feed_rate, rapid| GCodeCommand::Move {
+            from,
+            to,
+            distance,
+            feed_rate,
+            rapid,
+        };
+
+        /* Rapids ignore the feed rate */
+        assert_eq!(
+            model.duration(&line(Some(100.0), Some(1.0), true), &state),
+            1.2
+        );
+        assert_eq!(
+            model.duration(&line(Some(100.0), Some(600.0), false), &state),
+            10.0
+        );
+        /* Unknown distances or feed rates take no time */
+        assert_eq!(model.duration(&line(None, Some(600.0), false), &state), 0.0);
+        assert_eq!(model.duration(&line(Some(100.0), None, false), &state), 0.0);
+        assert_eq!(
+            model.duration(&line(Some(100.0), Some(0.0), false), &state),
+            0.0
+        );
+
+        let rotate = GCodeCommand::Rotate {
+            axis: GCodeRotaryAxis::A,
+            angle: 90.0,
+            travel: Some(90.0),
+            feed_rate: Some(180.0),
+            rapid: false,
+        };
+        assert_eq!(model.duration(&rotate, &state), 30.0);
+        assert_eq!(model.duration(&GCodeCommand::Dwell(2.5), &state), 2.5);
+        let spindle = GCodeCommand::Spindle(GCodeSpindle::Clockwise(1000.0));
+        assert_eq!(model.duration(&spindle, &state), 0.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn custom() -> Result<(), GCodeError> {
+        /* A second per command, and ten more per move with the spindle on,
+         * as of the state prior to the command */
+        struct PerCommand;
+        impl GCodeCostModel for PerCommand {
+            fn duration(&self, command: &GCodeCommand, state: &GCodeWriterState) -> f64 {
+                match (command, state.spindle) {
+                    (GCodeCommand::Move { .. }, GCodeSpindle::Clockwise(_)) => 11.0,
+                    _ => 1.0,
+                }
+            }
+        }
+
+        let mut gcw = GCodeWriter::new(vec![])?;
+        gcw.set_cost_model(PerCommand);
+        gcw.move_to(GCodePosition::from_f64_full(0.0, 0.0, 5.0)?, None, true)?;
+        gcw.set_spindle(GCodeSpindle::Clockwise(1000.0))?;
+        gcw.move_to(GCodePosition::from_f64_full(10.0, 0.0, 5.0)?, None, true)?;
+        gcw.set_spindle(GCodeSpindle::Off)?;
+        gcw.dwell(60.0)?;
+
+        assert_eq!(gcw.estimated_time(), 15.0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{GCodeBackplot, GCodeError};
+
+/// Options of coverage map generation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeCoverageOptions {
+    /// Width of the material deposited or removed by a move, e.g. the
+    /// extrusion width or tool diameter
+    pub line_width: f64,
+    /// Side length of each grid cell
+    pub cell_size: f64,
+    /// Only moves entirely within this Z range (inclusive) are considered,
+    /// e.g. to map a single layer
+    pub z_range: Option<(f64, f64)>,
+}
+
+/// Grid counting how many moves cover each cell, in the XY plane
+#[derive(Clone, Debug, PartialEq)]
+pub struct GCodeCoverageMap {
+    /// XY position of the corner of the first cell
+    pub origin: [f64; 2],
+    pub cell_size: f64,
+    pub columns: usize,
+    pub rows: usize,
+    /// Number of moves covering each cell, row by row
+    pub counts: Vec<u32>,
+}
+impl GCodeCoverageMap {
+    /// Coverage count of the cell containing XY position `pos`, None if
+    /// outside of the map or not finite
+    pub fn count_at(&self, pos: [f64; 2]) -> Option<u32> {
+        if !pos.iter().all(|val| val.is_finite()) {
+            return None;
+        }
+        let col = ((pos[0] - self.origin[0]) / self.cell_size).floor();
+        let row = ((pos[1] - self.origin[1]) / self.cell_size).floor();
+        if (col < 0.0) || (row < 0.0) {
+            return None;
+        }
+        let (col, row) = (col as usize, row as usize);
+        if (col >= self.columns) || (row >= self.rows) {
+            return None;
+        }
+        Some(self.counts[row * self.columns + col])
+    }
+
+    /// Highest coverage count of any cell
+    pub fn max_count(&self) -> u32 {
+        self.counts.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Number of cells covered exactly `count` times
+    pub fn cells_with_count(&self, count: u32) -> usize {
+        self.counts.iter().filter(|&&c| c == count).count()
+    }
+}
+
+/// Rasterizes the feed moves of a backplot into a coverage map. A cell is
+/// covered by a move if its center lies within half the line width of it.
+/// Cells covered by consecutive moves of a polyline, i.e. around corners,
+/// are only counted once. Rapid moves are ignored.
+///
+/// Uncovered cells surrounded by covered ones indicate gaps, cells covered
+/// more than once indicate overlaps.
+pub fn coverage_map(
+    backplot: &GCodeBackplot,
+    options: &GCodeCoverageOptions,
+) -> Result<GCodeCoverageMap, GCodeError> {
+    let valid = |val: f64| val.is_finite() && (val > 0.0);
+    if !valid(options.line_width) || !valid(options.cell_size) {
+        return Err(GCodeError::OutOfRangeError);
+    }
+    let radius = options.line_width / 2.0;
+    let in_range = |z: f64| {
+        options
+            .z_range
+            .is_none_or(|(min, max)| (z >= min) && (z <= max))
+    };
+
+    let segments: Vec<Vec<([f64; 2], [f64; 2])>> = backplot
+        .polylines()
+        .iter()
+        .filter(|line| !line.rapid)
+        .map(|line| {
+            line.points
+                .windows(2)
+                .filter(|seg| in_range(seg[0][2]) && in_range(seg[1][2]))
+                .map(|seg| ([seg[0][0], seg[0][1]], [seg[1][0], seg[1][1]]))
+                .collect()
+        })
+        .collect();
+
+    let mut min = [f64::INFINITY; 2];
+    let mut max = [f64::NEG_INFINITY; 2];
+    for &(from, to) in segments.iter().flatten() {
+        for i in 0..2 {
+            min[i] = min[i].min(from[i]).min(to[i]);
+            max[i] = max[i].max(from[i]).max(to[i]);
+        }
+    }
+    if min[0] > max[0] {
+        return Ok(GCodeCoverageMap {
+            origin: [0.0, 0.0],
+            cell_size: options.cell_size,
+            columns: 0,
+            rows: 0,
+            counts: vec![],
+        });
+    }
+
+    let origin = [min[0] - radius, min[1] - radius];
+    let cells = |i: usize| {
+        ((max[i] + radius - origin[i]) / options.cell_size)
+            .ceil()
+            .max(1.0) as usize
+    };
+    let (columns, rows) = (cells(0), cells(1));
+    let mut counts = vec![0u32; columns * rows];
+    /* Last segment covering each cell, as (polyline, segment) */
+    let mut last: Vec<Option<(usize, usize)>> = vec![None; columns * rows];
+
+    let center = |idx: usize, i: usize| origin[i] + (idx as f64 + 0.5) * options.cell_size;
+    let cell_range = |lo: f64, hi: f64, i: usize, n: usize| {
+        let lo = ((lo - radius - origin[i]) / options.cell_size)
+            .floor()
+            .max(0.0) as usize;
+        let hi = ((hi + radius - origin[i]) / options.cell_size).ceil() as usize;
+        lo..hi.min(n)
+    };
+
+    for (line_idx, line) in segments.iter().enumerate() {
+        for (seg_idx, &(from, to)) in line.iter().enumerate() {
+            for row in cell_range(from[1].min(to[1]), from[1].max(to[1]), 1, rows) {
+                for col in cell_range(from[0].min(to[0]), from[0].max(to[0]), 0, columns) {
+                    let point = [center(col, 0), center(row, 1)];
+                    if point_segment_distance(point, from, to) > radius {
+                        continue;
+                    }
+                    let cell = row * columns + col;
+                    let joined = (seg_idx > 0) && (last[cell] == Some((line_idx, seg_idx - 1)));
+                    if !joined {
+                        counts[cell] += 1;
+                    }
+                    last[cell] = Some((line_idx, seg_idx));
+                }
+            }
+        }
+    }
+
+    Ok(GCodeCoverageMap {
+        origin,
+        cell_size: options.cell_size,
+        columns,
+        rows,
+        counts,
+    })
+}
+
+fn point_segment_distance(point: [f64; 2], from: [f64; 2], to: [f64; 2]) -> f64 {
+    let dir = [to[0] - from[0], to[1] - from[1]];
+    let rel = [point[0] - from[0], point[1] - from[1]];
+    let len_sq = dir[0] * dir[0] + dir[1] * dir[1];
+    let t = if len_sq > 0.0 {
+        ((rel[0] * dir[0] + rel[1] * dir[1]) / len_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (rel[0] - dir[0] * t).hypot(rel[1] - dir[1] * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GCodeBackplotBuilder, GCodePosition};
+
+    #[test]
+    fn coverage() -> Result<(), GCodeError> {
+        let mut builder = GCodeBackplotBuilder::new();
+        /* Two passes 0.8 apart with a width of 1.0, overlapping by 0.2, and
+         * a third leaving a gap of 0.2 */
+        builder
+            .start_at(GCodePosition::from_f64_full(0.0, 0.0, 0.2)?)
+            .move_to(1, GCodePosition::from_f64(Some(10.0), None, None)?, false)
+            .move_to(2, GCodePosition::from_f64(None, Some(0.8), None)?, false)
+            .move_to(3, GCodePosition::from_f64(Some(0.0), None, None)?, false)
+            .move_to(4, GCodePosition::from_f64(None, Some(2.0), None)?, true)
+            .move_to(5, GCodePosition::from_f64(Some(10.0), None, None)?, false)
+            /* Next layer */
+            .move_to(
+                6,
+                GCodePosition::from_f64(None, Some(0.0), Some(0.4))?,
+                false,
+            );
+        let backplot = builder.build();
+
+        let options = GCodeCoverageOptions {
+            line_width: 1.0,
+            cell_size: 0.1,
+            z_range: Some((0.1, 0.3)),
+        };
+        let map = coverage_map(&backplot, &options)?;
+
+        assert_eq!((map.columns, map.rows), (110, 30));
+        assert_eq!(map.count_at([5.0, 0.0]), Some(1));
+        assert_eq!(map.count_at([5.0, 0.45]), Some(2));
+        assert_eq!(map.count_at([5.0, 1.41]), Some(0));
+        assert_eq!(map.count_at([5.0, 2.0]), Some(1));
+        /* Corner between moves 1 and 2 is only covered once by them */
+        assert_eq!(map.count_at([10.0, 0.05]), Some(1));
+        assert_eq!(map.count_at([50.0, 0.0]), None);
+        assert_eq!(map.max_count(), 2);
+
+        let options = GCodeCoverageOptions {
+            line_width: 0.0,
+            ..options
+        };
+        assert_eq!(
+            coverage_map(&backplot, &options),
+            Err(GCodeError::OutOfRangeError)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn coverage_edge_cases() -> Result<(), GCodeError> {
+        let options = GCodeCoverageOptions {
+            line_width: 1.0,
+            cell_size: 0.1,
+            z_range: None,
+        };
+        let empty = GCodeCoverageMap {
+            origin: [0.0, 0.0],
+            cell_size: 0.1,
+            columns: 0,
+            rows: 0,
+            counts: vec![],
+        };
+
+        let map = coverage_map(&GCodeBackplotBuilder::new().build(), &options)?;
+        assert_eq!(map, empty);
+        assert_eq!(map.count_at([0.0, 0.0]), None);
+        assert_eq!(map.max_count(), 0);
+        assert_eq!(map.cells_with_count(0), 0);
+
+        let mut builder = GCodeBackplotBuilder::new();
+        builder
+            .start_at(GCodePosition::from_f64_full(0.0, 0.0, 1.0)?)
+            .move_to(1, GCodePosition::from_f64(Some(10.0), None, None)?, true)
+            .move_to(2, GCodePosition::from_f64(Some(0.0), None, None)?, false);
+        let backplot = builder.build();
+
+        /* Maps without moves in the Z range are empty, rapids are ignored */
+        let layer = GCodeCoverageOptions {
+            z_range: Some((0.0, 0.5)),
+            ..options
+        };
+        assert_eq!(coverage_map(&backplot, &layer)?, empty);
+
+        let map = coverage_map(&backplot, &options)?;
+        assert_eq!((map.columns, map.rows), (110, 10));
+        assert_eq!(map.max_count(), 1);
+        assert_eq!(map.count_at([f64::NAN, 0.0]), None);
+        assert_eq!(map.count_at([-0.6, 0.0]), None);
+
+        for bad in [
+            GCodeCoverageOptions {
+                line_width: f64::NAN,
+                ..options
+            },
+            GCodeCoverageOptions {
+                cell_size: -0.1,
+                ..options
+            },
+            GCodeCoverageOptions {
+                cell_size: f64::INFINITY,
+                ..options
+            },
+        ] {
+            assert_eq!(
+                coverage_map(&backplot, &bad),
+                Err(GCodeError::OutOfRangeError)
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,34 @@
+use crate::GCodeFeedMode;
+
+/// Flavour of G-code understood by the target controller
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GCodeDialect {
+    /// Plain RS274, without any controller-specific extensions
+    #[default]
+    Generic,
+    /// Fanuc and compatible controllers
+    Fanuc,
+    /// LinuxCNC
+    LinuxCnc,
+    /// Marlin and RepRap 3D printer firmware
+    Marlin,
+}
+impl GCodeDialect {
+    /// Code selecting feed mode `mode`, None if the mode is unsupported
+    pub fn feed_mode_code(&self, mode: GCodeFeedMode) -> Option<&'static str> {
+        /* Feed rates are always in units per minute on Marlin */
+        if *self == Self::Marlin {
+            return None;
+        }
+        Some(match mode {
+            GCodeFeedMode::InverseTime => "G93",
+            GCodeFeedMode::UnitsPerMinute => "G94",
+            GCodeFeedMode::UnitsPerRevolution => "G95",
+        })
+    }
+
+    /// Whether the controller rotates the coordinate system itself (G68/G69)
+    pub fn supports_rotation(&self) -> bool {
+        matches!(self, Self::Fanuc)
+    }
+}
@@ -0,0 +1,570 @@
+use std::fmt::Display;
+
+use crate::interp::{code_of, modal_group, Interpreter, Motion};
+
+/// Options of program comparison
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeDiffOptions {
+    /// Largest difference of positions, feed rates and spindle speeds
+    /// considered equal
+    pub tolerance: f64,
+}
+impl Default for GCodeDiffOptions {
+    fn default() -> Self {
+        Self { tolerance: 0.001 }
+    }
+}
+
+/// Kind of difference found by `diff_programs()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GCodeDiffKind {
+    /// Operation only present in the left program
+    Removed,
+    /// Operation only present in the right program
+    Inserted,
+    /// Move whose end point or arc center differs by the contained distance
+    MoveChanged(f64),
+    /// Feed move with a different feed rate, as (left, right)
+    FeedChanged(Option<f64>, Option<f64>),
+    /// Operation with a different spindle speed, as (left, right)
+    SpeedChanged(Option<f64>, Option<f64>),
+}
+impl Display for GCodeDiffKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let opt = |val: &Option<f64>| val.map_or("none".into(), |val| val.to_string());
+        match self {
+            Self::Removed => write!(f, "removed"),
+            Self::Inserted => write!(f, "inserted"),
+            Self::MoveChanged(dist) => write!(f, "move differs by {}", dist),
+            Self::FeedChanged(l, r) => write!(f, "feed rate {} -> {}", opt(l), opt(r)),
+            Self::SpeedChanged(l, r) => write!(f, "spindle speed {} -> {}", opt(l), opt(r)),
+        }
+    }
+}
+
+/// Difference found by `diff_programs()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeDiffEntry {
+    /// Line number within the left program, starting at 1
+    pub left: Option<usize>,
+    /// Line number within the right program, starting at 1
+    pub right: Option<usize>,
+    pub kind: GCodeDiffKind,
+}
+impl Display for GCodeDiffEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let opt = |line: Option<usize>| line.map_or("-".into(), |line| line.to_string());
+        write!(f, "{}:{}: {}", opt(self.left), opt(self.right), self.kind)
+    }
+}
+
+/// Operation of a program, with modal state resolved
+#[derive(Clone, Debug, PartialEq)]
+enum Operation {
+    Move {
+        /// Motion mode, as G code number
+        motion: u8,
+        end: [Option<f64>; 3],
+        /// Absolute arc center, if given by I/J
+        center: Option<(f64, f64)>,
+        feed: Option<f64>,
+        speed: Option<f64>,
+    },
+    /// Non-motion words, e.g. M codes, tool changes or modal G codes
+    /// changing the state
+    Words(Vec<(char, f64)>, Option<f64>),
+    /// Line which could not be analyzed, compared as text
+    Raw(String),
+}
+
+/// Compares two programs by what they do rather than how they are written,
+/// e.g. to verify that post-processor changes do not alter toolpaths.
+/// Modal state is resolved, such that moves are compared by their absolute
+/// end points, along with the feed rate and spindle speed in effect. Line
+/// numbers, comments, formatting and redundant modal words are ignored.
+/// Modal G codes not causing motion, e.g. G17 or G54, are compared as
+/// changes of state preceding the moves of their line.
+///
+/// Operations are aligned by a longest common subsequence, moves matching
+/// where they end within the tolerance. Unmatched moves at the same place
+/// in both programs are reported as changed, any others as removed or
+/// inserted. Arcs are compared by center only when given by I/J. Lines
+/// using parameters, expressions or control flow are compared as text.
+///
+/// The alignment is found by Myers' algorithm, in its linear space variant:
+/// runtime is proportional to the sum of program lengths times the number
+/// of differing operations, and memory to the sum of program lengths.
+pub fn diff_programs(left: &str, right: &str, options: &GCodeDiffOptions) -> Vec<GCodeDiffEntry> {
+    let (left, right) = (operations(left), operations(right));
+    let tol = options.tolerance;
+
+    let mut diffs = vec![];
+    let (mut i, mut j) = (0, 0);
+    for (l, r) in align(&left, &right, tol) {
+        let (removed, inserted): (Vec<usize>, Vec<usize>) = ((i..l).collect(), (j..r).collect());
+        diffs.extend(hunk(&left, &right, &removed, &inserted, tol));
+        compare(&mut diffs, &left[l], &right[r], tol);
+        (i, j) = (l + 1, r + 1);
+    }
+    let (removed, inserted): (Vec<usize>, Vec<usize>) =
+        ((i..left.len()).collect(), (j..right.len()).collect());
+    diffs.extend(hunk(&left, &right, &removed, &inserted, tol));
+
+    diffs
+}
+
+/// Longest common subsequence of two programs, as pairs of matching operation
+/// indices in ascending order. Where an operation matches several in a row,
+/// it is paired with the earliest.
+fn align(
+    left: &[(usize, Operation)],
+    right: &[(usize, Operation)],
+    tol: f64,
+) -> Vec<(usize, usize)> {
+    let mut pairs = vec![];
+    align_range(
+        left,
+        right,
+        (0, left.len()),
+        (0, right.len()),
+        tol,
+        &mut pairs,
+    );
+
+    let eq = |i: usize, j: usize| matches(&left[i].1, &right[j].1, tol);
+    let (mut i, mut j) = (0, 0);
+    for (l, r) in pairs.iter_mut() {
+        while (*r > j) && eq(*l, *r - 1) {
+            *r -= 1;
+        }
+        while (*l > i) && eq(*l - 1, *r) {
+            *l -= 1;
+        }
+        (i, j) = (*l + 1, *r + 1);
+    }
+
+    pairs
+}
+
+/// Aligns the operations `left[l.0..l.1]` and `right[r.0..r.1]`, by splitting
+/// them at a middle snake, appending matching pairs to `pairs`
+fn align_range(
+    left: &[(usize, Operation)],
+    right: &[(usize, Operation)],
+    (mut l0, mut l1): (usize, usize),
+    (mut r0, mut r1): (usize, usize),
+    tol: f64,
+    pairs: &mut Vec<(usize, usize)>,
+) {
+    let eq = |i: usize, j: usize| matches(&left[i].1, &right[j].1, tol);
+
+    /* Common prefix and suffix are matched as-is */
+    while (l0 < l1) && (r0 < r1) && eq(l0, r0) {
+        pairs.push((l0, r0));
+        l0 += 1;
+        r0 += 1;
+    }
+    let mut suffix = 0;
+    while (l0 < l1) && (r0 < r1) && eq(l1 - 1, r1 - 1) {
+        l1 -= 1;
+        r1 -= 1;
+        suffix += 1;
+    }
+
+    if (l0 < l1) && (r0 < r1) {
+        let (x, y, u, v) = middle_snake(&eq, (l0, l1), (r0, r1));
+        align_range(left, right, (l0, x), (r0, y), tol, pairs);
+        pairs.extend((x..u).zip(y..v));
+        align_range(left, right, (u, l1), (v, r1), tol, pairs);
+    }
+    pairs.extend((l1..(l1 + suffix)).zip(r1..(r1 + suffix)));
+}
+
+/// Middle snake of the shortest edit script between `l0..l1` and `r0..r1`,
+/// as its start and end points (x, y, u, v), by searching from both ends
+/// until the paths overlap
+fn middle_snake(
+    eq: &impl Fn(usize, usize) -> bool,
+    (l0, l1): (usize, usize),
+    (r0, r1): (usize, usize),
+) -> (usize, usize, usize, usize) {
+    let (n, m) = ((l1 - l0) as isize, (r1 - r0) as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    /* Furthest x reached on each diagonal k = x - y, offset by max + 1,
+     * forward from the start and backward from the end */
+    let offset = max + 1;
+    let mut forward = vec![0isize; (2 * offset + 1) as usize];
+    let mut backward = forward.clone();
+    let at = |k: isize| (k + offset) as usize;
+
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = match (k == -d) || ((k != d) && (forward[at(k - 1)] < forward[at(k + 1)])) {
+                true => forward[at(k + 1)],
+                false => forward[at(k - 1)] + 1,
+            };
+            let (x0, y0) = (x, x - k);
+            let mut y = y0;
+            while (x < n) && (y < m) && eq(l0 + x as usize, r0 + y as usize) {
+                x += 1;
+                y += 1;
+            }
+            forward[at(k)] = x;
+            let back = delta - k;
+            if odd && (back.abs() < d) && (x + backward[at(back)] >= n) {
+                return (
+                    l0 + x0 as usize,
+                    r0 + y0 as usize,
+                    l0 + x as usize,
+                    r0 + y as usize,
+                );
+            }
+        }
+
+        for k in (-d..=d).step_by(2) {
+            let mut x = match (k == -d) || ((k != d) && (backward[at(k - 1)] < backward[at(k + 1)]))
+            {
+                true => backward[at(k + 1)],
+                false => backward[at(k - 1)] + 1,
+            };
+            let (x0, y0) = (x, x - k);
+            let mut y = y0;
+            while (x < n) && (y < m) && eq(l1 - 1 - x as usize, r1 - 1 - y as usize) {
+                x += 1;
+                y += 1;
+            }
+            backward[at(k)] = x;
+            let fwd = delta - k;
+            if !odd && (fwd.abs() <= d) && (x + forward[at(fwd)] >= n) {
+                return (
+                    l1 - x as usize,
+                    r1 - y as usize,
+                    l1 - x0 as usize,
+                    r1 - y0 as usize,
+                );
+            }
+        }
+    }
+
+    /* The paths overlap by d = max at the latest */
+    unreachable!()
+}
+
+/// Whether two operations are considered the same for alignment
+fn matches(left: &Operation, right: &Operation, tol: f64) -> bool {
+    match (left, right) {
+        (Operation::Move { motion: l, .. }, Operation::Move { motion: r, .. }) => {
+            (l == r) && (move_distance(left, right) <= tol)
+        }
+        (Operation::Words(l, _), Operation::Words(r, _)) => {
+            (l.len() == r.len())
+                && l.iter()
+                    .zip(r)
+                    .all(|(l, r)| (l.0 == r.0) && ((l.1 - r.1).abs() <= tol))
+        }
+        (Operation::Raw(l), Operation::Raw(r)) => l == r,
+        _ => false,
+    }
+}
+
+/// Largest distance between the end points and arc centers of two moves
+fn move_distance(left: &Operation, right: &Operation) -> f64 {
+    let (
+        Operation::Move {
+            end: le,
+            center: lc,
+            ..
+        },
+        Operation::Move {
+            end: re,
+            center: rc,
+            ..
+        },
+    ) = (left, right)
+    else {
+        return f64::INFINITY;
+    };
+
+    let mut sum = 0.0;
+    for (l, r) in le.iter().zip(re) {
+        match (l, r) {
+            (Some(l), Some(r)) => sum += (l - r) * (l - r),
+            (None, None) => (),
+            _ => return f64::INFINITY,
+        }
+    }
+    let center = match (lc, rc) {
+        (Some(l), Some(r)) => (l.0 - r.0).hypot(l.1 - r.1),
+        _ => 0.0,
+    };
+
+    sum.sqrt().max(center)
+}
+
+/// Reports feed and speed changes between two aligned operations
+fn compare(
+    diffs: &mut Vec<GCodeDiffEntry>,
+    left: &(usize, Operation),
+    right: &(usize, Operation),
+    tol: f64,
+) {
+    let differ = |l: Option<f64>, r: Option<f64>| match (l, r) {
+        (Some(l), Some(r)) => (l - r).abs() > tol,
+        (l, r) => l.is_some() != r.is_some(),
+    };
+    let mut push = |kind| {
+        diffs.push(GCodeDiffEntry {
+            left: Some(left.0),
+            right: Some(right.0),
+            kind,
+        })
+    };
+
+    let (l_speed, r_speed) = match (&left.1, &right.1) {
+        (
+            Operation::Move {
+                motion,
+                feed: lf,
+                speed: ls,
+                ..
+            },
+            Operation::Move {
+                feed: rf,
+                speed: rs,
+                ..
+            },
+        ) => {
+            if (*motion != 0) && differ(*lf, *rf) {
+                push(GCodeDiffKind::FeedChanged(*lf, *rf));
+            }
+            (*ls, *rs)
+        }
+        (Operation::Words(_, ls), Operation::Words(_, rs)) => (*ls, *rs),
+        _ => return,
+    };
+    if differ(l_speed, r_speed) {
+        push(GCodeDiffKind::SpeedChanged(l_speed, r_speed));
+    }
+}
+
+/// Reports a run of operations only present in either program. Moves of the
+/// same kind at the same place within the run are paired up as changed.
+fn hunk(
+    left: &[(usize, Operation)],
+    right: &[(usize, Operation)],
+    removed: &[usize],
+    inserted: &[usize],
+    tol: f64,
+) -> Vec<GCodeDiffEntry> {
+    let mut diffs = vec![];
+    let (mut r, mut i) = (0, 0);
+
+    while (r < removed.len()) || (i < inserted.len()) {
+        let l_op = removed.get(r).map(|&idx| &left[idx]);
+        let r_op = inserted.get(i).map(|&idx| &right[idx]);
+        match (l_op, r_op) {
+            (
+                Some(l @ (_, Operation::Move { motion: lm, .. })),
+                Some(rt @ (_, Operation::Move { motion: rm, .. })),
+            ) if lm == rm => {
+                diffs.push(GCodeDiffEntry {
+                    left: Some(l.0),
+                    right: Some(rt.0),
+                    kind: GCodeDiffKind::MoveChanged(move_distance(&l.1, &rt.1)),
+                });
+                compare(&mut diffs, l, rt, tol);
+                r += 1;
+                i += 1;
+            }
+            (Some(l), _) => {
+                diffs.push(GCodeDiffEntry {
+                    left: Some(l.0),
+                    right: None,
+                    kind: GCodeDiffKind::Removed,
+                });
+                r += 1;
+            }
+            (None, Some(rt)) => {
+                diffs.push(GCodeDiffEntry {
+                    left: None,
+                    right: Some(rt.0),
+                    kind: GCodeDiffKind::Inserted,
+                });
+                i += 1;
+            }
+            (None, None) => break,
+        }
+    }
+
+    diffs
+}
+
+/// Resolves a program into its operations, with their line numbers
+fn operations(program: &str) -> Vec<(usize, Operation)> {
+    let mut ops = vec![];
+    let mut interp = Interpreter::new();
+
+    for (idx, text) in program.lines().enumerate() {
+        let line = idx + 1;
+        let trimmed = text.trim();
+        if trimmed.starts_with('%') {
+            continue;
+        }
+        let groups = interp.state.groups;
+        let Some(block) = interp.line(trimmed) else {
+            ops.push((line, Operation::Raw(trimmed.into())));
+            continue;
+        };
+        let state = &interp.state;
+
+        let mut modal = vec![];
+        let mut rest = vec![];
+        for &(letter, val) in &block.words {
+            let code = code_of(val);
+            match (letter, code) {
+                ('G', 0 | 10 | 20 | 30 | 900 | 910) => (),
+                ('X' | 'Y' | 'Z' | 'I' | 'J' | 'F' | 'N', _) => (),
+                /* S words of e.g. temperatures are kept */
+                ('S', _) if block.speed.is_some() => (),
+                ('G', _) => match modal_group(code) {
+                    Some(group) => {
+                        if groups[group] != Some(code) {
+                            modal.push((letter, val));
+                        }
+                    }
+                    None => rest.push((letter, val)),
+                },
+                _ => rest.push((letter, val)),
+            }
+        }
+        /* State changes precede the other words of their line */
+        rest.splice(0..0, modal);
+
+        let (motion, end) = match block.motion {
+            Motion::Work { mode, end, .. } => (mode, end),
+            Motion::Machine { .. } | Motion::Other => {
+                /* Axis words of e.g. G28, G53 or G92 are compared as-is */
+                for (axis, val) in ['X', 'Y', 'Z'].into_iter().zip(block.axes) {
+                    if let Some(val) = val {
+                        rest.push((axis, val));
+                    }
+                }
+                ops.push((line, Operation::Words(rest, state.speed)));
+                continue;
+            }
+            Motion::None => {
+                if !rest.is_empty() {
+                    ops.push((line, Operation::Words(rest, state.speed)));
+                }
+                continue;
+            }
+        };
+        if !rest.is_empty() {
+            ops.push((line, Operation::Words(rest, state.speed)));
+        }
+
+        let (i, j) = (block.word('I'), block.word('J'));
+        let center = match (motion, block.motion) {
+            (2 | 3, Motion::Work { start, .. }) if (i.is_some() || j.is_some()) => {
+                match (start[0], start[1]) {
+                    (Some(x), Some(y)) => Some((x + i.unwrap_or(0.0), y + j.unwrap_or(0.0))),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        ops.push((
+            line,
+            Operation::Move {
+                motion,
+                end,
+                center,
+                feed: state.feed,
+                speed: state.speed,
+            },
+        ));
+    }
+
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff() {
+        let left = "\
+            G21 G90\n\
+            S1000 M03\n\
+            G00 X0 Y0 Z5\n\
+            G01 Z-1 F100\n\
+            G01 X10\n\
+            G02 X20 Y0 I5 J0\n\
+            G01 Y10\n\
+            G00 Z5\n\
+            M05\n";
+        /* Reformatted, with incremental moves, a changed arc, an extra move
+         * and a different feed rate */
+        let right = "\
+            N10 G21 G90 (mm)\n\
+            N20 M3 S1000\n\
+            N30 G0 X0.0001 Y0 Z5\n\
+            N40 G1 Z-1 F100\n\
+            N50 G91 X10\n\
+            N60 G90 G2 X20 Y0 I5 J0.5\n\
+            N70 G1 Y10 F150\n\
+            N80 X20 Y10\n\
+            N90 G0 Z5\n\
+            N100 M5\n";
+
+        let diffs = diff_programs(left, right, &GCodeDiffOptions::default());
+        assert_eq!((diffs[0].left, diffs[0].right), (Some(6), Some(6)));
+        assert!(matches!(diffs[0].kind, GCodeDiffKind::MoveChanged(d) if (d - 0.5).abs() < 1e-6));
+        assert_eq!(
+            diffs[1..],
+            [
+                GCodeDiffEntry {
+                    left: Some(7),
+                    right: Some(7),
+                    kind: GCodeDiffKind::FeedChanged(Some(100.0), Some(150.0)),
+                },
+                GCodeDiffEntry {
+                    left: None,
+                    right: Some(8),
+                    kind: GCodeDiffKind::Inserted,
+                },
+            ]
+        );
+        assert_eq!(diffs[1].to_string(), "7:7: feed rate 100 -> 150");
+        assert!(diff_programs(left, left, &GCodeDiffOptions::default()).is_empty());
+
+        /* Modal codes are state, whether sharing a line with a move or not */
+        let left = "G21 G90\nG00 G54 X0 Y0\nG01 X10 F100\nG54 G01 X20\n";
+        let right = "G21 G90\nG54\nG00 X0 Y0\nG01 X10 F100\nG01 X20\n";
+        assert!(diff_programs(left, right, &GCodeDiffOptions::default()).is_empty());
+        assert_eq!(
+            diff_programs(
+                left,
+                "G21 G90\nG00 X0 Y0\nG01 X10 F100\nG01 X20\n",
+                &GCodeDiffOptions::default()
+            ),
+            [GCodeDiffEntry {
+                left: Some(2),
+                right: None,
+                kind: GCodeDiffKind::Removed,
+            }]
+        );
+
+        /* Long programs with few differences are compared without a table
+         * of the product of their lengths */
+        let left: String = (0..20000).map(|i| format!("G01 X{} F100\n", i)).collect();
+        let right = left.replacen("X100 ", "X100.5 ", 1) + "M05\n";
+        let diffs = diff_programs(&left, &right, &GCodeDiffOptions::default());
+        assert_eq!((diffs.len(), diffs[1].right), (2, Some(20001)));
+        assert_eq!((diffs[0].left, diffs[0].right), (Some(101), Some(101)));
+    }
+}
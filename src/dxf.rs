@@ -0,0 +1,330 @@
+use crate::{GCodeContour, GCodeContourSegment, GCodeError};
+
+/// Largest distance between entity end points considered connected
+const JOIN_TOLERANCE: f64 = 1e-6;
+
+/// Entity of the ENTITIES section, as (type, group codes and values)
+type Entity<'a> = (&'a str, Vec<(i32, &'a str)>);
+
+/// Reads the contours of an ASCII DXF drawing, for use with
+/// `contour_passes()`. LINE, ARC, CIRCLE and LWPOLYLINE entities are read,
+/// others are ignored. Lines and arcs whose ends meet are joined into a
+/// single contour, which is closed if it ends where it starts. Polylines
+/// and circles form contours of their own.
+///
+/// Only X and Y are read, entities are assumed to lie in the XY plane.
+/// Fails with `ParseError` for malformed files.
+pub fn parse_dxf(text: &str) -> Result<Vec<GCodeContour>, GCodeError> {
+    let mut contours = vec![];
+    let mut pieces = vec![];
+
+    for (kind, groups) in entities(text)? {
+        let num = |code| -> Result<f64, GCodeError> {
+            let (_, val) = groups
+                .iter()
+                .find(|(c, _)| *c == code)
+                .ok_or(GCodeError::ParseError)?;
+            val.parse().map_err(|_| GCodeError::ParseError)
+        };
+        let point = |angle: f64, center: [f64; 2], radius: f64| {
+            let angle = angle.to_radians();
+            [
+                center[0] + radius * angle.cos(),
+                center[1] + radius * angle.sin(),
+            ]
+        };
+
+        match kind {
+            "LINE" => pieces.push(GCodeContour {
+                start: [num(10)?, num(20)?],
+                segments: vec![GCodeContourSegment::Line([num(11)?, num(21)?])],
+                closed: false,
+            }),
+            "ARC" => {
+                let (center, radius) = ([num(10)?, num(20)?], num(40)?);
+                pieces.push(GCodeContour {
+                    start: point(num(50)?, center, radius),
+                    segments: vec![GCodeContourSegment::Arc {
+                        end: point(num(51)?, center, radius),
+                        center,
+                        clockwise: false,
+                    }],
+                    closed: false,
+                });
+            }
+            "CIRCLE" => {
+                let (center, radius) = ([num(10)?, num(20)?], num(40)?);
+                let start = point(0.0, center, radius);
+                contours.push(GCodeContour {
+                    start,
+                    segments: vec![GCodeContourSegment::Arc {
+                        end: start,
+                        center,
+                        clockwise: false,
+                    }],
+                    closed: true,
+                });
+            }
+            "LWPOLYLINE" => contours.push(polyline(&groups)?),
+            _ => (),
+        }
+    }
+
+    contours.splice(0..0, join(pieces));
+    Ok(contours)
+}
+
+/// Splits the ENTITIES section into entities
+fn entities(text: &str) -> Result<Vec<Entity<'_>>, GCodeError> {
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    if lines.len() % 2 != 0 {
+        return Err(GCodeError::ParseError);
+    }
+    let mut groups = vec![];
+    for pair in lines.chunks(2) {
+        let code: i32 = pair[0].parse().map_err(|_| GCodeError::ParseError)?;
+        groups.push((code, pair[1]));
+    }
+
+    let mut entities: Vec<Entity> = vec![];
+    let mut in_entities = false;
+    for (idx, &(code, val)) in groups.iter().enumerate() {
+        match (code, val) {
+            (0, "SECTION") => {
+                in_entities = groups.get(idx + 1) == Some(&(2, "ENTITIES"));
+            }
+            (0, "ENDSEC") => in_entities = false,
+            (0, kind) if in_entities => entities.push((kind, vec![])),
+            (code, val) if in_entities => {
+                if let Some((_, groups)) = entities.last_mut() {
+                    groups.push((code, val));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(entities)
+}
+
+/// Contour of a LWPOLYLINE entity, with arcs given by bulge (group 42)
+fn polyline(groups: &[(i32, &str)]) -> Result<GCodeContour, GCodeError> {
+    let parse =
+        |val: &str| -> Result<f64, GCodeError> { val.parse().map_err(|_| GCodeError::ParseError) };
+
+    /* Vertices, as (x, y, bulge of the segment starting there) */
+    let mut vertices: Vec<[f64; 3]> = vec![];
+    let mut closed = false;
+    for &(code, val) in groups {
+        match code {
+            10 => vertices.push([parse(val)?, 0.0, 0.0]),
+            20 | 42 => {
+                let vertex = vertices.last_mut().ok_or(GCodeError::ParseError)?;
+                vertex[if code == 20 { 1 } else { 2 }] = parse(val)?;
+            }
+            70 => closed = ((parse(val)? as i64) & 1) != 0,
+            _ => (),
+        }
+    }
+    if vertices.len() < 2 {
+        return Err(GCodeError::ParseError);
+    }
+
+    let mut ends: Vec<usize> = (1..vertices.len()).collect();
+    if closed {
+        ends.push(0);
+    }
+    let segments = ends
+        .iter()
+        .map(|&end| {
+            let from = vertices[(end + vertices.len() - 1) % vertices.len()];
+            let to = vertices[end];
+            bulge_segment(from, to)
+        })
+        .collect();
+
+    Ok(GCodeContour {
+        start: [vertices[0][0], vertices[0][1]],
+        segments,
+        closed,
+    })
+}
+
+/// Segment from vertex `from` to `to`, an arc if `from` has a bulge, i.e.
+/// the tangent of a quarter of the angle swept, positive counterclockwise
+fn bulge_segment(from: [f64; 3], to: [f64; 3]) -> GCodeContourSegment {
+    let (bulge, end) = (from[2], [to[0], to[1]]);
+    if bulge == 0.0 {
+        return GCodeContourSegment::Line(end);
+    }
+
+    let (dx, dy) = (to[0] - from[0], to[1] - from[1]);
+    let mid = [(from[0] + to[0]) / 2.0, (from[1] + to[1]) / 2.0];
+    /* Distance of the center from the chord midpoint, relative to the chord
+     * length, towards the left of the chord */
+    let offset = (1.0 - bulge * bulge) / (4.0 * bulge);
+    GCodeContourSegment::Arc {
+        end,
+        center: [mid[0] - dy * offset, mid[1] + dx * offset],
+        clockwise: bulge < 0.0,
+    }
+}
+
+/// Joins open contours whose ends meet, reversing them where needed
+fn join(mut pieces: Vec<GCodeContour>) -> Vec<GCodeContour> {
+    let near = |a: [f64; 2], b: [f64; 2]| (a[0] - b[0]).hypot(a[1] - b[1]) <= JOIN_TOLERANCE;
+
+    let mut contours = vec![];
+    while !pieces.is_empty() {
+        let mut contour = pieces.remove(0);
+        /* Extends the end, then the start of the contour by reversing it */
+        let mut flipped = false;
+        while !near(contour.end(), contour.start) {
+            let end = contour.end();
+            let next = match pieces.iter().position(|p| near(p.start, end)) {
+                Some(idx) => pieces.remove(idx),
+                None => match pieces.iter().position(|p| near(p.end(), end)) {
+                    Some(idx) => reversed(&pieces.remove(idx)),
+                    None if !flipped => {
+                        contour = reversed(&contour);
+                        flipped = true;
+                        continue;
+                    }
+                    None => break,
+                },
+            };
+            contour.segments.extend(next.segments);
+        }
+        contour.closed = near(contour.end(), contour.start);
+        contours.push(contour);
+    }
+
+    contours
+}
+
+/// Contour running from the end of `contour` back to its start
+fn reversed(contour: &GCodeContour) -> GCodeContour {
+    let mut points = vec![contour.start];
+    points.extend(contour.segments.iter().map(|seg| seg.end()));
+
+    let segments = contour
+        .segments
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(idx, seg)| match *seg {
+            GCodeContourSegment::Line(_) => GCodeContourSegment::Line(points[idx]),
+            GCodeContourSegment::Arc {
+                center, clockwise, ..
+            } => GCodeContourSegment::Arc {
+                end: points[idx],
+                center,
+                clockwise: !clockwise,
+            },
+        })
+        .collect();
+
+    GCodeContour {
+        start: contour.end(),
+        segments,
+        closed: contour.closed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx_eq(left: [f64; 2], right: [f64; 2]) -> bool {
+        (left[0] - right[0]).abs() < 1e-9 && (left[1] - right[1]).abs() < 1e-9
+    }
+
+    #[test]
+    fn dxf() -> Result<(), GCodeError> {
+        /* A slot drawn as two lines and two arcs, the second line reversed,
+         * and a rectangle with one bulged edge */
+        let text = "\
+            0\nSECTION\n2\nHEADER\n0\nENDSEC\n\
+            0\nSECTION\n2\nENTITIES\n\
+            0\nLINE\n8\n0\n10\n0\n20\n0\n11\n10\n21\n0\n\
+            0\nARC\n8\n0\n10\n10\n20\n5\n40\n5\n50\n270\n51\n90\n\
+            0\nLINE\n8\n0\n10\n0\n20\n10\n11\n10\n21\n10\n\
+            0\nARC\n8\n0\n10\n0\n20\n5\n40\n5\n50\n90\n51\n270\n\
+            0\nLWPOLYLINE\n90\n4\n70\n1\n\
+            10\n20\n20\n0\n10\n30\n20\n0\n42\n1\n10\n30\n20\n10\n10\n20\n20\n10\n\
+            0\nTEXT\n1\nignored\n\
+            0\nENDSEC\n0\nEOF\n";
+        let contours = parse_dxf(text)?;
+
+        assert_eq!(contours.len(), 2);
+        let slot = &contours[0];
+        assert!(slot.closed);
+        assert_eq!(slot.segments.len(), 4);
+        assert!(approx_eq(slot.end(), [0.0, 0.0]));
+        assert_eq!(slot.segments[2], GCodeContourSegment::Line([0.0, 10.0]));
+        match slot.segments[3] {
+            GCodeContourSegment::Arc {
+                end,
+                center,
+                clockwise,
+            } => {
+                assert!(approx_eq(end, [0.0, 0.0]));
+                assert_eq!((center, clockwise), ([0.0, 5.0], false));
+            }
+            _ => panic!("expected arc"),
+        }
+
+        let rect = &contours[1];
+        assert!(rect.closed);
+        assert_eq!(rect.segments.len(), 4);
+        match rect.segments[1] {
+            GCodeContourSegment::Arc {
+                end,
+                center,
+                clockwise,
+            } => {
+                assert_eq!(end, [30.0, 10.0]);
+                assert!(approx_eq(center, [30.0, 5.0]));
+                assert!(!clockwise);
+            }
+            _ => panic!("expected arc"),
+        }
+        assert_eq!(rect.segments[3], GCodeContourSegment::Line([20.0, 0.0]));
+
+        assert_eq!(parse_dxf("0\nSECTION\n2"), Err(GCodeError::ParseError));
+
+        Ok(())
+    }
+
+    #[test]
+    fn dxf_edge_cases() {
+        /* Drawings without entities have no contours */
+        assert_eq!(parse_dxf(""), Ok(vec![]));
+        assert_eq!(
+            parse_dxf("0\nSECTION\n2\nHEADER\n0\nLINE\n10\n0\n0\nENDSEC\n0\nEOF\n"),
+            Ok(vec![])
+        );
+
+        let entities = |body: &str| format!("0\nSECTION\n2\nENTITIES\n{}0\nENDSEC\n", body);
+        /* Missing end point */
+        assert_eq!(
+            parse_dxf(&entities("0\nLINE\n10\n0\n20\n0\n11\n10\n")),
+            Err(GCodeError::ParseError)
+        );
+        assert_eq!(
+            parse_dxf(&entities("0\nCIRCLE\n10\n0\n20\nx\n40\n5\n")),
+            Err(GCodeError::ParseError)
+        );
+        /* Polylines need two vertices, and Y following X */
+        assert_eq!(
+            parse_dxf(&entities("0\nLWPOLYLINE\n10\n0\n20\n0\n")),
+            Err(GCodeError::ParseError)
+        );
+        assert_eq!(
+            parse_dxf(&entities("0\nLWPOLYLINE\n20\n0\n10\n0\n10\n1\n")),
+            Err(GCodeError::ParseError)
+        );
+        /* Group codes are numbers */
+        assert_eq!(parse_dxf("zero\nSECTION\n"), Err(GCodeError::ParseError));
+    }
+}
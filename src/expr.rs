@@ -0,0 +1,478 @@
+use std::collections::HashMap;
+
+use crate::{GCodeDecimalSeparator, GCodeError};
+
+/// Reference to a parameter (variable)
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GCodeParam {
+    /// Numbered parameter, e.g. `#100`
+    Numbered(u32),
+    /// Named parameter, e.g. `#<depth>`. Only supported by LinuxCNC.
+    Named(String),
+}
+impl GCodeParam {
+    /// Parses a parameter reference, e.g. `#100` or `#<depth>`
+    pub fn parse(input: &str) -> Result<Self, GCodeError> {
+        let mut parser = Parser::new(input);
+        let param = parser.param()?;
+        parser.end()?;
+        Ok(param)
+    }
+}
+impl std::fmt::Display for GCodeParam {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Numbered(num) => write!(f, "#{}", num),
+            Self::Named(name) => write!(f, "#<{}>", name),
+        }
+    }
+}
+
+/// Binary operators usable within expressions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GCodeBinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+}
+impl GCodeBinaryOp {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Mod => "MOD",
+            Self::Pow => "**",
+        }
+    }
+}
+
+/// Functions usable within expressions. Trigonometric functions operate in
+/// degrees.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GCodeFunc {
+    Abs,
+    Sqrt,
+    Sin,
+    Cos,
+    Tan,
+    Round,
+    /// Round down
+    Fix,
+    /// Round up
+    Fup,
+}
+impl GCodeFunc {
+    const ALL: [Self; 8] = [
+        Self::Abs,
+        Self::Sqrt,
+        Self::Sin,
+        Self::Cos,
+        Self::Tan,
+        Self::Round,
+        Self::Fix,
+        Self::Fup,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Abs => "ABS",
+            Self::Sqrt => "SQRT",
+            Self::Sin => "SIN",
+            Self::Cos => "COS",
+            Self::Tan => "TAN",
+            Self::Round => "ROUND",
+            Self::Fix => "FIX",
+            Self::Fup => "FUP",
+        }
+    }
+}
+
+/// Value of a G-code word, possibly referencing parameters
+#[derive(Clone, Debug, PartialEq)]
+pub enum GCodeExpr {
+    Number(f64),
+    Param(GCodeParam),
+    Neg(Box<GCodeExpr>),
+    Binary(GCodeBinaryOp, Box<GCodeExpr>, Box<GCodeExpr>),
+    Func(GCodeFunc, Box<GCodeExpr>),
+}
+impl GCodeExpr {
+    /// Parses an expression, e.g. `[#<depth> * 2 + 1]`
+    pub fn parse(input: &str) -> Result<Self, GCodeError> {
+        let mut parser = Parser::new(input);
+        let expr = parser.expr()?;
+        parser.end()?;
+        Ok(expr)
+    }
+
+    pub fn binary(op: GCodeBinaryOp, lhs: GCodeExpr, rhs: GCodeExpr) -> Self {
+        Self::Binary(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    /// Evaluates the expression, looking up parameters within `params`
+    pub fn eval(&self, params: &GCodeParamTable) -> Result<f64, GCodeError> {
+        Ok(match self {
+            Self::Number(val) => *val,
+            Self::Param(param) => params.get(param)?,
+            Self::Neg(expr) => -expr.eval(params)?,
+            Self::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(params)?, rhs.eval(params)?);
+                match op {
+                    GCodeBinaryOp::Add => lhs + rhs,
+                    GCodeBinaryOp::Sub => lhs - rhs,
+                    GCodeBinaryOp::Mul => lhs * rhs,
+                    GCodeBinaryOp::Div if rhs == 0.0 => return Err(GCodeError::OutOfRangeError),
+                    GCodeBinaryOp::Div => lhs / rhs,
+                    GCodeBinaryOp::Mod if rhs == 0.0 => return Err(GCodeError::OutOfRangeError),
+                    GCodeBinaryOp::Mod => lhs.rem_euclid(rhs),
+                    GCodeBinaryOp::Pow => lhs.powf(rhs),
+                }
+            }
+            Self::Func(func, arg) => {
+                let arg = arg.eval(params)?;
+                match func {
+                    GCodeFunc::Abs => arg.abs(),
+                    GCodeFunc::Sqrt if arg < 0.0 => return Err(GCodeError::OutOfRangeError),
+                    GCodeFunc::Sqrt => arg.sqrt(),
+                    GCodeFunc::Sin => arg.to_radians().sin(),
+                    GCodeFunc::Cos => arg.to_radians().cos(),
+                    GCodeFunc::Tan => arg.to_radians().tan(),
+                    GCodeFunc::Round => arg.round(),
+                    GCodeFunc::Fix => arg.floor(),
+                    GCodeFunc::Fup => arg.ceil(),
+                }
+            }
+        })
+    }
+
+    /// Displays the expression using `separator` within numbers
+    pub fn display_with(&self, separator: GCodeDecimalSeparator) -> impl std::fmt::Display + '_ {
+        ExprDisplay {
+            expr: self,
+            separator,
+        }
+    }
+
+    /// Whether the expression references any named parameters
+    pub fn has_named_params(&self) -> bool {
+        match self {
+            Self::Number(_) => false,
+            Self::Param(param) => matches!(param, GCodeParam::Named(_)),
+            Self::Neg(expr) | Self::Func(_, expr) => expr.has_named_params(),
+            Self::Binary(_, lhs, rhs) => lhs.has_named_params() || rhs.has_named_params(),
+        }
+    }
+}
+impl From<f64> for GCodeExpr {
+    fn from(value: f64) -> Self {
+        Self::Number(value)
+    }
+}
+impl From<GCodeParam> for GCodeExpr {
+    fn from(value: GCodeParam) -> Self {
+        Self::Param(value)
+    }
+}
+impl std::fmt::Display for GCodeExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display_with(GCodeDecimalSeparator::Point))
+    }
+}
+
+struct ExprDisplay<'a> {
+    expr: &'a GCodeExpr,
+    separator: GCodeDecimalSeparator,
+}
+impl std::fmt::Display for ExprDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sub = |expr| ExprDisplay {
+            expr,
+            separator: self.separator,
+        };
+        match self.expr {
+            GCodeExpr::Number(val) => match self.separator {
+                GCodeDecimalSeparator::Point => write!(f, "{}", val),
+                sep => write!(
+                    f,
+                    "{}",
+                    val.to_string().replace('.', &sep.as_char().to_string())
+                ),
+            },
+            GCodeExpr::Param(param) => write!(f, "{}", param),
+            GCodeExpr::Neg(expr) => write!(f, "-{}", sub(expr)),
+            /* Binary operations must always be bracketed */
+            GCodeExpr::Binary(op, lhs, rhs) => {
+                write!(f, "[{} {} {}]", sub(lhs), op.symbol(), sub(rhs))
+            }
+            GCodeExpr::Func(func, arg) => match **arg {
+                GCodeExpr::Binary(..) => write!(f, "{}{}", func.name(), sub(arg)),
+                _ => write!(f, "{}[{}]", func.name(), sub(arg)),
+            },
+        }
+    }
+}
+
+/// Table of parameter values used to evaluate expressions
+///
+/// As in LinuxCNC, unset numbered parameters evaluate to 0, while reading an
+/// unset named parameter is an error. Names are case-insensitive.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GCodeParamTable {
+    numbered: HashMap<u32, f64>,
+    named: HashMap<String, f64>,
+}
+impl GCodeParamTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, param: &GCodeParam, value: f64) {
+        match param {
+            GCodeParam::Numbered(num) => {
+                self.numbered.insert(*num, value);
+            }
+            GCodeParam::Named(name) => {
+                self.named.insert(name.to_lowercase(), value);
+            }
+        }
+    }
+
+    pub fn get(&self, param: &GCodeParam) -> Result<f64, GCodeError> {
+        match param {
+            GCodeParam::Numbered(num) => Ok(self.numbered.get(num).copied().unwrap_or(0.0)),
+            GCodeParam::Named(name) => self
+                .named
+                .get(&name.to_lowercase())
+                .copied()
+                .ok_or(GCodeError::NotFoundError),
+        }
+    }
+}
+
+/// Recursive descent expression parser
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input, pos: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_ws(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consumes `token` (case-insensitive) if it is next in the input
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_ws();
+        let rest = self.rest();
+        if (rest.len() >= token.len())
+            && rest.is_char_boundary(token.len())
+            && rest[..token.len()].eq_ignore_ascii_case(token)
+        {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), GCodeError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(GCodeError::ParseError)
+        }
+    }
+
+    fn end(&mut self) -> Result<(), GCodeError> {
+        self.skip_ws();
+        if self.rest().is_empty() {
+            Ok(())
+        } else {
+            Err(GCodeError::ParseError)
+        }
+    }
+
+    /// expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<GCodeExpr, GCodeError> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = if self.eat("+") {
+                GCodeBinaryOp::Add
+            } else if self.eat("-") {
+                GCodeBinaryOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            lhs = GCodeExpr::binary(op, lhs, self.term()?);
+        }
+    }
+
+    /// term := unary (('*' | '/' | 'MOD') unary)*
+    fn term(&mut self) -> Result<GCodeExpr, GCodeError> {
+        let mut lhs = self.unary()?;
+        loop {
+            /* '**' must be checked prior to '*' */
+            let op = if self.rest().trim_start().starts_with("**") {
+                return Ok(lhs);
+            } else if self.eat("*") {
+                GCodeBinaryOp::Mul
+            } else if self.eat("/") {
+                GCodeBinaryOp::Div
+            } else if self.eat("MOD") {
+                GCodeBinaryOp::Mod
+            } else {
+                return Ok(lhs);
+            };
+            lhs = GCodeExpr::binary(op, lhs, self.unary()?);
+        }
+    }
+
+    /// unary := ('-' | '+') unary | power
+    fn unary(&mut self) -> Result<GCodeExpr, GCodeError> {
+        if self.eat("-") {
+            Ok(GCodeExpr::Neg(Box::new(self.unary()?)))
+        } else if self.eat("+") {
+            self.unary()
+        } else {
+            self.power()
+        }
+    }
+
+    /// power := atom ('**' atom)*
+    fn power(&mut self) -> Result<GCodeExpr, GCodeError> {
+        let mut lhs = self.atom()?;
+        while self.eat("**") {
+            lhs = GCodeExpr::binary(GCodeBinaryOp::Pow, lhs, self.atom()?);
+        }
+        Ok(lhs)
+    }
+
+    /// atom := number | param | '[' expr ']' | func '[' expr ']'
+    fn atom(&mut self) -> Result<GCodeExpr, GCodeError> {
+        self.skip_ws();
+        if self.eat("[") {
+            let expr = self.expr()?;
+            self.expect("]")?;
+            return Ok(expr);
+        }
+        if self.rest().starts_with('#') {
+            return Ok(GCodeExpr::Param(self.param()?));
+        }
+        for func in GCodeFunc::ALL {
+            if self.eat(func.name()) {
+                self.expect("[")?;
+                let arg = self.expr()?;
+                self.expect("]")?;
+                return Ok(GCodeExpr::Func(func, Box::new(arg)));
+            }
+        }
+
+        /* Decimal commas are accepted as well as points, but not both */
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_ascii_digit() || (c == '.') || (c == ',')))
+            .unwrap_or(self.rest().len());
+        let text = &self.rest()[..len];
+        if text.contains('.') && text.contains(',') {
+            return Err(GCodeError::ParseError);
+        }
+        let num = text
+            .replace(',', ".")
+            .parse()
+            .map_err(|_| GCodeError::ParseError)?;
+        self.pos += len;
+        Ok(GCodeExpr::Number(num))
+    }
+
+    /// param := '#' digits | '#<' name '>'
+    fn param(&mut self) -> Result<GCodeParam, GCodeError> {
+        self.expect("#")?;
+        if self.eat("<") {
+            let len = self.rest().find('>').ok_or(GCodeError::ParseError)?;
+            let name = self.rest()[..len].trim();
+            if name.is_empty() {
+                return Err(GCodeError::ParseError);
+            }
+            self.pos += len + 1;
+            Ok(GCodeParam::Named(name.to_string()))
+        } else {
+            self.skip_ws();
+            let len = self
+                .rest()
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(self.rest().len());
+            let num = self.rest()[..len]
+                .parse()
+                .map_err(|_| GCodeError::ParseError)?;
+            self.pos += len;
+            Ok(GCodeParam::Numbered(num))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expr_parse() -> Result<(), GCodeError> {
+        assert_eq!(
+            GCodeParam::parse("#<Depth>")?,
+            GCodeParam::Named("Depth".to_string())
+        );
+        assert_eq!(GCodeParam::parse("# 100")?, GCodeParam::Numbered(100));
+
+        let expr = GCodeExpr::parse("[#<depth> * 2 + -1.5 ** 2 MOD 2]")?;
+        assert_eq!(expr.to_string(), "[[#<depth> * 2] + [-[1.5 ** 2] MOD 2]]");
+        /* Display output parses back to the same expression */
+        assert_eq!(GCodeExpr::parse(&expr.to_string())?, expr);
+
+        let expr = GCodeExpr::parse("sqrt[#1*#1 + 9]")?;
+        assert_eq!(expr.to_string(), "SQRT[[#1 * #1] + 9]");
+
+        assert_eq!(GCodeExpr::parse("[1 + 2"), Err(GCodeError::ParseError));
+        assert_eq!(GCodeExpr::parse("1 2"), Err(GCodeError::ParseError));
+        assert_eq!(GCodeExpr::parse("#<>"), Err(GCodeError::ParseError));
+
+        /* Decimal commas */
+        assert_eq!(GCodeExpr::parse("-1,25")?, GCodeExpr::parse("-1.25")?);
+        assert_eq!(GCodeExpr::parse("1,2.5"), Err(GCodeError::ParseError));
+        assert_eq!(GCodeExpr::parse("1,,5"), Err(GCodeError::ParseError));
+        let expr = GCodeExpr::parse("[#1 * 0.125 + 3]")?;
+        let text = expr.display_with(GCodeDecimalSeparator::Comma).to_string();
+        assert_eq!(text, "[[#1 * 0,125] + 3]");
+        assert_eq!(GCodeExpr::parse(&text)?, expr);
+
+        Ok(())
+    }
+
+    #[test]
+    fn expr_eval() -> Result<(), GCodeError> {
+        let mut params = GCodeParamTable::new();
+        params.set(&GCodeParam::Named("Depth".to_string()), 3.0);
+        params.set(&GCodeParam::Numbered(1), 4.0);
+
+        let eval = |input: &str| GCodeExpr::parse(input)?.eval(&params);
+        assert_eq!(eval("[#<depth> * 2 + -1.5 ** 2 MOD 2]")?, 7.75);
+        assert_eq!(eval("sqrt[#1*#1 + 9]")?, 5.0);
+        assert_eq!(eval("FIX[-0.5] + FUP[0.5] + ROUND[#2]")?, 0.0);
+        assert!((eval("COS[60]")? - 0.5).abs() < 1e-12);
+        assert_eq!(eval("#<missing>"), Err(GCodeError::NotFoundError));
+        assert_eq!(eval("1 / [#1 - 4]"), Err(GCodeError::OutOfRangeError));
+
+        Ok(())
+    }
+}
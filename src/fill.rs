@@ -0,0 +1,509 @@
+use crate::{offset_contour, GCodeCompensation, GCodeError, GCodePosition};
+
+/// Pattern used to fill a polygon
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GCodeFillPattern {
+    /// Parallel passes, alternating in direction, at `angle` degrees from
+    /// the X axis
+    ZigZag { angle: f64 },
+    /// Passes following the outline of the polygon and its holes, moving
+    /// inwards (or away from holes) by the stepover each pass
+    ContourParallel,
+}
+
+/// Parameters of a polygon fill
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeFillOptions {
+    pub pattern: GCodeFillPattern,
+    /// Radius of the tool, the tool center is kept at least this far from
+    /// the polygon edges
+    pub tool_radius: f64,
+    /// Distance between adjacent passes
+    pub stepover: f64,
+    /// Maximum deviation of line segments approximating arcs
+    pub tolerance: f64,
+}
+
+type Point = (f64, f64);
+
+/// Generates toolpaths filling the simple polygon `boundary`, excluding the
+/// regions within `holes`.
+///
+/// Polygons are closed implicitly and may have either orientation. Only X
+/// and Y are considered, resulting paths do not contain Z, which may be
+/// filled in using `GCodePosition::merge()`.
+pub fn fill_polygon(
+    boundary: &[GCodePosition],
+    holes: &[Vec<GCodePosition>],
+    options: &GCodeFillOptions,
+) -> Result<Vec<Vec<GCodePosition>>, GCodeError> {
+    if (options.tool_radius < 0.0) || (options.stepover <= 0.0) || (options.tolerance <= 0.0) {
+        return Err(GCodeError::OutOfRangeError);
+    }
+
+    let mut rings = vec![to_points(boundary)?];
+    for hole in holes {
+        rings.push(to_points(hole)?);
+    }
+    let edges: Vec<(Point, Point)> = rings
+        .iter()
+        .flat_map(|ring| (0..ring.len()).map(move |i| (ring[i], ring[(i + 1) % ring.len()])))
+        .collect();
+
+    let paths = match options.pattern {
+        GCodeFillPattern::ZigZag { angle } => zigzag(&edges, angle, options),
+        GCodeFillPattern::ContourParallel => contour_parallel(&rings, &edges, options)?,
+    };
+
+    paths
+        .into_iter()
+        .map(|path| {
+            path.into_iter()
+                .map(|(x, y)| GCodePosition::from_f64(Some(x), Some(y), None))
+                .collect()
+        })
+        .collect()
+}
+
+fn to_points(polygon: &[GCodePosition]) -> Result<Vec<Point>, GCodeError> {
+    let mut points = vec![];
+    for pos in polygon {
+        match (pos.x_f64(), pos.y_f64()) {
+            (Some(x), Some(y)) => points.push((x, y)),
+            _ => return Err(GCodeError::GeometryError),
+        }
+    }
+    points.dedup();
+    if (points.len() > 1) && (points.first() == points.last()) {
+        points.pop();
+    }
+    if points.len() < 3 {
+        return Err(GCodeError::GeometryError);
+    }
+    Ok(points)
+}
+
+fn zigzag(edges: &[(Point, Point)], angle: f64, options: &GCodeFillOptions) -> Vec<Vec<Point>> {
+    let radius = options.tool_radius;
+    let (sin, cos) = angle.to_radians().sin_cos();
+    /* Work in a frame rotated such that passes are horizontal */
+    let rotate = |(x, y): Point| (x * cos + y * sin, y * cos - x * sin);
+    let unrotate = |(x, y): Point| (x * cos - y * sin, y * cos + x * sin);
+    let edges: Vec<(Point, Point)> = edges.iter().map(|&(a, b)| (rotate(a), rotate(b))).collect();
+
+    let (min_y, max_y) = edges
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |acc, e| {
+            (acc.0.min(e.0 .1), acc.1.max(e.0 .1))
+        });
+    let mut ys = vec![];
+    let mut y = min_y + radius;
+    while y <= (max_y - radius) {
+        ys.push(y);
+        y += options.stepover;
+    }
+    /* Ensure the last pass reaches the far edge */
+    if let Some(&last) = ys.last() {
+        if (max_y - radius - last) > 1e-9 {
+            ys.push(max_y - radius);
+        }
+    }
+
+    let mut lines: Vec<Vec<Option<(f64, f64)>>> = ys
+        .iter()
+        .map(|&y| scanline(&edges, y, radius).into_iter().map(Some).collect())
+        .collect();
+
+    let eps = 1e-9;
+    let mut paths = vec![];
+    for start in 0..lines.len() {
+        for seg in 0..lines[start].len() {
+            let (x0, x1) = match lines[start][seg].take() {
+                Some(seg) => seg,
+                None => continue,
+            };
+
+            let mut path = vec![(x0, ys[start]), (x1, ys[start])];
+            for (line, &y) in lines.iter_mut().zip(&ys).skip(start + 1) {
+                let cur = path[path.len() - 1];
+
+                /* Continue with the segment whose closest end is nearest, if
+                 * it can be reached without approaching an edge */
+                let next = line
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, seg)| seg.map(|seg| (i, seg)))
+                    .map(|(i, (x0, x1))| {
+                        if (x0 - cur.0).abs() <= (x1 - cur.0).abs() {
+                            (i, (x0, y), (x1, y))
+                        } else {
+                            (i, (x1, y), (x0, y))
+                        }
+                    })
+                    .filter(|&(_, near, _)| {
+                        edges
+                            .iter()
+                            .all(|&(a, b)| segment_distance(cur, near, a, b) >= (radius - eps))
+                    })
+                    .min_by(|a, b| (a.1 .0 - cur.0).abs().total_cmp(&(b.1 .0 - cur.0).abs()));
+
+                match next {
+                    Some((i, near, far)) => {
+                        line[i] = None;
+                        path.push(near);
+                        path.push(far);
+                    }
+                    None => break,
+                }
+            }
+
+            paths.push(path.into_iter().map(unrotate).collect());
+        }
+    }
+
+    paths
+}
+
+/// Intervals along the horizontal line at `y` which are within the polygon
+/// and at least `radius` from all edges
+fn scanline(edges: &[(Point, Point)], y: f64, radius: f64) -> Vec<(f64, f64)> {
+    let mut xs: Vec<f64> = edges
+        .iter()
+        .filter(|(a, b)| (a.1 <= y) != (b.1 <= y))
+        .map(|(a, b)| a.0 + (y - a.1) * (b.0 - a.0) / (b.1 - a.1))
+        .collect();
+    xs.sort_by(f64::total_cmp);
+
+    let mut intervals: Vec<(f64, f64)> = xs.chunks_exact(2).map(|c| (c[0], c[1])).collect();
+    for &(a, b) in edges {
+        if let Some((lo, hi)) = capsule_interval(a, b, radius, y) {
+            intervals = intervals
+                .into_iter()
+                .flat_map(|(x0, x1)| {
+                    let mut out = vec![];
+                    if lo > x0 {
+                        out.push((x0, lo.min(x1)));
+                    }
+                    if hi < x1 {
+                        out.push((hi.max(x0), x1));
+                    }
+                    out
+                })
+                .filter(|(x0, x1)| (x1 - x0) > 1e-9)
+                .collect();
+        }
+    }
+
+    intervals
+}
+
+/// Interval along the horizontal line at `y` lying closer than `radius` to
+/// the segment from `a` to `b`
+fn capsule_interval(a: Point, b: Point, radius: f64, y: f64) -> Option<(f64, f64)> {
+    let mut lo = f64::INFINITY;
+    let mut hi = f64::NEG_INFINITY;
+    let mut include = |range: Option<(f64, f64)>| {
+        if let Some((l, h)) = range {
+            lo = lo.min(l);
+            hi = hi.max(h);
+        }
+    };
+
+    /* Circles around both ends */
+    for (px, py) in [a, b] {
+        let dy = y - py;
+        if dy.abs() < radius {
+            let half = (radius * radius - dy * dy).sqrt();
+            include(Some((px - half, px + half)));
+        }
+    }
+
+    /* Band alongside the segment, as the intersection of the ranges of x
+     * within which the projection onto the segment and the perpendicular
+     * distance are in range */
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len2 = dx * dx + dy * dy;
+    let len = len2.sqrt();
+    if len > 0.0 {
+        let linear = |alpha: f64, beta: f64, lo: f64, hi: f64| {
+            if alpha == 0.0 {
+                ((beta > lo) && (beta < hi)).then_some((f64::NEG_INFINITY, f64::INFINITY))
+            } else {
+                let (x0, x1) = ((lo - beta) / alpha, (hi - beta) / alpha);
+                Some((x0.min(x1), x0.max(x1)))
+            }
+        };
+        let proj = linear(dx / len2, (dy * (y - a.1) - dx * a.0) / len2, 0.0, 1.0);
+        let perp = linear(
+            -dy / len,
+            (dx * (y - a.1) + dy * a.0) / len,
+            -radius,
+            radius,
+        );
+        if let (Some(p0), Some(p1)) = (proj, perp) {
+            let (l, h) = (p0.0.max(p1.0), p0.1.min(p1.1));
+            if l < h {
+                include(Some((l, h)));
+            }
+        }
+    }
+
+    (lo < hi).then_some((lo, hi))
+}
+
+fn contour_parallel(
+    rings: &[Vec<Point>],
+    edges: &[(Point, Point)],
+    options: &GCodeFillOptions,
+) -> Result<Vec<Vec<Point>>, GCodeError> {
+    let (mut min, mut max) = (
+        (f64::INFINITY, f64::INFINITY),
+        (f64::NEG_INFINITY, f64::NEG_INFINITY),
+    );
+    for &(x, y) in rings.iter().flatten() {
+        min = (min.0.min(x), min.1.min(y));
+        max = (max.0.max(x), max.1.max(y));
+    }
+    let extent = (max.0 - min.0).hypot(max.1 - min.1);
+    let eps = options.tolerance * 1.01 + 1e-9;
+
+    let mut paths = vec![];
+    let mut dist = options.tool_radius;
+    while dist < extent {
+        let mut found = false;
+
+        for (i, ring) in rings.iter().enumerate() {
+            /* Offset the boundary inwards, and holes outwards */
+            let ccw = signed_area(ring) > 0.0;
+            let side = if ccw == (i == 0) {
+                GCodeCompensation::Left
+            } else {
+                GCodeCompensation::Right
+            };
+            let ring: Vec<GCodePosition> = ring
+                .iter()
+                .map(|&(x, y)| GCodePosition::from_f64(Some(x), Some(y), None))
+                .collect::<Result<_, _>>()?;
+            /* Rings collapsing entirely end the fill of their region, rather
+             * than failing it */
+            let offset = match offset_contour(&ring, side, dist, options.tolerance)
+                .and_then(|offset| to_points(&offset))
+            {
+                Ok(offset) => offset,
+                Err(GCodeError::GeometryError) => continue,
+                Err(err) => return Err(err),
+            };
+
+            /* Subdivide the offset ring, and keep only the runs of points far
+             * enough away from all edges. This removes parts of the ring that
+             * collapsed, or interfere with other rings. */
+            let mut points = vec![];
+            for j in 0..offset.len() {
+                let (a, b) = (offset[j], offset[(j + 1) % offset.len()]);
+                let steps =
+                    ((a.0 - b.0).hypot(a.1 - b.1) / (options.stepover / 2.0)).ceil() as usize;
+                for step in 0..steps.max(1) {
+                    let t = (step as f64) / (steps.max(1) as f64);
+                    points.push((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t));
+                }
+            }
+            let valid: Vec<bool> = points
+                .iter()
+                .map(|&p| {
+                    inside(edges, p)
+                        && edges
+                            .iter()
+                            .all(|&(a, b)| segment_distance(p, p, a, b) >= (dist - eps))
+                })
+                .collect();
+
+            if valid.iter().all(|&v| v) {
+                let mut path = points.clone();
+                path.push(points[0]);
+                paths.push(path);
+                found = true;
+                continue;
+            }
+
+            /* Start from an invalid point so runs are not split at the seam */
+            let start = valid.iter().position(|&v| !v).unwrap_or(0);
+            let mut run: Vec<Point> = vec![];
+            for k in 0..=points.len() {
+                let idx = (start + k) % points.len();
+                if valid[idx] && (k < points.len()) {
+                    run.push(points[idx]);
+                } else {
+                    if run.len() > 1 {
+                        paths.push(std::mem::take(&mut run));
+                        found = true;
+                    }
+                    run.clear();
+                }
+            }
+        }
+
+        if !found {
+            break;
+        }
+        dist += options.stepover;
+    }
+
+    Ok(paths)
+}
+
+fn signed_area(ring: &[Point]) -> f64 {
+    (0..ring.len())
+        .map(|i| {
+            let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum::<f64>()
+        / 2.0
+}
+
+/// Even-odd test of whether `p` lies within the region bounded by `edges`
+fn inside(edges: &[(Point, Point)], p: Point) -> bool {
+    edges
+        .iter()
+        .filter(|(a, b)| (a.1 <= p.1) != (b.1 <= p.1))
+        .filter(|(a, b)| p.0 < (a.0 + (p.1 - a.1) * (b.0 - a.0) / (b.1 - a.1)))
+        .count()
+        % 2
+        == 1
+}
+
+/// Minimum distance between segments `p0`-`p1` and `q0`-`q1`
+fn segment_distance(p0: Point, p1: Point, q0: Point, q1: Point) -> f64 {
+    let cross =
+        |o: Point, a: Point, b: Point| (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0);
+    let (d1, d2) = (cross(q0, q1, p0), cross(q0, q1, p1));
+    let (d3, d4) = (cross(p0, p1, q0), cross(p0, p1, q1));
+    if (d1 * d2 < 0.0) && (d3 * d4 < 0.0) {
+        return 0.0;
+    }
+
+    let point_dist = |p: Point, a: Point, b: Point| {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let len2 = dx * dx + dy * dy;
+        let t = if len2 > 0.0 {
+            (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len2).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        (p.0 - a.0 - dx * t).hypot(p.1 - a.1 - dy * t)
+    };
+    point_dist(p0, q0, q1)
+        .min(point_dist(p1, q0, q1))
+        .min(point_dist(q0, p0, p1))
+        .min(point_dist(q1, p0, p1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(min: f64, max: f64) -> Result<Vec<GCodePosition>, GCodeError> {
+        Ok(vec![
+            GCodePosition::from_f64(Some(min), Some(min), None)?,
+            GCodePosition::from_f64(Some(max), Some(min), None)?,
+            GCodePosition::from_f64(Some(max), Some(max), None)?,
+            GCodePosition::from_f64(Some(min), Some(max), None)?,
+        ])
+    }
+
+    /// Checks that all points of all paths keep at least `radius` from the
+    /// edges of the 10x10 square, and the 4-6 square hole if present
+    fn check_clearance(paths: &[Vec<GCodePosition>], radius: f64, hole: bool) {
+        for pos in paths.iter().flatten() {
+            let (x, y) = (pos.x_f64().unwrap(), pos.y_f64().unwrap());
+            let outer = x.min(y).min(10.0 - x).min(10.0 - y);
+            assert!(outer >= radius - 1e-3, "({}, {})", x, y);
+            if hole {
+                let dx = (4.0 - x).max(x - 6.0).max(0.0);
+                let dy = (4.0 - y).max(y - 6.0).max(0.0);
+                assert!(dx.hypot(dy) >= radius - 1e-3, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn fill_zigzag() -> Result<(), GCodeError> {
+        let mut options = GCodeFillOptions {
+            pattern: GCodeFillPattern::ZigZag { angle: 0.0 },
+            tool_radius: 0.5,
+            stepover: 1.0,
+            tolerance: 0.01,
+        };
+
+        let paths = fill_polygon(&square(0.0, 10.0)?, &[], &options)?;
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].len(), 20);
+        assert_eq!(
+            paths[0][0],
+            GCodePosition::from_f64(Some(0.5), Some(0.5), None)?
+        );
+        assert_eq!(
+            paths[0][1],
+            GCodePosition::from_f64(Some(9.5), Some(0.5), None)?
+        );
+        assert_eq!(
+            paths[0][2],
+            GCodePosition::from_f64(Some(9.5), Some(1.5), None)?
+        );
+        assert_eq!(
+            paths[0][19],
+            GCodePosition::from_f64(Some(0.5), Some(9.5), None)?
+        );
+
+        /* A hole splits the passes */
+        let paths = fill_polygon(&square(0.0, 10.0)?, &[square(4.0, 6.0)?], &options)?;
+        assert!(paths.len() > 1);
+        check_clearance(&paths, 0.5, true);
+
+        options.pattern = GCodeFillPattern::ZigZag { angle: 30.0 };
+        let paths = fill_polygon(&square(0.0, 10.0)?, &[square(4.0, 6.0)?], &options)?;
+        check_clearance(&paths, 0.5, true);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fill_contour() -> Result<(), GCodeError> {
+        let options = GCodeFillOptions {
+            pattern: GCodeFillPattern::ContourParallel,
+            tool_radius: 0.5,
+            stepover: 1.0,
+            tolerance: 0.01,
+        };
+
+        let paths = fill_polygon(&square(0.0, 10.0)?, &[], &options)?;
+        assert_eq!(paths.len(), 5);
+        for path in &paths {
+            assert_eq!(path.first(), path.last());
+        }
+        assert_eq!(
+            paths[0][0],
+            GCodePosition::from_f64(Some(0.5), Some(0.5), None)?
+        );
+        check_clearance(&paths, 0.5, false);
+
+        let paths = fill_polygon(&square(0.0, 10.0)?, &[square(4.0, 6.0)?], &options)?;
+        check_clearance(&paths, 0.5, true);
+
+        /* The innermost ring collapses, the fill stops at the last one */
+        let options = GCodeFillOptions {
+            tool_radius: 1.0,
+            ..options
+        };
+        let paths = fill_polygon(&square(0.0, 10.0)?, &[], &options)?;
+        assert_eq!(paths.len(), 4);
+        check_clearance(&paths, 1.0, false);
+
+        assert_eq!(
+            fill_polygon(&square(0.0, 10.0)?[..2], &[], &options),
+            Err(GCodeError::GeometryError)
+        );
+
+        Ok(())
+    }
+}
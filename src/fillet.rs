@@ -0,0 +1,343 @@
+use crate::{GCodeError, GCodePosition};
+
+/// Element of a path consisting of lines and arcs, each moving from the end
+/// of the previous element
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GCodePathSegment {
+    /// Linear move to the contained position
+    Line(GCodePosition),
+    /// Arc in the XY plane around `center` to `end`
+    Arc {
+        end: GCodePosition,
+        center: GCodePosition,
+        clockwise: bool,
+    },
+}
+impl GCodePathSegment {
+    pub fn end(&self) -> GCodePosition {
+        match self {
+            Self::Line(end) | Self::Arc { end, .. } => *end,
+        }
+    }
+}
+
+/// Shape of an arc in the XY plane, as found by `arc_geometry()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct ArcGeometry {
+    pub center: (f64, f64),
+    pub radius: f64,
+    /// Angle of the start point around the center, in radians
+    pub start_angle: f64,
+    /// Angle swept, in radians, always positive
+    pub sweep: f64,
+    /// Change of Z along the arc
+    pub dz: f64,
+}
+impl ArcGeometry {
+    /// Length along the arc, including any change of Z
+    pub fn length(&self) -> f64 {
+        (self.radius * self.sweep).hypot(self.dz)
+    }
+}
+
+/// Resolves an arc from `from` around `center` to `to`. An arc ending where
+/// it starts is a full circle. Fails with `StateError` if X or Y of `from`
+/// is unknown, and with `GeometryError` for centers not equidistant from
+/// both ends.
+pub(crate) fn arc_geometry(
+    from: &GCodePosition,
+    to: &GCodePosition,
+    center: &GCodePosition,
+    clockwise: bool,
+) -> Result<ArcGeometry, GCodeError> {
+    let (cx, cy) = center
+        .x_f64()
+        .zip(center.y_f64())
+        .ok_or(GCodeError::GeometryError)?;
+    let (sx, sy) = from
+        .x_f64()
+        .zip(from.y_f64())
+        .ok_or(GCodeError::StateError)?;
+    let (ex, ey) = (to.x_f64().unwrap_or(sx), to.y_f64().unwrap_or(sy));
+
+    let radius = (sx - cx).hypot(sy - cy);
+    if (radius == 0.0) || ((ex - cx).hypot(ey - cy) - radius).abs() > radius * 1e-3 {
+        return Err(GCodeError::GeometryError);
+    }
+    let tau = std::f64::consts::TAU;
+    let (start_angle, end_angle) = ((sy - cy).atan2(sx - cx), (ey - cy).atan2(ex - cx));
+    let sweep = if clockwise {
+        start_angle - end_angle
+    } else {
+        end_angle - start_angle
+    }
+    .rem_euclid(tau);
+    let dz = match (from.z_f64(), to.z_f64()) {
+        (Some(from), Some(to)) => to - from,
+        _ => 0.0,
+    };
+
+    Ok(ArcGeometry {
+        center: (cx, cy),
+        radius,
+        start_angle,
+        sweep: if sweep == 0.0 { tau } else { sweep },
+        dz,
+    })
+}
+
+/// Options of corner filleting
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeFilletOptions {
+    /// Radius of inserted fillets
+    pub radius: f64,
+    /// Largest allowed distance between a fillet and the corner it replaces,
+    /// the radius is reduced for sharp corners exceeding it
+    pub tolerance: f64,
+}
+
+/// Replaces corners between consecutive linear moves of an open path with
+/// tangent arcs, reducing jerk on controllers without path smoothing.
+///
+/// Fillets are reduced in radius where needed to stay within the tolerance,
+/// and to use up at most half of each adjacent move. Corners whose moves
+/// are not in the same XY plane, i.e. with differing Z, are kept as-is. The
+/// result starts with a line to the first point of `path`.
+pub fn fillet_path(
+    path: &[GCodePosition],
+    options: &GCodeFilletOptions,
+) -> Result<Vec<GCodePathSegment>, GCodeError> {
+    let valid = |val: f64| val.is_finite() && (val >= 0.0);
+    if !valid(options.radius) || !valid(options.tolerance) {
+        return Err(GCodeError::OutOfRangeError);
+    }
+
+    let mut points = Vec::with_capacity(path.len());
+    for pos in path {
+        match pos.as_f64() {
+            (Some(x), Some(y), z) => points.push((x, y, z)),
+            _ => return Err(GCodeError::GeometryError),
+        }
+    }
+    points.dedup();
+
+    let mut result = vec![];
+    if let Some(&first) = path.first() {
+        result.push(GCodePathSegment::Line(first));
+    }
+    for i in 1..points.len() {
+        let (x, y, z) = points[i];
+        let corner = match points.get(i + 1) {
+            Some(&next) if (points[i - 1].2 == z) && (next.2 == z) => Some((points[i - 1], next)),
+            _ => None,
+        };
+        let fillet = corner.and_then(|((x0, y0, _), (x1, y1, _))| {
+            let len_in = (x - x0).hypot(y - y0);
+            let len_out = (x1 - x).hypot(y1 - y);
+            let dir_in = ((x - x0) / len_in, (y - y0) / len_in);
+            let dir_out = ((x1 - x) / len_out, (y1 - y) / len_out);
+
+            /* Angle by which the path turns at the corner */
+            let cross = dir_in.0 * dir_out.1 - dir_in.1 * dir_out.0;
+            let dot = dir_in.0 * dir_out.0 + dir_in.1 * dir_out.1;
+            let turn = cross.atan2(dot).abs();
+            if !(1e-9..=std::f64::consts::PI - 1e-9).contains(&turn) {
+                return None;
+            }
+
+            /* Distance from the corner to the tangent points, and from the
+             * corner to the fillet, are r * tan(turn / 2) and
+             * r * (1 / cos(turn / 2) - 1) respectively */
+            let half = turn / 2.0;
+            let radius = options
+                .radius
+                .min(options.tolerance / (1.0 / half.cos() - 1.0))
+                .min(len_in.min(len_out) / 2.0 / half.tan());
+            if radius < 1e-9 {
+                return None;
+            }
+            let dist = radius * half.tan();
+
+            /* Center lies on the inside of the turn */
+            let side = cross.signum();
+            let start = (x - dir_in.0 * dist, y - dir_in.1 * dist);
+            let end = (x + dir_out.0 * dist, y + dir_out.1 * dist);
+            let center = (
+                start.0 - dir_in.1 * side * radius,
+                start.1 + dir_in.0 * side * radius,
+            );
+            Some((start, end, center, side < 0.0))
+        });
+
+        match fillet {
+            Some((start, end, center, clockwise)) => {
+                let pos = |(px, py): (f64, f64)| GCodePosition::from_f64(Some(px), Some(py), z);
+                result.push(GCodePathSegment::Line(pos(start)?));
+                result.push(GCodePathSegment::Arc {
+                    end: pos(end)?,
+                    center: pos(center)?,
+                    clockwise,
+                });
+            }
+            None => result.push(GCodePathSegment::Line(GCodePosition::from_f64(
+                Some(x),
+                Some(y),
+                z,
+            )?)),
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fillet() -> Result<(), GCodeError> {
+        let path = [
+            GCodePosition::from_f64(Some(0.0), Some(0.0), None)?,
+            GCodePosition::from_f64(Some(10.0), Some(0.0), None)?,
+            GCodePosition::from_f64(Some(10.0), Some(10.0), None)?,
+            GCodePosition::from_f64(Some(20.0), Some(10.0), None)?,
+        ];
+        let options = GCodeFilletOptions {
+            radius: 1.0,
+            tolerance: 1.0,
+        };
+        let res = fillet_path(&path, &options)?;
+
+        let pos = |x, y| GCodePosition::from_f64(Some(x), Some(y), None);
+        let expected = [
+            GCodePathSegment::Line(path[0]),
+            GCodePathSegment::Line(pos(9.0, 0.0)?),
+            GCodePathSegment::Arc {
+                end: pos(10.0, 1.0)?,
+                center: pos(9.0, 1.0)?,
+                clockwise: false,
+            },
+            GCodePathSegment::Line(pos(10.0, 9.0)?),
+            GCodePathSegment::Arc {
+                end: pos(11.0, 10.0)?,
+                center: pos(11.0, 9.0)?,
+                clockwise: true,
+            },
+            GCodePathSegment::Line(path[3]),
+        ];
+        assert_eq!(res.len(), expected.len());
+        for (res, expected) in res.iter().zip(&expected) {
+            assert!(res.end().approx_eq(&expected.end(), 1));
+            match (res, expected) {
+                (
+                    GCodePathSegment::Arc {
+                        center, clockwise, ..
+                    },
+                    GCodePathSegment::Arc {
+                        center: exp_center,
+                        clockwise: exp_clockwise,
+                        ..
+                    },
+                ) => {
+                    assert!(center.approx_eq(exp_center, 1));
+                    assert_eq!(clockwise, exp_clockwise);
+                }
+                (GCodePathSegment::Line(_), GCodePathSegment::Line(_)) => (),
+                _ => panic!("Segment kind mismatch"),
+            }
+        }
+
+        /* Deviation of a 90 degree fillet is r * (sqrt(2) - 1) */
+        let options = GCodeFilletOptions {
+            radius: 1.0,
+            tolerance: 0.1,
+        };
+        let res = fillet_path(&path, &options)?;
+        let radius = 0.1 / (2f64.sqrt() - 1.0);
+        match res[2] {
+            GCodePathSegment::Arc { end, .. } => {
+                assert!((end.y_f64().unwrap() - radius).abs() < 1e-4)
+            }
+            _ => panic!("Expected arc"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn fillet_edge_cases() -> Result<(), GCodeError> {
+        let pos = |x, y, z| GCodePosition::from_f64(Some(x), Some(y), z);
+        let options = GCodeFilletOptions {
+            radius: 1.0,
+            tolerance: 1.0,
+        };
+
+        assert!(fillet_path(&[], &options)?.is_empty());
+        let point = pos(1.0, 1.0, None)?;
+        assert_eq!(
+            fillet_path(&[point], &options)?,
+            [GCodePathSegment::Line(point)]
+        );
+        for options in [
+            GCodeFilletOptions {
+                radius: -1.0,
+                ..options
+            },
+            GCodeFilletOptions {
+                tolerance: f64::NAN,
+                ..options
+            },
+        ] {
+            assert_eq!(
+                fillet_path(&[point], &options),
+                Err(GCodeError::OutOfRangeError)
+            );
+        }
+        /* X and Y of every point must be known */
+        let no_y = GCodePosition::from_f64(Some(1.0), None, None)?;
+        assert_eq!(
+            fillet_path(&[point, no_y], &options),
+            Err(GCodeError::GeometryError)
+        );
+
+        /* Repeated points, straight continuations, corners changing Z and a
+         * zero radius leave the path as-is */
+        let straight = [
+            pos(0.0, 0.0, None)?,
+            pos(5.0, 0.0, None)?,
+            pos(5.0, 0.0, None)?,
+            pos(10.0, 0.0, None)?,
+        ];
+        assert_eq!(fillet_path(&straight, &options)?.len(), 3);
+        let ramp = [
+            pos(0.0, 0.0, Some(0.0))?,
+            pos(10.0, 0.0, Some(0.0))?,
+            pos(10.0, 10.0, Some(-1.0))?,
+        ];
+        assert!(fillet_path(&ramp, &options)?
+            .iter()
+            .all(|seg| matches!(seg, GCodePathSegment::Line(_))));
+        let corner = [ramp[0], ramp[1], pos(10.0, 10.0, Some(0.0))?];
+        let sharp = GCodeFilletOptions {
+            radius: 0.0,
+            ..options
+        };
+        assert_eq!(fillet_path(&corner, &sharp)?.len(), 3);
+
+        /* Arcs ending at their start are full circles */
+        let center = pos(0.0, 0.0, None)?;
+        let start = pos(1.0, 0.0, None)?;
+        let arc = arc_geometry(&start, &start, &center, true)?;
+        assert_eq!(arc.sweep, std::f64::consts::TAU);
+        assert_eq!(
+            arc_geometry(&start, &start, &start, true),
+            Err(GCodeError::GeometryError)
+        );
+        assert_eq!(
+            arc_geometry(&no_y, &start, &center, true),
+            Err(GCodeError::StateError)
+        );
+
+        Ok(())
+    }
+}
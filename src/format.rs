@@ -0,0 +1,192 @@
+use crate::interp::parse_words;
+
+/// Order of words within a formatted line, letters not listed follow in
+/// their original order
+const WORD_ORDER: &str = "NGMXYZABCUVWEIJKRFST";
+
+/// Letters whose values are written as integers when integral, e.g.
+/// program numbers (P, Q), which Fanuc rejects with a decimal point
+const INTEGER_WORDS: &str = "NTHDLOPQS";
+
+/// Options of program formatting
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeFormatOptions {
+    /// Number of decimal places of axis values, feed rates and other
+    /// numeric words. Values given with more decimal places keep them, so
+    /// no digits are lost.
+    pub precision: usize,
+    /// Increment of line numbers. If set, existing N words are replaced by
+    /// consecutive numbers starting at the increment.
+    pub renumber: Option<u32>,
+    /// Whether comments are kept, following the words of their line
+    pub comments: bool,
+}
+impl Default for GCodeFormatOptions {
+    fn default() -> Self {
+        Self {
+            precision: 4,
+            renumber: None,
+            comments: true,
+        }
+    }
+}
+
+/// Reformats a program with consistent spacing and number formatting, the
+/// opposite of `minify_program()`. Words are separated by a single space
+/// and ordered as N, G, M, axes, arc offsets, F, S, T, followed by any
+/// other words. G and M codes are written with two digits, as the writer
+/// does.
+///
+/// Lines which can not be analyzed, e.g. using parameters or expressions,
+/// are kept as-is apart from surrounding whitespace, and are not
+/// renumbered.
+pub fn format_program(program: &str, options: &GCodeFormatOptions) -> String {
+    let mut out = String::new();
+    let mut number = 0u64;
+
+    for line in program.lines() {
+        let trimmed = line.trim();
+        let words = match parse_words(trimmed) {
+            Some(words) if !trimmed.starts_with('%') => words,
+            _ => {
+                out.push_str(trimmed);
+                out.push('\n');
+                continue;
+            }
+        };
+
+        let mut words: Vec<(char, f64)> = match options.renumber {
+            Some(_) => words.into_iter().filter(|&(l, _)| l != 'N').collect(),
+            None => words,
+        };
+        words.sort_by_key(|&(letter, _)| WORD_ORDER.find(letter).unwrap_or(WORD_ORDER.len()));
+        if let (Some(increment), false) = (options.renumber, words.is_empty()) {
+            number += u64::from(increment);
+            words.insert(0, ('N', number as f64));
+        }
+
+        let mut parts = vec![];
+        for (letter, val) in words {
+            parts.push(format!(
+                "{}{}",
+                letter,
+                format_word(letter, val, options.precision)
+            ));
+        }
+        if options.comments {
+            parts.extend(comments(trimmed));
+        }
+        if parts.is_empty() && !trimmed.is_empty() {
+            /* Comment-only line without comments */
+            continue;
+        }
+
+        if trimmed.starts_with('/') {
+            out.push('/');
+        }
+        out.push_str(&parts.join(" "));
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Formats the value of a single word
+fn format_word(letter: char, val: f64, precision: usize) -> String {
+    /* Avoids -0 */
+    let val = if val == 0.0 { 0.0 } else { val };
+    let integral = val.fract() == 0.0;
+
+    match letter {
+        'G' | 'M' if integral => format!("{:02}", val),
+        'G' | 'M' => format!("{:04.1}", val),
+        l if integral && INTEGER_WORDS.contains(l) => format!("{}", val),
+        _ => {
+            /* Shortest representation has the decimal places of the input,
+             * less trailing zeros */
+            let shortest = format!("{}", val);
+            let places = shortest.find('.').map_or(0, |i| shortest.len() - i - 1);
+            format!("{:.*}", precision.max(places), val)
+        }
+    }
+}
+
+/// Comments within `line`, in their original form
+fn comments(line: &str) -> Vec<String> {
+    let mut result = vec![];
+    let mut rest = line;
+
+    while let Some(start) = rest.find(['(', ';']) {
+        if rest[start..].starts_with(';') {
+            result.push(rest[start..].trim_end().to_string());
+            break;
+        }
+        match rest[start..].find(')') {
+            Some(end) => {
+                result.push(rest[start..=start + end].to_string());
+                rest = &rest[start + end + 1..];
+            }
+            None => break,
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format() {
+        let program = "\
+            %\n\
+            (Facing)\n\
+            n10 g21g90 g94\n\
+            \n\
+            N20 X0Y0 G0 Z5 ; Start\n\
+            F100 z-1.5 G1 (plunge)\n\
+            /G38.2 Z-10 F50\n\
+            #<depth>=[1 + 2]\n\
+            M3 S1000 T1 G2 X-0 Y1 I0.5 J+0.5\n\
+            %\n";
+
+        assert_eq!(
+            format_program(program, &GCodeFormatOptions::default()),
+            "%\n\
+             (Facing)\n\
+             N10 G21 G90 G94\n\
+             \n\
+             N20 G00 X0.0000 Y0.0000 Z5.0000 ; Start\n\
+             G01 Z-1.5000 F100.0000 (plunge)\n\
+             /G38.2 Z-10.0000 F50.0000\n\
+             #<depth>=[1 + 2]\n\
+             G02 M03 X0.0000 Y1.0000 I0.5000 J0.5000 S1000 T1\n\
+             %\n"
+        );
+
+        let options = GCodeFormatOptions {
+            precision: 2,
+            renumber: Some(5),
+            comments: false,
+        };
+        assert_eq!(
+            format_program(program, &options),
+            "%\n\
+             N5 G21 G90 G94\n\
+             \n\
+             N10 G00 X0.00 Y0.00 Z5.00\n\
+             N15 G01 Z-1.50 F100.00\n\
+             /N20 G38.2 Z-10.00 F50.00\n\
+             #<depth>=[1 + 2]\n\
+             N25 G02 M03 X0.00 Y1.00 I0.50 J0.50 S1000 T1\n\
+             %\n"
+        );
+
+        /* Program numbers stay integers, and input digits are kept */
+        assert_eq!(
+            format_program("M98 P1000 L2\nG01 Y0.005 E0.12345 S1.5\n", &options),
+            "N5 M98 P1000 L2\nN10 G01 Y0.005 E0.12345 S1.50\n"
+        );
+    }
+}
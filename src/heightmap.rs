@@ -0,0 +1,149 @@
+use crate::GCodeError;
+
+/// Grid of surface heights in the XY plane, e.g. probed across warped stock
+/// or a bed, for Z correction by `GCodeCommandIterator::level()`
+#[derive(Clone, Debug, PartialEq)]
+pub struct GCodeHeightMap {
+    /// XY position of the first grid point
+    origin: [f64; 2],
+    /// Distance between neighboring grid points
+    spacing: f64,
+    columns: usize,
+    rows: usize,
+    /// Height at each grid point, row by row
+    heights: Vec<f64>,
+}
+impl GCodeHeightMap {
+    /// Grid of `columns` points per row, starting at `origin`. Fails with
+    /// `OutOfRangeError` for non-finite values, a spacing that is not
+    /// positive or no columns, and with `GeometryError` unless `heights`
+    /// fills at least one row, and whole rows only.
+    pub fn new(
+        origin: [f64; 2],
+        spacing: f64,
+        columns: usize,
+        heights: Vec<f64>,
+    ) -> Result<Self, GCodeError> {
+        let finite = origin.iter().chain(&heights).all(|val| val.is_finite());
+        if !(spacing.is_finite() && (spacing > 0.0)) || (columns == 0) || !finite {
+            return Err(GCodeError::OutOfRangeError);
+        }
+        if heights.is_empty() || (heights.len() % columns != 0) {
+            return Err(GCodeError::GeometryError);
+        }
+
+        Ok(Self {
+            origin,
+            spacing,
+            columns,
+            rows: heights.len() / columns,
+            heights,
+        })
+    }
+
+    /// XY position of the first grid point
+    pub fn origin(&self) -> [f64; 2] {
+        self.origin
+    }
+
+    /// Distance between neighboring grid points
+    pub fn spacing(&self) -> f64 {
+        self.spacing
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Height at each grid point, row by row
+    pub fn heights(&self) -> &[f64] {
+        &self.heights
+    }
+
+    /// Height at XY position `pos`, interpolated bilinearly between the
+    /// surrounding grid points. Positions outside of the grid take the
+    /// height of the nearest point on its edge.
+    pub fn height_at(&self, pos: [f64; 2]) -> f64 {
+        let cell = |val: f64, origin: f64, count: usize| {
+            let max = (count - 1) as f64;
+            let val = ((val - origin) / self.spacing).clamp(0.0, max);
+            /* Index of the lower grid point, and the fraction towards the
+             * next one */
+            let idx = (val.floor() as usize).min(count.saturating_sub(2));
+            (idx, val - idx as f64)
+        };
+        let (col, tx) = cell(pos[0], self.origin[0], self.columns);
+        let (row, ty) = cell(pos[1], self.origin[1], self.rows);
+
+        let at = |c: usize, r: usize| {
+            let (c, r) = (c.min(self.columns - 1), r.min(self.rows - 1));
+            self.heights[r * self.columns + c]
+        };
+        let lower = at(col, row) * (1.0 - tx) + at(col + 1, row) * tx;
+        let upper = at(col, row + 1) * (1.0 - tx) + at(col + 1, row + 1) * tx;
+
+        lower * (1.0 - ty) + upper * ty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn height_map() -> Result<(), GCodeError> {
+        let map = GCodeHeightMap::new([10.0, 0.0], 10.0, 3, vec![0.0, 1.0, 2.0, 1.0, 2.0, 4.0])?;
+        assert_eq!((map.columns(), map.rows()), (3, 2));
+
+        assert_eq!(map.height_at([10.0, 0.0]), 0.0);
+        assert_eq!(map.height_at([15.0, 5.0]), 1.0);
+        assert_eq!(map.height_at([25.0, 10.0]), 3.0);
+        /* Clamped to the edges */
+        assert_eq!(map.height_at([100.0, 100.0]), 4.0);
+        assert_eq!(map.height_at([0.0, -5.0]), 0.0);
+
+        let single = GCodeHeightMap::new([0.0, 0.0], 1.0, 1, vec![0.5])?;
+        assert_eq!(single.height_at([3.0, 3.0]), 0.5);
+        assert_eq!(
+            GCodeHeightMap::new([0.0, 0.0], 1.0, 2, vec![0.0; 3]),
+            Err(GCodeError::GeometryError)
+        );
+
+        Ok(())
+    }
+    #[test]
+    fn height_map_edge_cases() -> Result<(), GCodeError> {
+        for (spacing, columns, heights) in [
+            (0.0, 1, vec![0.0]),
+            (-1.0, 1, vec![0.0]),
+            (f64::NAN, 1, vec![0.0]),
+            (1.0, 0, vec![0.0]),
+            (1.0, 1, vec![f64::INFINITY]),
+        ] {
+            assert_eq!(
+                GCodeHeightMap::new([0.0, 0.0], spacing, columns, heights),
+                Err(GCodeError::OutOfRangeError)
+            );
+        }
+        assert_eq!(
+            GCodeHeightMap::new([f64::NAN, 0.0], 1.0, 1, vec![0.0]),
+            Err(GCodeError::OutOfRangeError)
+        );
+        assert_eq!(
+            GCodeHeightMap::new([0.0, 0.0], 1.0, 2, vec![]),
+            Err(GCodeError::GeometryError)
+        );
+
+        /* Single rows and columns interpolate along their only axis */
+        let row = GCodeHeightMap::new([0.0, 0.0], 2.0, 2, vec![0.0, 1.0])?;
+        assert_eq!(row.height_at([1.0, 5.0]), 0.5);
+        let column = GCodeHeightMap::new([0.0, 0.0], 2.0, 1, vec![0.0, 1.0])?;
+        assert_eq!((column.rows(), column.height_at([-5.0, 1.5])), (2, 0.75));
+
+        Ok(())
+    }
+}
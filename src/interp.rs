@@ -0,0 +1,339 @@
+/// G codes of each modal group other than motion and positioning, in tenths
+const MODAL_GROUPS: [&[i64]; 8] = [
+    /* Plane */
+    &[170, 180, 190],
+    /* Units */
+    &[200, 210],
+    /* Cutter compensation */
+    &[400, 410, 420],
+    /* Tool length offset */
+    &[430, 490],
+    /* Work coordinate system */
+    &[540, 550, 560, 570, 580, 590, 591, 592, 593],
+    /* Path control */
+    &[610, 640],
+    /* Feed mode */
+    &[930, 940, 950],
+    /* Canned cycle return */
+    &[980, 990],
+];
+
+/// Modal group of G codes, by index into `MODAL_GROUPS`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ModalGroup {
+    Plane = 0,
+    Units = 1,
+    Compensation = 2,
+    LengthOffset = 3,
+    Wcs = 4,
+    FeedMode = 6,
+}
+
+/// Modal state of a program read as text, None where never set. Positions
+/// are in work coordinates and program units.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ModalState {
+    /// Active motion mode, as G code number
+    pub motion: Option<u8>,
+    /// Positioning selected by G90/G91, incremental if true
+    pub incremental: Option<bool>,
+    /// Active code of each modal group, see `ModalGroup`
+    pub groups: [Option<i64>; 8],
+    pub position: [Option<f64>; 3],
+    pub feed: Option<f64>,
+    /// Spindle speed last set
+    pub speed: Option<f64>,
+    /// Spindle direction, as M code number, None when stopped
+    pub spindle: Option<u8>,
+    pub tool: Option<u32>,
+}
+impl ModalState {
+    /// Active code of `group`, in tenths
+    pub fn group(&self, group: ModalGroup) -> Option<i64> {
+        self.groups[group as usize]
+    }
+
+    pub fn is_incremental(&self) -> bool {
+        self.incremental == Some(true)
+    }
+}
+
+/// Motion caused by a line, as resolved by `Interpreter`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Motion {
+    /// No axis words, or no motion mode selected yet
+    None,
+    /// Move in the active motion mode, in work coordinates
+    Work {
+        /// Motion mode, as G code number
+        mode: u8,
+        start: [Option<f64>; 3],
+        end: [Option<f64>; 3],
+    },
+    /// Move in machine coordinates (G53) to the given axes, which are
+    /// unknown in work coordinates afterwards
+    Machine {
+        /// Motion mode, as G code number
+        mode: u8,
+        start: [Option<f64>; 3],
+        axes: [Option<f64>; 3],
+    },
+    /// Non-modal G code using axis words other than for motion in work
+    /// coordinates, e.g. G28 or G92, making the position unknown
+    Other,
+}
+
+/// Single line of a program, as resolved by `Interpreter`
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Block {
+    /// Words of the line, as (letter, value)
+    pub words: Vec<(char, f64)>,
+    /// X, Y and Z words, as written
+    pub axes: [Option<f64>; 3],
+    pub motion: Motion,
+    pub feed: Option<f64>,
+    /// S word setting the spindle speed, i.e. not that of e.g. a
+    /// temperature
+    pub speed: Option<f64>,
+    /// T word selecting a tool, i.e. not the hotend of e.g. a temperature
+    pub tool: Option<u32>,
+}
+impl Block {
+    /// Value of the last `letter` word of the line
+    pub fn word(&self, letter: char) -> Option<f64> {
+        self.words
+            .iter()
+            .rev()
+            .find(|(l, _)| *l == letter)
+            .map(|(_, val)| *val)
+    }
+
+    /// Whether the line contains code `code` of `letter`, in tenths
+    pub fn has(&self, letter: char, code: i64) -> bool {
+        self.words
+            .iter()
+            .any(|&(l, val)| (l == letter) && (code_of(val) == code))
+    }
+}
+
+/// Tracks the modal state of a program read one line at a time, shared by
+/// all analyses of programs as text
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Interpreter {
+    pub state: ModalState,
+}
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Interprets a single line, updating the modal state. Returns None for
+    /// lines which can not be analyzed, i.e. using parameters, expressions
+    /// or control flow, which make the position unknown.
+    pub fn line(&mut self, text: &str) -> Option<Block> {
+        let Some(words) = parse_words(text) else {
+            self.state.position = [None; 3];
+            return None;
+        };
+        let state = &mut self.state;
+
+        /* S and T words of e.g. M104 or M106 do not concern the spindle */
+        let other_m = words.iter().any(|&(l, val)| (l == 'M') && (val >= 100.0));
+        let mut axes = [None; 3];
+        let (mut feed, mut speed, mut tool) = (None, None, None);
+        let (mut machine, mut other) = (false, None);
+        for &(letter, val) in &words {
+            let code = code_of(val);
+            match (letter, code) {
+                ('G', 0 | 10 | 20 | 30) => state.motion = Some((code / 10) as u8),
+                ('G', 800) => state.motion = None,
+                ('G', 900) => state.incremental = Some(false),
+                ('G', 910) => state.incremental = Some(true),
+                /* Dwell, given by P */
+                ('G', 40) => (),
+                ('G', 530) => machine = true,
+                ('G', _) => match modal_group(code) {
+                    Some(group) => state.groups[group] = Some(code),
+                    None => other = Some(code),
+                },
+                ('M', 30 | 40) => state.spindle = Some((code / 10) as u8),
+                ('M', 50) => state.spindle = None,
+                ('X', _) => axes[0] = Some(val),
+                ('Y', _) => axes[1] = Some(val),
+                ('Z', _) => axes[2] = Some(val),
+                ('F', _) => feed = Some(val),
+                ('S', _) if !other_m => speed = Some(val),
+                ('T', _) if !other_m => tool = tool_of(val).or(tool),
+                _ => (),
+            }
+        }
+        state.feed = feed.or(state.feed);
+        state.speed = speed.or(state.speed);
+        state.tool = tool.or(state.tool);
+
+        let given = axes.iter().any(Option::is_some);
+        let start = state.position;
+        let motion = match (other, state.motion) {
+            /* G28 and G30 move all axes when given none */
+            (Some(code), _) if given || matches!(code, 280 | 300) => {
+                state.position = [None; 3];
+                Motion::Other
+            }
+            (_, Some(mode)) if given && machine => {
+                for (pos, axis) in state.position.iter_mut().zip(axes) {
+                    if axis.is_some() {
+                        *pos = None;
+                    }
+                }
+                Motion::Machine { mode, start, axes }
+            }
+            (_, Some(mode)) if given => {
+                for (i, val) in axes.iter().enumerate() {
+                    if let Some(val) = val {
+                        state.position[i] = match (state.is_incremental(), start[i]) {
+                            (true, Some(cur)) => Some(cur + val),
+                            (true, None) => None,
+                            (false, _) => Some(*val),
+                        };
+                    }
+                }
+                Motion::Work {
+                    mode,
+                    start,
+                    end: state.position,
+                }
+            }
+            _ => Motion::None,
+        };
+
+        Some(Block {
+            words,
+            axes,
+            motion,
+            feed,
+            speed,
+            tool,
+        })
+    }
+}
+
+/// Index of the modal group of G code `code`, in tenths, into
+/// `ModalState::groups`
+pub(crate) fn modal_group(code: i64) -> Option<usize> {
+    MODAL_GROUPS.iter().position(|group| group.contains(&code))
+}
+
+/// Value of a G or M word in tenths, e.g. 381 for G38.1
+pub(crate) fn code_of(val: f64) -> i64 {
+    (val * 10.0).round() as i64
+}
+
+/// Tool number of a T word, None unless a whole number within the range of
+/// tool numbers
+pub(crate) fn tool_of(val: f64) -> Option<u32> {
+    ((val >= 0.0) && (val <= u32::MAX as f64) && (val.fract() == 0.0)).then_some(val as u32)
+}
+
+/// Splits a line into words, as (letter, value). Decimal commas are
+/// accepted as well as points. Returns None for lines which can not be
+/// analyzed.
+pub(crate) fn parse_words(line: &str) -> Option<Vec<(char, f64)>> {
+    let mut words = vec![];
+    let mut chars = line.trim_start().trim_start_matches('/').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            ';' | '%' => break,
+            '(' => {
+                chars.find(|&c| c == ')')?;
+            }
+            c if c.is_ascii_alphabetic() => {
+                let mut num = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_digit() || (c == '.') || (c == '-') || (c == '+') {
+                        num.push(c);
+                        chars.next();
+                    } else if c == ',' {
+                        num.push('.');
+                        chars.next();
+                    } else if c.is_whitespace() && num.is_empty() {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                words.push((c.to_ascii_uppercase(), num.parse().ok()?));
+            }
+            _ => return None,
+        }
+    }
+
+    Some(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpret() {
+        fn motion(interp: &mut Interpreter, text: &str) -> Option<Motion> {
+            interp.line(text).map(|block| block.motion)
+        }
+        let mut interp = Interpreter::new();
+
+        assert_eq!(
+            motion(&mut interp, "G21 G90 G54 (setup)"),
+            Some(Motion::None)
+        );
+        assert_eq!(
+            motion(&mut interp, "G00 X1 Y2 Z5"),
+            Some(Motion::Work {
+                mode: 0,
+                start: [None; 3],
+                end: [Some(1.0), Some(2.0), Some(5.0)],
+            })
+        );
+        assert_eq!(
+            motion(&mut interp, "G91 G01 Z-1,5 F100"),
+            Some(Motion::Work {
+                mode: 1,
+                start: [Some(1.0), Some(2.0), Some(5.0)],
+                end: [Some(1.0), Some(2.0), Some(3.5)],
+            })
+        );
+        assert_eq!(
+            motion(&mut interp, "G53 G00 Z0"),
+            Some(Motion::Machine {
+                mode: 0,
+                start: [Some(1.0), Some(2.0), Some(3.5)],
+                axes: [None, None, Some(0.0)],
+            })
+        );
+        assert_eq!(interp.state.position, [Some(1.0), Some(2.0), None]);
+        assert_eq!(motion(&mut interp, "G28"), Some(Motion::Other));
+        assert_eq!(interp.state.position, [None; 3]);
+        assert_eq!(motion(&mut interp, "G90 X[1 + 2]"), None);
+
+        let block = interp.line("M104 T1 S200").unwrap();
+        assert_eq!((block.tool, block.speed), (None, None));
+        assert_eq!(block.word('T'), Some(1.0));
+        let block = interp.line("T2 M06 S1000 M03").unwrap();
+        assert_eq!((block.tool, block.speed), (Some(2), Some(1000.0)));
+        assert!(block.has('M', 60));
+
+        let state = &interp.state;
+        assert_eq!(state.group(ModalGroup::Units), Some(210));
+        assert_eq!(state.group(ModalGroup::Wcs), Some(540));
+        assert_eq!(state.incremental, Some(true));
+        assert_eq!(state.feed, Some(100.0));
+        assert_eq!((state.spindle, state.tool), (Some(3), Some(2)));
+
+        assert_eq!(
+            parse_words("X1,5 y-2 (comment) ; end"),
+            Some(vec![('X', 1.5), ('Y', -2.0)])
+        );
+        assert_eq!(parse_words("#1 = 2"), None);
+    }
+}
@@ -1,10 +1,88 @@
+mod backplot;
+mod canonical;
+mod contour;
+mod cost;
+mod coverage;
+mod dialect;
+mod diff;
+#[cfg(feature = "dxf")]
+mod dxf;
+mod expr;
+mod fill;
+mod fillet;
+mod format;
+mod heightmap;
+mod interp;
+mod limits;
+mod lint;
+mod mesh;
+mod minify;
+mod offset;
 mod options;
+mod pipeline;
 mod position;
+mod probe;
+mod raster;
+pub mod render;
+mod resume;
+mod reverse;
+mod rotary;
+mod sample;
+mod sequence;
+mod setup;
+mod stats;
+mod verify;
 mod writer;
 
-pub use crate::options::GCodeOptions;
+pub use crate::backplot::{
+    GCodeBackplot, GCodeBackplotBuilder, GCodeBackplotHit, GCodeBackplotPolyline,
+};
+pub use crate::canonical::{GCodeCanonical, GCodeCanonicalRecorder};
+pub use crate::contour::{contour_passes, GCodeContour, GCodeContourSegment};
+pub use crate::cost::{GCodeBasicCostModel, GCodeCommand, GCodeCostModel};
+pub use crate::coverage::{coverage_map, GCodeCoverageMap, GCodeCoverageOptions};
+pub use crate::dialect::GCodeDialect;
+pub use crate::diff::{diff_programs, GCodeDiffEntry, GCodeDiffKind, GCodeDiffOptions};
+#[cfg(feature = "dxf")]
+pub use crate::dxf::parse_dxf;
+pub use crate::expr::{GCodeBinaryOp, GCodeExpr, GCodeFunc, GCodeParam, GCodeParamTable};
+pub use crate::fill::{fill_polygon, GCodeFillOptions, GCodeFillPattern};
+pub use crate::fillet::{fillet_path, GCodeFilletOptions, GCodePathSegment};
+pub use crate::format::{format_program, GCodeFormatOptions};
+pub use crate::heightmap::GCodeHeightMap;
+pub use crate::limits::{GCodeLimit, GCodeLimitViolation, GCodeMachineLimits, GCodeMachineProfile};
+pub use crate::lint::{lint_program, GCodeLintDiagnostic, GCodeLintKind, GCodeLintOptions};
+pub use crate::mesh::{export_mesh, GCodeMeshGroup, GCodeMoveKind};
+pub use crate::minify::minify_program;
+pub use crate::offset::{offset_contour, GCodeCompensation};
+pub use crate::options::{
+    GCodeBlockDelete, GCodeCylinderWrap, GCodeDecimalSeparator, GCodeFanSpeed, GCodeFeedMode,
+    GCodeFirstLayer, GCodeHeater, GCodeIdleAction, GCodeIdleGuard, GCodeLaserMode, GCodeLetterCase,
+    GCodeLineEnding, GCodeOptions, GCodeOptionsBuilder, GCodePlunge, GCodeRetract, GCodeRotation,
+    GCodeSpindle,
+};
+pub use crate::pipeline::{
+    read_commands, GCodeCheckLimits, GCodeCommandIterator, GCodeLevel, GCodeLinearize, GCodeLint,
+    GCodeMinify, GCodeReadCommands, GCodeSegment, GCodeTransform, GCodeWriteError,
+};
 pub use crate::position::{GCodeOffset, GCodePosition};
-pub use crate::writer::GCodeWriter;
+pub use crate::probe::GCodeProbeResult;
+pub use crate::raster::{raster_row, GCodeRasterOptions};
+pub use crate::render::svg::{export_svg, GCodeSvgOptions};
+pub use crate::resume::{resume_from, GCodeResumeOptions};
+pub use crate::reverse::{reverse_path, reverse_program};
+pub use crate::rotary::{GCodeAngleUnit, GCodeRotaryAxis, GCodeRotaryConfig, GCodeRotaryWrap};
+pub use crate::sample::{sample_path, write_samples_csv, GCodeSample};
+pub use crate::sequence::{
+    order_passes, sequence_loops, GCodePass, GCodePassOrder, GCodeSequenceOptions,
+    GCodeSequencedLoop, GCodeTabOptions,
+};
+pub use crate::setup::{GCodeSetupOperation, GCodeSetupSheet, GCodeSetupTool, GCodeSetupWcs};
+pub use crate::stats::{program_stats, GCodeProgramStats, GCodeToolUsage};
+pub use crate::verify::{
+    verify_program, GCodeVerifyDiagnostic, GCodeVerifyKind, GCodeVerifyOptions,
+};
+pub use crate::writer::{GCodeWriter, GCodeWriterBuilder, GCodeWriterState};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum GCodeError {
@@ -12,13 +90,24 @@ pub enum GCodeError {
     IOError,
     /// Value out of range
     OutOfRangeError,
+    /// Malformed input
+    ParseError,
+    /// Probe did not make contact, or its result is otherwise unusable
+    ProbeError,
+    /// Invalid or degenerate geometry
+    GeometryError,
+    /// Referenced item (e.g. subprogram) has not been defined
+    NotFoundError,
+    /// Feature not supported by the selected dialect
+    UnsupportedError,
+    /// Command is not valid in the current machine state, e.g. a move in
+    /// inverse time mode without a feed rate
+    StateError,
 }
 impl From<std::io::Error> for GCodeError {
-    fn from(value: std::io::Error) -> Self {
-        match value.kind() {
-            /* TODO */
-            _ => GCodeError::IOError,
-        }
+    fn from(_value: std::io::Error) -> Self {
+        /* TODO: Map value.kind() to more specific errors */
+        GCodeError::IOError
     }
 }
 impl std::fmt::Display for GCodeError {
@@ -26,6 +115,12 @@ impl std::fmt::Display for GCodeError {
         let name = match self {
             Self::IOError => "IOError",
             Self::OutOfRangeError => "OutOfRangeError",
+            Self::ParseError => "ParseError",
+            Self::ProbeError => "ProbeError",
+            Self::GeometryError => "GeometryError",
+            Self::NotFoundError => "NotFoundError",
+            Self::UnsupportedError => "UnsupportedError",
+            Self::StateError => "StateError",
         };
 
         write!(f, "GCodeError::{}", name)
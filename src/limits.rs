@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+use crate::GCodePosition;
+
+/// Soft limits of a machine
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GCodeMachineLimits {
+    /// Allowed range of X, Y and Z, as (min, max), None if unlimited
+    pub axes: [Option<(f64, f64)>; 3],
+    /// Travel of X, Y and Z in machine coordinates, as (min, max), None if
+    /// unlimited. Checked wherever the origin of the active work coordinate
+    /// system is known.
+    pub travel: [Option<(f64, f64)>; 3],
+    /// Highest feed rate, in units per minute
+    pub max_feed_rate: Option<f64>,
+    /// Highest spindle speed, in RPM
+    pub max_spindle_speed: Option<f64>,
+}
+
+/// Configuration of a machine, shared by all programs written for it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GCodeMachineProfile {
+    pub limits: GCodeMachineLimits,
+    /// Positions reachable by `GCodeWriter::go_to_named()`, e.g. park, tool
+    /// change or probe start positions, in machine coordinates
+    pub named_positions: BTreeMap<String, GCodePosition>,
+}
+impl GCodeMachineProfile {
+    pub fn named_position(&self, name: &str) -> Option<GCodePosition> {
+        self.named_positions.get(name).copied()
+    }
+
+    /// Defines a position, replacing any earlier definition of `name`
+    pub fn set_named_position(&mut self, name: &str, pos: GCodePosition) {
+        self.named_positions.insert(name.into(), pos);
+    }
+}
+
+/// Limit exceeded by a command
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GCodeLimit {
+    X,
+    Y,
+    Z,
+    /// Travel of X, in machine coordinates
+    TravelX,
+    TravelY,
+    TravelZ,
+    FeedRate,
+    SpindleSpeed,
+}
+
+/// Violation of a machine limit, found by `GCodeMachineLimits`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeLimitViolation {
+    pub limit: GCodeLimit,
+    /// Value requested by the command
+    pub value: f64,
+    /// Bound exceeded by `value`
+    pub bound: f64,
+}
+
+impl GCodeMachineLimits {
+    /// Checks the present axes of `pos` against the axis limits
+    pub fn check_position(&self, pos: &GCodePosition) -> Option<GCodeLimitViolation> {
+        Self::check_ranges(
+            pos,
+            [GCodeLimit::X, GCodeLimit::Y, GCodeLimit::Z],
+            self.axes,
+        )
+    }
+
+    /// Checks the present axes of `pos`, in machine coordinates, against the
+    /// machine travel
+    pub fn check_travel(&self, pos: &GCodePosition) -> Option<GCodeLimitViolation> {
+        let limits = [
+            GCodeLimit::TravelX,
+            GCodeLimit::TravelY,
+            GCodeLimit::TravelZ,
+        ];
+        Self::check_ranges(pos, limits, self.travel)
+    }
+
+    pub fn check_feed_rate(&self, feed_rate: f64) -> Option<GCodeLimitViolation> {
+        Self::check_max(GCodeLimit::FeedRate, feed_rate, self.max_feed_rate)
+    }
+
+    pub fn check_spindle_speed(&self, speed: f64) -> Option<GCodeLimitViolation> {
+        Self::check_max(GCodeLimit::SpindleSpeed, speed, self.max_spindle_speed)
+    }
+
+    fn check_ranges(
+        pos: &GCodePosition,
+        limits: [GCodeLimit; 3],
+        ranges: [Option<(f64, f64)>; 3],
+    ) -> Option<GCodeLimitViolation> {
+        let (x, y, z) = pos.as_f64();
+        limits
+            .into_iter()
+            .zip([x, y, z])
+            .zip(ranges)
+            .find_map(|((limit, value), range)| {
+                let (value, (min, max)) = value.zip(range)?;
+                let bound = if value < min {
+                    min
+                } else if value > max {
+                    max
+                } else {
+                    return None;
+                };
+                Some(GCodeLimitViolation {
+                    limit,
+                    value,
+                    bound,
+                })
+            })
+    }
+
+    fn check_max(limit: GCodeLimit, value: f64, max: Option<f64>) -> Option<GCodeLimitViolation> {
+        max.filter(|&max| value > max)
+            .map(|bound| GCodeLimitViolation {
+                limit,
+                value,
+                bound,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GCodeError;
+
+    #[test]
+    fn limits() -> Result<(), GCodeError> {
+        let limits = GCodeMachineLimits {
+            axes: [Some((0.0, 300.0)), None, Some((-100.0, 0.0))],
+            travel: [None, None, Some((-200.0, 0.0))],
+            max_feed_rate: Some(5000.0),
+            max_spindle_speed: None,
+        };
+
+        let pos = GCodePosition::from_f64_full(300.0, -1000.0, -100.0)?;
+        assert_eq!(limits.check_position(&pos), None);
+        let pos = GCodePosition::from_f64(None, None, Some(1.0))?;
+        assert_eq!(
+            limits.check_position(&pos),
+            Some(GCodeLimitViolation {
+                limit: GCodeLimit::Z,
+                value: 1.0,
+                bound: 0.0
+            })
+        );
+        assert_eq!(
+            limits.check_travel(&pos).map(|v| v.limit),
+            Some(GCodeLimit::TravelZ)
+        );
+        assert_eq!(
+            limits.check_feed_rate(6000.0).map(|v| v.limit),
+            Some(GCodeLimit::FeedRate)
+        );
+        assert_eq!(limits.check_spindle_speed(1e6), None);
+
+        Ok(())
+    }
+}
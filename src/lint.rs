@@ -0,0 +1,211 @@
+use std::fmt::Display;
+
+use crate::interp::{Interpreter, ModalGroup, Motion};
+
+/// Options of program linting
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeLintOptions {
+    /// Largest allowed difference between the start and end radius of arcs
+    pub arc_tolerance: f64,
+    /// Lowest Z any move may reach, in work coordinates and program units
+    pub min_z: Option<f64>,
+    /// Whether cutting moves require the spindle to be on, disable e.g. for
+    /// 3D printers
+    pub require_spindle: bool,
+}
+impl Default for GCodeLintOptions {
+    fn default() -> Self {
+        Self {
+            arc_tolerance: 0.002,
+            min_z: None,
+            require_spindle: true,
+        }
+    }
+}
+
+/// Kind of mistake found by `lint_program()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GCodeLintKind {
+    /// Feed move prior to any feed rate being set
+    MissingFeedRate,
+    /// Feed move with the spindle off
+    SpindleOff,
+    /// Arc whose start and end radius differ by the contained amount
+    ArcRadiusMismatch(f64),
+    /// Motion prior to selecting units (G20/G21)
+    MissingUnits,
+    /// Motion prior to selecting absolute or incremental positioning
+    /// (G90/G91)
+    MissingPositioning,
+    /// Move to the contained Z, below the configured minimum
+    BelowMinZ(f64),
+}
+impl Display for GCodeLintKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingFeedRate => write!(f, "feed move without feed rate"),
+            Self::SpindleOff => write!(f, "feed move with spindle off"),
+            Self::ArcRadiusMismatch(diff) => write!(f, "arc radius mismatch of {}", diff),
+            Self::MissingUnits => write!(f, "motion before units are selected"),
+            Self::MissingPositioning => write!(f, "motion before positioning mode is selected"),
+            Self::BelowMinZ(z) => write!(f, "move to Z{} below minimum", z),
+        }
+    }
+}
+
+/// Mistake found by `lint_program()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeLintDiagnostic {
+    /// Line number, starting at 1
+    pub line: usize,
+    pub kind: GCodeLintKind,
+}
+impl Display for GCodeLintDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+/// Checks a program for common mistakes. Arcs are checked in the XY plane
+/// only, and only when given by center (I/J). Lines using parameters,
+/// expressions or control flow are not analyzed, and make the position
+/// unknown, as do non-modal codes using axis words, such as G28 or G92.
+/// Moves in machine coordinates (G53) are not checked, and make the axes
+/// moved unknown.
+pub fn lint_program(program: &str, options: &GCodeLintOptions) -> Vec<GCodeLintDiagnostic> {
+    let mut diags = vec![];
+    let mut interp = Interpreter::new();
+    let (mut units_warned, mut positioning_warned) = (false, false);
+
+    for (idx, text) in program.lines().enumerate() {
+        let Some(block) = interp.line(text) else {
+            continue;
+        };
+        let mut push = |kind| {
+            diags.push(GCodeLintDiagnostic {
+                line: idx + 1,
+                kind,
+            })
+        };
+        let state = &interp.state;
+        let Motion::Work { mode, start, end } = block.motion else {
+            continue;
+        };
+
+        if state.group(ModalGroup::Units).is_none() && !units_warned {
+            units_warned = true;
+            push(GCodeLintKind::MissingUnits);
+        }
+        if state.incremental.is_none() && !positioning_warned {
+            positioning_warned = true;
+            push(GCodeLintKind::MissingPositioning);
+        }
+        if mode != 0 {
+            if state.feed.is_none() {
+                push(GCodeLintKind::MissingFeedRate);
+            }
+            if options.require_spindle && state.spindle.is_none() {
+                push(GCodeLintKind::SpindleOff);
+            }
+        }
+
+        if let (Some(z), Some(min_z)) = (end[2], options.min_z) {
+            if (block.axes[2].is_some()) && (z < min_z) {
+                push(GCodeLintKind::BelowMinZ(z));
+            }
+        }
+
+        if (mode == 2) || (mode == 3) {
+            if let (Some(sx), Some(sy), Some(ex), Some(ey)) = (start[0], start[1], end[0], end[1]) {
+                if block.word('R').is_none() {
+                    let offset = |letter| block.word(letter).unwrap_or(0.0);
+                    let (cx, cy) = (sx + offset('I'), sy + offset('J'));
+                    let diff = ((sx - cx).hypot(sy - cy) - (ex - cx).hypot(ey - cy)).abs();
+                    if diff > options.arc_tolerance {
+                        push(GCodeLintKind::ArcRadiusMismatch(diff));
+                    }
+                }
+            }
+        }
+    }
+
+    diags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lint() {
+        let program = "\
+            (Facing)\n\
+            G00 X0 Y0 Z5\n\
+            G21 G90\n\
+            G01 Z-1\n\
+            M03 S1000\n\
+            G01 X10 F300\n\
+            G02 X20 Y0 I5 J0\n\
+            G03 X30 Y0 I6 J0\n\
+            G91 G01 Z-2.5\n\
+            #<depth>=[1 + 2]\n\
+            G00 Z[#<depth>]\n\
+            ; Done\n\
+            M05\n\
+            G90\n\
+            G53 G00 Z-50\n\
+            G92 Z-20\n\
+            G00 X0\n";
+        let options = GCodeLintOptions {
+            min_z: Some(-3.0),
+            ..Default::default()
+        };
+        let diags = lint_program(program, &options);
+
+        assert_eq!(
+            diags,
+            [
+                GCodeLintDiagnostic {
+                    line: 2,
+                    kind: GCodeLintKind::MissingUnits
+                },
+                GCodeLintDiagnostic {
+                    line: 2,
+                    kind: GCodeLintKind::MissingPositioning
+                },
+                GCodeLintDiagnostic {
+                    line: 4,
+                    kind: GCodeLintKind::MissingFeedRate
+                },
+                GCodeLintDiagnostic {
+                    line: 4,
+                    kind: GCodeLintKind::SpindleOff
+                },
+                GCodeLintDiagnostic {
+                    line: 8,
+                    kind: GCodeLintKind::ArcRadiusMismatch(2.0)
+                },
+                GCodeLintDiagnostic {
+                    line: 9,
+                    kind: GCodeLintKind::BelowMinZ(-3.5)
+                },
+            ]
+        );
+        assert_eq!(diags[5].to_string(), "line 9: move to Z-3.5 below minimum");
+
+        /* Decimal commas are read, lines not analyzed lose the position */
+        let program = "\
+            G21 G90\n\
+            M03 S1000\n\
+            G01 X0 Y0 Z-3,5 F100\n\
+            G00 Z[1 + 2]\n\
+            G91 G01 Z-1\n";
+        assert_eq!(
+            lint_program(program, &options),
+            [GCodeLintDiagnostic {
+                line: 3,
+                kind: GCodeLintKind::BelowMinZ(-3.5)
+            }]
+        );
+    }
+}
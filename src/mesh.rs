@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+
+use crate::GCodeBackplot;
+
+/// Classification of a move for display purposes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GCodeMoveKind {
+    /// Rapid (G00) move
+    Rapid,
+    /// Feed move changing Z, e.g. plunges and ramps
+    Plunge,
+    /// Feed move at a constant Z
+    Cut,
+}
+
+/// Set of line segments sharing a move classification and Z level, laid out
+/// for direct upload to a GPU
+#[derive(Clone, Debug, PartialEq)]
+pub struct GCodeMeshGroup {
+    pub kind: GCodeMoveKind,
+    /// Z level of `Cut` groups, None for other kinds
+    pub z: Option<f64>,
+    /// Display color, as RGB in the range 0.0 - 1.0
+    pub color: [f32; 3],
+    /// Vertex positions, as consecutive XYZ triplets
+    pub vertices: Vec<f32>,
+    /// Line list, as pairs of indices into `vertices`
+    pub indices: Vec<u32>,
+}
+
+/// Number of Z level keys per unit, Z levels closer than this are merged
+const Z_RESOLUTION: f64 = 1_000_000.0;
+
+/// Tessellates a backplot into line lists, grouped by move kind and Z level.
+///
+/// Groups are ordered by kind, then by ascending Z. `Cut` groups are colored
+/// along a gradient from blue at the lowest Z to red at the highest, rapids
+/// are grey and plunges yellow.
+pub fn export_mesh(backplot: &GCodeBackplot) -> Vec<GCodeMeshGroup> {
+    mesh_runs(backplot)
+        .into_iter()
+        .map(|group| {
+            let mut mesh = GCodeMeshGroup {
+                kind: group.kind,
+                z: group.z,
+                color: group.color,
+                vertices: vec![],
+                indices: vec![],
+            };
+            for run in &group.runs {
+                let mut last = push_vertex(&mut mesh, run[0]);
+                for &point in &run[1..] {
+                    let idx = push_vertex(&mut mesh, point);
+                    mesh.indices.extend([last, idx]);
+                    last = idx;
+                }
+            }
+            mesh
+        })
+        .collect()
+}
+
+/// Group of `export_mesh()`, with segments kept in full precision as runs
+/// of connected points
+pub(crate) struct MeshRuns {
+    pub kind: GCodeMoveKind,
+    pub z: Option<f64>,
+    pub color: [f32; 3],
+    pub runs: Vec<Vec<[f64; 3]>>,
+}
+
+/// Groups the segments of a backplot as `export_mesh()` does
+pub(crate) fn mesh_runs(backplot: &GCodeBackplot) -> Vec<MeshRuns> {
+    let mut groups: BTreeMap<(GCodeMoveKind, i64), MeshRuns> = BTreeMap::new();
+
+    for line in backplot.polylines() {
+        /* Group and point index the last segment of this polyline ended at,
+         * so that runs of segments are continued */
+        let mut last: Option<((GCodeMoveKind, i64), usize)> = None;
+
+        for (i, seg) in line.points.windows(2).enumerate() {
+            let (from, to) = (seg[0], seg[1]);
+            let (kind, z) = if line.rapid {
+                (GCodeMoveKind::Rapid, None)
+            } else if from[2] != to[2] {
+                (GCodeMoveKind::Plunge, None)
+            } else {
+                (GCodeMoveKind::Cut, Some(from[2]))
+            };
+            let key = (kind, z.map_or(0, |z| (z * Z_RESOLUTION).round() as i64));
+
+            let group = groups.entry(key).or_insert_with(|| MeshRuns {
+                kind,
+                z,
+                color: [0.0; 3],
+                runs: vec![],
+            });
+
+            match (last, group.runs.last_mut()) {
+                (Some((last_key, idx)), Some(run)) if (last_key == key) && (idx == i) => {
+                    run.push(to)
+                }
+                _ => group.runs.push(vec![from, to]),
+            }
+            last = Some((key, i + 1));
+        }
+    }
+
+    let z_range =
+        groups
+            .values()
+            .filter_map(|group| group.z)
+            .fold(None, |acc: Option<(f64, f64)>, z| match acc {
+                Some((min, max)) => Some((min.min(z), max.max(z))),
+                None => Some((z, z)),
+            });
+
+    groups
+        .into_values()
+        .map(|mut group| {
+            group.color = match (group.kind, group.z, z_range) {
+                (GCodeMoveKind::Rapid, _, _) => [0.5, 0.5, 0.5],
+                (GCodeMoveKind::Plunge, _, _) => [1.0, 0.8, 0.0],
+                (GCodeMoveKind::Cut, Some(z), Some((min, max))) => {
+                    let t = if max > min {
+                        ((z - min) / (max - min)) as f32
+                    } else {
+                        1.0
+                    };
+                    [t, 0.0, 1.0 - t]
+                }
+                (GCodeMoveKind::Cut, _, _) => [1.0, 0.0, 0.0],
+            };
+            group
+        })
+        .collect()
+}
+
+fn push_vertex(group: &mut GCodeMeshGroup, point: [f64; 3]) -> u32 {
+    let idx = (group.vertices.len() / 3) as u32;
+    group
+        .vertices
+        .extend([point[0] as f32, point[1] as f32, point[2] as f32]);
+    idx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GCodeBackplotBuilder, GCodeError, GCodePosition};
+
+    #[test]
+    fn mesh_groups() -> Result<(), GCodeError> {
+        let mut builder = GCodeBackplotBuilder::new();
+        builder.start_at(GCodePosition::from_f64_full(0.0, 0.0, 5.0)?);
+        for (i, z) in [-1.0, -2.0].into_iter().enumerate() {
+            let cmd = i * 10;
+            builder
+                .move_to(cmd, GCodePosition::from_f64_full(0.0, 0.0, z)?, false)
+                .move_to(cmd + 1, GCodePosition::from_f64_full(10.0, 0.0, z)?, false)
+                .move_to(cmd + 2, GCodePosition::from_f64_full(10.0, 10.0, z)?, false)
+                .move_to(cmd + 3, GCodePosition::from_f64_full(0.0, 0.0, 5.0)?, true);
+        }
+
+        let groups = export_mesh(&builder.build());
+        assert_eq!(groups.len(), 4);
+
+        assert_eq!(groups[0].kind, GCodeMoveKind::Rapid);
+        assert_eq!(groups[0].indices.len(), 4);
+        assert_eq!(groups[1].kind, GCodeMoveKind::Plunge);
+        assert_eq!(groups[1].indices.len(), 4);
+
+        assert_eq!(groups[2].kind, GCodeMoveKind::Cut);
+        assert_eq!(groups[2].z, Some(-2.0));
+        assert_eq!(groups[2].color, [0.0, 0.0, 1.0]);
+        assert_eq!(
+            groups[2].vertices,
+            [0.0, 0.0, -2.0, 10.0, 0.0, -2.0, 10.0, 10.0, -2.0]
+        );
+        assert_eq!(groups[2].indices, [0, 1, 1, 2]);
+
+        assert_eq!(groups[3].z, Some(-1.0));
+        assert_eq!(groups[3].color, [1.0, 0.0, 0.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mesh_edge_cases() -> Result<(), GCodeError> {
+        assert!(export_mesh(&GCodeBackplotBuilder::new().build()).is_empty());
+
+        /* Moves from an unknown position add no segments */
+        let mut builder = GCodeBackplotBuilder::new();
+        builder.move_to(0, GCodePosition::from_f64_full(5.0, 5.0, 1.0)?, true);
+        assert!(export_mesh(&builder.build()).is_empty());
+
+        /* Axes never set are taken as 0 */
+        let mut builder = GCodeBackplotBuilder::new();
+        builder
+            .start_at(GCodePosition::from_f64(Some(1.0), None, None)?)
+            .move_to(1, GCodePosition::from_f64(Some(2.0), None, None)?, false)
+            .move_to(2, GCodePosition::from_f64(None, Some(3.0), None)?, false);
+        let groups = export_mesh(&builder.build());
+
+        /* A single Z level is colored as the highest */
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].kind, GCodeMoveKind::Cut);
+        assert_eq!(groups[0].z, Some(0.0));
+        assert_eq!(groups[0].color, [1.0, 0.0, 0.0]);
+        assert_eq!(groups[0].indices, [0, 1, 1, 2]);
+        assert_eq!(
+            groups[0].vertices,
+            [1.0, 0.0, 0.0, 2.0, 0.0, 0.0, 2.0, 3.0, 0.0]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use crate::interp::parse_words;
+
+/// Modal groups of G codes tracked by the minifier, as codes in tenths
+const MODAL_GROUPS: [&[i64]; 5] = [
+    /* Motion */
+    &[0, 10, 20, 30],
+    /* Plane */
+    &[170, 180, 190],
+    /* Units */
+    &[200, 210],
+    /* Distance mode */
+    &[900, 910],
+    /* Feed mode */
+    &[930, 940, 950],
+];
+const MOTION: usize = 0;
+const UNITS: usize = 2;
+const DISTANCE: usize = 3;
+const FEED_MODE: usize = 4;
+
+/// Modal state known to the minifier, None where unknown
+#[derive(Clone, Default)]
+struct MinifyState {
+    groups: [Option<i64>; 5],
+    feed: Option<f64>,
+    position: [Option<f64>; 3],
+}
+
+/// Rewrites a program into a compact equivalent, for controllers with
+/// little storage or slow links. Comments, blank lines, whitespace and line
+/// numbers are removed, numbers are written in their shortest form, and
+/// words repeating the current modal state are dropped: modal G codes, feed
+/// rates, and axis values of linear moves equal to the current absolute
+/// position. End points of arcs are always kept, as controllers require
+/// them, and changing units makes the position unknown.
+///
+/// Lines which can not be analyzed, e.g. using parameters or expressions,
+/// are kept as-is and reset the known state, as do G codes not tracked by
+/// the minifier (e.g. G28 or G92). Optional (`/`) blocks are only compacted,
+/// and reset the state they may change.
+pub fn minify_program(program: &str) -> String {
+    let mut out = String::new();
+    let mut state = MinifyState::default();
+
+    for line in program.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('%') {
+            out.push_str("%\n");
+            continue;
+        }
+        let optional = trimmed.starts_with('/');
+        let words = match parse_words(trimmed) {
+            Some(words) => words,
+            None => {
+                out.push_str(trimmed);
+                out.push('\n');
+                state = MinifyState::default();
+                continue;
+            }
+        };
+
+        let mut kept = vec![];
+        let mut next = state.clone();
+        let mut untracked = false;
+        for &(letter, val) in &words {
+            let code = (val * 10.0).round() as i64;
+            match letter {
+                'N' => continue,
+                'G' => match MODAL_GROUPS.iter().position(|g| g.contains(&code)) {
+                    Some(group) => {
+                        let redundant = state.groups[group] == Some(code);
+                        next.groups[group] = Some(code);
+                        if redundant && !optional {
+                            continue;
+                        }
+                    }
+                    None => untracked = true,
+                },
+                'F' => {
+                    let inverse_time = next.groups[FEED_MODE] == Some(930);
+                    let redundant = state.feed == Some(val);
+                    next.feed = Some(val);
+                    if redundant && !inverse_time && !optional {
+                        continue;
+                    }
+                }
+                _ => (),
+            }
+            kept.push((letter, val));
+        }
+
+        /* Positions are given in the selected units */
+        if next.groups[UNITS] != state.groups[UNITS] {
+            next.position = [None; 3];
+        }
+
+        /* Axis words equal to the current position are dropped, if the
+         * move is known to be linear and absolute */
+        let axes = ['X', 'Y', 'Z'];
+        let absolute = next.groups[DISTANCE] == Some(900);
+        let linear = matches!(next.groups[MOTION], Some(0 | 10)) && !untracked;
+        kept.retain(
+            |&(letter, val)| match axes.iter().position(|&a| a == letter) {
+                Some(axis) => {
+                    let redundant = absolute && linear && (next.position[axis] == Some(val));
+                    next.position[axis] = match next.groups[DISTANCE] {
+                        Some(900) => Some(val),
+                        Some(910) => next.position[axis].map(|cur| cur + val),
+                        _ => None,
+                    };
+                    !redundant || optional
+                }
+                None => true,
+            },
+        );
+        if untracked {
+            next.position = [None; 3];
+        }
+
+        state = if optional {
+            /* May or may not have been executed */
+            let keep = |cur: Option<f64>, new: Option<f64>| cur.filter(|_| cur == new);
+            MinifyState {
+                groups: [0, 1, 2, 3, 4]
+                    .map(|i| state.groups[i].filter(|_| state.groups[i] == next.groups[i])),
+                feed: keep(state.feed, next.feed),
+                position: [0, 1, 2].map(|i| keep(state.position[i], next.position[i])),
+            }
+        } else {
+            next
+        };
+
+        if kept.is_empty() {
+            continue;
+        }
+        if optional {
+            out.push('/');
+        }
+        for (letter, val) in kept {
+            out.push(letter);
+            out.push_str(&format_number(val));
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Shortest decimal representation of `val`
+pub(crate) fn format_number(val: f64) -> String {
+    if val == 0.0 {
+        /* Avoids -0 */
+        return "0".into();
+    }
+    format!("{}", val)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minify() {
+        let program = "\
+            %\n\
+            (Facing)\n\
+            N10 G21 G90 G94\n\
+            \n\
+            N20 G00 X0.0000 Y0.0000 Z5.0000 ; Start\n\
+            G01 Z-1.0000 F100.00\n\
+            G01 X10.0000 Y0.0000 F100.00\n\
+            G01 X10.0000 Y10.0000 F200.00\n\
+            G90 G01 X0.0000\n\
+            /G00 Z+5.0000\n\
+            G00 Z5.0000\n\
+            #<depth>=[1 + 2]\n\
+            G00 X0 Z5\n\
+            G28\n\
+            G00 X0\n\
+            %\n";
+
+        assert_eq!(
+            minify_program(program),
+            "%\n\
+             G21G90G94\n\
+             G0X0Y0Z5\n\
+             G1Z-1F100\n\
+             X10\n\
+             Y10F200\n\
+             X0\n\
+             /G0Z5\n\
+             G0Z5\n\
+             #<depth>=[1 + 2]\n\
+             G0X0Z5\n\
+             G28\n\
+             X0\n\
+             %\n"
+        );
+
+        /* Changing units makes the position unknown */
+        assert_eq!(
+            minify_program("G21 G90\nG00 X1 Y0\nG20\nG00 X1 Y0\n"),
+            "G21G90\nG0X1Y0\nG20\nX1Y0\n"
+        );
+        /* Arc end points are kept, e.g. of full circles */
+        assert_eq!(
+            minify_program("G21 G90\nG00 X10 Y0\nG02 X10 Y0 I-5 J0\n"),
+            "G21G90\nG0X10Y0\nG2X10Y0I-5J0\n"
+        );
+    }
+}
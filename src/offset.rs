@@ -0,0 +1,194 @@
+use crate::{GCodeError, GCodePosition};
+
+/// Side of the path on which the tool is kept, relative to the direction of
+/// travel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GCodeCompensation {
+    /// No compensation, tool center follows the path (G40)
+    Off,
+    /// Tool is kept to the left of the path (G41)
+    Left,
+    /// Tool is kept to the right of the path (G42)
+    Right,
+}
+
+/// Offsets a closed 2D contour by the tool radius, for use with controllers
+/// lacking cutter radius compensation.
+///
+/// The contour is closed implicitly, the last point should not repeat the
+/// first. Corners on the outside of the offset are rounded with arcs
+/// approximated by line segments deviating at most `tolerance` from the true
+/// arc; corners on the inside are trimmed to the intersection of the offset
+/// edges. Z values are carried over from the original vertices.
+///
+/// Features narrower than the tool diameter result in a self-intersecting
+/// contour, these are not removed.
+pub fn offset_contour(
+    contour: &[GCodePosition],
+    side: GCodeCompensation,
+    radius: f64,
+    tolerance: f64,
+) -> Result<Vec<GCodePosition>, GCodeError> {
+    let sign = match side {
+        GCodeCompensation::Off => return Ok(contour.to_vec()),
+        GCodeCompensation::Left => 1.0,
+        GCodeCompensation::Right => -1.0,
+    };
+    if radius < 0.0 || tolerance <= 0.0 {
+        return Err(GCodeError::OutOfRangeError);
+    }
+
+    let mut points = Vec::with_capacity(contour.len());
+    for pos in contour {
+        match pos.as_f64() {
+            (Some(x), Some(y), z) => points.push((x, y, z)),
+            _ => return Err(GCodeError::GeometryError),
+        }
+    }
+    /* Drop duplicate points, including an explicitly closed end point, as
+     * they have no defined direction. */
+    points.dedup_by(|a, b| (a.0 == b.0) && (a.1 == b.1));
+    while (points.len() > 1)
+        && (points[0].0 == points[points.len() - 1].0)
+        && (points[0].1 == points[points.len() - 1].1)
+    {
+        points.pop();
+    }
+    if points.len() < 3 {
+        return Err(GCodeError::GeometryError);
+    }
+
+    /* Unit direction and offset vectors of the edge leaving each point */
+    let count = points.len();
+    let edges: Vec<((f64, f64), (f64, f64))> = (0..count)
+        .map(|i| {
+            let (x0, y0, _) = points[i];
+            let (x1, y1, _) = points[(i + 1) % count];
+            let len = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+            let dir = ((x1 - x0) / len, (y1 - y0) / len);
+            /* Left-hand normal, flipped for right-side compensation */
+            let norm = (-dir.1 * sign * radius, dir.0 * sign * radius);
+            (dir, norm)
+        })
+        .collect();
+
+    let mut result = vec![];
+    for i in 0..count {
+        let (x, y, z) = points[i];
+        let (dir_in, norm_in) = edges[(i + count - 1) % count];
+        let (dir_out, norm_out) = edges[i];
+
+        let cross = dir_in.0 * dir_out.1 - dir_in.1 * dir_out.0;
+        let dot = dir_in.0 * dir_out.0 + dir_in.1 * dir_out.1;
+
+        let mut push = |px: f64, py: f64| -> Result<(), GCodeError> {
+            result.push(GCodePosition::from_f64(Some(px), Some(py), z)?);
+            Ok(())
+        };
+
+        if (cross * sign) > 1e-9 {
+            /* Inside corner, meet at the intersection of both offset edges */
+            let t = ((norm_out.0 - norm_in.0) * dir_out.1 - (norm_out.1 - norm_in.1) * dir_out.0)
+                / cross;
+            push(x + norm_in.0 + dir_in.0 * t, y + norm_in.1 + dir_in.1 * t)?;
+        } else if (cross.abs() <= 1e-9) && (dot > 0.0) {
+            /* Straight through */
+            push(x + norm_out.0, y + norm_out.1)?;
+        } else {
+            /* Outside corner, arc around the vertex */
+            let start = norm_in.1.atan2(norm_in.0);
+            let mut sweep = norm_out.1.atan2(norm_out.0) - start;
+            /* Outside arcs turn opposite to the offset side */
+            if sign > 0.0 {
+                while sweep > 0.0 {
+                    sweep -= std::f64::consts::TAU;
+                }
+            } else {
+                while sweep < 0.0 {
+                    sweep += std::f64::consts::TAU;
+                }
+            }
+
+            let max_step = if tolerance >= radius {
+                std::f64::consts::PI
+            } else {
+                2.0 * (1.0 - tolerance / radius).acos()
+            };
+            let steps = ((sweep.abs() / max_step).ceil() as usize).max(1);
+
+            push(x + norm_in.0, y + norm_in.1)?;
+            for step in 1..steps {
+                let angle = start + sweep * (step as f64) / (steps as f64);
+                push(x + radius * angle.cos(), y + radius * angle.sin())?;
+            }
+            push(x + norm_out.0, y + norm_out.1)?;
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square() -> Result<Vec<GCodePosition>, GCodeError> {
+        /* Counter-clockwise 10x10 square */
+        Ok(vec![
+            GCodePosition::from_f64_full(0.0, 0.0, -1.0)?,
+            GCodePosition::from_f64_full(10.0, 0.0, -1.0)?,
+            GCodePosition::from_f64_full(10.0, 10.0, -1.0)?,
+            GCodePosition::from_f64_full(0.0, 10.0, -1.0)?,
+        ])
+    }
+
+    #[test]
+    fn offset_inside() -> Result<(), GCodeError> {
+        let res = offset_contour(&square()?, GCodeCompensation::Left, 1.0, 0.01)?;
+        assert_eq!(
+            res,
+            vec![
+                GCodePosition::from_f64_full(1.0, 1.0, -1.0)?,
+                GCodePosition::from_f64_full(9.0, 1.0, -1.0)?,
+                GCodePosition::from_f64_full(9.0, 9.0, -1.0)?,
+                GCodePosition::from_f64_full(1.0, 9.0, -1.0)?,
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn offset_outside() -> Result<(), GCodeError> {
+        /* Coarse tolerance, arcs degrade to a single chord */
+        let res = offset_contour(&square()?, GCodeCompensation::Right, 1.0, 1.0)?;
+        assert_eq!(res.len(), 8);
+        assert_eq!(res[0], GCodePosition::from_f64_full(-1.0, 0.0, -1.0)?);
+        assert_eq!(res[1], GCodePosition::from_f64_full(0.0, -1.0, -1.0)?);
+        assert_eq!(res[2], GCodePosition::from_f64_full(10.0, -1.0, -1.0)?);
+
+        /* Fine tolerance, all points remain on the offset */
+        let res = offset_contour(&square()?, GCodeCompensation::Right, 1.0, 0.001)?;
+        assert!(res.len() > 8);
+        for pos in res {
+            let (x, y, _) = pos.as_f64();
+            let (x, y) = (x.unwrap(), y.unwrap());
+            let dx = x - x.clamp(0.0, 10.0);
+            let dy = y - y.clamp(0.0, 10.0);
+            assert!(((dx * dx + dy * dy).sqrt() - 1.0).abs() < 1e-4);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn offset_invalid() -> Result<(), GCodeError> {
+        let line = &square()?[..2];
+        assert_eq!(
+            offset_contour(line, GCodeCompensation::Left, 1.0, 0.01),
+            Err(GCodeError::GeometryError)
+        );
+
+        Ok(())
+    }
+}
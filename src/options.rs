@@ -1,4 +1,299 @@
-#[derive(Copy, Clone, Debug, Default)]
-pub struct GCodeOptions {
+use crate::GCodeError;
+
+/// Options of a single move, borrowing the comment for lifetime `'a`
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GCodeOptions<'a> {
     pub feed_rate: Option<f64>,
+    /// Spindle speed or laser power (S), set with the move
+    pub power: Option<f64>,
+    /// Amount of filament to extrude along the move (E), Marlin only
+    pub extrusion: Option<f64>,
+    /// Acceleration of this and all following moves, in units per second
+    /// squared, Marlin only (M204)
+    pub acceleration: Option<f64>,
+    /// Dwell following the move, in seconds
+    pub dwell: Option<f64>,
+    /// Comment appended to the move
+    pub comment: Option<&'a str>,
+}
+impl<'a> GCodeOptions<'a> {
+    pub fn builder() -> GCodeOptionsBuilder<'a> {
+        GCodeOptionsBuilder::default()
+    }
+
+    /// Checks that all values are within their valid range, and that the
+    /// comment can be written as such
+    pub fn validate(&self) -> Result<(), GCodeError> {
+        let positive = |val: Option<f64>| val.is_none_or(|val| val.is_finite() && (val > 0.0));
+        let non_negative = |val: Option<f64>| val.is_none_or(|val| val.is_finite() && (val >= 0.0));
+        let finite = |val: Option<f64>| val.is_none_or(f64::is_finite);
+        if !positive(self.feed_rate)
+            || !non_negative(self.power)
+            || !finite(self.extrusion)
+            || !positive(self.acceleration)
+            || !non_negative(self.dwell)
+        {
+            return Err(GCodeError::OutOfRangeError);
+        }
+        if let Some(comment) = self.comment {
+            if comment.contains(['(', ')', '\n', '\r']) {
+                return Err(GCodeError::ParseError);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Builds `GCodeOptions`, validating the result
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GCodeOptionsBuilder<'a> {
+    options: GCodeOptions<'a>,
+}
+impl<'a> GCodeOptionsBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed_rate(&mut self, feed_rate: f64) -> &mut Self {
+        self.options.feed_rate = Some(feed_rate);
+        self
+    }
+
+    pub fn power(&mut self, power: f64) -> &mut Self {
+        self.options.power = Some(power);
+        self
+    }
+
+    pub fn extrusion(&mut self, extrusion: f64) -> &mut Self {
+        self.options.extrusion = Some(extrusion);
+        self
+    }
+
+    pub fn acceleration(&mut self, acceleration: f64) -> &mut Self {
+        self.options.acceleration = Some(acceleration);
+        self
+    }
+
+    pub fn dwell(&mut self, seconds: f64) -> &mut Self {
+        self.options.dwell = Some(seconds);
+        self
+    }
+
+    pub fn comment(&mut self, comment: &'a str) -> &mut Self {
+        self.options.comment = Some(comment);
+        self
+    }
+
+    pub fn build(&self) -> Result<GCodeOptions<'a>, GCodeError> {
+        self.options.validate()?;
+        Ok(self.options)
+    }
+}
+
+/// Character separating the integer and fractional parts of numbers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GCodeDecimalSeparator {
+    /// `1.5`
+    #[default]
+    Point,
+    /// `1,5`, used by some controllers in locales using decimal commas
+    Comma,
+}
+impl GCodeDecimalSeparator {
+    pub fn as_char(&self) -> char {
+        match self {
+            Self::Point => '.',
+            Self::Comma => ',',
+        }
+    }
+}
+
+/// Sequence terminating each line of output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GCodeLineEnding {
+    /// `\n`
+    #[default]
+    Lf,
+    /// `\r\n`, as expected by some older controllers and DNC software
+    CrLf,
+}
+
+/// Case of letters within the output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GCodeLetterCase {
+    Upper,
+    Lower,
+}
+
+/// Output of optional blocks, which the controller skips when its block
+/// delete switch is on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GCodeBlockDelete {
+    /// Prefix each line with `/`, leaving the choice to the operator
+    #[default]
+    Emit,
+    /// Write lines as regular blocks, as if the switch were off
+    Include,
+    /// Leave lines out entirely, as if the switch were on
+    Omit,
+}
+
+/// Interpretation of F words
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GCodeFeedMode {
+    /// Feed rate in units per minute (G94)
+    #[default]
+    UnitsPerMinute,
+    /// Each move completes in 1/F minutes (G93)
+    InverseTime,
+    /// Feed rate in units per spindle revolution (G95)
+    UnitsPerRevolution,
+}
+
+/// State of the spindle, with speed in RPM
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GCodeSpindle {
+    #[default]
+    Off,
+    Clockwise(f64),
+    CounterClockwise(f64),
+}
+impl GCodeSpindle {
+    /// Speed of the spindle in RPM, 0 when off
+    pub fn speed(&self) -> f64 {
+        match self {
+            Self::Off => 0.0,
+            Self::Clockwise(speed) | Self::CounterClockwise(speed) => *speed,
+        }
+    }
+}
+
+/// Heater of a 3D printer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GCodeHeater {
+    /// Hotend of the given tool, or of the active tool if None
+    Hotend(Option<u32>),
+    Bed,
+    Chamber,
+}
+
+/// Speed of a fan
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GCodeFanSpeed {
+    /// PWM duty cycle, 0 - 255
+    Pwm(u8),
+    /// Percentage of full speed, 0.0 - 100.0
+    Percent(f64),
+}
+impl GCodeFanSpeed {
+    /// PWM duty cycle, OutOfRangeError if a percentage is out of range
+    pub fn pwm(&self) -> Result<u8, GCodeError> {
+        match *self {
+            Self::Pwm(pwm) => Ok(pwm),
+            Self::Percent(pct) if (0.0..=100.0).contains(&pct) => {
+                Ok((pct * 255.0 / 100.0).round() as u8)
+            }
+            Self::Percent(_) => Err(GCodeError::OutOfRangeError),
+        }
+    }
+}
+
+/// Action taken when the beam or torch would stay on without motion for too
+/// long
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GCodeIdleAction {
+    /// Fail with `GCodeError::StateError`
+    Reject,
+    /// Turn the spindle off for the duration of the dwell, and back on after
+    PowerOff,
+}
+
+/// Guard against the beam or torch dwelling in one spot, which may burn
+/// through the work or start a fire
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeIdleGuard {
+    /// Longest motionless interval allowed with the spindle on, in seconds
+    pub max_idle: f64,
+    pub action: GCodeIdleAction,
+}
+
+/// Power handling of a laser in GRBL style laser mode, where power (S) is
+/// given with each move
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GCodeLaserMode {
+    /// Constant power (M3)
+    Constant,
+    /// Power scaled with the actual speed (M4), avoiding darker spots where
+    /// the head accelerates, e.g. for engraving
+    #[default]
+    Dynamic,
+}
+
+/// Slowdown of the first layer of a print, or the first depth pass of a cut,
+/// e.g. for better bed adhesion. The first layer is the Z level of the first
+/// feed move, and ends with the first feed move to another Z level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeFirstLayer {
+    /// Factor applied to feed rates
+    pub feed_factor: f64,
+    /// Factor applied to the spindle speed, i.e. laser power
+    pub power_factor: Option<f64>,
+}
+
+/// Retract performed by `GCodeWriter::travel_to()` prior to moving in XY
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GCodeRetract {
+    /// Retract to the contained absolute Z, unless already above it
+    SafeZ(f64),
+    /// Lift by the contained distance above the current Z
+    Hop(f64),
+}
+
+/// Descent performed by `GCodeWriter::travel_to()` after moving in XY
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodePlunge {
+    /// Height above the target Z down to which the tool descends at rapid
+    pub clearance: f64,
+    /// Feed rate of the remaining descent
+    pub feed_rate: f64,
+}
+
+/// Rotation of the coordinate system in the XY plane, e.g. for parts
+/// fixtured at an angle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeRotation {
+    /// XY position rotated around
+    pub center: [f64; 2],
+    /// Counter-clockwise angle, in degrees
+    pub angle: f64,
+}
+impl GCodeRotation {
+    /// Rotates XY position `pos` around the center, or back if `inverse`
+    pub fn apply(&self, pos: [f64; 2], inverse: bool) -> [f64; 2] {
+        let angle = if inverse { -self.angle } else { self.angle }.to_radians();
+        let (sin, cos) = angle.sin_cos();
+        let (dx, dy) = (pos[0] - self.center[0], pos[1] - self.center[1]);
+        [
+            self.center[0] + dx * cos - dy * sin,
+            self.center[1] + dx * sin + dy * cos,
+        ]
+    }
+}
+
+/// Wrapping of a flat toolpath onto a cylinder turned by the A axis, e.g.
+/// for engraving on a 4th axis. Y positions become A angles, X runs along
+/// the cylinder axis and Z is unchanged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeCylinderWrap {
+    /// Diameter of the cylinder surface the flat Y coordinates lie on
+    pub diameter: f64,
+    /// Largest deviation of the linear moves replacing arcs
+    pub tolerance: f64,
+}
+impl GCodeCylinderWrap {
+    /// A angle, in degrees, of flat Y position `y`
+    pub fn angle(&self, y: f64) -> f64 {
+        (y / (std::f64::consts::PI * self.diameter)) * 360.0
+    }
 }
@@ -0,0 +1,942 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::io::Write;
+
+use crate::fillet::arc_geometry;
+use crate::interp::{code_of, tool_of, Interpreter, Motion};
+use crate::{
+    GCodeCanonical, GCodeCanonicalRecorder, GCodeCommand, GCodeError, GCodeHeater,
+    GCodeLimitViolation, GCodeLintKind, GCodeLintOptions, GCodeMachineLimits, GCodePosition,
+    GCodeRotaryAxis, GCodeSpindle, GCodeWriter, GCodeWriterState,
+};
+
+/// Failure of `GCodeCommandIterator::serialize()`, with the information
+/// needed to truncate or repair the partial output
+#[derive(Clone, Debug, PartialEq)]
+pub struct GCodeWriteError {
+    pub error: GCodeError,
+    /// Index of the failing command within the stream
+    pub index: usize,
+    /// Length of the output prior to the failing command, i.e. of all
+    /// commands written completely. Part of the failing command may
+    /// follow.
+    pub bytes_written: u64,
+    /// Modal state prior to the failing command
+    pub state: Box<GCodeWriterState>,
+}
+impl Display for GCodeWriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at command {}, after {} bytes",
+            self.error, self.index, self.bytes_written
+        )
+    }
+}
+impl std::error::Error for GCodeWriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+impl From<GCodeWriteError> for GCodeError {
+    fn from(value: GCodeWriteError) -> Self {
+        value.error
+    }
+}
+
+/// Transformation passes over a stream of commands, composable as iterator
+/// adapters, e.g. `commands.transform(f).linearize(0.01).write_to(&mut gcw)`.
+/// Commands are processed one at a time, so programs of any size can be
+/// streamed without holding them in memory.
+pub trait GCodeCommandIterator: Iterator<Item = GCodeCommand> + Sized {
+    /// Maps all positions of moves and arcs through `f`, e.g. to offset or
+    /// scale a program, updating their distances. Arc directions are kept,
+    /// so `f` must not mirror the XY plane.
+    fn transform<F>(self, f: F) -> GCodeTransform<Self, F>
+    where
+        F: FnMut(GCodePosition) -> GCodePosition,
+    {
+        GCodeTransform { iter: self, f }
+    }
+
+    /// Replaces arcs by linear moves deviating at most `tolerance` from
+    /// them. Arcs starting at an unknown position, and all arcs if the
+    /// tolerance is not positive, are kept as-is.
+    fn linearize(self, tolerance: f64) -> GCodeLinearize<Self> {
+        GCodeLinearize {
+            iter: self,
+            tolerance,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Splits linear feed moves longer than `max_length` into equal segments
+    /// no longer than it, e.g. for `level()`. Moves with unknown X or Y, and
+    /// all moves if the length is not positive, are kept as-is. Arcs are
+    /// not split, see `linearize()`.
+    fn segment(self, max_length: f64) -> GCodeSegment<Self> {
+        GCodeSegment {
+            iter: self,
+            max_length,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Adds the Z correction returned by `correction` for each XY position to
+    /// the start and end of linear moves, e.g. `|xy| map.height_at(xy)` for
+    /// a `GCodeHeightMap`. Only the ends of moves are corrected, so long
+    /// moves should be split by `segment()` first, and arcs by
+    /// `linearize()`.
+    fn level<F>(self, correction: F) -> GCodeLevel<Self, F>
+    where
+        F: FnMut([f64; 2]) -> f64,
+    {
+        GCodeLevel {
+            iter: self,
+            correction,
+        }
+    }
+
+    /// Drops moves which do not change the position, zero length dwells, and
+    /// spindle commands repeating the current spindle state
+    fn minify(self) -> GCodeMinify<Self> {
+        GCodeMinify {
+            iter: self,
+            spindle: None,
+        }
+    }
+
+    /// Passes on all commands, reporting those exceeding `limits` to
+    /// `report`
+    fn check_limits<F>(self, limits: GCodeMachineLimits, report: F) -> GCodeCheckLimits<Self, F>
+    where
+        F: FnMut(&GCodeCommand, GCodeLimitViolation),
+    {
+        GCodeCheckLimits {
+            iter: self,
+            limits,
+            report,
+        }
+    }
+
+    /// Passes on all commands, reporting feed moves without feed rate or
+    /// with the spindle off, and moves below the minimum Z, to `report`.
+    /// The spindle is assumed to be off initially.
+    fn lint<F>(self, options: GCodeLintOptions, report: F) -> GCodeLint<Self, F>
+    where
+        F: FnMut(&GCodeCommand, GCodeLintKind),
+    {
+        GCodeLint {
+            iter: self,
+            options,
+            report,
+            spindle: false,
+        }
+    }
+
+    /// Passes all commands to `target`, e.g. a `GCodeWriter`, stopping at the
+    /// first error. Only what `GCodeCommand` represents is passed on, so
+    /// e.g. work coordinate systems, feed modes, fans, tool changes and
+    /// comments of the source are lost, and need to be written to `target`
+    /// around the stream.
+    fn write_to(self, target: &mut impl GCodeCanonical) -> Result<(), GCodeError> {
+        for command in self {
+            target.execute(&command)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes all commands to `writer`, returning the number of commands
+    /// written. On failure, the error tells which command failed and how
+    /// much of the output is complete.
+    fn serialize<W: Write>(self, writer: &mut GCodeWriter<W>) -> Result<usize, GCodeWriteError> {
+        let mut count = 0;
+        for (index, command) in self.enumerate() {
+            /* Only state a command can not update in part is taken from
+             * the writer on failure, avoiding allocations for each command */
+            let bytes_written = writer.bytes_written();
+            let prior = GCodeWriterState {
+                temperatures: vec![],
+                fans: vec![],
+                ..*writer.state()
+            };
+            writer.execute(&command).map_err(|error| GCodeWriteError {
+                error,
+                index,
+                bytes_written,
+                state: Box::new(GCodeWriterState {
+                    temperatures: writer.state().temperatures.clone(),
+                    fans: writer.state().fans.clone(),
+                    ..prior
+                }),
+            })?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+impl<I: Iterator<Item = GCodeCommand>> GCodeCommandIterator for I {}
+
+/// Reads the commands of an existing program, one line at a time, as a
+/// source for `GCodeCommandIterator`, e.g.
+/// `read_commands(program.lines()).linearize(0.01).write_to(&mut gcw)`, or
+/// `read_commands(reader.lines().map_while(Result::ok))` to stream a file.
+///
+/// Moves, arcs in the XY plane given by I/J, rotary moves, dwells (G04 P,
+/// in seconds), spindle commands and temperatures (M104/M109, M140/M190,
+/// M141/M191) are read, with positions and feed rates as written, in the
+/// program's units. Hotend temperatures whose T word is not a valid tool
+/// number are skipped. Anything else, e.g. work coordinate systems, feed modes,
+/// fans, tool changes and comments, is skipped. Lines using parameters,
+/// expressions or control flow are skipped as well, and make the position
+/// unknown, as do G codes using axis words otherwise, e.g. G28 or G92.
+/// Moves in machine coordinates (G53) are skipped, and make the axes moved
+/// unknown.
+pub fn read_commands<I>(lines: I) -> GCodeReadCommands<I::IntoIter>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    GCodeReadCommands {
+        lines: lines.into_iter(),
+        recorder: GCodeCanonicalRecorder::new(),
+        pending: VecDeque::new(),
+        interp: Interpreter::new(),
+        spindle: GCodeSpindle::Off,
+    }
+}
+
+/// Iterator returned by `read_commands()`
+pub struct GCodeReadCommands<I> {
+    lines: I,
+    recorder: GCodeCanonicalRecorder,
+    pending: VecDeque<GCodeCommand>,
+    interp: Interpreter,
+    spindle: GCodeSpindle,
+}
+impl<I> GCodeReadCommands<I> {
+    /// Performs the canonical functions of a single line
+    fn read_line(&mut self, text: &str) {
+        let block = self.interp.line(text);
+        let state = &self.interp.state;
+        let Some(block) = block else {
+            self.recorder
+                .set_position(GCodePosition::from_raw(None, None, None));
+            return;
+        };
+
+        /* Order of execution within a line, as of RS274/NGC */
+        let tool = block.word('T').map(tool_of);
+        for &(letter, val) in &block.words {
+            let (heater, wait) = match (letter, code_of(val), tool) {
+                /* Hotends given by an invalid T word are not guessed */
+                ('M', 1040 | 1090, Some(None)) => continue,
+                ('M', 1040, _) => (GCodeHeater::Hotend(tool.flatten()), false),
+                ('M', 1090, _) => (GCodeHeater::Hotend(tool.flatten()), true),
+                ('M', 1400, _) => (GCodeHeater::Bed, false),
+                ('M', 1900, _) => (GCodeHeater::Bed, true),
+                ('M', 1410, _) => (GCodeHeater::Chamber, false),
+                ('M', 1910, _) => (GCodeHeater::Chamber, true),
+                _ => continue,
+            };
+            if let Some(temp) = block.word('S') {
+                let _ = self.recorder.set_temperature(heater, temp, wait);
+            }
+        }
+        let speed = state.speed.unwrap_or(0.0);
+        let spindle = match state.spindle {
+            Some(3) => GCodeSpindle::Clockwise(speed),
+            Some(4) => GCodeSpindle::CounterClockwise(speed),
+            _ => GCodeSpindle::Off,
+        };
+        if spindle != self.spindle {
+            self.spindle = spindle;
+            let _ = self.recorder.set_spindle(spindle);
+        }
+        if block.has('G', 40) {
+            let _ = self.recorder.dwell(block.word('P').unwrap_or(0.0));
+        }
+
+        let feed = block.feed;
+        let rapid = match block.motion {
+            Motion::Work { mode, start, end } => {
+                let to = GCodePosition::from_f64(end[0], end[1], end[2]);
+                let _ = to.and_then(|to| match (mode, start) {
+                    (0, _) => self.recorder.straight_traverse(to),
+                    (1, _) => self.recorder.straight_feed(to, feed),
+                    (_, [Some(x), Some(y), _]) => {
+                        let center = GCodePosition::from_f64(
+                            Some(x + block.word('I').unwrap_or(0.0)),
+                            Some(y + block.word('J').unwrap_or(0.0)),
+                            None,
+                        )?;
+                        self.recorder.arc_feed(to, center, mode == 2, feed)
+                    }
+                    _ => Err(GCodeError::GeometryError),
+                });
+                mode == 0
+            }
+            Motion::None => state.motion == Some(0),
+            Motion::Machine { .. } | Motion::Other => {
+                self.sync_position();
+                return;
+            }
+        };
+        /* Moves not recorded, e.g. arcs from an unknown position, still
+         * reach their end */
+        let moving = state.motion.is_some();
+        self.sync_position();
+        if !moving {
+            return;
+        }
+
+        let axes = [GCodeRotaryAxis::A, GCodeRotaryAxis::B, GCodeRotaryAxis::C];
+        for axis in axes {
+            if let Some(angle) = block.word(axis.letter()) {
+                let _ = self.recorder.rotary_motion(axis, angle, feed, rapid);
+            }
+        }
+    }
+
+    /// Updates the position of the recorder to that of the interpreter
+    fn sync_position(&mut self) {
+        let [x, y, z] = self.interp.state.position;
+        let position =
+            GCodePosition::from_f64(x, y, z).unwrap_or(GCodePosition::from_raw(None, None, None));
+        self.recorder.set_position(position);
+    }
+}
+impl<I> Iterator for GCodeReadCommands<I>
+where
+    I: Iterator,
+    I::Item: AsRef<str>,
+{
+    type Item = GCodeCommand;
+
+    fn next(&mut self) -> Option<GCodeCommand> {
+        loop {
+            if let Some(command) = self.pending.pop_front() {
+                return Some(command);
+            }
+            let line = self.lines.next()?;
+            self.read_line(line.as_ref());
+            self.pending.extend(self.recorder.commands.drain(..));
+        }
+    }
+}
+
+/// Iterator returned by `GCodeCommandIterator::transform()`
+pub struct GCodeTransform<I, F> {
+    iter: I,
+    f: F,
+}
+impl<I, F> Iterator for GCodeTransform<I, F>
+where
+    I: Iterator<Item = GCodeCommand>,
+    F: FnMut(GCodePosition) -> GCodePosition,
+{
+    type Item = GCodeCommand;
+
+    fn next(&mut self) -> Option<GCodeCommand> {
+        let command = match self.iter.next()? {
+            GCodeCommand::Move {
+                from,
+                to,
+                feed_rate,
+                rapid,
+                ..
+            } => {
+                let (from, to) = ((self.f)(from), (self.f)(to));
+                GCodeCommand::Move {
+                    from,
+                    to,
+                    distance: (from.is_complete() && to.is_complete())
+                        .then(|| GCodePosition::fixed_to_f64(from.distance_to(&to))),
+                    feed_rate,
+                    rapid,
+                }
+            }
+            GCodeCommand::Arc {
+                from,
+                to,
+                center,
+                clockwise,
+                feed_rate,
+                ..
+            } => {
+                let (from, to, center) = ((self.f)(from), (self.f)(to), (self.f)(center));
+                GCodeCommand::Arc {
+                    from,
+                    to,
+                    center,
+                    clockwise,
+                    distance: arc_geometry(&from, &to, &center, clockwise)
+                        .ok()
+                        .map(|arc| arc.length()),
+                    feed_rate,
+                }
+            }
+            command => command,
+        };
+
+        Some(command)
+    }
+}
+
+/// Iterator returned by `GCodeCommandIterator::linearize()`
+pub struct GCodeLinearize<I> {
+    iter: I,
+    tolerance: f64,
+    pending: VecDeque<GCodeCommand>,
+}
+impl<I: Iterator<Item = GCodeCommand>> Iterator for GCodeLinearize<I> {
+    type Item = GCodeCommand;
+
+    fn next(&mut self) -> Option<GCodeCommand> {
+        if let Some(command) = self.pending.pop_front() {
+            return Some(command);
+        }
+
+        let command = self.iter.next()?;
+        let GCodeCommand::Arc {
+            from,
+            to,
+            center,
+            clockwise,
+            feed_rate,
+            ..
+        } = command
+        else {
+            return Some(command);
+        };
+        let arc = match arc_geometry(&from, &to, &center, clockwise) {
+            Ok(arc) if self.tolerance.is_finite() && (self.tolerance > 0.0) => arc,
+            _ => return Some(command),
+        };
+
+        /* Largest angle whose chord stays within the tolerance */
+        let step = 2.0 * (1.0 - self.tolerance / arc.radius).max(-1.0).acos();
+        let count = (arc.sweep / step).ceil().max(1.0) as usize;
+        let direction = if clockwise { -1.0 } else { 1.0 };
+        let end = to.merge(&from);
+
+        let mut prev = from;
+        for i in 1..=count {
+            let t = (i as f64) / (count as f64);
+            let next = if i == count {
+                end
+            } else {
+                let angle = arc.start_angle + direction * arc.sweep * t;
+                let (x, y) = (
+                    arc.center.0 + arc.radius * angle.cos(),
+                    arc.center.1 + arc.radius * angle.sin(),
+                );
+                let z = from.z_f64().map(|z| z + arc.dz * t);
+                match GCodePosition::from_f64(Some(x), Some(y), z) {
+                    Ok(pos) => pos,
+                    Err(_) => return Some(command),
+                }
+            };
+            self.pending.push_back(GCodeCommand::Move {
+                from: prev,
+                to: next,
+                distance: Some(arc.length() / (count as f64)),
+                feed_rate,
+                rapid: false,
+            });
+            prev = next;
+        }
+
+        self.pending.pop_front()
+    }
+}
+
+/// Iterator returned by `GCodeCommandIterator::segment()`
+pub struct GCodeSegment<I> {
+    iter: I,
+    max_length: f64,
+    pending: VecDeque<GCodeCommand>,
+}
+impl<I: Iterator<Item = GCodeCommand>> Iterator for GCodeSegment<I> {
+    type Item = GCodeCommand;
+
+    fn next(&mut self) -> Option<GCodeCommand> {
+        if let Some(command) = self.pending.pop_front() {
+            return Some(command);
+        }
+
+        let command = self.iter.next()?;
+        let GCodeCommand::Move {
+            from,
+            to,
+            feed_rate,
+            rapid: false,
+            ..
+        } = command
+        else {
+            return Some(command);
+        };
+        let to = to.merge(&from);
+        let (start, end) = (from.as_f64(), to.as_f64());
+        let length = match (start, end) {
+            ((Some(x0), Some(y0), z0), (Some(x1), Some(y1), z1)) => {
+                let dz = z0.zip(z1).map_or(0.0, |(z0, z1)| z1 - z0);
+                (x1 - x0).hypot(y1 - y0).hypot(dz)
+            }
+            _ => return Some(command),
+        };
+        if !(self.max_length.is_finite() && (self.max_length > 0.0)) || (length <= self.max_length)
+        {
+            return Some(command);
+        }
+
+        let count = (length / self.max_length).ceil() as usize;
+        let mut prev = from;
+        for i in 1..=count {
+            let t = (i as f64) / (count as f64);
+            let lerp = |l: Option<f64>, r: Option<f64>| match (l, r) {
+                (Some(l), Some(r)) => Some(l + (r - l) * t),
+                _ => r,
+            };
+            let next = if i == count {
+                to
+            } else {
+                match GCodePosition::from_f64(
+                    lerp(start.0, end.0),
+                    lerp(start.1, end.1),
+                    lerp(start.2, end.2),
+                ) {
+                    Ok(pos) => pos,
+                    Err(_) => return Some(command),
+                }
+            };
+            self.pending.push_back(GCodeCommand::Move {
+                from: prev,
+                to: next,
+                distance: Some(length / (count as f64)),
+                feed_rate,
+                rapid: false,
+            });
+            prev = next;
+        }
+
+        self.pending.pop_front()
+    }
+}
+
+/// Iterator returned by `GCodeCommandIterator::level()`
+pub struct GCodeLevel<I, F> {
+    iter: I,
+    correction: F,
+}
+impl<I, F> Iterator for GCodeLevel<I, F>
+where
+    I: Iterator<Item = GCodeCommand>,
+    F: FnMut([f64; 2]) -> f64,
+{
+    type Item = GCodeCommand;
+
+    fn next(&mut self) -> Option<GCodeCommand> {
+        let command = self.iter.next()?;
+        let GCodeCommand::Move {
+            from,
+            to,
+            feed_rate,
+            rapid,
+            ..
+        } = command
+        else {
+            return Some(command);
+        };
+
+        let mut correct = |pos: GCodePosition| match pos.as_f64() {
+            (Some(x), Some(y), Some(z)) => pos.with_z(z + (self.correction)([x, y])).ok(),
+            _ => Some(pos),
+        };
+        let (Some(from), Some(to)) = (correct(from), correct(to.merge(&from))) else {
+            return Some(command);
+        };
+
+        Some(GCodeCommand::Move {
+            from,
+            to,
+            distance: (from.is_complete() && to.is_complete())
+                .then(|| GCodePosition::fixed_to_f64(from.distance_to(&to))),
+            feed_rate,
+            rapid,
+        })
+    }
+}
+
+/// Iterator returned by `GCodeCommandIterator::minify()`
+pub struct GCodeMinify<I> {
+    iter: I,
+    spindle: Option<GCodeSpindle>,
+}
+impl<I: Iterator<Item = GCodeCommand>> Iterator for GCodeMinify<I> {
+    type Item = GCodeCommand;
+
+    fn next(&mut self) -> Option<GCodeCommand> {
+        loop {
+            let command = self.iter.next()?;
+            let redundant = match command {
+                GCodeCommand::Move { from, to, .. } => to.merge(&from) == from,
+                GCodeCommand::Dwell(seconds) => seconds == 0.0,
+                GCodeCommand::Spindle(spindle) => self.spindle.replace(spindle) == Some(spindle),
+                _ => false,
+            };
+            if !redundant {
+                return Some(command);
+            }
+        }
+    }
+}
+
+/// Iterator returned by `GCodeCommandIterator::check_limits()`
+pub struct GCodeCheckLimits<I, F> {
+    iter: I,
+    limits: GCodeMachineLimits,
+    report: F,
+}
+impl<I, F> Iterator for GCodeCheckLimits<I, F>
+where
+    I: Iterator<Item = GCodeCommand>,
+    F: FnMut(&GCodeCommand, GCodeLimitViolation),
+{
+    type Item = GCodeCommand;
+
+    fn next(&mut self) -> Option<GCodeCommand> {
+        let command = self.iter.next()?;
+        let (to, feed_rate, speed) = match command {
+            GCodeCommand::Move {
+                to,
+                feed_rate,
+                rapid,
+                ..
+            } => (Some(to), feed_rate.filter(|_| !rapid), None),
+            GCodeCommand::Arc { to, feed_rate, .. } => (Some(to), feed_rate, None),
+            GCodeCommand::Spindle(spindle) => (None, None, Some(spindle.speed())),
+            _ => (None, None, None),
+        };
+
+        let violations = [
+            to.and_then(|to| self.limits.check_position(&to)),
+            feed_rate.and_then(|feed| self.limits.check_feed_rate(feed)),
+            speed.and_then(|speed| self.limits.check_spindle_speed(speed)),
+        ];
+        for violation in violations.into_iter().flatten() {
+            (self.report)(&command, violation);
+        }
+
+        Some(command)
+    }
+}
+
+/// Iterator returned by `GCodeCommandIterator::lint()`
+pub struct GCodeLint<I, F> {
+    iter: I,
+    options: GCodeLintOptions,
+    report: F,
+    spindle: bool,
+}
+impl<I, F> Iterator for GCodeLint<I, F>
+where
+    I: Iterator<Item = GCodeCommand>,
+    F: FnMut(&GCodeCommand, GCodeLintKind),
+{
+    type Item = GCodeCommand;
+
+    fn next(&mut self) -> Option<GCodeCommand> {
+        let command = self.iter.next()?;
+        let (to, feed_rate) = match command {
+            GCodeCommand::Move {
+                to,
+                feed_rate,
+                rapid,
+                ..
+            } => (to, (!rapid).then_some(feed_rate)),
+            GCodeCommand::Arc { to, feed_rate, .. } => (to, Some(feed_rate)),
+            GCodeCommand::Spindle(spindle) => {
+                self.spindle = spindle != GCodeSpindle::Off;
+                return Some(command);
+            }
+            _ => return Some(command),
+        };
+
+        if let Some(feed_rate) = feed_rate {
+            if feed_rate.is_none() {
+                (self.report)(&command, GCodeLintKind::MissingFeedRate);
+            }
+            if self.options.require_spindle && !self.spindle {
+                (self.report)(&command, GCodeLintKind::SpindleOff);
+            }
+        }
+        if let (Some(z), Some(min_z)) = (to.z_f64(), self.options.min_z) {
+            if z < min_z {
+                (self.report)(&command, GCodeLintKind::BelowMinZ(z));
+            }
+        }
+
+        Some(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GCodeHeightMap, GCodeLimit};
+
+    #[test]
+    fn pipeline() -> Result<(), GCodeError> {
+        let start = GCodePosition::from_f64_full(10.0, 0.0, -1.0)?;
+        let commands = vec![
+            GCodeCommand::Spindle(GCodeSpindle::Clockwise(1000.0)),
+            GCodeCommand::Move {
+                from: GCodePosition::from_f64(Some(10.0), Some(0.0), None)?,
+                to: start,
+                distance: None,
+                feed_rate: Some(100.0),
+                rapid: false,
+            },
+            GCodeCommand::Move {
+                from: start,
+                to: GCodePosition::from_f64(None, Some(0.0), None)?,
+                distance: Some(0.0),
+                feed_rate: Some(100.0),
+                rapid: false,
+            },
+            GCodeCommand::Arc {
+                from: start,
+                to: GCodePosition::from_f64_full(0.0, 10.0, -1.0)?,
+                center: GCodePosition::from_f64_full(0.0, 0.0, -1.0)?,
+                clockwise: false,
+                distance: None,
+                feed_rate: Some(100.0),
+            },
+            GCodeCommand::Spindle(GCodeSpindle::Clockwise(1000.0)),
+            GCodeCommand::Dwell(0.0),
+            GCodeCommand::Spindle(GCodeSpindle::Off),
+        ];
+        let offset = GCodePosition::from_f64_full(5.0, 0.0, 0.0)?;
+        let limits = GCodeMachineLimits {
+            max_feed_rate: Some(50.0),
+            ..Default::default()
+        };
+        let lint_options = GCodeLintOptions {
+            min_z: Some(0.0),
+            ..Default::default()
+        };
+
+        let (mut violations, mut diags) = (vec![], vec![]);
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        gcw.move_to(GCodePosition::from_f64_full(15.0, 0.0, 5.0)?, None, true)?;
+        commands
+            .into_iter()
+            .transform(|pos| pos + offset)
+            .linearize(1.0)
+            .minify()
+            .check_limits(limits, |_, violation| violations.push(violation.limit))
+            .lint(lint_options, |_, kind| diags.push(kind))
+            .write_to(&mut gcw)?;
+        gcw.writer()?;
+
+        /* Quarter circle of radius 10 within 1 takes 2 segments */
+        assert_eq!(violations, [GCodeLimit::FeedRate; 3]);
+        assert_eq!(diags, [GCodeLintKind::BelowMinZ(-1.0); 3]);
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G00 X15.0000 Y0.0000 Z5.0000\n\
+             M03 S1000\n\
+             G01 X15.0000 Y0.0000 Z-1.0000 F100.00\n\
+             G01 X12.0711 Y7.0711 Z-1.0000\n\
+             G01 X5.0000 Y10.0000 Z-1.0000\n\
+             M05\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn leveling() -> Result<(), GCodeError> {
+        let map = GCodeHeightMap::new([0.0, 0.0], 10.0, 2, vec![0.0, 1.0, 0.0, 1.0])?;
+        let commands = vec![
+            GCodeCommand::Move {
+                from: GCodePosition::from_f64(None, None, None)?,
+                to: GCodePosition::from_f64_full(0.0, 0.0, 0.0)?,
+                distance: None,
+                feed_rate: None,
+                rapid: true,
+            },
+            GCodeCommand::Move {
+                from: GCodePosition::from_f64_full(0.0, 0.0, 0.0)?,
+                to: GCodePosition::from_f64(Some(10.0), None, None)?,
+                distance: Some(10.0),
+                feed_rate: Some(100.0),
+                rapid: false,
+            },
+        ];
+
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        commands
+            .into_iter()
+            .segment(4.0)
+            .level(|xy| map.height_at(xy))
+            .write_to(&mut gcw)?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G00 X0.0000 Y0.0000 Z0.0000\n\
+             G01 X3.3333 Y0.0000 Z0.3333 F100.00\n\
+             G01 X6.6667 Y0.0000 Z0.6667\n\
+             G01 X10.0000 Y0.0000 Z1.0000\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_error() -> Result<(), GCodeError> {
+        let pos = |x| GCodePosition::from_f64_full(x, 0.0, 0.0);
+        let commands = vec![
+            GCodeCommand::Move {
+                from: GCodePosition::from_f64(None, None, None)?,
+                to: pos(0.0)?,
+                distance: None,
+                feed_rate: None,
+                rapid: true,
+            },
+            GCodeCommand::Move {
+                from: pos(0.0)?,
+                to: pos(10.0)?,
+                distance: Some(10.0),
+                feed_rate: Some(100.0),
+                rapid: false,
+            },
+            GCodeCommand::Move {
+                from: pos(10.0)?,
+                to: pos(500.0)?,
+                distance: Some(490.0),
+                feed_rate: Some(100.0),
+                rapid: false,
+            },
+            GCodeCommand::Dwell(1.0),
+        ];
+
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        gcw.set_machine_limits(GCodeMachineLimits {
+            axes: [Some((0.0, 300.0)), None, None],
+            ..Default::default()
+        });
+        assert_eq!(commands[..2].iter().copied().serialize(&mut gcw), Ok(2));
+        let written = gcw.bytes_written();
+        let err = commands.iter().copied().serialize(&mut gcw).unwrap_err();
+        gcw.writer()?;
+
+        assert_eq!(err.error, GCodeError::OutOfRangeError);
+        assert_eq!(err.index, 2);
+        assert_eq!(err.state.position, pos(10.0)?);
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "GCodeError::OutOfRangeError at command 2, after {} bytes",
+                err.bytes_written
+            )
+        );
+        /* Output up to the failure ends with the last complete command */
+        let output = String::from_utf8_lossy(&data[..err.bytes_written as usize]);
+        assert_eq!(output.len() as u64, err.bytes_written);
+        assert!(err.bytes_written > written);
+        assert!(output.ends_with("G01 X10.0000 Y0.0000 Z0.0000\n"));
+
+        /* Commands sharing a line are counted once complete */
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::new(&mut data)?;
+        gcw.set_machine_limits(GCodeMachineLimits {
+            axes: [Some((0.0, 300.0)), None, None],
+            ..Default::default()
+        });
+        let err = commands.into_iter().serialize(&mut gcw).unwrap_err();
+        gcw.writer()?;
+        let output = String::from_utf8_lossy(&data[..err.bytes_written as usize]);
+        assert_eq!(
+            output,
+            "G00 X0.0000 Y0.0000 Z0.0000 G01 X10.0000 Y0.0000 Z0.0000 F100.00"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn read() -> Result<(), GCodeError> {
+        let program = "\
+            G21 G90 G54\n\
+            M03 S1000 (spindle)\n\
+            G00 X0 Y0 Z1\n\
+            G01 Z-1 F100\n\
+            G91 X10\n\
+            G90 G03 X10 Y10 J5\n\
+            G04 P0.5\n\
+            S2000\n\
+            #<x> = 5\n\
+            G01 X0 Y0\n\
+            M104 S200 T1\n\
+            M104 S210 T300\n\
+            M109 S190 T-1\n\
+            M05\n";
+
+        let commands: Vec<_> = read_commands(program.lines()).collect();
+        assert_eq!(commands.len(), 11);
+        assert_eq!(
+            commands[0],
+            GCodeCommand::Spindle(GCodeSpindle::Clockwise(1000.0))
+        );
+        assert_eq!(commands[5], GCodeCommand::Dwell(0.5));
+        assert_eq!(
+            commands[6],
+            GCodeCommand::Spindle(GCodeSpindle::Clockwise(2000.0))
+        );
+        /* Unknown start after the parameter assignment */
+        assert!(matches!(
+            commands[7],
+            GCodeCommand::Move { distance: None, .. }
+        ));
+        assert_eq!(
+            commands[8],
+            GCodeCommand::Temperature {
+                heater: GCodeHeater::Hotend(Some(1)),
+                temp: 200.0,
+                wait: false
+            }
+        );
+        assert_eq!(
+            commands[9],
+            GCodeCommand::Temperature {
+                heater: GCodeHeater::Hotend(Some(300)),
+                temp: 210.0,
+                wait: false
+            }
+        );
+        assert_eq!(commands[10], GCodeCommand::Spindle(GCodeSpindle::Off));
+
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        read_commands(program.lines().take(6)).write_to(&mut gcw)?;
+        gcw.writer()?;
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "M03 S1000\n\
+             G00 X0.0000 Y0.0000 Z1.0000\n\
+             G01 X0.0000 Y0.0000 Z-1.0000 F100.00\n\
+             G01 X10.0000 Y0.0000 Z-1.0000\n\
+             G03 X10.0000 Y10.0000 Z-1.0000 I0.0000 J5.0000\n"
+        );
+
+        Ok(())
+    }
+}
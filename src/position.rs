@@ -4,7 +4,10 @@ use crate::GCodeError;
 ///
 /// Uses fixed-point rather than floating-point to preserve accuracy over
 /// repeated manipulation
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+///
+/// Positions are ordered in raster order: by Z, then Y, then X. An absent
+/// component orders before any present value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct GCodePosition {
     x: Option<i64>,
     y: Option<i64>,
@@ -12,8 +15,13 @@ pub struct GCodePosition {
 }
 impl GCodePosition {
     /// Coordinate values are multiplied by this value prior to being stored within
-    /// GCodePosition/GCodeOffset
-    const FIXED_SCALE: i64 = 1 << 16;
+    /// GCodePosition/GCodeOffset. Being decimal, values with up to six
+    /// decimal places, e.g. 0.1, are represented exactly.
+    pub const FIXED_SCALE: i64 = 1_000_000;
+
+    /// Binary scale used by earlier versions, for converting raw values
+    /// stored by these with `rescale_fixed()`
+    pub const BINARY_FIXED_SCALE: i64 = 1 << 16;
 
     /// Creates a new GCodePosition from floating point values
     pub fn from_f64(
@@ -22,21 +30,9 @@ impl GCodePosition {
         z: Option<f64>,
     ) -> Result<GCodePosition, GCodeError> {
         Ok(Self {
-            x: if x.is_some() {
-                Some(Self::f64_to_fixed(x.unwrap())?)
-            } else {
-                None
-            },
-            y: if y.is_some() {
-                Some(Self::f64_to_fixed(y.unwrap())?)
-            } else {
-                None
-            },
-            z: if z.is_some() {
-                Some(Self::f64_to_fixed(z.unwrap())?)
-            } else {
-                None
-            },
+            x: x.map(Self::f64_to_fixed).transpose()?,
+            y: y.map(Self::f64_to_fixed).transpose()?,
+            z: z.map(Self::f64_to_fixed).transpose()?,
         })
     }
 
@@ -56,9 +52,14 @@ impl GCodePosition {
     }
 
     /// Converts a floating-point value to the fixed-point representation used
-    /// bt GCodePosition
+    /// bt GCodePosition, rounding to the nearest value.
+    ///
+    /// Breaking change from 0.1: values used to be truncated, at the binary
+    /// scale now kept as `BINARY_FIXED_SCALE`. Raw values stored by earlier
+    /// versions can be converted with `rescale_fixed()`.
     pub fn f64_to_fixed(val: f64) -> Result<i64, GCodeError> {
-        let val = val * (Self::FIXED_SCALE as f64);
+        /* Rounded, as e.g. 0.3 is slightly below its decimal value */
+        let val = (val * (Self::FIXED_SCALE as f64)).round();
         if (val > (i64::MAX as f64)) || (val < (i64::MIN as f64)) {
             Err(GCodeError::OutOfRangeError)
         } else {
@@ -89,6 +90,296 @@ impl GCodePosition {
             self.z.map(|val| (val as f64) / (Self::FIXED_SCALE as f64)),
         )
     }
+
+    /// Returns raw fixed-point X component
+    pub fn x_raw(&self) -> Option<i64> {
+        self.x
+    }
+
+    /// Returns raw fixed-point Y component
+    pub fn y_raw(&self) -> Option<i64> {
+        self.y
+    }
+
+    /// Returns raw fixed-point Z component
+    pub fn z_raw(&self) -> Option<i64> {
+        self.z
+    }
+
+    /// Returns a copy with the X component set to `x`
+    pub fn with_x(self, x: f64) -> Result<Self, GCodeError> {
+        Ok(Self {
+            x: Some(Self::f64_to_fixed(x)?),
+            ..self
+        })
+    }
+
+    /// Returns a copy with the Y component set to `y`
+    pub fn with_y(self, y: f64) -> Result<Self, GCodeError> {
+        Ok(Self {
+            y: Some(Self::f64_to_fixed(y)?),
+            ..self
+        })
+    }
+
+    /// Returns a copy with the Z component set to `z`
+    pub fn with_z(self, z: f64) -> Result<Self, GCodeError> {
+        Ok(Self {
+            z: Some(Self::f64_to_fixed(z)?),
+            ..self
+        })
+    }
+
+    /// Sets or clears the X component
+    pub fn set_x(&mut self, x: Option<f64>) -> Result<(), GCodeError> {
+        self.x = x.map(Self::f64_to_fixed).transpose()?;
+        Ok(())
+    }
+
+    /// Sets or clears the Y component
+    pub fn set_y(&mut self, y: Option<f64>) -> Result<(), GCodeError> {
+        self.y = y.map(Self::f64_to_fixed).transpose()?;
+        Ok(())
+    }
+
+    /// Sets or clears the Z component
+    pub fn set_z(&mut self, z: Option<f64>) -> Result<(), GCodeError> {
+        self.z = z.map(Self::f64_to_fixed).transpose()?;
+        Ok(())
+    }
+
+    /// Returns a copy with absent components filled in from `other`
+    pub fn merge(&self, other: &Self) -> Self {
+        Self {
+            x: self.x.or(other.x),
+            y: self.y.or(other.y),
+            z: self.z.or(other.z),
+        }
+    }
+
+    /// Whether all components are present
+    pub fn is_complete(&self) -> bool {
+        self.x.is_some() && self.y.is_some() && self.z.is_some()
+    }
+
+    /// Whether all components are absent
+    pub fn is_empty(&self) -> bool {
+        self.x.is_none() && self.y.is_none() && self.z.is_none()
+    }
+
+    /// Whether each component is within `tolerance` fixed-point units of that
+    /// of `other`. Components absent from one position must be absent from
+    /// the other.
+    pub fn approx_eq(&self, other: &Self, tolerance: i64) -> bool {
+        let close = |l: Option<i64>, r: Option<i64>| match (l, r) {
+            (Some(l), Some(r)) => l.abs_diff(r) <= tolerance.unsigned_abs(),
+            (None, None) => true,
+            _ => false,
+        };
+        close(self.x, other.x) && close(self.y, other.y) && close(self.z, other.z)
+    }
+
+    /// Converts a fixed-point value, as returned by e.g. `length()`, to
+    /// floating-point
+    pub fn fixed_to_f64(val: i64) -> f64 {
+        (val as f64) / (Self::FIXED_SCALE as f64)
+    }
+
+    /// Converts raw fixed-point value `val` from scale `from` to scale `to`,
+    /// e.g. from `BINARY_FIXED_SCALE` to `FIXED_SCALE`, rounding half away
+    /// from zero. Fails with `OutOfRangeError` for non-positive scales or
+    /// results out of range.
+    pub fn rescale_fixed(val: i64, from: i64, to: i64) -> Result<i64, GCodeError> {
+        if (from <= 0) || (to <= 0) {
+            return Err(GCodeError::OutOfRangeError);
+        }
+        let num = (val.unsigned_abs() as u128) * (to as u128);
+        let (from, sign) = (from as u128, val.signum() as i128);
+        let res = ((num + from / 2) / from) as i128 * sign;
+        i64::try_from(res).map_err(|_| GCodeError::OutOfRangeError)
+    }
+
+    /// Appends fixed-point value `val` to `out` in decimal, rounded half away
+    /// from zero to `precision` digits after `separator`, without a
+    /// floating-point round-trip or allocation. A minus sign is kept for
+    /// negative values rounding to zero, as when formatting an f64.
+    pub(crate) fn format_fixed(val: i64, precision: usize, separator: u8, out: &mut Vec<u8>) {
+        let scale = Self::FIXED_SCALE as u128;
+        let pow = 10u128.pow(precision as u32);
+        let num = (val.unsigned_abs() as u128) * pow;
+        let mut digits = num / scale;
+        if (num % scale) * 2 >= scale {
+            digits += 1;
+        }
+
+        if val < 0 {
+            out.push(b'-');
+        }
+        /* Digits are produced from the least significant one */
+        let mut buf = [0u8; 48];
+        let mut pos = buf.len();
+        let mut count = 0;
+        while (digits > 0) || (count <= precision) {
+            if (count == precision) && (precision > 0) {
+                pos -= 1;
+                buf[pos] = separator;
+            }
+            pos -= 1;
+            buf[pos] = b'0' + (digits % 10) as u8;
+            digits /= 10;
+            count += 1;
+        }
+        out.extend(&buf[pos..]);
+    }
+
+    /// Applies `op` to each axis present in both self and `other`, axes absent
+    /// from `other` are kept as-is
+    fn zip_with(self, other: Self, op: impl Fn(i64, i64) -> i64) -> Self {
+        let zip = |l: Option<i64>, r: Option<i64>| match (l, r) {
+            (Some(l), Some(r)) => Some(op(l, r)),
+            _ => l,
+        };
+        Self {
+            x: zip(self.x, other.x),
+            y: zip(self.y, other.y),
+            z: zip(self.z, other.z),
+        }
+    }
+
+    /// Writes fixed-point value `val` in its exact, shortest decimal form
+    fn display_fixed(val: i64, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = Self::FIXED_SCALE.ilog10() as usize;
+        let mut out = vec![];
+        Self::format_fixed(val, digits, b'.', &mut out);
+        if digits > 0 {
+            while out.last() == Some(&b'0') {
+                out.pop();
+            }
+            if out.last() == Some(&b'.') {
+                out.pop();
+            }
+        }
+        f.write_str(std::str::from_utf8(&out).map_err(|_| std::fmt::Error)?)
+    }
+
+    /// Converts a 128-bit intermediate result back to fixed-point
+    fn narrow(val: i128, op: &str) -> i64 {
+        match i64::try_from(val) {
+            Ok(val) => val,
+            Err(_) => panic!("Over/underflow during GCodePosition {}", op),
+        }
+    }
+
+    /// Length of the vector formed by the present axes, in fixed-point.
+    /// Rounded down to the nearest fixed-point unit.
+    pub fn length(&self) -> i64 {
+        let sum: u128 = [self.x, self.y, self.z]
+            .into_iter()
+            .flatten()
+            .map(|val| val.unsigned_abs() as u128 * val.unsigned_abs() as u128)
+            .sum();
+        Self::narrow(sum.isqrt() as i128, "length")
+    }
+
+    /// Distance to `other` along the axes present in both, in fixed-point.
+    /// Rounded down to the nearest fixed-point unit.
+    pub fn distance_to(&self, other: &Self) -> i64 {
+        let diff = |l: Option<i64>, r: Option<i64>| l.zip(r).map(|(l, r)| r - l);
+        Self {
+            x: diff(self.x, other.x),
+            y: diff(self.y, other.y),
+            z: diff(self.z, other.z),
+        }
+        .length()
+    }
+
+    /// Dot product over the axes present in both, in fixed-point. Truncated
+    /// towards zero.
+    pub fn dot(&self, other: &Self) -> i64 {
+        let sum: i128 = [(self.x, other.x), (self.y, other.y), (self.z, other.z)]
+            .into_iter()
+            .filter_map(|(l, r)| l.zip(r))
+            .map(|(l, r)| (l as i128) * (r as i128))
+            .sum();
+        Self::narrow(sum / (Self::FIXED_SCALE as i128), "dot product")
+    }
+
+    /// Cross product. Each axis of the result is present only if the two
+    /// other axes are present in both operands. Truncated towards zero.
+    pub fn cross(&self, other: &Self) -> Self {
+        let term =
+            |a: Option<i64>, b: Option<i64>, c: Option<i64>, d: Option<i64>| match (a, b, c, d) {
+                (Some(a), Some(b), Some(c), Some(d)) => Some(Self::narrow(
+                    ((a as i128) * (b as i128) - (c as i128) * (d as i128))
+                        / (Self::FIXED_SCALE as i128),
+                    "cross product",
+                )),
+                _ => None,
+            };
+        Self {
+            x: term(self.y, other.z, self.z, other.y),
+            y: term(self.z, other.x, self.x, other.z),
+            z: term(self.x, other.y, self.y, other.x),
+        }
+    }
+
+    /// Vector of the same direction with a length of 1. Truncated towards
+    /// zero, so the resulting length may be slightly under 1.
+    pub fn normalized(&self) -> Result<Self, GCodeError> {
+        let len = self.length() as i128;
+        if len == 0 {
+            return Err(GCodeError::GeometryError);
+        }
+        let norm = |val: i64| ((val as i128) * (Self::FIXED_SCALE as i128) / len) as i64;
+        Ok(Self {
+            x: self.x.map(norm),
+            y: self.y.map(norm),
+            z: self.z.map(norm),
+        })
+    }
+
+    /// Linear interpolation towards `other`, with `t` of 0 giving self and 1
+    /// giving `other`. Axes absent from `other` are kept as-is. `t` is
+    /// converted to fixed-point, and the result truncated towards zero.
+    pub fn lerp(&self, other: &Self, t: f64) -> Self {
+        let t = match Self::f64_to_fixed(t) {
+            Ok(t) => t as i128,
+            Err(_) => panic!("Over/underflow during GCodePosition interpolation"),
+        };
+        self.zip_with(*other, |l, r| {
+            let delta = ((r as i128) - (l as i128)) * t / (Self::FIXED_SCALE as i128);
+            Self::narrow((l as i128) + delta, "interpolation")
+        })
+    }
+
+    /// Component-wise minimum, axes absent from `other` are kept as-is
+    pub fn component_min(&self, other: &Self) -> Self {
+        self.zip_with(*other, i64::min)
+    }
+
+    /// Component-wise maximum, axes absent from `other` are kept as-is
+    pub fn component_max(&self, other: &Self) -> Self {
+        self.zip_with(*other, i64::max)
+    }
+
+    /// Component-wise absolute value
+    pub fn abs(&self) -> Self {
+        Self {
+            x: self.x.map(i64::abs),
+            y: self.y.map(i64::abs),
+            z: self.z.map(i64::abs),
+        }
+    }
+}
+impl Ord for GCodePosition {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.z, self.y, self.x).cmp(&(other.z, other.y, other.x))
+    }
+}
+impl PartialOrd for GCodePosition {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
 }
 impl std::ops::Add<Self> for GCodePosition {
     type Output = Self;
@@ -203,10 +494,8 @@ impl std::ops::DivAssign<f64> for GCodePosition {
 impl core::fmt::Display for GCodePosition {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         fn fmt_fixed(val: Option<i64>, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            /* Not the best, but for now just converting back to floating-point
-             * in order to display. */
             if let Some(val) = val {
-                write!(f, "{}", (val as f64) / (GCodePosition::FIXED_SCALE as f64))
+                GCodePosition::display_fixed(val, f)
             } else {
                 write!(f, "_")
             }
@@ -229,6 +518,66 @@ mod tests {
     use super::*;
 
     #[test]
+    fn format_fixed() {
+        let format = |val: i64, precision: usize| {
+            let mut out = vec![];
+            GCodePosition::format_fixed(val, precision, b'.', &mut out);
+            String::from_utf8(out).unwrap()
+        };
+
+        for val in (-200_000i64..200_000)
+            .step_by(7)
+            .chain([1 << 52, -(1 << 52) - 1])
+        {
+            for precision in [0, 3, 4] {
+                /* Exact ties differ, f64 formatting rounds these to even */
+                let step = 10i64.pow(6 - precision as u32);
+                if val.abs() % step == step / 2 {
+                    continue;
+                }
+                let expected = format!("{:.*}", precision, GCodePosition::fixed_to_f64(val));
+                assert_eq!(format(val, precision), expected);
+            }
+        }
+        /* Ties round away from zero */
+        assert_eq!(format(50, 4), "0.0001");
+        assert_eq!(format(-1_234_500, 3), "-1.235");
+        assert_eq!(format(2_500_000, 0), "3");
+        assert_eq!(format(100_000, 4), "0.1000");
+    }
+
+    #[test]
+    fn fixed_decimal() -> Result<(), GCodeError> {
+        /* Decimal values are exact */
+        assert_eq!(GCodePosition::f64_to_fixed(0.1)?, 100_000);
+        assert_eq!(GCodePosition::f64_to_fixed(0.3)?, 300_000);
+        assert_eq!(GCodePosition::f64_to_fixed(-0.000_001)?, -1);
+
+        let pos = GCodePosition::from_f64(Some(0.1), Some(-12.345), None)?;
+        assert_eq!(format!("{}", pos), "(0.1,-12.345,_)");
+
+        /* Conversion from the binary scale */
+        let rescale = |val| {
+            GCodePosition::rescale_fixed(
+                val,
+                GCodePosition::BINARY_FIXED_SCALE,
+                GCodePosition::FIXED_SCALE,
+            )
+        };
+        assert_eq!(rescale(1 << 16)?, 1_000_000);
+        assert_eq!(rescale(-(1 << 15))?, -500_000);
+        assert_eq!(rescale(1)?, 15);
+        assert_eq!(rescale(i64::MAX), Err(GCodeError::OutOfRangeError));
+        assert_eq!(
+            GCodePosition::rescale_fixed(1, 0, 1),
+            Err(GCodeError::OutOfRangeError)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::identity_op)]
     fn position_conv() -> Result<(), GCodeError> {
         /* from_f64 */
         let pos = GCodePosition::from_f64_full(1.0, 2.0, 3.0)?;
@@ -343,4 +692,114 @@ mod tests {
         assert_eq!(pos, GCodePosition::from_f64(None, Some(12.0), Some(-14.0))?);
         Ok(())
     }
+
+    #[test]
+    fn position_vector() -> Result<(), GCodeError> {
+        let a = GCodePosition::from_f64_full(3.0, 4.0, 0.0)?;
+        let b = GCodePosition::from_f64_full(0.0, 4.0, 12.0)?;
+
+        assert_eq!(GCodePosition::fixed_to_f64(a.length()), 5.0);
+        assert_eq!(
+            a.distance_to(&b),
+            (153f64.sqrt() * (GCodePosition::FIXED_SCALE as f64)) as i64
+        );
+        assert_eq!(GCodePosition::fixed_to_f64(a.dot(&b)), 16.0);
+        assert_eq!(
+            a.cross(&b),
+            GCodePosition::from_f64_full(48.0, -36.0, 12.0)?
+        );
+        assert_eq!(
+            a.normalized()?,
+            GCodePosition::from_f64_full(0.6, 0.8, 0.0)?
+        );
+        assert_eq!(
+            a.lerp(&b, 0.25),
+            GCodePosition::from_f64_full(2.25, 4.0, 3.0)?
+        );
+        assert_eq!(
+            a.component_min(&b),
+            GCodePosition::from_f64_full(0.0, 4.0, 0.0)?
+        );
+        assert_eq!(
+            (a * -1.0).component_max(&(b * -1.0)).abs(),
+            GCodePosition::from_f64_full(0.0, 4.0, 0.0)?
+        );
+
+        /* Partial positions */
+        let p = GCodePosition::from_f64(Some(3.0), None, Some(4.0))?;
+        assert_eq!(GCodePosition::fixed_to_f64(p.length()), 5.0);
+        assert_eq!(GCodePosition::fixed_to_f64(p.dot(&a)), 9.0);
+        assert_eq!(
+            p.cross(&b),
+            GCodePosition::from_f64(None, Some(-36.0), None)?
+        );
+        assert_eq!(
+            GCodePosition::from_f64(None, None, None)?.normalized(),
+            Err(GCodeError::GeometryError)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn position_axes() -> Result<(), GCodeError> {
+        let mut pos = GCodePosition::from_f64(Some(1.0), None, None)?;
+        assert_eq!(pos.x_raw(), Some(GCodePosition::FIXED_SCALE));
+        assert_eq!(pos.y_raw(), None);
+        assert!(!pos.is_complete() && !pos.is_empty());
+
+        pos = pos.with_y(2.0)?;
+        pos.set_z(Some(-3.0))?;
+        assert_eq!(pos, GCodePosition::from_f64_full(1.0, 2.0, -3.0)?);
+        assert!(pos.is_complete());
+
+        pos.set_x(None)?;
+        pos.set_y(None)?;
+        assert_eq!(pos.z_raw(), Some(-3 * GCodePosition::FIXED_SCALE));
+        assert_eq!(pos.set_z(Some(f64::MAX)), Err(GCodeError::OutOfRangeError));
+        pos.set_z(None)?;
+        assert!(pos.is_empty());
+
+        let pos = GCodePosition::from_f64(None, Some(5.0), None)?;
+        assert_eq!(
+            pos.merge(&GCodePosition::from_f64_full(1.0, 2.0, 3.0)?),
+            GCodePosition::from_f64_full(1.0, 5.0, 3.0)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn position_compare() -> Result<(), GCodeError> {
+        let mut positions = vec![
+            GCodePosition::from_f64_full(2.0, 1.0, 0.0)?,
+            GCodePosition::from_f64_full(1.0, 2.0, 0.0)?,
+            GCodePosition::from_f64_full(1.0, 1.0, 0.0)?,
+            GCodePosition::from_f64_full(0.0, 0.0, -1.0)?,
+            GCodePosition::from_f64(Some(5.0), None, Some(0.0))?,
+            GCodePosition::from_f64_full(1.0, 1.0, 0.0)?,
+        ];
+        positions.sort();
+        positions.dedup();
+        assert_eq!(
+            positions,
+            [
+                GCodePosition::from_f64_full(0.0, 0.0, -1.0)?,
+                GCodePosition::from_f64(Some(5.0), None, Some(0.0))?,
+                GCodePosition::from_f64_full(1.0, 1.0, 0.0)?,
+                GCodePosition::from_f64_full(2.0, 1.0, 0.0)?,
+                GCodePosition::from_f64_full(1.0, 2.0, 0.0)?,
+            ]
+        );
+
+        let set: std::collections::HashSet<GCodePosition> = positions.iter().copied().collect();
+        assert!(set.contains(&GCodePosition::from_f64_full(1.0, 1.0, 0.0)?));
+
+        let pos = GCodePosition::from_raw(Some(100), None, Some(-100));
+        assert!(pos.approx_eq(&GCodePosition::from_raw(Some(102), None, Some(-98)), 2));
+        assert!(!pos.approx_eq(&GCodePosition::from_raw(Some(103), None, Some(-100)), 2));
+        assert!(!pos.approx_eq(&GCodePosition::from_raw(Some(100), Some(0), Some(-100)), 2));
+
+        Ok(())
+    }
 }
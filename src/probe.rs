@@ -0,0 +1,132 @@
+use crate::{GCodeError, GCodeOffset, GCodePosition};
+
+/// Result of a probing cycle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeProbeResult {
+    /// Position at which the probe triggered
+    pub position: GCodePosition,
+    /// Whether the probe made contact
+    pub success: bool,
+}
+impl GCodeProbeResult {
+    pub fn new(position: GCodePosition, success: bool) -> Self {
+        Self { position, success }
+    }
+
+    /// Parses a GRBL probe report, e.g. `[PRB:1.000,2.000,-3.000:1]`
+    pub fn from_grbl_report(report: &str) -> Result<Self, GCodeError> {
+        let body = report
+            .trim()
+            .strip_prefix("[PRB:")
+            .and_then(|s| s.strip_suffix(']'))
+            .ok_or(GCodeError::ParseError)?;
+        let (coords, success) = body.rsplit_once(':').ok_or(GCodeError::ParseError)?;
+
+        let success = match success {
+            "1" => true,
+            "0" => false,
+            _ => return Err(GCodeError::ParseError),
+        };
+
+        let mut values = [0.0; 3];
+        let mut count = 0;
+        for coord in coords.split(',') {
+            /* Machines with more than 3 axes report them as well, only XYZ
+             * are of interest here. */
+            if count < values.len() {
+                values[count] = coord.parse().map_err(|_| GCodeError::ParseError)?;
+            }
+            count += 1;
+        }
+        if count < values.len() {
+            return Err(GCodeError::ParseError);
+        }
+
+        Ok(Self {
+            position: GCodePosition::from_f64_full(values[0], values[1], values[2])?,
+            success,
+        })
+    }
+
+    /// Computes the value to be given to `G10 L20` so that the feature found by
+    /// this probe becomes the origin of a work coordinate system.
+    ///
+    /// `current` is the current position of the machine, in the same
+    /// coordinate system as the probe result. `feature_offset` is the offset
+    /// from the probe trigger position to the feature (e.g. the probe tip
+    /// radius when probing an edge), only the axes present within it are
+    /// included in the result.
+    pub fn wcs_value(
+        &self,
+        current: GCodePosition,
+        feature_offset: GCodeOffset,
+    ) -> Result<GCodePosition, GCodeError> {
+        if !self.success {
+            return Err(GCodeError::ProbeError);
+        }
+
+        let value = |cur: Option<f64>, probe: Option<f64>, offset: Option<f64>| match (
+            cur, probe, offset,
+        ) {
+            (Some(cur), Some(probe), Some(offset)) => Ok(Some(cur - (probe + offset))),
+            (_, _, None) => Ok(None),
+            /* Axis requested, but the position along it is not known */
+            _ => Err(GCodeError::ProbeError),
+        };
+
+        let (cx, cy, cz) = current.as_f64();
+        let (px, py, pz) = self.position.as_f64();
+        let (ox, oy, oz) = feature_offset.as_f64();
+
+        GCodePosition::from_f64(value(cx, px, ox)?, value(cy, py, oy)?, value(cz, pz, oz)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_grbl_report() -> Result<(), GCodeError> {
+        assert_eq!(
+            GCodeProbeResult::from_grbl_report("[PRB:1.500,-2.000,-10.250:1]\r\n")?,
+            GCodeProbeResult::new(GCodePosition::from_f64_full(1.5, -2.0, -10.25)?, true)
+        );
+        assert_eq!(
+            GCodeProbeResult::from_grbl_report("[PRB:0.000,0.000,0.000,90.000:0]")?,
+            GCodeProbeResult::new(GCodePosition::from_f64_full(0.0, 0.0, 0.0)?, false)
+        );
+        assert_eq!(
+            GCodeProbeResult::from_grbl_report("[PRB:1.000,2.000:1]"),
+            Err(GCodeError::ParseError)
+        );
+        assert_eq!(
+            GCodeProbeResult::from_grbl_report("[MSG:Probe]"),
+            Err(GCodeError::ParseError)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn probe_wcs_value() -> Result<(), GCodeError> {
+        /* Probed the left edge of a part moving in +X with a 2mm diameter
+         * probe, and retracted 5mm afterwards */
+        let probe =
+            GCodeProbeResult::new(GCodePosition::from_f64_full(-120.0, -80.0, -30.0)?, true);
+        let current = GCodePosition::from_f64_full(-125.0, -80.0, -30.0)?;
+        let offset = GCodeOffset::from_f64(Some(1.0), None, None)?;
+        assert_eq!(
+            probe.wcs_value(current, offset)?,
+            GCodePosition::from_f64(Some(-6.0), None, None)?
+        );
+
+        let probe = GCodeProbeResult::new(probe.position, false);
+        assert_eq!(
+            probe.wcs_value(current, offset),
+            Err(GCodeError::ProbeError)
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{GCodeError, GCodePosition};
+
+/// Options of raster engraving
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeRasterOptions {
+    /// Width of each pixel along the row
+    pub pixel_size: f64,
+    /// Laser power of full intensity pixels
+    pub max_power: f64,
+}
+
+/// Converts a row of pixel intensities, from 0 (blank) to 1 (full power),
+/// into laser moves along X at Y of `start`, as (end, power) pairs for
+/// `GCodeWriter::laser_to()`. Runs of pixels with equal intensity become a
+/// single move.
+///
+/// `start` is the left edge of the first pixel. Rows are engraved left to
+/// right, or right to left if `reverse`, e.g. for alternate rows of an
+/// image. The first pair is the start of the row at zero power, for the
+/// head to reach prior to engraving.
+///
+/// Fails with `OutOfRangeError` unless pixel size and maximum power are
+/// positive, `start` is finite and intensities are within 0 to 1.
+pub fn raster_row(
+    start: [f64; 2],
+    intensities: &[f64],
+    reverse: bool,
+    options: &GCodeRasterOptions,
+) -> Result<Vec<(GCodePosition, f64)>, GCodeError> {
+    let valid = |val: f64| val.is_finite() && (val > 0.0);
+    if !valid(options.pixel_size) || !valid(options.max_power) {
+        return Err(GCodeError::OutOfRangeError);
+    }
+    if !start.iter().all(|val| val.is_finite()) {
+        return Err(GCodeError::OutOfRangeError);
+    }
+    if !intensities.iter().all(|val| (0.0..=1.0).contains(val)) {
+        return Err(GCodeError::OutOfRangeError);
+    }
+
+    let edge = |idx: usize| {
+        GCodePosition::from_f64(
+            Some(start[0] + (idx as f64) * options.pixel_size),
+            Some(start[1]),
+            None,
+        )
+    };
+    let power = |val: f64| (val * options.max_power).round();
+
+    let mut moves = vec![];
+    let count = intensities.len();
+    let order: Vec<usize> = if reverse {
+        (0..count).rev().collect()
+    } else {
+        (0..count).collect()
+    };
+    moves.push((edge(if reverse { count } else { 0 })?, 0.0));
+
+    let mut pixels = order.iter().peekable();
+    while let Some(&idx) = pixels.next() {
+        let level = power(intensities[idx]);
+        let mut last = idx;
+        while let Some(&&next) = pixels.peek() {
+            if power(intensities[next]) != level {
+                break;
+            }
+            last = next;
+            pixels.next();
+        }
+        /* Far edge of the run, in the direction of travel */
+        let end = if reverse { last } else { last + 1 };
+        moves.push((edge(end)?, level));
+    }
+
+    Ok(moves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raster() -> Result<(), GCodeError> {
+        let options = GCodeRasterOptions {
+            pixel_size: 0.5,
+            max_power: 1000.0,
+        };
+        let row = [0.0, 0.5, 0.5, 1.0, 0.0];
+        let pos = |x| GCodePosition::from_f64(Some(x), Some(2.0), None);
+
+        assert_eq!(
+            raster_row([10.0, 2.0], &row, false, &options)?,
+            [
+                (pos(10.0)?, 0.0),
+                (pos(10.5)?, 0.0),
+                (pos(11.5)?, 500.0),
+                (pos(12.0)?, 1000.0),
+                (pos(12.5)?, 0.0),
+            ]
+        );
+        assert_eq!(
+            raster_row([10.0, 2.0], &row, true, &options)?,
+            [
+                (pos(12.5)?, 0.0),
+                (pos(12.0)?, 0.0),
+                (pos(11.5)?, 1000.0),
+                (pos(10.5)?, 500.0),
+                (pos(10.0)?, 0.0),
+            ]
+        );
+        assert_eq!(
+            raster_row([0.0, 0.0], &[1.5], false, &options),
+            Err(GCodeError::OutOfRangeError)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn raster_edge_cases() -> Result<(), GCodeError> {
+        let options = GCodeRasterOptions {
+            pixel_size: 0.5,
+            max_power: 1000.0,
+        };
+        let pos = |x| GCodePosition::from_f64(Some(x), Some(2.0), None);
+
+        /* Empty rows only move to their start */
+        assert_eq!(
+            raster_row([10.0, 2.0], &[], false, &options)?,
+            [(pos(10.0)?, 0.0)]
+        );
+        assert_eq!(
+            raster_row([10.0, 2.0], &[], true, &options)?,
+            [(pos(10.0)?, 0.0)]
+        );
+        /* Intensities rounding to the same power form a single run */
+        assert_eq!(
+            raster_row([10.0, 2.0], &[0.5, 0.5001, 1.0], true, &options)?,
+            [(pos(11.5)?, 0.0), (pos(11.0)?, 1000.0), (pos(10.0)?, 500.0)]
+        );
+
+        for bad in [
+            GCodeRasterOptions {
+                pixel_size: 0.0,
+                ..options
+            },
+            GCodeRasterOptions {
+                pixel_size: f64::INFINITY,
+                ..options
+            },
+            GCodeRasterOptions {
+                max_power: -1.0,
+                ..options
+            },
+            GCodeRasterOptions {
+                max_power: f64::NAN,
+                ..options
+            },
+        ] {
+            assert_eq!(
+                raster_row([0.0, 0.0], &[1.0], false, &bad),
+                Err(GCodeError::OutOfRangeError)
+            );
+        }
+        for row in [[-0.1], [f64::NAN]] {
+            assert_eq!(
+                raster_row([0.0, 0.0], &row, false, &options),
+                Err(GCodeError::OutOfRangeError)
+            );
+        }
+        assert_eq!(
+            raster_row([f64::NAN, 0.0], &[1.0], false, &options),
+            Err(GCodeError::OutOfRangeError)
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,3 @@
+//! Visualization of toolpaths
+
+pub mod svg;
@@ -0,0 +1,210 @@
+use std::io::Write;
+
+use crate::mesh::mesh_runs;
+use crate::{GCodeBackplot, GCodeError, GCodeMoveKind};
+
+/// Options of SVG export
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeSvgOptions {
+    /// SVG units per toolpath unit
+    pub scale: f64,
+    /// Empty space around the toolpath, in SVG units
+    pub margin: f64,
+    /// Width of drawn lines, in SVG units
+    pub stroke_width: f64,
+    /// Whether to draw rapid moves
+    pub rapids: bool,
+}
+impl Default for GCodeSvgOptions {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            margin: 5.0,
+            stroke_width: 0.5,
+            rapids: true,
+        }
+    }
+}
+
+/// Writes a top view of a backplot as SVG, fitted to the extents of the
+/// toolpath. Colors follow `export_mesh()`: cuts are colored by depth from
+/// blue at the lowest Z to red at the highest, plunges are yellow and rapids
+/// grey and dashed. Deeper cuts are drawn on top. Fails with
+/// `OutOfRangeError` unless scale and stroke width are positive and the
+/// margin is not negative.
+pub fn export_svg(
+    backplot: &GCodeBackplot,
+    options: &GCodeSvgOptions,
+    mut writer: impl Write,
+) -> Result<(), GCodeError> {
+    let positive = |val: f64| val.is_finite() && (val > 0.0);
+    let margin = options.margin.is_finite() && (options.margin >= 0.0);
+    if !positive(options.scale) || !positive(options.stroke_width) || !margin {
+        return Err(GCodeError::OutOfRangeError);
+    }
+    let (min, max) = backplot.bounds().unwrap_or_default();
+    let width = (max[0] - min[0]) * options.scale + 2.0 * options.margin;
+    let height = (max[1] - min[1]) * options.scale + 2.0 * options.margin;
+    /* SVG Y axis points down */
+    let map = |point: [f64; 3]| {
+        (
+            (point[0] - min[0]) * options.scale + options.margin,
+            (max[1] - point[1]) * options.scale + options.margin,
+        )
+    };
+
+    writeln!(
+        writer,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w:.3}\" height=\"{h:.3}\" viewBox=\"0 0 {w:.3} {h:.3}\">",
+        w = width,
+        h = height
+    )?;
+
+    let mut groups = mesh_runs(backplot);
+    /* Highest Z first, such that deeper cuts are drawn on top */
+    groups.sort_by(|a, b| {
+        let order = |kind| match kind {
+            GCodeMoveKind::Rapid => 0,
+            GCodeMoveKind::Plunge => 1,
+            GCodeMoveKind::Cut => 2,
+        };
+        order(a.kind)
+            .cmp(&order(b.kind))
+            .then(b.z.unwrap_or(0.0).total_cmp(&a.z.unwrap_or(0.0)))
+    });
+    for group in groups {
+        if (group.kind == GCodeMoveKind::Rapid) && !options.rapids {
+            continue;
+        }
+
+        let [r, g, b] = group
+            .color
+            .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        write!(
+            writer,
+            "<path fill=\"none\" stroke=\"#{:02x}{:02x}{:02x}\" stroke-width=\"{:.3}\"",
+            r, g, b, options.stroke_width
+        )?;
+        if group.kind == GCodeMoveKind::Rapid {
+            write!(
+                writer,
+                " stroke-dasharray=\"{:.3}\"",
+                options.stroke_width * 4.0
+            )?;
+        }
+        write!(writer, " d=\"")?;
+
+        for (i, run) in group.runs.iter().enumerate() {
+            let (x, y) = map(run[0]);
+            let sep = if i > 0 { " " } else { "" };
+            write!(writer, "{}M{:.3} {:.3}", sep, x, y)?;
+            for &point in &run[1..] {
+                let (x, y) = map(point);
+                write!(writer, " L{:.3} {:.3}", x, y)?;
+            }
+        }
+        writeln!(writer, "\"/>")?;
+    }
+
+    writeln!(writer, "</svg>")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GCodeBackplotBuilder, GCodePosition};
+
+    #[test]
+    fn svg() -> Result<(), GCodeError> {
+        let mut builder = GCodeBackplotBuilder::new();
+        builder
+            .start_at(GCodePosition::from_f64_full(0.0, 0.0, 1.0)?)
+            .move_to(1, GCodePosition::from_f64(None, None, Some(-1.0))?, false)
+            .move_to(2, GCodePosition::from_f64(Some(10.0), None, None)?, false)
+            .move_to(3, GCodePosition::from_f64(None, Some(5.0), None)?, false)
+            .move_to(4, GCodePosition::from_f64(None, None, Some(1.0))?, true);
+        let backplot = builder.build();
+
+        let options = GCodeSvgOptions {
+            scale: 2.0,
+            margin: 1.0,
+            ..Default::default()
+        };
+        let mut data = vec![];
+        export_svg(&backplot, &options, &mut data)?;
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"22.000\" height=\"12.000\" viewBox=\"0 0 22.000 12.000\">\n\
+             <path fill=\"none\" stroke=\"#808080\" stroke-width=\"0.500\" stroke-dasharray=\"2.000\" d=\"M21.000 1.000 L21.000 1.000\"/>\n\
+             <path fill=\"none\" stroke=\"#ffcc00\" stroke-width=\"0.500\" d=\"M1.000 11.000 L1.000 11.000\"/>\n\
+             <path fill=\"none\" stroke=\"#ff0000\" stroke-width=\"0.500\" d=\"M1.000 11.000 L21.000 11.000 L21.000 1.000\"/>\n\
+             </svg>\n"
+        );
+
+        let options = GCodeSvgOptions {
+            rapids: false,
+            ..options
+        };
+        let mut data = vec![];
+        export_svg(&backplot, &options, &mut data)?;
+        assert!(!String::from_utf8_lossy(&data).contains("dasharray"));
+
+        /* Coordinates are not narrowed to f32, which would lose the
+         * fraction here */
+        let mut builder = GCodeBackplotBuilder::new();
+        builder
+            .start_at(GCodePosition::from_f64_full(1000000.0, 0.0, -1.0)?)
+            .move_to(
+                1,
+                GCodePosition::from_f64(Some(1000000.1), None, None)?,
+                false,
+            );
+        let mut data = vec![];
+        export_svg(&builder.build(), &options, &mut data)?;
+        assert!(String::from_utf8_lossy(&data).contains("d=\"M1.000 1.000 L1.200 1.000\""));
+
+        Ok(())
+    }
+
+    #[test]
+    fn svg_edge_cases() -> Result<(), GCodeError> {
+        /* Empty backplots give an empty drawing of the margins */
+        let backplot = GCodeBackplotBuilder::new().build();
+        let mut data = vec![];
+        export_svg(&backplot, &GCodeSvgOptions::default(), &mut data)?;
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"10.000\" height=\"10.000\" viewBox=\"0 0 10.000 10.000\">\n\
+             </svg>\n"
+        );
+
+        for options in [
+            GCodeSvgOptions {
+                scale: 0.0,
+                ..Default::default()
+            },
+            GCodeSvgOptions {
+                scale: f64::NAN,
+                ..Default::default()
+            },
+            GCodeSvgOptions {
+                margin: -1.0,
+                ..Default::default()
+            },
+            GCodeSvgOptions {
+                stroke_width: f64::INFINITY,
+                ..Default::default()
+            },
+        ] {
+            let mut data = vec![];
+            assert_eq!(
+                export_svg(&backplot, &options, &mut data),
+                Err(GCodeError::OutOfRangeError)
+            );
+            assert!(data.is_empty());
+        }
+
+        Ok(())
+    }
+}
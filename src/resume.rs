@@ -0,0 +1,366 @@
+use std::fmt::Write;
+
+use crate::interp::{code_of, tool_of, Block, Interpreter, ModalGroup, ModalState, Motion};
+use crate::minify::format_number;
+use crate::GCodeError;
+
+/// Options of resuming a program
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeResumeOptions {
+    /// Z height, in work coordinates, at which to travel to the resume
+    /// position
+    pub safe_z: f64,
+    /// Feed rate of the move down from `safe_z`, the feed rate in effect at
+    /// the resume line if None
+    pub plunge_feed: Option<f64>,
+}
+
+/// State of a program beyond that tracked by `Interpreter`, None where
+/// never set
+#[derive(Clone, Default)]
+struct ResumeState {
+    mist: bool,
+    flood: bool,
+    /// Hotend temperatures, as (T word, temperature)
+    hotends: Vec<(Option<u32>, f64)>,
+    bed: Option<f64>,
+    /// Fan speeds, as (P word, speed)
+    fans: Vec<(u32, f64)>,
+    extrude_relative: bool,
+    extruder: Option<f64>,
+    /// H word of the active tool length offset (G43)
+    length_offset: Option<f64>,
+    /// D word of the active cutter compensation (G41/G42)
+    compensation: Option<f64>,
+    /// Whether X or Y became unknown by a move in other than work
+    /// coordinates, e.g. G28 or G53
+    position_lost: bool,
+}
+
+/// Rewrites a program to resume at `line`, starting at 1, e.g. after a power
+/// failure. The modal state in effect at that line is reconstructed from the
+/// preceding lines, and restored by a preamble before the remaining lines:
+/// units, plane, work coordinate system, tool, tool length offset, cutter
+/// compensation, temperatures (waiting for them to be reached), fans,
+/// spindle, coolant, then a rapid to `safe_z` above the resume position and
+/// a feed move down to it, and finally the feed rate, positioning mode and
+/// motion mode.
+///
+/// Returns `OutOfRangeError` if `line` is beyond the end of the program, and
+/// `UnsupportedError` if the state can not be determined, i.e. if preceding
+/// lines use parameters, expressions or control flow, set coordinates via
+/// G92, or leave X or Y unknown by moving other than in work coordinates,
+/// e.g. by G28 or G53.
+pub fn resume_from(
+    program: &str,
+    line: usize,
+    options: &GCodeResumeOptions,
+) -> Result<String, GCodeError> {
+    let lines: Vec<&str> = program.lines().collect();
+    if (line == 0) || (line > lines.len() + 1) || !options.safe_z.is_finite() {
+        return Err(GCodeError::OutOfRangeError);
+    }
+
+    let mut interp = Interpreter::new();
+    let mut state = ResumeState::default();
+    for text in &lines[..(line - 1)] {
+        let block = interp.line(text).ok_or(GCodeError::UnsupportedError)?;
+        apply(&mut state, &block)?;
+        let [x, y, _] = interp.state.position;
+        state.position_lost = match block.motion {
+            Motion::Machine { .. } | Motion::Other => x.is_none() || y.is_none(),
+            _ => state.position_lost && (x.is_none() || y.is_none()),
+        };
+    }
+    if state.position_lost {
+        return Err(GCodeError::UnsupportedError);
+    }
+
+    let mut out = preamble(&state, &interp.state, options);
+    for text in &lines[(line - 1)..] {
+        out.push_str(text);
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+/// Updates the state by the words of a single line
+fn apply(state: &mut ResumeState, block: &Block) -> Result<(), GCodeError> {
+    for &(letter, val) in &block.words {
+        match (letter, code_of(val)) {
+            ('G', 900) | ('M', 820) => state.extrude_relative = false,
+            ('G', 910) | ('M', 830) => state.extrude_relative = true,
+            ('M', 70) => state.mist = true,
+            ('M', 80) => state.flood = true,
+            ('M', 90) => (state.mist, state.flood) = (false, false),
+            ('M', 1040 | 1090) => {
+                if let Some(temp) = block.word('S') {
+                    let tool = match block.word('T') {
+                        Some(t) => Some(tool_of(t).ok_or(GCodeError::UnsupportedError)?),
+                        None => None,
+                    };
+                    state.hotends.retain(|(t, _)| *t != tool);
+                    state.hotends.push((tool, temp));
+                }
+            }
+            ('G', 430) => state.length_offset = block.word('H'),
+            ('G', 490) => state.length_offset = None,
+            ('G', 410 | 420) => state.compensation = block.word('D'),
+            ('G', 400) => state.compensation = None,
+            ('M', 1400 | 1900) => state.bed = block.word('S').or(state.bed),
+            ('M', code @ (1060 | 1070)) => {
+                let fan = block.word('P').unwrap_or(0.0) as u32;
+                let speed = match code {
+                    1060 => block.word('S').unwrap_or(255.0),
+                    _ => 0.0,
+                };
+                state.fans.retain(|(f, _)| *f != fan);
+                state.fans.push((fan, speed));
+            }
+            _ => (),
+        }
+    }
+
+    let extrude = block.word('E');
+    if block.has('G', 920) {
+        /* G92 E only resets the extruder */
+        if block.motion == Motion::Other {
+            return Err(GCodeError::UnsupportedError);
+        }
+        state.extruder = extrude.or(state.extruder);
+    } else if let Some(val) = extrude {
+        state.extruder = match (state.extrude_relative, state.extruder) {
+            (true, Some(cur)) => Some(cur + val),
+            _ => Some(val),
+        };
+    }
+
+    Ok(())
+}
+
+/// Commands restoring `state` and `modal`, ending with a line break
+fn preamble(state: &ResumeState, modal: &ModalState, options: &GCodeResumeOptions) -> String {
+    /* Writes to a String can not fail */
+    let mut out = String::new();
+    let num = format_number;
+
+    let mut modes = vec![];
+    modes.extend(modal.group(ModalGroup::Units));
+    modes.push(900);
+    modes.extend(modal.group(ModalGroup::Plane));
+    modes.extend(modal.group(ModalGroup::Wcs));
+    let modes: Vec<String> = modes.iter().map(|code| g_code(*code)).collect();
+    let _ = writeln!(out, "{}", modes.join(" "));
+
+    if let Some(tool) = modal.tool {
+        let _ = writeln!(out, "T{} M06", tool);
+    }
+    /* Offsets apply to the tool loaded, and to all moves following */
+    let offset = |letter, code, val: Option<f64>| match val {
+        Some(val) => format!("{} {}{}", g_code(code), letter, num(val)),
+        None => g_code(code),
+    };
+    if let Some(code) = modal.group(ModalGroup::LengthOffset) {
+        let _ = writeln!(out, "{}", offset('H', code, state.length_offset));
+    }
+    if let Some(code) = modal.group(ModalGroup::Compensation) {
+        let _ = writeln!(out, "{}", offset('D', code, state.compensation));
+    }
+
+    let hotend = |code, tool: Option<u32>, temp| match tool {
+        Some(tool) => format!("{} T{} S{}", code, tool, num(temp)),
+        None => format!("{} S{}", code, num(temp)),
+    };
+    if let Some(temp) = state.bed {
+        let _ = writeln!(out, "M140 S{}", num(temp));
+    }
+    for &(tool, temp) in &state.hotends {
+        let _ = writeln!(out, "{}", hotend("M104", tool, temp));
+    }
+    if let Some(temp) = state.bed.filter(|temp| *temp > 0.0) {
+        let _ = writeln!(out, "M190 S{}", num(temp));
+    }
+    for &(tool, temp) in state.hotends.iter().filter(|(_, temp)| *temp > 0.0) {
+        let _ = writeln!(out, "{}", hotend("M109", tool, temp));
+    }
+    for &(fan, speed) in &state.fans {
+        let _ = match fan {
+            0 => writeln!(out, "M106 S{}", num(speed)),
+            _ => writeln!(out, "M106 P{} S{}", fan, num(speed)),
+        };
+    }
+
+    if let Some(spindle) = modal.spindle {
+        let _ = match modal.speed {
+            Some(speed) => writeln!(out, "S{} M{:02}", num(speed), spindle),
+            None => writeln!(out, "M{:02}", spindle),
+        };
+    }
+    if state.mist {
+        let _ = writeln!(out, "M07");
+    }
+    if state.flood {
+        let _ = writeln!(out, "M08");
+    }
+
+    let [x, y, z] = modal.position;
+    let _ = writeln!(out, "G00 Z{}", num(options.safe_z));
+    let xy: Vec<String> = [('X', x), ('Y', y)]
+        .iter()
+        .filter_map(|(axis, val)| val.map(|val| format!("{}{}", axis, num(val))))
+        .collect();
+    if !xy.is_empty() {
+        let _ = writeln!(out, "G00 {}", xy.join(" "));
+    }
+    let plunge = options.plunge_feed.or(modal.feed);
+    if let Some(z) = z {
+        let _ = match plunge {
+            Some(feed) => writeln!(out, "G01 Z{} F{}", num(z), num(feed)),
+            None => writeln!(out, "G01 Z{}", num(z)),
+        };
+    }
+
+    if let Some(code) = modal.group(ModalGroup::FeedMode) {
+        let _ = writeln!(out, "{}", g_code(code));
+    }
+    if let Some(feed) = modal
+        .feed
+        .filter(|feed| (Some(*feed) != plunge) || z.is_none())
+    {
+        let _ = writeln!(out, "F{}", num(feed));
+    }
+    if let Some(val) = state.extruder {
+        let _ = writeln!(out, "G92 E{}", num(val));
+    }
+    if state.extrude_relative && !modal.is_incremental() {
+        let _ = writeln!(out, "M83");
+    }
+    if modal.is_incremental() {
+        let _ = writeln!(out, "G91");
+    }
+    if let Some(motion) = modal.motion {
+        let _ = writeln!(out, "G{:02}", motion);
+    }
+
+    out
+}
+
+/// G code from its number in tenths, e.g. "G59.1" for 591
+fn g_code(code: i64) -> String {
+    match code % 10 {
+        0 => format!("G{:02}", code / 10),
+        frac => format!("G{:02}.{}", code / 10, frac),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume() -> Result<(), GCodeError> {
+        let program = "\
+            G21 G90 G17\n\
+            G55\n\
+            T2 M06\n\
+            G43 H2\n\
+            M140 S60\n\
+            M104 S210\n\
+            M106 S128\n\
+            S10000 M03\n\
+            M08\n\
+            G00 X5 Y5 Z5\n\
+            G01 Z-1 F100\n\
+            G41 D2\n\
+            G01 X10 F600\n\
+            G02 X20 Y5 I5 J0\n\
+            G01 Y10\n\
+            M05\n";
+        let options = GCodeResumeOptions {
+            safe_z: 10.0,
+            plunge_feed: Some(100.0),
+        };
+
+        assert_eq!(
+            resume_from(program, 15, &options)?,
+            "G21 G90 G17 G55\n\
+             T2 M06\n\
+             G43 H2\n\
+             G41 D2\n\
+             M140 S60\n\
+             M104 S210\n\
+             M190 S60\n\
+             M109 S210\n\
+             M106 S128\n\
+             S10000 M03\n\
+             M08\n\
+             G00 Z10\n\
+             G00 X20 Y5\n\
+             G01 Z-1 F100\n\
+             F600\n\
+             G02\n\
+             G01 Y10\n\
+             M05\n"
+        );
+
+        assert_eq!(
+            resume_from(program, 18, &options),
+            Err(GCodeError::OutOfRangeError)
+        );
+        assert_eq!(
+            resume_from("G92 X0\nG01 X1\n", 2, &options),
+            Err(GCodeError::UnsupportedError)
+        );
+        /* X and Y must be known to travel to the resume position */
+        for (program, line) in [("G00 X1 Y1\nG28\nG01 X2\n", 3), ("G53 G00 X0\nG00 Z1\n", 2)] {
+            assert_eq!(
+                resume_from(program, line, &options),
+                Err(GCodeError::UnsupportedError)
+            );
+        }
+        assert_eq!(
+            resume_from(
+                "G00 X1 Y1 Z1\nG53 G00 Z0\nG43 H1\nG49\nG01 X2\n",
+                5,
+                &options
+            )?,
+            "G90\n\
+             G49\n\
+             G00 Z10\n\
+             G00 X1 Y1\n\
+             G00\n\
+             G01 X2\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn resume_printer() -> Result<(), GCodeError> {
+        let program = "\
+            M83\n\
+            G01 X10 Y0 Z0.2 E1.5 F1200\n\
+            G01 X20 E2\n\
+            G92 E0\n\
+            G01 X30 E1\n";
+        let options = GCodeResumeOptions {
+            safe_z: 5.0,
+            plunge_feed: None,
+        };
+
+        assert_eq!(
+            resume_from(program, 5, &options)?,
+            "G90\n\
+             G00 Z5\n\
+             G00 X20 Y0\n\
+             G01 Z0.2 F1200\n\
+             G92 E0\n\
+             M83\n\
+             G01\n\
+             G01 X30 E1\n"
+        );
+
+        Ok(())
+    }
+}
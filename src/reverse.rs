@@ -0,0 +1,333 @@
+use crate::interp::{code_of, Interpreter, ModalGroup, Motion};
+use crate::minify::format_number;
+use crate::{GCodeError, GCodePathSegment, GCodePosition};
+
+/// Move of a program, in absolute coordinates
+struct ReverseMove {
+    /// Motion mode, as G code number
+    motion: u8,
+    start: [Option<f64>; 3],
+    end: [Option<f64>; 3],
+    /// Axes given by the move
+    axes: [bool; 3],
+    /// Arc center offsets (I, J, K), relative to the start
+    offsets: [Option<f64>; 3],
+    radius: Option<f64>,
+    feed: Option<f64>,
+}
+
+/// Reverses a path, such that it is run from its end back to its start, e.g.
+/// for back-and-forth polishing passes or return moves. The first segment
+/// of `path` is taken as the starting position, as produced by
+/// `fillet_path()`, and the result likewise starts at the end of `path`.
+///
+/// Moves are returned in reverse order, with arcs changing direction. Axes
+/// left out by a segment keep their value from the preceding segments, and
+/// are written out in full in the result. As feed rates are given to the
+/// writer per move, they do not need to be moved between segments. See
+/// `reverse_program()` for reversing a written program.
+pub fn reverse_path(path: &[GCodePathSegment]) -> Vec<GCodePathSegment> {
+    /* Absolute position after each segment */
+    let mut positions: Vec<GCodePosition> = vec![];
+    for segment in path {
+        let pos = match positions.last() {
+            Some(prev) => segment.end().merge(prev),
+            None => segment.end(),
+        };
+        positions.push(pos);
+    }
+
+    let mut result = vec![];
+    if let Some(&last) = positions.last() {
+        result.push(GCodePathSegment::Line(last));
+    }
+    for (i, segment) in path.iter().enumerate().skip(1).rev() {
+        let start = positions[i - 1];
+        result.push(match *segment {
+            GCodePathSegment::Line(_) => GCodePathSegment::Line(start),
+            GCodePathSegment::Arc {
+                center, clockwise, ..
+            } => GCodePathSegment::Arc {
+                end: start,
+                center,
+                clockwise: !clockwise,
+            },
+        });
+    }
+
+    result
+}
+
+/// Reverses a program, such that its moves are run from the end back to the
+/// start, like `reverse_path()` does for paths. The first move of `program`
+/// is taken as the starting position, and replaced by a move of the same
+/// motion mode to the end position.
+///
+/// Lines before the first move, e.g. setting units or starting the spindle,
+/// and lines after the last move are kept as-is. Every move keeps its motion
+/// mode and feed rate, so F words are moved to the moves they apply to in
+/// the result, and arcs change direction with their center offsets given
+/// relative to the new start point. Rapids which move down once reversed,
+/// e.g. retracts becoming plunges, are turned into feed moves at
+/// `plunge_feed`, so as not to rapid into the stock. Comments between moves
+/// are dropped.
+///
+/// Returns `OutOfRangeError` if `plunge_feed` is not positive and finite, and
+/// `UnsupportedError` if the program can not be reversed, i.e. if
+/// it uses parameters, expressions, control flow, incremental positioning
+/// or inverse time feed rates, if lines between the first and last move
+/// contain anything other than moves and feed rates, if the first move is
+/// an arc, or if a move uses an axis whose value at its start is unknown.
+pub fn reverse_program(program: &str, plunge_feed: f64) -> Result<String, GCodeError> {
+    if !plunge_feed.is_finite() || (plunge_feed <= 0.0) {
+        return Err(GCodeError::OutOfRangeError);
+    }
+    let lines: Vec<&str> = program.lines().collect();
+    let mut moves: Vec<ReverseMove> = vec![];
+    /* Line indices of the first and last move */
+    let mut first = None;
+    let mut last = None;
+    /* Line index of the first line after a move which is not a move */
+    let mut blocker = None;
+
+    let mut interp = Interpreter::new();
+    for (idx, text) in lines.iter().enumerate() {
+        let block = interp.line(text).ok_or(GCodeError::UnsupportedError)?;
+        let modal = &interp.state;
+        if modal.is_incremental() || (modal.group(ModalGroup::FeedMode) == Some(930)) {
+            return Err(GCodeError::UnsupportedError);
+        }
+
+        /* Anything but moves and feed rates */
+        let other = block.words.iter().any(|&(letter, val)| {
+            !matches!(
+                (letter, code_of(val)),
+                ('G', 0 | 10 | 20 | 30 | 900)
+                    | ('N' | 'X' | 'Y' | 'Z' | 'I' | 'J' | 'K' | 'R' | 'F', _)
+            )
+        });
+        let (motion, start, end) = match block.motion {
+            Motion::Work { mode, start, end } if !other => (mode, start, end),
+            _ => {
+                if other && first.is_some() && blocker.is_none() {
+                    blocker = Some(idx);
+                }
+                continue;
+            }
+        };
+        if blocker.is_some() {
+            return Err(GCodeError::UnsupportedError);
+        }
+
+        moves.push(ReverseMove {
+            motion,
+            start,
+            end,
+            axes: block.axes.map(|val| val.is_some()),
+            offsets: [block.word('I'), block.word('J'), block.word('K')],
+            radius: block.word('R'),
+            feed: modal.feed,
+        });
+        first.get_or_insert(idx);
+        last = Some(idx);
+    }
+
+    let (first, last) = match (first, last) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Ok(lines.iter().map(|line| format!("{}\n", line)).collect()),
+    };
+    if moves[0].motion >= 2 {
+        return Err(GCodeError::UnsupportedError);
+    }
+
+    let mut out = String::new();
+    for line in &lines[..first] {
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    /* Move to the end position, in place of the first move */
+    let end = moves[moves.len() - 1].end;
+    let mut words = vec![format!("G{:02}", moves[0].motion)];
+    for (letter, val) in ['X', 'Y', 'Z'].into_iter().zip(end) {
+        if let Some(val) = val {
+            words.push(format!("{}{}", letter, format_number(val)));
+        }
+    }
+    let mut current_feed = None;
+    if moves[0].motion != 0 {
+        push_feed(&mut words, &mut current_feed, moves[0].feed);
+    }
+    out.push_str(&words.join(" "));
+    out.push('\n');
+
+    for mv in moves[1..].iter().rev() {
+        /* Reversed, the move runs from its end down to its start */
+        let plunge = (mv.motion == 0)
+            && mv.axes[2]
+            && matches!((mv.start[2], mv.end[2]), (Some(to), Some(from)) if to < from);
+        let motion = match plunge {
+            true => 1,
+            false => reversed_motion(mv.motion),
+        };
+        let mut words = vec![format!("G{:02}", motion)];
+        let arc = mv.motion >= 2;
+        let mut targets = vec![];
+        for (i, letter) in ['X', 'Y', 'Z'].into_iter().enumerate() {
+            if !mv.axes[i] {
+                continue;
+            }
+            let target = mv.start[i].ok_or(GCodeError::UnsupportedError)?;
+            targets.push((letter, target, Some(target) != mv.end[i]));
+        }
+        /* Full circles give their end point, which is unchanged */
+        let full = !targets.iter().any(|&(_, _, changed)| changed);
+        for (letter, target, changed) in targets {
+            if changed || (arc && full) {
+                words.push(format!("{}{}", letter, format_number(target)));
+            }
+        }
+        if arc {
+            for (i, letter) in ['I', 'J', 'K'].into_iter().enumerate() {
+                if let Some(offset) = mv.offsets[i] {
+                    /* Relative to the original end point, the new start */
+                    let (from, to) = match (mv.start[i], mv.end[i]) {
+                        (Some(from), Some(to)) => (from, to),
+                        _ => return Err(GCodeError::UnsupportedError),
+                    };
+                    words.push(format!("{}{}", letter, format_number(from + offset - to)));
+                }
+            }
+            if let Some(radius) = mv.radius {
+                words.push(format!("R{}", format_number(radius)));
+            }
+        }
+        if plunge {
+            push_feed(&mut words, &mut current_feed, Some(plunge_feed));
+        } else if mv.motion != 0 {
+            push_feed(&mut words, &mut current_feed, mv.feed);
+        }
+        out.push_str(&words.join(" "));
+        out.push('\n');
+    }
+
+    for line in &lines[(last + 1)..] {
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+/// Motion mode of a move run backwards, swapping arc directions
+fn reversed_motion(motion: u8) -> u8 {
+    match motion {
+        2 => 3,
+        3 => 2,
+        other => other,
+    }
+}
+
+/// Appends an F word to `words` if `feed` differs from the current feed rate
+fn push_feed(words: &mut Vec<String>, current: &mut Option<f64>, feed: Option<f64>) {
+    if let Some(feed) = feed.filter(|&feed| Some(feed) != *current) {
+        words.push(format!("F{}", format_number(feed)));
+        *current = Some(feed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GCodeError;
+
+    #[test]
+    fn reverse() -> Result<(), GCodeError> {
+        let pos = |x, y| GCodePosition::from_f64(Some(x), Some(y), None);
+        let path = [
+            GCodePathSegment::Line(GCodePosition::from_f64_full(0.0, 0.0, -1.0)?),
+            GCodePathSegment::Line(pos(8.0, 0.0)?),
+            GCodePathSegment::Arc {
+                end: pos(10.0, 2.0)?,
+                center: pos(8.0, 2.0)?,
+                clockwise: false,
+            },
+            GCodePathSegment::Line(GCodePosition::from_f64(None, Some(10.0), None)?),
+        ];
+
+        let reversed = reverse_path(&path);
+        assert_eq!(
+            reversed,
+            [
+                GCodePathSegment::Line(GCodePosition::from_f64_full(10.0, 10.0, -1.0)?),
+                GCodePathSegment::Line(GCodePosition::from_f64_full(10.0, 2.0, -1.0)?),
+                GCodePathSegment::Arc {
+                    end: GCodePosition::from_f64_full(8.0, 0.0, -1.0)?,
+                    center: pos(8.0, 2.0)?,
+                    clockwise: true,
+                },
+                GCodePathSegment::Line(GCodePosition::from_f64_full(0.0, 0.0, -1.0)?),
+            ]
+        );
+        /* Running it backwards again gives the original path, in full */
+        assert_eq!(
+            reverse_path(&reversed)[3],
+            GCodePathSegment::Line(pos(10.0, 10.0)?.merge(&path[0].end()))
+        );
+        assert!(reverse_path(&[]).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reverse_program_moves() -> Result<(), GCodeError> {
+        let program = "\
+            G21 G90\n\
+            M03 S1000\n\
+            G00 X0 Y0 Z5\n\
+            G01 Z-1 F100\n\
+            G01 X10 F500\n\
+            G02 X20 Y0 I5 J0 F300\n\
+            G03 X30 Y0 R5\n\
+            (Retract)\n\
+            G00 Z5\n\
+            M05\n\
+            M30\n";
+
+        /* Feed rates move along with their moves, and the retract becomes a
+         * plunge at the plunge feed rate */
+        assert_eq!(
+            reverse_program(program, 50.0)?,
+            "G21 G90\n\
+             M03 S1000\n\
+             G00 X30 Y0 Z5\n\
+             G01 Z-1 F50\n\
+             G02 X20 R5 F300\n\
+             G03 X10 I-5 J0\n\
+             G01 X0 F500\n\
+             G01 Z5 F100\n\
+             M05\n\
+             M30\n"
+        );
+
+        assert_eq!(
+            reverse_program("G91\nG01 X1 F100\n", 100.0),
+            Err(GCodeError::UnsupportedError)
+        );
+        assert_eq!(
+            reverse_program("G00 X0\nM08\nG01 X1 F100\n", 100.0),
+            Err(GCodeError::UnsupportedError)
+        );
+        assert_eq!(
+            reverse_program(program, 0.0),
+            Err(GCodeError::OutOfRangeError)
+        );
+        /* Z of the start of the second move is unknown */
+        assert_eq!(
+            reverse_program("G00 X0 Y0\nG01 Z-1 F100\n", 100.0),
+            Err(GCodeError::UnsupportedError)
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+/// Rotary axis
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GCodeRotaryAxis {
+    /// Rotation about X
+    A,
+    /// Rotation about Y
+    B,
+    /// Rotation about Z
+    C,
+}
+impl GCodeRotaryAxis {
+    pub(crate) fn index(&self) -> usize {
+        match self {
+            Self::A => 0,
+            Self::B => 1,
+            Self::C => 2,
+        }
+    }
+
+    pub(crate) fn letter(&self) -> char {
+        match self {
+            Self::A => 'A',
+            Self::B => 'B',
+            Self::C => 'C',
+        }
+    }
+}
+
+/// Units in which rotary positions are given to GCodeWriter. Output is always
+/// in degrees.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GCodeAngleUnit {
+    #[default]
+    Degrees,
+    Radians,
+}
+
+/// How rotary positions are mapped to emitted angles
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GCodeRotaryWrap {
+    /// Angles are emitted as given, values beyond 360 degrees represent
+    /// additional full turns
+    #[default]
+    Continuous,
+    /// Angles are treated modulo 360 degrees, and the equivalent angle
+    /// nearest to the current position is emitted, such that the axis takes
+    /// the shortest path. Emitted angles are not normalized, e.g. 370 is
+    /// emitted for 10 from 350, as is needed for axes the controller does
+    /// not wrap itself.
+    Nearest,
+}
+
+/// Configuration of rotary axes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GCodeRotaryConfig {
+    pub units: GCodeAngleUnit,
+    pub wrap: GCodeRotaryWrap,
+}
+impl GCodeRotaryConfig {
+    /// Converts a requested angle, in configured units, into the angle in
+    /// degrees to be emitted, given the current angle of the axis in degrees
+    pub fn resolve(&self, current: Option<f64>, angle: f64) -> f64 {
+        let angle = match self.units {
+            GCodeAngleUnit::Degrees => angle,
+            GCodeAngleUnit::Radians => angle.to_degrees(),
+        };
+
+        match (self.wrap, current) {
+            (GCodeRotaryWrap::Nearest, Some(current)) => {
+                let delta = (angle - current).rem_euclid(360.0);
+                if delta > 180.0 {
+                    current + delta - 360.0
+                } else {
+                    current + delta
+                }
+            }
+            /* Without a known starting point, there is no shortest path */
+            _ => angle,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotary_resolve() {
+        let continuous = GCodeRotaryConfig::default();
+        assert_eq!(continuous.resolve(Some(350.0), 10.0), 10.0);
+        assert_eq!(continuous.resolve(Some(350.0), 370.0), 370.0);
+
+        let nearest = GCodeRotaryConfig {
+            units: GCodeAngleUnit::Degrees,
+            wrap: GCodeRotaryWrap::Nearest,
+        };
+        assert_eq!(nearest.resolve(Some(350.0), 10.0), 370.0);
+        assert_eq!(nearest.resolve(Some(10.0), 350.0), -10.0);
+        assert_eq!(nearest.resolve(Some(720.0), 90.0), 810.0);
+        assert_eq!(nearest.resolve(None, 400.0), 400.0);
+
+        let radians = GCodeRotaryConfig {
+            units: GCodeAngleUnit::Radians,
+            wrap: GCodeRotaryWrap::Continuous,
+        };
+        assert_eq!(radians.resolve(None, std::f64::consts::PI), 180.0);
+    }
+}
@@ -0,0 +1,164 @@
+use std::io::Write;
+
+use crate::{GCodeBackplot, GCodeError};
+
+/// Point of a toolpath resampled at a fixed spatial interval
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeSample {
+    /// Length of the toolpath up to this point
+    pub distance: f64,
+    pub position: [f64; 3],
+    /// Index of the command producing the move containing this point
+    pub command: usize,
+    /// Whether the point lies on a rapid (G00) move
+    pub rapid: bool,
+}
+
+/// Resamples the moves of a backplot into points spaced `step` apart along
+/// the toolpath, starting at its first point. The end of the toolpath is only
+/// included if its length is a multiple of `step`.
+///
+/// Rapid moves are sampled as well, and marked as such. Discontinuities
+/// between polylines do not count towards the toolpath length.
+pub fn sample_path(backplot: &GCodeBackplot, step: f64) -> Result<Vec<GCodeSample>, GCodeError> {
+    if !step.is_finite() || (step <= 0.0) {
+        return Err(GCodeError::OutOfRangeError);
+    }
+
+    let mut samples = vec![];
+    /* Length of the toolpath up to the start of the current segment */
+    let mut travelled = 0.0;
+    for line in backplot.polylines() {
+        for (seg, &command) in line.points.windows(2).zip(&line.commands) {
+            let (from, to) = (seg[0], seg[1]);
+            let len = (0..3)
+                .map(|i| (to[i] - from[i]).powi(2))
+                .sum::<f64>()
+                .sqrt();
+
+            loop {
+                /* Computed from the sample count, to avoid accumulating
+                 * rounding errors over long toolpaths */
+                let distance = (samples.len() as f64) * step;
+                if distance > travelled + len {
+                    break;
+                }
+                let t = (distance - travelled) / len;
+                samples.push(GCodeSample {
+                    distance,
+                    position: [0, 1, 2].map(|i| from[i] + (to[i] - from[i]) * t),
+                    command,
+                    rapid: line.rapid,
+                });
+            }
+            travelled += len;
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Writes samples as CSV, with a header line followed by one line per sample
+pub fn write_samples_csv(
+    samples: &[GCodeSample],
+    mut writer: impl Write,
+) -> Result<(), GCodeError> {
+    writeln!(writer, "distance,x,y,z,command,rapid")?;
+    for sample in samples {
+        let [x, y, z] = sample.position;
+        writeln!(
+            writer,
+            "{:.4},{:.4},{:.4},{:.4},{},{}",
+            sample.distance,
+            x,
+            y,
+            z,
+            sample.command,
+            u8::from(sample.rapid)
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GCodeBackplotBuilder, GCodePosition};
+
+    #[test]
+    fn sample() -> Result<(), GCodeError> {
+        let mut builder = GCodeBackplotBuilder::new();
+        builder
+            .start_at(GCodePosition::from_f64_full(0.0, 0.0, 0.0)?)
+            .move_to(1, GCodePosition::from_f64(Some(1.0), None, None)?, false)
+            .move_to(2, GCodePosition::from_f64(None, Some(0.6), None)?, true);
+        let backplot = builder.build();
+
+        assert_eq!(
+            sample_path(&backplot, 0.0),
+            Err(GCodeError::OutOfRangeError)
+        );
+
+        let samples = sample_path(&backplot, 0.25)?;
+        assert_eq!(samples.len(), 7);
+        assert_eq!(samples[1].position, [0.25, 0.0, 0.0]);
+        assert_eq!(samples[4].position, [1.0, 0.0, 0.0]);
+        assert_eq!(samples[4].command, 1);
+        assert_eq!(samples[6].position, [1.0, 0.5, 0.0]);
+        assert_eq!(samples[6].command, 2);
+        assert!(samples[6].rapid);
+
+        let mut data = vec![];
+        write_samples_csv(&samples[5..], &mut data)?;
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "distance,x,y,z,command,rapid\n\
+             1.2500,1.0000,0.2500,0.0000,2,1\n\
+             1.5000,1.0000,0.5000,0.0000,2,1\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn sample_edge_cases() -> Result<(), GCodeError> {
+        let backplot = GCodeBackplotBuilder::new().build();
+        assert!(sample_path(&backplot, 1.0)?.is_empty());
+        for step in [-1.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(
+                sample_path(&backplot, step),
+                Err(GCodeError::OutOfRangeError)
+            );
+        }
+
+        let mut data = vec![];
+        write_samples_csv(&[], &mut data)?;
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "distance,x,y,z,command,rapid\n"
+        );
+
+        /* The gap between disconnected moves is not sampled */
+        let mut builder = GCodeBackplotBuilder::new();
+        builder
+            .start_at(GCodePosition::from_f64_full(0.0, 0.0, 0.0)?)
+            .move_to(1, GCodePosition::from_f64(Some(1.0), None, None)?, false)
+            .start_at(GCodePosition::from_f64(Some(5.0), None, None)?)
+            .move_to(2, GCodePosition::from_f64(Some(6.0), None, None)?, false);
+        let backplot = builder.build();
+
+        let samples = sample_path(&backplot, 0.5)?;
+        assert_eq!(samples.len(), 5);
+        assert_eq!(samples[2].position, [1.0, 0.0, 0.0]);
+        assert_eq!(samples[2].command, 1);
+        assert_eq!(samples[3].position, [5.5, 0.0, 0.0]);
+        assert_eq!(samples[4].distance, 2.0);
+
+        /* Steps longer than the toolpath only sample its start */
+        let samples = sample_path(&backplot, 10.0)?;
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].position, [0.0, 0.0, 0.0]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,379 @@
+use crate::{GCodeError, GCodePosition};
+
+/// Micro-tabs left uncut to hold parts within the sheet
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeTabOptions {
+    /// Length of each uncut gap, measured along the path
+    pub width: f64,
+    /// Number of tabs per loop, evenly spaced along the perimeter
+    pub count: usize,
+    /// Loops with a perimeter shorter than this receive no tabs
+    pub min_perimeter: f64,
+}
+
+/// Options of closed loop sequencing
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GCodeSequenceOptions {
+    /// Position of the head prior to cutting, used to minimize travel
+    pub start: Option<GCodePosition>,
+    /// Tabs to insert into part outlines
+    pub tabs: Option<GCodeTabOptions>,
+}
+
+/// Closed loop, positioned within a cutting sequence
+#[derive(Clone, Debug, PartialEq)]
+pub struct GCodeSequencedLoop {
+    /// Index of the loop within the input
+    pub index: usize,
+    /// Number of other loops containing this one. Even depths are part
+    /// outlines, odd depths are holes within parts.
+    pub depth: usize,
+    /// Paths to cut, with laser off travel between them. Without tabs this
+    /// is a single path, closed by repeating its first point.
+    pub paths: Vec<Vec<GCodePosition>>,
+}
+
+/// Order of depth passes over multiple contours
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GCodePassOrder {
+    /// Each contour is cut to full depth before moving on to the next,
+    /// minimizing travel between contours. Parts are freed as soon as their
+    /// contour is done, so small parts may need tabs to stay in place.
+    #[default]
+    DepthFirst,
+    /// All contours are cut at one depth before stepping down, keeping
+    /// parts attached to the stock until the final pass, and letting heat
+    /// dissipate between passes over a contour
+    ContourFirst,
+}
+
+/// Single pass over a contour, as ordered by `order_passes()`
+#[derive(Clone, Debug, PartialEq)]
+pub struct GCodePass {
+    /// Index of the contour within the input
+    pub contour: usize,
+    /// Index of the depth, starting at the shallowest pass
+    pub pass: usize,
+    /// Contour with Z set to the depth of this pass
+    pub path: Vec<GCodePosition>,
+}
+
+type Point = (f64, f64);
+
+/// Orders closed cut loops for sheet cutting, such that every loop is cut
+/// prior to any loop containing it. Holes and parts nested within a part
+/// are thus cut before the part itself is freed from the sheet. Among loops
+/// ready to be cut, the one nearest to the current position is cut next,
+/// starting from its nearest vertex.
+///
+/// Loops are closed implicitly and may have either orientation, only X and
+/// Y are considered for ordering.
+pub fn sequence_loops(
+    loops: &[Vec<GCodePosition>],
+    options: &GCodeSequenceOptions,
+) -> Result<Vec<GCodeSequencedLoop>, GCodeError> {
+    let mut rings = vec![];
+    for lp in loops {
+        let mut ring = vec![];
+        for pos in lp {
+            match (pos.x_f64(), pos.y_f64()) {
+                (Some(x), Some(y)) => ring.push((x, y)),
+                _ => return Err(GCodeError::GeometryError),
+            }
+        }
+        if (ring.len() > 1) && (ring.first() == ring.last()) {
+            ring.pop();
+        }
+        if ring.len() < 3 {
+            return Err(GCodeError::GeometryError);
+        }
+        rings.push(ring);
+    }
+
+    let areas: Vec<f64> = rings.iter().map(|ring| signed_area(ring).abs()).collect();
+    /* parents[i] lists all loops containing loop i */
+    let parents: Vec<Vec<usize>> = (0..rings.len())
+        .map(|i| {
+            (0..rings.len())
+                .filter(|&j| (j != i) && (areas[j] > areas[i]) && inside(&rings[j], rings[i][0]))
+                .collect()
+        })
+        .collect();
+    let mut children = vec![0; rings.len()];
+    for parent in parents.iter().flatten() {
+        children[*parent] += 1;
+    }
+
+    let mut current = options
+        .start
+        .and_then(|pos| pos.x_f64().zip(pos.y_f64()))
+        .unwrap_or((0.0, 0.0));
+    let mut done = vec![false; rings.len()];
+    let mut result = vec![];
+
+    while result.len() < rings.len() {
+        /* Nearest loop whose contents have all been cut, and its nearest
+         * vertex. There is always at least one, as containment is acyclic. */
+        let (idx, vertex) = (0..rings.len())
+            .filter(|&i| !done[i] && (children[i] == 0))
+            .flat_map(|i| (0..rings[i].len()).map(move |v| (i, v)))
+            .min_by(|&(a, va), &(b, vb)| {
+                dist(current, rings[a][va]).total_cmp(&dist(current, rings[b][vb]))
+            })
+            .ok_or(GCodeError::GeometryError)?;
+
+        done[idx] = true;
+        for parent in &parents[idx] {
+            children[*parent] -= 1;
+        }
+
+        let lp = &loops[idx];
+        let count = rings[idx].len();
+        let path: Vec<GCodePosition> = (0..=count).map(|i| lp[(vertex + i) % count]).collect();
+        let depth = parents[idx].len();
+
+        let paths = match options.tabs {
+            Some(tabs) if depth % 2 == 0 => insert_tabs(&path, &tabs)?,
+            _ => vec![path],
+        };
+        current = rings[idx][vertex];
+
+        result.push(GCodeSequencedLoop {
+            index: idx,
+            depth,
+            paths,
+        });
+    }
+
+    Ok(result)
+}
+
+/// Orders the passes of cutting `contours` at each of `depths`, in either
+/// order. Contours are kept in their given order within each depth, e.g. as
+/// found by `sequence_loops()`, and depths are cut in their given order.
+pub fn order_passes(
+    contours: &[Vec<GCodePosition>],
+    depths: &[f64],
+    order: GCodePassOrder,
+) -> Result<Vec<GCodePass>, GCodeError> {
+    let pairs: Vec<(usize, usize)> = match order {
+        GCodePassOrder::DepthFirst => (0..contours.len())
+            .flat_map(|c| (0..depths.len()).map(move |p| (c, p)))
+            .collect(),
+        GCodePassOrder::ContourFirst => (0..depths.len())
+            .flat_map(|p| (0..contours.len()).map(move |c| (c, p)))
+            .collect(),
+    };
+
+    let mut passes = vec![];
+    for (contour, pass) in pairs {
+        let path = contours[contour]
+            .iter()
+            .map(|pos| pos.with_z(depths[pass]))
+            .collect::<Result<_, _>>()?;
+        passes.push(GCodePass {
+            contour,
+            pass,
+            path,
+        });
+    }
+
+    Ok(passes)
+}
+
+/// Splits closed path `path` into separate paths around evenly spaced tabs
+fn insert_tabs(
+    path: &[GCodePosition],
+    tabs: &GCodeTabOptions,
+) -> Result<Vec<Vec<GCodePosition>>, GCodeError> {
+    let lengths: Vec<f64> = path
+        .windows(2)
+        .map(|w| GCodePosition::fixed_to_f64(w[0].distance_to(&w[1])))
+        .collect();
+    let perimeter: f64 = lengths.iter().sum();
+    if (tabs.count == 0) || (perimeter < tabs.min_perimeter) || (tabs.width <= 0.0) {
+        return Ok(vec![path.to_vec()]);
+    }
+    let spacing = perimeter / (tabs.count as f64);
+    if tabs.width >= spacing {
+        return Err(GCodeError::OutOfRangeError);
+    }
+
+    /* Cut intervals along the perimeter, tabs are centered between the
+     * starts of consecutive intervals */
+    let mut intervals = vec![];
+    for i in 0..tabs.count {
+        let tab_end = (i as f64) * spacing + tabs.width / 2.0;
+        let next_tab_start = ((i + 1) as f64) * spacing - tabs.width / 2.0;
+        intervals.push((tab_end, next_tab_start));
+    }
+
+    /* Interpolated in floating-point, as GCodePosition::lerp() only offers
+     * the precision of a fixed-point t */
+    let point_at = |dist: f64| {
+        let mut acc = 0.0;
+        for (i, &len) in lengths.iter().enumerate() {
+            if (dist <= acc + len) || (i == lengths.len() - 1) {
+                let t = if len > 0.0 { (dist - acc) / len } else { 0.0 };
+                let t = t.clamp(0.0, 1.0);
+                let (from, to) = (path[i].as_f64(), path[i + 1].as_f64());
+                let lerp = |l: Option<f64>, r: Option<f64>| match (l, r) {
+                    (Some(l), Some(r)) => Some(l + (r - l) * t),
+                    _ => l,
+                };
+                return GCodePosition::from_f64(
+                    lerp(from.0, to.0),
+                    lerp(from.1, to.1),
+                    lerp(from.2, to.2),
+                );
+            }
+            acc += len;
+        }
+        Ok(path[0])
+    };
+
+    let mut paths = vec![];
+    for (start, end) in intervals {
+        let mut cut = vec![point_at(start)?];
+        let mut acc = 0.0;
+        for (i, &len) in lengths.iter().enumerate() {
+            acc += len;
+            if (acc > start) && (acc < end) {
+                cut.push(path[i + 1]);
+            }
+        }
+        cut.push(point_at(end)?);
+        paths.push(cut);
+    }
+
+    Ok(paths)
+}
+
+fn dist(a: Point, b: Point) -> f64 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+fn signed_area(ring: &[Point]) -> f64 {
+    (0..ring.len())
+        .map(|i| {
+            let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum::<f64>()
+        / 2.0
+}
+
+/// Even-odd test of whether `p` lies within `ring`
+fn inside(ring: &[Point], p: Point) -> bool {
+    (0..ring.len())
+        .map(|i| (ring[i], ring[(i + 1) % ring.len()]))
+        .filter(|(a, b)| (a.1 <= p.1) != (b.1 <= p.1))
+        .filter(|(a, b)| p.0 < (a.0 + (p.1 - a.1) * (b.0 - a.0) / (b.1 - a.1)))
+        .count()
+        % 2
+        == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(min: f64, max: f64) -> Result<Vec<GCodePosition>, GCodeError> {
+        Ok(vec![
+            GCodePosition::from_f64(Some(min), Some(min), None)?,
+            GCodePosition::from_f64(Some(max), Some(min), None)?,
+            GCodePosition::from_f64(Some(max), Some(max), None)?,
+            GCodePosition::from_f64(Some(min), Some(max), None)?,
+        ])
+    }
+
+    #[test]
+    fn sequence_order() -> Result<(), GCodeError> {
+        /* Part with a hole, and a separate part */
+        let loops = vec![
+            square(10.0, 30.0)?,
+            square(15.0, 25.0)?,
+            square(50.0, 70.0)?,
+        ];
+        let seq = sequence_loops(&loops, &GCodeSequenceOptions::default())?;
+
+        let order: Vec<(usize, usize)> = seq.iter().map(|lp| (lp.index, lp.depth)).collect();
+        assert_eq!(order, [(1, 1), (0, 0), (2, 0)]);
+
+        /* Part outline starts at the vertex nearest to the hole */
+        assert_eq!(seq[1].paths.len(), 1);
+        assert_eq!(seq[1].paths[0].len(), 5);
+        assert_eq!(
+            seq[1].paths[0][0],
+            GCodePosition::from_f64(Some(10.0), Some(10.0), None)?
+        );
+        assert_eq!(seq[1].paths[0].first(), seq[1].paths[0].last());
+
+        Ok(())
+    }
+
+    #[test]
+    fn sequence_tabs() -> Result<(), GCodeError> {
+        let loops = vec![square(10.0, 30.0)?, square(15.0, 25.0)?];
+        let options = GCodeSequenceOptions {
+            start: None,
+            tabs: Some(GCodeTabOptions {
+                width: 2.0,
+                count: 2,
+                min_perimeter: 50.0,
+            }),
+        };
+        let seq = sequence_loops(&loops, &options)?;
+
+        /* Hole is too small and scrap anyway, part is split by two tabs */
+        assert_eq!(seq[0].paths.len(), 1);
+        assert_eq!(seq[1].paths.len(), 2);
+        assert_eq!(
+            seq[1].paths[0],
+            [
+                GCodePosition::from_f64(Some(11.0), Some(10.0), None)?,
+                GCodePosition::from_f64(Some(30.0), Some(10.0), None)?,
+                GCodePosition::from_f64(Some(30.0), Some(29.0), None)?,
+            ]
+        );
+        assert_eq!(
+            seq[1].paths[1],
+            [
+                GCodePosition::from_f64(Some(29.0), Some(30.0), None)?,
+                GCodePosition::from_f64(Some(10.0), Some(30.0), None)?,
+                GCodePosition::from_f64(Some(10.0), Some(11.0), None)?,
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn pass_order() -> Result<(), GCodeError> {
+        let contours = vec![square(0.0, 10.0)?, square(20.0, 30.0)?];
+        let depths = [-1.0, -2.0];
+
+        let order = |order| -> Result<Vec<(usize, usize)>, GCodeError> {
+            Ok(order_passes(&contours, &depths, order)?
+                .iter()
+                .map(|pass| (pass.contour, pass.pass))
+                .collect())
+        };
+        assert_eq!(
+            order(GCodePassOrder::DepthFirst)?,
+            [(0, 0), (0, 1), (1, 0), (1, 1)]
+        );
+        assert_eq!(
+            order(GCodePassOrder::ContourFirst)?,
+            [(0, 0), (1, 0), (0, 1), (1, 1)]
+        );
+
+        let passes = order_passes(&contours, &depths, GCodePassOrder::DepthFirst)?;
+        assert_eq!(
+            passes[1].path[2],
+            GCodePosition::from_f64_full(10.0, 10.0, -2.0)?
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::{GCodeOffset, GCodePosition};
+
+/// Work coordinate system origin listed on a setup sheet
+#[derive(Clone, Debug, PartialEq)]
+pub struct GCodeSetupWcs {
+    /// Name of the coordinate system, e.g. "G54"
+    pub name: String,
+    /// Origin of the coordinate system, in machine coordinates
+    pub origin: GCodePosition,
+}
+
+/// Tool listed on a setup sheet
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GCodeSetupTool {
+    /// Tool number, as used in T words
+    pub number: u32,
+    pub description: String,
+    pub diameter: Option<f64>,
+    /// Length of tool protruding from the holder
+    pub stick_out: Option<f64>,
+    /// Overall tool length, including holder
+    pub length: Option<f64>,
+}
+
+/// Operation listed on a setup sheet
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GCodeSetupOperation {
+    pub name: String,
+    /// Number of the tool used by this operation
+    pub tool: Option<u32>,
+    /// Estimated run time of the operation
+    pub time: Option<Duration>,
+}
+
+/// Collection of information needed by an operator to set up a job
+///
+/// Can be rendered as JSON via `to_json()`, or as plain text via `Display`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GCodeSetupSheet {
+    pub title: Option<String>,
+    /// Stock dimensions along each axis
+    pub stock_size: Option<GCodeOffset>,
+    pub wcs: Vec<GCodeSetupWcs>,
+    pub tools: Vec<GCodeSetupTool>,
+    pub operations: Vec<GCodeSetupOperation>,
+}
+impl GCodeSetupSheet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_wcs(&mut self, name: &str, origin: GCodePosition) -> &mut Self {
+        self.wcs.push(GCodeSetupWcs {
+            name: name.to_string(),
+            origin,
+        });
+        self
+    }
+
+    pub fn add_tool(&mut self, tool: GCodeSetupTool) -> &mut Self {
+        self.tools.push(tool);
+        self
+    }
+
+    pub fn add_operation(&mut self, operation: GCodeSetupOperation) -> &mut Self {
+        self.operations.push(operation);
+        self
+    }
+
+    /// Sum of the estimated times of all operations, None if no operation has
+    /// an estimated time
+    pub fn total_time(&self) -> Option<Duration> {
+        self.operations
+            .iter()
+            .filter_map(|op| op.time)
+            .fold(None, |acc, time| Some(acc.unwrap_or_default() + time))
+    }
+
+    /// Renders the setup sheet as a JSON document
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("{\"title\":");
+        json_opt_str(&mut out, self.title.as_deref());
+        out.push_str(",\"stock_size\":");
+        match self.stock_size {
+            Some(size) => json_position(&mut out, &size),
+            None => out.push_str("null"),
+        }
+
+        out.push_str(",\"wcs\":[");
+        for (i, wcs) in self.wcs.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str("{\"name\":");
+            json_str(&mut out, &wcs.name);
+            out.push_str(",\"origin\":");
+            json_position(&mut out, &wcs.origin);
+            out.push('}');
+        }
+
+        out.push_str("],\"tools\":[");
+        for (i, tool) in self.tools.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{{\"number\":{},\"description\":", tool.number);
+            json_str(&mut out, &tool.description);
+            out.push_str(",\"diameter\":");
+            json_opt_f64(&mut out, tool.diameter);
+            out.push_str(",\"stick_out\":");
+            json_opt_f64(&mut out, tool.stick_out);
+            out.push_str(",\"length\":");
+            json_opt_f64(&mut out, tool.length);
+            out.push('}');
+        }
+
+        out.push_str("],\"operations\":[");
+        for (i, op) in self.operations.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str("{\"name\":");
+            json_str(&mut out, &op.name);
+            out.push_str(",\"tool\":");
+            match op.tool {
+                Some(tool) => {
+                    let _ = write!(out, "{}", tool);
+                }
+                None => out.push_str("null"),
+            }
+            out.push_str(",\"time\":");
+            json_opt_f64(&mut out, op.time.map(|t| t.as_secs_f64()));
+            out.push('}');
+        }
+
+        out.push_str("],\"total_time\":");
+        json_opt_f64(&mut out, self.total_time().map(|t| t.as_secs_f64()));
+        out.push('}');
+
+        out
+    }
+}
+impl std::fmt::Display for GCodeSetupSheet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn fmt_time(time: Duration) -> String {
+            let secs = time.as_secs();
+            format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+        }
+
+        writeln!(f, "Setup sheet: {}", self.title.as_deref().unwrap_or("-"))?;
+        if let Some(size) = self.stock_size {
+            writeln!(f, "Stock size: {}", size)?;
+        }
+
+        writeln!(f, "Work offsets:")?;
+        for wcs in &self.wcs {
+            writeln!(f, "  {}: {}", wcs.name, wcs.origin)?;
+        }
+
+        writeln!(f, "Tools:")?;
+        for tool in &self.tools {
+            write!(f, "  T{}: {}", tool.number, tool.description)?;
+            if let Some(diameter) = tool.diameter {
+                write!(f, ", diameter {}", diameter)?;
+            }
+            if let Some(stick_out) = tool.stick_out {
+                write!(f, ", stick-out {}", stick_out)?;
+            }
+            if let Some(length) = tool.length {
+                write!(f, ", length {}", length)?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f, "Operations:")?;
+        for (i, op) in self.operations.iter().enumerate() {
+            write!(f, "  {}. {}", i + 1, op.name)?;
+            if let Some(tool) = op.tool {
+                write!(f, " (T{})", tool)?;
+            }
+            if let Some(time) = op.time {
+                write!(f, " {}", fmt_time(time))?;
+            }
+            writeln!(f)?;
+        }
+
+        if let Some(time) = self.total_time() {
+            writeln!(f, "Total time: {}", fmt_time(time))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn json_str(out: &mut String, val: &str) {
+    out.push('"');
+    for c in val.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn json_opt_str(out: &mut String, val: Option<&str>) {
+    match val {
+        Some(val) => json_str(out, val),
+        None => out.push_str("null"),
+    }
+}
+
+fn json_opt_f64(out: &mut String, val: Option<f64>) {
+    match val {
+        /* JSON has no representation of NaN or infinity */
+        Some(val) if val.is_finite() => {
+            let _ = write!(out, "{}", val);
+        }
+        _ => out.push_str("null"),
+    }
+}
+
+fn json_position(out: &mut String, pos: &GCodePosition) {
+    let (x, y, z) = pos.as_f64();
+    out.push_str("{\"x\":");
+    json_opt_f64(out, x);
+    out.push_str(",\"y\":");
+    json_opt_f64(out, y);
+    out.push_str(",\"z\":");
+    json_opt_f64(out, z);
+    out.push('}');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GCodeError;
+
+    fn sheet() -> Result<GCodeSetupSheet, GCodeError> {
+        let mut sheet = GCodeSetupSheet::new();
+        sheet.title = Some("Bracket \"A\"".to_string());
+        sheet.stock_size = Some(GCodeOffset::from_f64_full(100.0, 50.0, 10.0)?);
+        sheet
+            .add_wcs("G54", GCodePosition::from_f64_full(-200.0, -150.5, -80.0)?)
+            .add_tool(GCodeSetupTool {
+                number: 1,
+                description: "6mm flat endmill".to_string(),
+                diameter: Some(6.0),
+                stick_out: Some(20.0),
+                length: None,
+            })
+            .add_operation(GCodeSetupOperation {
+                name: "Facing".to_string(),
+                tool: Some(1),
+                time: Some(Duration::from_secs(90)),
+            })
+            .add_operation(GCodeSetupOperation {
+                name: "Contour".to_string(),
+                tool: Some(1),
+                time: Some(Duration::from_secs(3630)),
+            });
+        Ok(sheet)
+    }
+
+    #[test]
+    fn setup_json() -> Result<(), GCodeError> {
+        assert_eq!(
+            sheet()?.to_json(),
+            "{\"title\":\"Bracket \\\"A\\\"\",\
+             \"stock_size\":{\"x\":100,\"y\":50,\"z\":10},\
+             \"wcs\":[{\"name\":\"G54\",\"origin\":{\"x\":-200,\"y\":-150.5,\"z\":-80}}],\
+             \"tools\":[{\"number\":1,\"description\":\"6mm flat endmill\",\
+             \"diameter\":6,\"stick_out\":20,\"length\":null}],\
+             \"operations\":[{\"name\":\"Facing\",\"tool\":1,\"time\":90},\
+             {\"name\":\"Contour\",\"tool\":1,\"time\":3630}],\
+             \"total_time\":3720}"
+        );
+        assert_eq!(
+            GCodeSetupSheet::new().to_json(),
+            "{\"title\":null,\"stock_size\":null,\"wcs\":[],\"tools\":[],\
+             \"operations\":[],\"total_time\":null}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn setup_text() -> Result<(), GCodeError> {
+        assert_eq!(
+            sheet()?.to_string(),
+            "Setup sheet: Bracket \"A\"\n\
+             Stock size: (100,50,10)\n\
+             Work offsets:\n  \
+             G54: (-200,-150.5,-80)\n\
+             Tools:\n  \
+             T1: 6mm flat endmill, diameter 6, stick-out 20\n\
+             Operations:\n  \
+             1. Facing (T1) 0:01:30\n  \
+             2. Contour (T1) 1:00:30\n\
+             Total time: 1:02:00\n"
+        );
+
+        Ok(())
+    }
+}
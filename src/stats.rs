@@ -0,0 +1,299 @@
+use crate::interp::{Interpreter, ModalGroup, Motion};
+
+/// Usage of a single tool, as reported by `program_stats()`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GCodeToolUsage {
+    /// Tool number, as used in T words
+    pub tool: u32,
+    /// Length of feed moves made with this tool
+    pub cutting_distance: f64,
+    /// Number of feed moves made with this tool
+    pub cutting_moves: usize,
+}
+
+/// Summary of a program, as reported by `program_stats()`
+///
+/// Distances are given in program units, i.e. the units selected by
+/// G20/G21.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GCodeProgramStats {
+    /// Length of linear and arc feed moves
+    pub cutting_distance: f64,
+    /// Length of rapid moves
+    pub rapid_distance: f64,
+    /// Number of rapid (G00) moves
+    pub rapid_moves: usize,
+    /// Number of linear feed (G01) moves
+    pub linear_moves: usize,
+    /// Number of arc (G02/G03) moves
+    pub arc_moves: usize,
+    /// Number of distinct Z heights at which feed moves in the XY plane
+    /// are made, e.g. the number of printed layers
+    pub layers: usize,
+    /// Net length of filament extruded, taking retractions into account
+    pub filament: f64,
+    /// Lowest and highest feed rate set in units per minute, i.e. not in
+    /// inverse time (G93) or per revolution (G95)
+    pub feed_range: Option<(f64, f64)>,
+    /// Lowest and highest spindle speed set, by S words starting the
+    /// spindle (M03/M04), changing its speed while running, or given with
+    /// a move, e.g. laser power. S words of other M codes, e.g.
+    /// temperatures or fan speeds, are not included.
+    pub spindle_range: Option<(f64, f64)>,
+    /// Usage of each tool, in order of first selection
+    pub tools: Vec<GCodeToolUsage>,
+    /// Number of lines which could not be analyzed
+    pub skipped_lines: usize,
+}
+
+#[derive(Default)]
+struct StatsState {
+    extrude_relative: bool,
+    extruder: f64,
+    /// Index into the tool list of the selected tool
+    tool: Option<usize>,
+}
+
+/// Largest difference of Z heights considered the same layer
+const LAYER_TOLERANCE: f64 = 1e-6;
+
+/// Gathers statistics of a program, e.g. to show on a job overview without
+/// running it through a controller. Arcs are measured in the XY plane, and
+/// only when given by center (I/J); helical arcs include their Z travel.
+///
+/// Moves starting from an unknown position are counted, but do not add to
+/// any distance, as are moves in machine coordinates (G53). Lines using
+/// parameters, expressions or control flow are counted in `skipped_lines`
+/// and reset the known position, as do G codes using axis words otherwise,
+/// e.g. G28 or G92.
+pub fn program_stats(program: &str) -> GCodeProgramStats {
+    let mut stats = GCodeProgramStats::default();
+    let mut interp = Interpreter::new();
+    let mut state = StatsState::default();
+    let mut layers: Vec<f64> = vec![];
+
+    for text in program.lines() {
+        let Some(block) = interp.line(text) else {
+            stats.skipped_lines += 1;
+            continue;
+        };
+        let modal = &interp.state;
+
+        if block.has('G', 900) || block.has('M', 820) {
+            state.extrude_relative = false;
+        }
+        if block.has('G', 910) || block.has('M', 830) {
+            state.extrude_relative = true;
+        }
+        if let Some(tool) = block.tool {
+            state.tool = Some(select_tool(&mut stats, tool));
+        }
+
+        let per_minute = !matches!(modal.group(ModalGroup::FeedMode), Some(930 | 950));
+        if let Some(val) = block.feed.filter(|_| per_minute) {
+            stats.feed_range = Some(widen(stats.feed_range, val));
+        }
+        let with_move = matches!(block.motion, Motion::Work { .. });
+        let spindle_start = block.has('M', 30) || block.has('M', 40);
+        match block.speed {
+            Some(val) if modal.spindle.is_some() || with_move => {
+                stats.spindle_range = Some(widen(stats.spindle_range, val));
+            }
+            None if spindle_start => {
+                if let Some(val) = modal.speed {
+                    stats.spindle_range = Some(widen(stats.spindle_range, val));
+                }
+            }
+            _ => (),
+        }
+
+        match block.word('E') {
+            /* E.g. G92 E0 resets the extruder position */
+            Some(val) if block.has('G', 920) => state.extruder = val,
+            Some(val) => {
+                let delta = if state.extrude_relative {
+                    val
+                } else {
+                    val - state.extruder
+                };
+                stats.filament += delta;
+                state.extruder += delta;
+            }
+            None => (),
+        }
+
+        let (motion, start, end) = match block.motion {
+            Motion::Work { mode, start, end } => (mode, start, end),
+            Motion::Machine { mode, .. } => (mode, [None; 3], [None; 3]),
+            Motion::None | Motion::Other => continue,
+        };
+
+        let distance = match motion {
+            2 | 3 => arc_length(start, end, (block.word('I'), block.word('J')), motion == 2),
+            _ => line_length(start, end),
+        };
+        match motion {
+            0 => stats.rapid_moves += 1,
+            1 => stats.linear_moves += 1,
+            _ => stats.arc_moves += 1,
+        }
+        if motion == 0 {
+            stats.rapid_distance += distance.unwrap_or(0.0);
+            continue;
+        }
+
+        stats.cutting_distance += distance.unwrap_or(0.0);
+        if let Some(tool) = state.tool {
+            let usage = &mut stats.tools[tool];
+            usage.cutting_distance += distance.unwrap_or(0.0);
+            usage.cutting_moves += 1;
+        }
+        if let (Some(z0), Some(z1)) = (start[2], end[2]) {
+            let new = !layers.iter().any(|z| (z - z1).abs() <= LAYER_TOLERANCE);
+            if ((z1 - z0).abs() <= LAYER_TOLERANCE) && new {
+                layers.push(z1);
+            }
+        }
+    }
+
+    stats.layers = layers.len();
+    stats
+}
+
+/// Extends a range of values by `val`
+fn widen(range: Option<(f64, f64)>, val: f64) -> (f64, f64) {
+    range.map_or((val, val), |(min, max)| (min.min(val), max.max(val)))
+}
+
+/// Index of the usage entry of `tool`, added if not yet present
+fn select_tool(stats: &mut GCodeProgramStats, tool: u32) -> usize {
+    match stats.tools.iter().position(|usage| usage.tool == tool) {
+        Some(idx) => idx,
+        None => {
+            stats.tools.push(GCodeToolUsage {
+                tool,
+                ..Default::default()
+            });
+            stats.tools.len() - 1
+        }
+    }
+}
+
+fn line_length(start: [Option<f64>; 3], end: [Option<f64>; 3]) -> Option<f64> {
+    let mut sum = 0.0;
+    for (from, to) in start.iter().zip(end) {
+        match (from, to) {
+            (Some(from), Some(to)) => sum += (to - from) * (to - from),
+            (None, None) => (),
+            _ => return None,
+        }
+    }
+    Some(sum.sqrt())
+}
+
+fn arc_length(
+    start: [Option<f64>; 3],
+    end: [Option<f64>; 3],
+    offsets: (Option<f64>, Option<f64>),
+    clockwise: bool,
+) -> Option<f64> {
+    if offsets.0.is_none() && offsets.1.is_none() {
+        return None;
+    }
+    let (sx, sy, ex, ey) = (start[0]?, start[1]?, end[0]?, end[1]?);
+    let (cx, cy) = (sx + offsets.0.unwrap_or(0.0), sy + offsets.1.unwrap_or(0.0));
+    let radius = (sx - cx).hypot(sy - cy);
+
+    let start_angle = (sy - cy).atan2(sx - cx);
+    let end_angle = (ey - cy).atan2(ex - cx);
+    let mut sweep = end_angle - start_angle;
+    if clockwise {
+        sweep = -sweep;
+    }
+    /* Start and end coinciding is a full circle */
+    if sweep <= 0.0 {
+        sweep += std::f64::consts::TAU;
+    }
+
+    let dz = match (start[2], end[2]) {
+        (Some(from), Some(to)) => to - from,
+        _ => 0.0,
+    };
+    Some((radius * sweep).hypot(dz))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx_eq(left: f64, right: f64) -> bool {
+        (left - right).abs() < 1e-9
+    }
+
+    #[test]
+    fn stats() {
+        let program = "\
+            G21 G90\n\
+            T1 M06\n\
+            M03 S12000\n\
+            G00 X0 Y0 Z5\n\
+            G01 Z0 F200\n\
+            G01 X10 F600\n\
+            G02 X20 Y0 I5 J0\n\
+            G00 Z5\n\
+            T2 M06\n\
+            M03 S8000\n\
+            G00 X0 Y0\n\
+            G01 Z-1\n\
+            G91 G01 Y10\n\
+            G28 X0\n\
+            G01 X5\n\
+            #<depth>=[1 + 2]\n";
+        let stats = program_stats(program);
+
+        let arc = std::f64::consts::PI * 5.0;
+        assert!(approx_eq(
+            stats.cutting_distance,
+            5.0 + 10.0 + arc + 6.0 + 10.0
+        ));
+        assert!(approx_eq(stats.rapid_distance, 5.0 + 20.0));
+        assert_eq!(
+            (stats.rapid_moves, stats.linear_moves, stats.arc_moves),
+            (3, 5, 1)
+        );
+        assert_eq!(stats.layers, 2);
+        assert_eq!(stats.feed_range, Some((200.0, 600.0)));
+        assert_eq!(stats.spindle_range, Some((8000.0, 12000.0)));
+        assert_eq!(stats.tools.len(), 2);
+        assert_eq!((stats.tools[0].tool, stats.tools[0].cutting_moves), (1, 3));
+        assert!(approx_eq(stats.tools[0].cutting_distance, 15.0 + arc));
+        assert_eq!((stats.tools[1].tool, stats.tools[1].cutting_moves), (2, 3));
+        assert!(approx_eq(stats.tools[1].cutting_distance, 16.0));
+        assert_eq!(stats.skipped_lines, 1);
+
+        /* Temperatures, fans, accelerations and inverse time are left out */
+        let stats = program_stats(
+            "M104 S200\nM106 S255\nM204 S500\nS9000\nM03\nS10000\nG01 X1 F100\nG93 G01 X2 F0.5\n",
+        );
+        assert_eq!(stats.feed_range, Some((100.0, 100.0)));
+        assert_eq!(stats.spindle_range, Some((9000.0, 10000.0)));
+    }
+
+    #[test]
+    fn filament() {
+        let program = "\
+            M82\n\
+            G01 X10 Y0 Z0.2 E1.5 F1200\n\
+            G01 E0.5\n\
+            G01 E1.5\n\
+            G01 X20 E3\n\
+            G92 E0\n\
+            M83\n\
+            G01 Z0.4 E0\n\
+            G01 X10 E2\n";
+        let stats = program_stats(program);
+
+        assert!(approx_eq(stats.filament, 5.0));
+        assert_eq!(stats.layers, 2);
+    }
+}
@@ -0,0 +1,361 @@
+use std::fmt::Display;
+
+use crate::interp::{Interpreter, ModalGroup, Motion};
+
+/// Tolerance of Z comparisons, to not flag moves ending exactly at a limit
+const EPSILON: f64 = 1e-6;
+
+/// Options of program verification. Lengths and feed rates are in program
+/// units, i.e. as selected by G20/G21, which are not converted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GCodeVerifyOptions {
+    /// Stock extents in work coordinates, as (min, max) corners
+    pub stock: Option<([f64; 3], [f64; 3])>,
+    /// Deepest a feed move may cut below the Z it starts at, or below the
+    /// local floor if lower, i.e. the deepest point cut so far at the same
+    /// XY position, or else the stock top
+    pub max_step_down: Option<f64>,
+    /// Highest feed rate allowed for any tool
+    pub max_feed: Option<f64>,
+    /// Highest feed rate allowed for specific tools, as (tool, feed rate),
+    /// taking precedence over `max_feed`
+    pub tool_max_feed: Vec<(u32, f64)>,
+    /// Whether the machine has already been homed when the program starts
+    pub homed: bool,
+    /// Whether a work coordinate system has already been selected when the
+    /// program starts
+    pub wcs_selected: bool,
+}
+
+/// Kind of dangerous situation found by `verify_program()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GCodeVerifyKind {
+    /// Rapid move within the stock outline moving down below the stock
+    /// top, or moving in XY while below it
+    RapidIntoStock,
+    /// Feed move cutting the contained depth, below its start or the local
+    /// floor, exceeding the maximum step down
+    StepDownExceeded(f64),
+    /// Motion prior to homing (G28)
+    NotHomed,
+    /// Motion prior to selecting a work coordinate system (G54-G59)
+    MissingWcs,
+    /// Feed move at the contained feed rate, above the limit of the tool
+    FeedTooHigh(f64),
+}
+impl Display for GCodeVerifyKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RapidIntoStock => write!(f, "rapid move into stock"),
+            Self::StepDownExceeded(depth) => write!(f, "step down of {} exceeds maximum", depth),
+            Self::NotHomed => write!(f, "motion before homing"),
+            Self::MissingWcs => write!(f, "motion before work coordinate system is selected"),
+            Self::FeedTooHigh(feed) => write!(f, "feed rate {} exceeds tool limit", feed),
+        }
+    }
+}
+
+/// Dangerous situation found by `verify_program()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCodeVerifyDiagnostic {
+    /// Line number, starting at 1
+    pub line: usize,
+    pub kind: GCodeVerifyKind,
+}
+impl Display for GCodeVerifyDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+#[derive(Default)]
+struct VerifyState {
+    homed: bool,
+    homing_warned: bool,
+    wcs: bool,
+    wcs_warned: bool,
+    /// Deepest Z reached by feed moves within the stock, by XY end point
+    floors: Vec<([f64; 2], f64)>,
+}
+
+/// Replays a program against the given stock and initial machine state
+/// before running it for real, flagging moves which may crash the machine
+/// or break a tool.
+///
+/// Positions with unknown axes, e.g. at program start, are assumed to be
+/// within the stock outline. Arcs are checked by their end points only.
+/// Moves in machine coordinates (G53) are checked for homing, and rapids
+/// among them for moving in XY while below the stock top, but leave the
+/// axes moved unknown. Lines using parameters, expressions or control flow
+/// are not analyzed, and reset the known position, as do G codes using axis
+/// words otherwise, e.g. G28 or G92.
+pub fn verify_program(program: &str, options: &GCodeVerifyOptions) -> Vec<GCodeVerifyDiagnostic> {
+    let mut diags = vec![];
+    let mut interp = Interpreter::new();
+    let mut state = VerifyState {
+        homed: options.homed,
+        wcs: options.wcs_selected,
+        ..Default::default()
+    };
+
+    for (idx, text) in program.lines().enumerate() {
+        let Some(block) = interp.line(text) else {
+            continue;
+        };
+        let mut push = |kind| {
+            diags.push(GCodeVerifyDiagnostic {
+                line: idx + 1,
+                kind,
+            })
+        };
+        let modal = &interp.state;
+        state.homed |= block.has('G', 280);
+        state.wcs |= modal.group(ModalGroup::Wcs).is_some();
+
+        let (motion, start, end) = match block.motion {
+            Motion::Work { mode, start, end } => (mode, start, end),
+            Motion::Machine { mode, start, axes } => {
+                if !state.homed && !state.homing_warned {
+                    state.homing_warned = true;
+                    push(GCodeVerifyKind::NotHomed);
+                }
+                /* Z is unknown in work coordinates once moved */
+                let xy_move = axes[0].is_some() || axes[1].is_some();
+                if let (0, true, None, Some((min, max))) = (mode, xy_move, axes[2], options.stock) {
+                    let below = start[2].is_some_and(|z| z < max[2] - EPSILON);
+                    if below && overlaps_xy(start, start, min, max) {
+                        push(GCodeVerifyKind::RapidIntoStock);
+                    }
+                }
+                continue;
+            }
+            Motion::None | Motion::Other => continue,
+        };
+
+        if !state.homed && !state.homing_warned {
+            state.homing_warned = true;
+            push(GCodeVerifyKind::NotHomed);
+        }
+        if !state.wcs && !state.wcs_warned {
+            state.wcs_warned = true;
+            push(GCodeVerifyKind::MissingWcs);
+        }
+
+        if motion == 0 {
+            if let Some((min, max)) = options.stock {
+                /* Retracts never reach into the stock */
+                let xy_move = (0..2).any(|i| block.axes[i].is_some() && (start[i] != end[i]));
+                let z = match (start[2], end[2]) {
+                    (from, Some(to)) if !xy_move && from.is_none_or(|from| to < from) => Some(to),
+                    _ if xy_move => lowest_z(start, end),
+                    _ => None,
+                };
+                if z.is_some_and(|z| z < max[2] - EPSILON) && overlaps_xy(start, end, min, max) {
+                    push(GCodeVerifyKind::RapidIntoStock);
+                }
+            }
+            continue;
+        }
+
+        let limit = modal
+            .tool
+            .and_then(|tool| {
+                options
+                    .tool_max_feed
+                    .iter()
+                    .find(|(t, _)| *t == tool)
+                    .map(|(_, feed)| *feed)
+            })
+            .or(options.max_feed);
+        if let (Some(feed), Some(limit)) = (modal.feed, limit) {
+            if feed > limit {
+                push(GCodeVerifyKind::FeedTooHigh(feed));
+            }
+        }
+
+        if let (Some((min, max)), Some(z)) = (options.stock, end[2]) {
+            if overlaps_xy(end, end, min, max) {
+                let floor = |xy: [Option<f64>; 3]| match (xy[0], xy[1]) {
+                    (Some(x), Some(y)) => state
+                        .floors
+                        .iter()
+                        .filter(|(at, _)| (at[0] - x).hypot(at[1] - y) <= EPSILON)
+                        .map(|&(_, z)| z)
+                        .fold(max[2], f64::min),
+                    _ => max[2],
+                };
+                let top = start[2].map_or(max[2], |from| from.min(floor(start)));
+                let depth = top - z;
+                if options
+                    .max_step_down
+                    .is_some_and(|step| depth > step + EPSILON)
+                {
+                    push(GCodeVerifyKind::StepDownExceeded(depth));
+                }
+                if let (Some(x), Some(y)) = (end[0], end[1]) {
+                    if z < floor(end) {
+                        state.floors.push(([x, y], z));
+                    }
+                }
+            }
+        }
+    }
+
+    diags
+}
+
+/// Lowest known Z of a straight move
+fn lowest_z(start: [Option<f64>; 3], end: [Option<f64>; 3]) -> Option<f64> {
+    match (start[2], end[2]) {
+        (Some(from), Some(to)) => Some(from.min(to)),
+        (from, to) => from.or(to),
+    }
+}
+
+/// Whether the XY extent of a straight move overlaps the stock outline
+fn overlaps_xy(
+    start: [Option<f64>; 3],
+    end: [Option<f64>; 3],
+    min: [f64; 3],
+    max: [f64; 3],
+) -> bool {
+    (0..2).all(|i| match (start[i], end[i]) {
+        (Some(from), Some(to)) => (from.max(to) > min[i]) && (from.min(to) < max[i]),
+        (Some(val), None) | (None, Some(val)) => (val > min[i]) && (val < max[i]),
+        (None, None) => true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify() {
+        let program = "\
+            G21 G90\n\
+            G00 Z10\n\
+            G28\n\
+            G54\n\
+            T1 M06\n\
+            G00 X-5 Y-5 Z5\n\
+            G00 Z-1\n\
+            G01 Z-2 F100\n\
+            G00 Z5\n\
+            G00 X10 Y10\n\
+            G00 Z1\n\
+            G01 Z-1.5 F100\n\
+            G01 Z-4 F100\n\
+            G01 X20 F1500\n\
+            T2 M06\n\
+            G01 X30\n\
+            G00 Z-6\n";
+        let options = GCodeVerifyOptions {
+            stock: Some(([0.0, 0.0, -10.0], [50.0, 50.0, 0.0])),
+            max_step_down: Some(2.0),
+            max_feed: Some(2000.0),
+            tool_max_feed: vec![(1, 1000.0)],
+            ..Default::default()
+        };
+        let diags = verify_program(program, &options);
+
+        assert_eq!(
+            diags,
+            [
+                GCodeVerifyDiagnostic {
+                    line: 2,
+                    kind: GCodeVerifyKind::NotHomed
+                },
+                GCodeVerifyDiagnostic {
+                    line: 2,
+                    kind: GCodeVerifyKind::MissingWcs
+                },
+                GCodeVerifyDiagnostic {
+                    line: 13,
+                    kind: GCodeVerifyKind::StepDownExceeded(2.5)
+                },
+                GCodeVerifyDiagnostic {
+                    line: 14,
+                    kind: GCodeVerifyKind::FeedTooHigh(1500.0)
+                },
+                GCodeVerifyDiagnostic {
+                    line: 17,
+                    kind: GCodeVerifyKind::RapidIntoStock
+                },
+            ]
+        );
+        assert_eq!(
+            diags[2].to_string(),
+            "line 13: step down of 2.5 exceeds maximum"
+        );
+    }
+
+    #[test]
+    fn verify_per_plunge() {
+        let program = "\
+            G00 X10 Y10 Z5\n\
+            G01 Z-2 F100\n\
+            G01 Z-4\n\
+            G01 Z-6\n\
+            G00 Z5\n\
+            G00 X30 Y30\n\
+            G00 Z1\n\
+            G01 Z-3\n\
+            G00 Z5\n\
+            G00 X10 Y10\n\
+            G00 Z1\n\
+            G01 Z-7\n\
+            G00 X20 Z2\n";
+        let options = GCodeVerifyOptions {
+            stock: Some(([0.0, 0.0, -10.0], [50.0, 50.0, 0.0])),
+            max_step_down: Some(2.0),
+            homed: true,
+            wcs_selected: true,
+            ..Default::default()
+        };
+
+        /* Retracts are not flagged, and depths are measured per plunge,
+         * from the floor already cut at the same position */
+        assert_eq!(
+            verify_program(program, &options),
+            [
+                GCodeVerifyDiagnostic {
+                    line: 8,
+                    kind: GCodeVerifyKind::StepDownExceeded(3.0)
+                },
+                GCodeVerifyDiagnostic {
+                    line: 13,
+                    kind: GCodeVerifyKind::RapidIntoStock
+                },
+            ]
+        );
+
+        /* Moves in machine coordinates are checked as far as known */
+        let program = "\
+            G54\n\
+            G53 G00 Z0\n\
+            G28\n\
+            G00 X10 Y10 Z1\n\
+            G01 Z-1 F100\n\
+            G53 G00 X0 Y0\n\
+            G53 G00 Z0\n\
+            G53 G00 X0 Y0\n";
+        let options = GCodeVerifyOptions {
+            stock: Some(([0.0, 0.0, -10.0], [50.0, 50.0, 0.0])),
+            ..Default::default()
+        };
+        assert_eq!(
+            verify_program(program, &options),
+            [
+                GCodeVerifyDiagnostic {
+                    line: 2,
+                    kind: GCodeVerifyKind::NotHomed
+                },
+                GCodeVerifyDiagnostic {
+                    line: 6,
+                    kind: GCodeVerifyKind::RapidIntoStock
+                },
+            ]
+        );
+    }
+}
@@ -1,17 +1,994 @@
-use std::io::Write;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::mem;
 
-use crate::{GCodeError, GCodeOptions, GCodePosition};
+use crate::fillet::arc_geometry;
+use crate::{
+    GCodeBasicCostModel, GCodeBlockDelete, GCodeCommand, GCodeCommandIterator, GCodeCompensation,
+    GCodeCostModel, GCodeCylinderWrap, GCodeDecimalSeparator, GCodeDialect, GCodeError, GCodeExpr,
+    GCodeFanSpeed, GCodeFeedMode, GCodeFirstLayer, GCodeHeater, GCodeIdleAction, GCodeIdleGuard,
+    GCodeLaserMode, GCodeLetterCase, GCodeLimitViolation, GCodeLineEnding, GCodeMachineLimits,
+    GCodeMachineProfile, GCodeOffset, GCodeOptions, GCodeParam, GCodePathSegment, GCodePlunge,
+    GCodePosition, GCodeProbeResult, GCodeRetract, GCodeRotaryAxis, GCodeRotaryConfig,
+    GCodeRotation, GCodeSpindle,
+};
 
-pub struct GCodeWriter<'a> {
-    writer: Box<dyn Write + 'a>,
+pub struct GCodeWriter<'a, W: Write> {
+    writer: W,
+    /// Current line, passed on to the output once complete
+    line: Vec<u8>,
+    /// Output style, None if lines are passed on as-is
+    style: Option<GCodeWriterBuilder>,
+    /// Current line after styling
+    styled: Vec<u8>,
+    /// Styling carried from one line to the next
+    style_state: StyleState,
+    /// Output captured instead of being passed on, see `optional_block()`
+    capture: Option<Vec<u8>>,
+    /// Number of bytes passed on to the output
+    bytes_written: u64,
+    /// Whether the last command passed on was left without a line ending,
+    /// such that the next is separated from it by a space
+    line_open: bool,
+    dialect: GCodeDialect,
+    /// Whether each command is terminated by a line ending
+    auto_newline: bool,
+    /// Defined subprograms, by number
+    subprograms: BTreeMap<u32, Subprogram>,
+    rotary_config: GCodeRotaryConfig,
+    decimal_separator: GCodeDecimalSeparator,
+    state: GCodeWriterState,
+    block_delete: GCodeBlockDelete,
+    idle_guard: Option<GCodeIdleGuard>,
+    laser_mode: Option<GCodeLaserMode>,
+    cylinder_wrap: Option<GCodeCylinderWrap>,
+    /// Feed mode in effect before wrapping, restored when it stops
+    unwrapped_feed_mode: GCodeFeedMode,
+    first_layer: Option<GCodeFirstLayer>,
+    layer: FirstLayerState,
+    profile: GCodeMachineProfile,
+    /// Origin of each work coordinate system in machine coordinates, axes
+    /// are absent where unknown
+    wcs_origins: [GCodePosition; 9],
+    /// Default retract of `travel_to()`
+    retract: Option<GCodeRetract>,
+    /// Descent of `travel_to()`
+    plunge: Option<GCodePlunge>,
+    /// Whether the controller rotation changed since the last move in X/Y
+    rotation_changed: bool,
+    cost_model: Box<dyn GCodeCostModel + 'a>,
+    /// Sum of the estimated durations of all commands, in seconds
+    estimated_time: f64,
+    /// Time spent without motion since the last move, in seconds
+    idle_time: f64,
 }
 
-impl<'a> GCodeWriter<'a> {
-    pub fn new(writer: impl Write + 'a) -> Result<Self, GCodeError> {
-        Ok(Self {
-            writer: Box::new(writer),
+/// Modal state of the machine, as modeled by a `GCodeWriter`
+#[derive(Clone, Debug, PartialEq)]
+pub struct GCodeWriterState {
+    /// Current position, axes are absent until first moved
+    pub position: GCodePosition,
+    /// Last emitted angle of each rotary axis, in degrees
+    pub rotary: [Option<f64>; 3],
+    /// Last programmed feed rate, in units per minute
+    pub feed_rate: Option<f64>,
+    pub feed_mode: GCodeFeedMode,
+    pub spindle: GCodeSpindle,
+    pub compensation: GCodeCompensation,
+    /// Active work coordinate system, 1 = G54 through 9 = G59.3
+    pub wcs: u8,
+    /// Active coordinate system rotation
+    pub rotation: Option<GCodeRotation>,
+    /// Target temperature of each heater set so far, in degrees Celsius
+    pub temperatures: Vec<(GCodeHeater, f64)>,
+    /// PWM duty cycle of each fan, by index
+    pub fans: Vec<u8>,
+}
+impl Default for GCodeWriterState {
+    fn default() -> Self {
+        Self {
+            position: GCodePosition::from_raw(None, None, None),
+            rotary: [None; 3],
+            feed_rate: None,
+            feed_mode: GCodeFeedMode::default(),
+            spindle: GCodeSpindle::default(),
+            compensation: GCodeCompensation::Off,
+            wcs: 1,
+            rotation: None,
+            temperatures: vec![],
+            fans: vec![],
+        }
+    }
+}
+
+/// Progress through the first layer, see `GCodeFirstLayer`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FirstLayerState {
+    /// No feed move with known Z yet
+    Pending,
+    /// Within the first layer, at the contained raw Z
+    Active(i64),
+    Done,
+}
+
+/// Configures the output style of a `GCodeWriter`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GCodeWriterBuilder {
+    dialect: GCodeDialect,
+    decimal_separator: GCodeDecimalSeparator,
+    line_ending: GCodeLineEnding,
+    word_spacing: bool,
+    letter_case: Option<GCodeLetterCase>,
+    auto_newline: bool,
+    auto_flush: bool,
+}
+impl Default for GCodeWriterBuilder {
+    fn default() -> Self {
+        Self {
+            dialect: GCodeDialect::default(),
+            decimal_separator: GCodeDecimalSeparator::default(),
+            line_ending: GCodeLineEnding::default(),
+            word_spacing: true,
+            letter_case: None,
+            auto_newline: false,
+            auto_flush: false,
+        }
+    }
+}
+impl GCodeWriterBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn dialect(&mut self, dialect: GCodeDialect) -> &mut Self {
+        self.dialect = dialect;
+        self
+    }
+
+    pub fn decimal_separator(&mut self, separator: GCodeDecimalSeparator) -> &mut Self {
+        self.decimal_separator = separator;
+        self
+    }
+
+    pub fn line_ending(&mut self, line_ending: GCodeLineEnding) -> &mut Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Whether words are separated by spaces, enabled by default. Without
+    /// spacing, e.g. `G01X1.0000F100.00` is written, saving space on
+    /// controllers with limited memory.
+    pub fn word_spacing(&mut self, spacing: bool) -> &mut Self {
+        self.word_spacing = spacing;
+        self
+    }
+
+    /// Converts all letters outside of comments to the given case, by
+    /// default letters are written in the case customary for each command
+    pub fn letter_case(&mut self, case: GCodeLetterCase) -> &mut Self {
+        self.letter_case = Some(case);
+        self
+    }
+
+    /// Whether each command is terminated by a line ending, disabled by
+    /// default. When disabled, commands are written to the same line,
+    /// separated by spaces, until `GCodeWriter::newline()` is called.
+    pub fn auto_newline(&mut self, auto_newline: bool) -> &mut Self {
+        self.auto_newline = auto_newline;
+        self
+    }
+
+    /// Whether the underlying writer is flushed after each line, disabled by
+    /// default
+    pub fn auto_flush(&mut self, auto_flush: bool) -> &mut Self {
+        self.auto_flush = auto_flush;
+        self
+    }
+
+    pub fn build<'a, W: Write>(&self, writer: W) -> Result<GCodeWriter<'a, W>, GCodeError> {
+        /* Avoid per-byte processing when no styling is required */
+        let plain = (self.line_ending == GCodeLineEnding::Lf)
+            && self.word_spacing
+            && self.letter_case.is_none()
+            && !self.auto_flush;
+
+        Ok(GCodeWriter {
+            writer,
+            line: Vec::with_capacity(128),
+            style: (!plain).then_some(*self),
+            styled: vec![],
+            style_state: StyleState::default(),
+            capture: None,
+            bytes_written: 0,
+            line_open: false,
+            dialect: self.dialect,
+            auto_newline: self.auto_newline,
+            subprograms: BTreeMap::new(),
+            rotary_config: GCodeRotaryConfig::default(),
+            decimal_separator: self.decimal_separator,
+            state: GCodeWriterState::default(),
+            block_delete: GCodeBlockDelete::default(),
+            idle_guard: None,
+            laser_mode: None,
+            cylinder_wrap: None,
+            unwrapped_feed_mode: GCodeFeedMode::default(),
+            first_layer: None,
+            layer: FirstLayerState::Pending,
+            profile: GCodeMachineProfile::default(),
+            wcs_origins: [GCodePosition::from_raw(None, None, None); 9],
+            retract: None,
+            plunge: None,
+            rotation_changed: false,
+            cost_model: Box::new(GCodeBasicCostModel::default()),
+            estimated_time: 0.0,
+            idle_time: 0.0,
         })
     }
+}
+
+/// Subprogram written by `GCodeWriter::define_subprogram()`
+#[derive(Clone, Debug)]
+struct Subprogram {
+    /// Lines of the body
+    body: Vec<u8>,
+    /// State the body was written from
+    entry: GCodeWriterState,
+    /// State following the body
+    exit: GCodeWriterState,
+    /// Estimated duration of the body, in seconds
+    estimated_time: f64,
+}
+
+/// Progress of `style_line()` through the output, carried from one call to
+/// the next
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct StyleState {
+    /// Byte ending the comment being copied, if within one
+    comment_end: Option<u8>,
+}
+
+/// Applies output style `style` to `line`, appending the result to `out`.
+/// Comments are copied unchanged, other than their line ending.
+fn style_line(style: &GCodeWriterBuilder, state: &mut StyleState, line: &[u8], out: &mut Vec<u8>) {
+    for &c in line {
+        if let Some(end) = state.comment_end {
+            if (c == end) || (c == b'\n') {
+                state.comment_end = None;
+            }
+            if c != b'\n' {
+                out.push(c);
+                continue;
+            }
+        }
+
+        match c {
+            b'\n' => match style.line_ending {
+                GCodeLineEnding::Lf => out.push(b'\n'),
+                GCodeLineEnding::CrLf => out.extend(b"\r\n"),
+            },
+            b' ' if !style.word_spacing => (),
+            c => {
+                match c {
+                    b'(' => state.comment_end = Some(b')'),
+                    b';' => state.comment_end = Some(b'\n'),
+                    _ => (),
+                }
+                out.push(match style.letter_case {
+                    Some(GCodeLetterCase::Upper) => c.to_ascii_uppercase(),
+                    Some(GCodeLetterCase::Lower) => c.to_ascii_lowercase(),
+                    None => c,
+                });
+            }
+        }
+    }
+}
+
+impl GCodeWriter<'_, io::Sink> {
+    /// Same as `GCodeWriterBuilder::new()`. Defined for a single writer type
+    /// only, such that it can be called without naming one.
+    pub fn builder() -> GCodeWriterBuilder {
+        GCodeWriterBuilder::new()
+    }
+}
+
+impl<'a, W: Write> GCodeWriter<'a, W> {
+    /// Creates a writer with the default output style, see
+    /// `GCodeWriterBuilder` for other styles
+    pub fn new(writer: W) -> Result<Self, GCodeError> {
+        GCodeWriterBuilder::new().build(writer)
+    }
+
+    pub fn dialect(&self) -> GCodeDialect {
+        self.dialect
+    }
+
+    pub fn set_dialect(&mut self, dialect: GCodeDialect) {
+        self.dialect = dialect;
+    }
+
+    pub fn decimal_separator(&self) -> GCodeDecimalSeparator {
+        self.decimal_separator
+    }
+
+    pub fn set_decimal_separator(&mut self, separator: GCodeDecimalSeparator) {
+        self.decimal_separator = separator;
+    }
+
+    pub fn rotary_config(&self) -> GCodeRotaryConfig {
+        self.rotary_config
+    }
+
+    pub fn set_rotary_config(&mut self, config: GCodeRotaryConfig) {
+        self.rotary_config = config;
+    }
+
+    pub fn block_delete(&self) -> GCodeBlockDelete {
+        self.block_delete
+    }
+
+    /// Selects how blocks written by `optional_block()` are output
+    pub fn set_block_delete(&mut self, block_delete: GCodeBlockDelete) {
+        self.block_delete = block_delete;
+    }
+
+    /// Writes commands of `body` as optional blocks, e.g. probing which may
+    /// be skipped by the operator. Whether these are executed is only known
+    /// when emitted with `/`, so axes moved within `body` are afterwards
+    /// considered unknown, other state assumes `body` was executed. When
+    /// omitted, all state is left as prior to `body`.
+    pub fn optional_block(
+        &mut self,
+        body: impl FnOnce(&mut Self) -> Result<(), GCodeError>,
+    ) -> Result<(), GCodeError> {
+        let (state, estimated_time) = (self.state.clone(), self.estimated_time);
+        let (wcs_origins, layer, idle_time) = (self.wcs_origins, self.layer, self.idle_time);
+
+        /* Block delete only applies to the start of a line */
+        self.close_line()?;
+        let outer = self.capture.replace(vec![]);
+        let res = body(self).and_then(|_| self.close_line());
+        let data = mem::replace(&mut self.capture, outer).unwrap_or_default();
+        self.line_open = false;
+        res?;
+
+        match self.block_delete {
+            GCodeBlockDelete::Emit => {
+                for line in data.split_inclusive(|&c| c == b'\n') {
+                    /* Lines of nested optional blocks are already marked */
+                    if !line.starts_with(b"/") {
+                        self.line.push(b'/');
+                    }
+                    self.line.extend(line);
+                }
+                self.emit()?;
+
+                let keep =
+                    |before: Option<i64>, after: Option<i64>| after.filter(|_| before == after);
+                let (position, cur) = (state.position, self.state.position);
+                self.state.position = GCodePosition::from_raw(
+                    keep(position.x_raw(), cur.x_raw()),
+                    keep(position.y_raw(), cur.y_raw()),
+                    keep(position.z_raw(), cur.z_raw()),
+                );
+                for (cur, before) in self.state.rotary.iter_mut().zip(state.rotary) {
+                    if *cur != before {
+                        *cur = None;
+                    }
+                }
+            }
+            GCodeBlockDelete::Include => {
+                self.line.extend(&data);
+                self.emit()?;
+            }
+            GCodeBlockDelete::Omit => {
+                self.state = state;
+                self.estimated_time = estimated_time;
+                self.wcs_origins = wcs_origins;
+                self.layer = layer;
+                self.idle_time = idle_time;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets the model used to estimate the duration of each command written
+    /// from now on, `GCodeBasicCostModel` by default
+    pub fn set_cost_model(&mut self, model: impl GCodeCostModel + 'a) {
+        self.cost_model = Box::new(model);
+    }
+
+    /// Estimated duration of all commands written so far, in seconds
+    pub fn estimated_time(&self) -> f64 {
+        self.estimated_time
+    }
+
+    pub fn machine_limits(&self) -> GCodeMachineLimits {
+        self.profile.limits
+    }
+
+    /// Sets the soft limits of the machine. Commands exceeding these fail
+    /// with `GCodeError::OutOfRangeError`, `limit_violation()` provides the
+    /// details. Moves to expressions can only be checked for their feed rate.
+    pub fn set_machine_limits(&mut self, limits: GCodeMachineLimits) {
+        self.profile.limits = limits;
+    }
+
+    pub fn machine_profile(&self) -> &GCodeMachineProfile {
+        &self.profile
+    }
+
+    /// Sets the configuration of the machine, i.e. its soft limits (see
+    /// `set_machine_limits()`) and named positions (see `go_to_named()`)
+    pub fn set_machine_profile(&mut self, profile: GCodeMachineProfile) {
+        self.profile = profile;
+    }
+
+    pub fn rotation(&self) -> Option<GCodeRotation> {
+        self.state.rotation
+    }
+
+    /// Rotates the coordinate system of all following moves, or cancels
+    /// rotation if None. Dialects supporting it rotate on the controller
+    /// (G68/G69), for all others the writer rotates positions prior to
+    /// writing them. Positions tracked and checked by the writer are those
+    /// written, i.e. unrotated for the former and rotated for the latter.
+    /// Moves to expressions in X or Y can not be rotated by the writer.
+    ///
+    /// As Fanuc requires, the first move in X or Y after the rotation on the
+    /// controller changes gives both X and Y, completed from the current
+    /// position. LinuxCNC only rotates work coordinate systems around their
+    /// origin (G10 L2 R), so the writer rotates positions for it.
+    pub fn set_rotation(&mut self, rotation: Option<GCodeRotation>) -> Result<(), GCodeError> {
+        if let Some(rotation) = rotation {
+            let values = [rotation.center[0], rotation.center[1], rotation.angle];
+            if !values.iter().all(|val| val.is_finite()) {
+                return Err(GCodeError::OutOfRangeError);
+            }
+        }
+        self.state.rotation = rotation;
+        if !self.dialect.supports_rotation() {
+            return Ok(());
+        }
+
+        match rotation {
+            Some(rotation) => {
+                write!(self.line, "G68 X")?;
+                self.write_number(rotation.center[0], 4)?;
+                write!(self.line, " Y")?;
+                self.write_number(rotation.center[1], 4)?;
+                write!(self.line, " R")?;
+                self.write_number(rotation.angle, 4)?;
+            }
+            None => write!(self.line, "G69")?,
+        }
+        self.rotation_changed = true;
+        self.end_line()
+    }
+
+    pub fn cylinder_wrap(&self) -> Option<GCodeCylinderWrap> {
+        self.cylinder_wrap
+    }
+
+    /// Wraps all following moves onto a cylinder turned by the A axis, or
+    /// stops wrapping if None. Moves are given as if the cylinder surface
+    /// were unrolled, Y being written as the A angle, and arcs are replaced
+    /// by linear moves. As the surface speed of a rotary move depends on
+    /// the diameter, feed rates are written in inverse time (G93) while
+    /// wrapping, stopping switches back to the feed mode in effect before.
+    ///
+    /// Positions tracked and checked by the writer are the unrolled ones.
+    /// Arcs can not be combined with software rotation, nor moves to
+    /// expressions in Y, failing with `GCodeError::UnsupportedError`.
+    pub fn set_cylinder_wrap(&mut self, wrap: Option<GCodeCylinderWrap>) -> Result<(), GCodeError> {
+        if let Some(wrap) = wrap {
+            let valid = |val: f64| val.is_finite() && (val > 0.0);
+            if !valid(wrap.diameter) || !valid(wrap.tolerance) {
+                return Err(GCodeError::OutOfRangeError);
+            }
+        }
+        let prior = self.state.feed_mode;
+        match (wrap, self.cylinder_wrap) {
+            (Some(_), _) => self.set_feed_mode(GCodeFeedMode::InverseTime)?,
+            (None, Some(_)) => self.set_feed_mode(self.unwrapped_feed_mode)?,
+            (None, None) => (),
+        }
+        if self.cylinder_wrap.is_none() {
+            self.unwrapped_feed_mode = prior;
+        }
+        self.cylinder_wrap = wrap;
+        Ok(())
+    }
+
+    pub fn retract(&self) -> Option<GCodeRetract> {
+        self.retract
+    }
+
+    /// Sets the retract used by `travel_to()` when none is given per call
+    pub fn set_retract(&mut self, retract: Option<GCodeRetract>) {
+        self.retract = retract;
+    }
+
+    pub fn plunge(&self) -> Option<GCodePlunge> {
+        self.plunge
+    }
+
+    /// Sets the descent of `travel_to()`. If None, the whole descent is fed
+    /// at the current feed rate.
+    pub fn set_plunge(&mut self, plunge: Option<GCodePlunge>) {
+        self.plunge = plunge;
+    }
+
+    /// Travels to `pos` without touching the work: retracts, moves in X/Y at
+    /// rapid, then descends to Z of `pos`, or back to the prior Z if `pos`
+    /// has none. The descent is rapid down to the clearance of the
+    /// configured plunge, and fed at its feed rate from there on, given in
+    /// inverse time if that is the feed mode. Z above the retract is reached
+    /// at rapid.
+    /// `retract` overrides the configured retract, if neither is set this
+    /// fails with `GCodeError::StateError`, as it does for Z-hops, returns
+    /// to an unknown Z, and descents without a known feed rate.
+    pub fn travel_to(
+        &mut self,
+        pos: GCodePosition,
+        retract: Option<GCodeRetract>,
+    ) -> Result<(), GCodeError> {
+        let retract = retract.or(self.retract).ok_or(GCodeError::StateError)?;
+        let cur_z = self.state.position.z_f64();
+        let safe_z = self.retract_z(retract)?;
+        let end_z = pos.z_f64().or(cur_z).ok_or(GCodeError::StateError)?;
+
+        let (rapid_z, feed_rate) = match self.plunge {
+            Some(plunge) => {
+                let valid = plunge.clearance.is_finite()
+                    && (plunge.clearance >= 0.0)
+                    && plunge.feed_rate.is_finite()
+                    && (plunge.feed_rate > 0.0);
+                if !valid {
+                    return Err(GCodeError::OutOfRangeError);
+                }
+                (
+                    (end_z + plunge.clearance).min(safe_z),
+                    Some(plunge.feed_rate),
+                )
+            }
+            None => (safe_z, self.state.feed_rate),
+        };
+        if (end_z < rapid_z) && feed_rate.is_none() {
+            return Err(GCodeError::StateError);
+        }
+        let feed = GCodeOptions {
+            feed_rate,
+            ..Default::default()
+        };
+
+        let (x, y, _) = pos.as_f64();
+        let moves = [
+            (GCodePosition::from_f64(None, None, Some(safe_z))?, true),
+            (GCodePosition::from_f64(x, y, None)?, true),
+            (GCodePosition::from_f64(None, None, Some(rapid_z))?, true),
+            /* Rising to a Z above the retract needs no feed */
+            (
+                GCodePosition::from_f64(None, None, Some(end_z))?,
+                end_z >= rapid_z,
+            ),
+        ];
+        /* Inverse time feed rates are given per move */
+        let inverse = self.state.feed_mode == GCodeFeedMode::InverseTime;
+        for (step, rapid) in moves {
+            /* Drop steps not changing the position */
+            if !step.is_empty() && (step.merge(&self.state.position) != self.state.position) {
+                let options =
+                    (!rapid && (inverse || (feed_rate != self.state.feed_rate))).then_some(feed);
+                self.move_to(step, options, rapid)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Z reached by `retract` from the current position
+    fn retract_z(&self, retract: GCodeRetract) -> Result<f64, GCodeError> {
+        match (retract, self.state.position.z_f64()) {
+            (GCodeRetract::SafeZ(z), Some(cur)) => Ok(z.max(cur)),
+            (GCodeRetract::SafeZ(z), None) => Ok(z),
+            (GCodeRetract::Hop(hop), Some(cur)) if hop.is_finite() && (hop >= 0.0) => Ok(cur + hop),
+            (GCodeRetract::Hop(_), Some(_)) => Err(GCodeError::OutOfRangeError),
+            (GCodeRetract::Hop(_), None) => Err(GCodeError::StateError),
+        }
+    }
+
+    /// Rapidly moves to the position named `name` in the machine profile,
+    /// in machine coordinates (G53), such that the tool stays clear of the
+    /// work: retracts first, by the configured retract (see
+    /// `set_retract()`), or else to the top of the Z travel. X/Y are moved
+    /// next, and Z to that of the position last. Fails with
+    /// `GCodeError::NotFoundError` if `name` is not defined, and with
+    /// `GCodeError::StateError` if neither the retract nor the Z travel is
+    /// known.
+    pub fn go_to_named(&mut self, name: &str) -> Result<(), GCodeError> {
+        let target = self
+            .profile
+            .named_position(name)
+            .ok_or(GCodeError::NotFoundError)?;
+        let (x, y, z) = target.as_f64();
+
+        match (self.retract, self.profile.limits.travel[2]) {
+            (Some(retract), _) => {
+                let safe_z = GCodePosition::from_f64(None, None, Some(self.retract_z(retract)?))?;
+                if safe_z.merge(&self.state.position) != self.state.position {
+                    self.move_to(safe_z, None, true)?;
+                }
+            }
+            (None, Some((_, top))) => {
+                self.move_to_machine(GCodePosition::from_f64(None, None, Some(top))?)?
+            }
+            (None, None) => return Err(GCodeError::StateError),
+        }
+
+        let moves = [
+            GCodePosition::from_f64(x, y, None)?,
+            GCodePosition::from_f64(None, None, z)?,
+        ];
+        for pos in moves.into_iter().filter(|pos| !pos.is_empty()) {
+            self.move_to_machine(pos)?;
+        }
+
+        Ok(())
+    }
+
+    /// First machine limit which a move to `pos` with `options` would exceed
+    pub fn limit_violation(
+        &self,
+        pos: GCodePosition,
+        options: Option<GCodeOptions>,
+    ) -> Option<GCodeLimitViolation> {
+        let feed_rate = options.and_then(|options| options.feed_rate);
+        let machine = offset_axes(pos, self.wcs_origins[usize::from(self.state.wcs) - 1], 1);
+        self.profile
+            .limits
+            .check_position(&pos)
+            .or_else(|| self.profile.limits.check_travel(&machine))
+            .or_else(|| feed_rate.and_then(|feed| self.profile.limits.check_feed_rate(feed)))
+    }
+
+    /// Modal state resulting from all commands written so far
+    pub fn state(&self) -> &GCodeWriterState {
+        &self.state
+    }
+
+    /// Panics, listing all differences, if the modeled state differs from
+    /// `expected`. Intended for tests of code generating programs, e.g.
+    /// checking that the spindle is off at the end of a program.
+    pub fn assert_state(&self, expected: &GCodeWriterState) {
+        let (cur, exp) = (&self.state, expected);
+        let mut diffs = vec![];
+        if cur.position != exp.position {
+            diffs.push(format!(
+                "position: expected {}, found {}",
+                exp.position, cur.position
+            ));
+        }
+        let mut diff = |name: &str, exp: &dyn std::fmt::Debug, cur: &dyn std::fmt::Debug| {
+            let (exp, cur) = (format!("{:?}", exp), format!("{:?}", cur));
+            if exp != cur {
+                diffs.push(format!("{}: expected {}, found {}", name, exp, cur));
+            }
+        };
+        diff("rotary", &exp.rotary, &cur.rotary);
+        diff("feed_rate", &exp.feed_rate, &cur.feed_rate);
+        diff("feed_mode", &exp.feed_mode, &cur.feed_mode);
+        diff("spindle", &exp.spindle, &cur.spindle);
+        diff("compensation", &exp.compensation, &cur.compensation);
+        diff("wcs", &exp.wcs, &cur.wcs);
+        diff("rotation", &exp.rotation, &cur.rotation);
+        diff("temperatures", &exp.temperatures, &cur.temperatures);
+        diff("fans", &exp.fans, &cur.fans);
+
+        if !diffs.is_empty() {
+            panic!("GCodeWriter state mismatch:\n  {}", diffs.join("\n  "));
+        }
+    }
+
+    /// Current position, as last moved to. Axes which have not been moved, or
+    /// were moved to the result of an expression, are absent.
+    pub fn position(&self) -> GCodePosition {
+        self.state.position
+    }
+
+    pub fn feed_mode(&self) -> GCodeFeedMode {
+        self.state.feed_mode
+    }
+
+    /// Selects how feed rates are emitted. Feed rates passed via
+    /// `GCodeOptions` are always given in units per minute, and converted to
+    /// the selected mode for each move:
+    ///  - Inverse time: using the length of the move, which must then be
+    ///    known, and a feed rate given for every non-rapid move
+    ///  - Units per revolution: using the current spindle speed
+    pub fn set_feed_mode(&mut self, mode: GCodeFeedMode) -> Result<(), GCodeError> {
+        if mode == self.state.feed_mode {
+            return Ok(());
+        }
+        let code = self
+            .dialect
+            .feed_mode_code(mode)
+            .ok_or(GCodeError::UnsupportedError)?;
+        self.state.feed_mode = mode;
+
+        write!(self.line, "{}", code)?;
+        self.end_line()
+    }
+
+    pub fn spindle(&self) -> GCodeSpindle {
+        self.state.spindle
+    }
+
+    /// Starts (M03/M04) or stops (M05) the spindle
+    pub fn set_spindle(&mut self, spindle: GCodeSpindle) -> Result<(), GCodeError> {
+        match spindle {
+            GCodeSpindle::Off => (),
+            GCodeSpindle::Clockwise(speed) | GCodeSpindle::CounterClockwise(speed) => {
+                if !speed.is_finite()
+                    || (speed < 0.0)
+                    || self.profile.limits.check_spindle_speed(speed).is_some()
+                {
+                    return Err(GCodeError::OutOfRangeError);
+                }
+            }
+        }
+        let factor = match (self.layer, self.first_layer) {
+            (FirstLayerState::Active(_), Some(layer)) => layer.power_factor.unwrap_or(1.0),
+            _ => 1.0,
+        };
+        self.write_spindle(spindle, factor)?;
+        self.account(GCodeCommand::Spindle(spindle));
+        self.state.spindle = spindle;
+        self.idle_time = 0.0;
+
+        Ok(())
+    }
+
+    /// Writes a spindle command for `spindle`, with its speed scaled by
+    /// `factor`
+    fn write_spindle(&mut self, spindle: GCodeSpindle, factor: f64) -> Result<(), GCodeError> {
+        match spindle {
+            GCodeSpindle::Off => write!(self.line, "M05")?,
+            GCodeSpindle::Clockwise(speed) => {
+                write!(self.line, "M03 S")?;
+                self.write_number(speed * factor, 0)?;
+            }
+            GCodeSpindle::CounterClockwise(speed) => {
+                write!(self.line, "M04 S")?;
+                self.write_number(speed * factor, 0)?;
+            }
+        }
+        self.end_line()
+    }
+
+    pub fn first_layer(&self) -> Option<GCodeFirstLayer> {
+        self.first_layer
+    }
+
+    /// Sets the slowdown applied to the first layer, restarting detection of
+    /// the first layer. Only moves written by `move_to()` are considered.
+    pub fn set_first_layer(&mut self, first_layer: Option<GCodeFirstLayer>) {
+        self.first_layer = first_layer;
+        self.layer = FirstLayerState::Pending;
+    }
+
+    pub fn idle_guard(&self) -> Option<GCodeIdleGuard> {
+        self.idle_guard
+    }
+
+    /// Sets the guard applied to motionless intervals with the spindle, i.e.
+    /// a laser beam or plasma torch, on. Consecutive dwells and zero-length
+    /// moves form a single interval.
+    pub fn set_idle_guard(&mut self, guard: Option<GCodeIdleGuard>) {
+        self.idle_guard = guard;
+    }
+
+    pub fn laser_mode(&self) -> Option<GCodeLaserMode> {
+        self.laser_mode
+    }
+
+    /// Enables laser mode, for moves with power written by `laser_to()`.
+    /// While the laser is on, rapid moves written by `move_to()` force its
+    /// power to zero.
+    pub fn set_laser_mode(&mut self, mode: Option<GCodeLaserMode>) {
+        self.laser_mode = mode;
+    }
+
+    /// Feed move to `pos` with the laser at `power` (S), written only where
+    /// it changes. The laser is switched on at zero power first if off, or
+    /// if on in a different mode.
+    pub fn laser_to(
+        &mut self,
+        pos: GCodePosition,
+        power: f64,
+        options: Option<GCodeOptions>,
+    ) -> Result<(), GCodeError> {
+        let mode = self.laser_mode.ok_or(GCodeError::StateError)?;
+        if !power.is_finite()
+            || (power < 0.0)
+            || self.profile.limits.check_spindle_speed(power).is_some()
+        {
+            return Err(GCodeError::OutOfRangeError);
+        }
+
+        let spindle = |speed| match mode {
+            GCodeLaserMode::Constant => GCodeSpindle::Clockwise(speed),
+            GCodeLaserMode::Dynamic => GCodeSpindle::CounterClockwise(speed),
+        };
+        let current = self.state.spindle;
+        if current != spindle(current.speed()) {
+            self.set_spindle(spindle(0.0))?;
+        }
+
+        let changed = self.state.spindle.speed() != power;
+        self.write_move(pos, options, false, changed.then_some(power))?;
+        self.state.spindle = spindle(power);
+
+        Ok(())
+    }
+
+    /// Pauses motion for `seconds` (G04)
+    pub fn dwell(&mut self, seconds: f64) -> Result<(), GCodeError> {
+        if !seconds.is_finite() || (seconds < 0.0) {
+            return Err(GCodeError::OutOfRangeError);
+        }
+
+        let spindle = self.state.spindle;
+        let exceeded = match self.idle_guard {
+            Some(guard) if spindle.speed() > 0.0 => self.idle_time + seconds > guard.max_idle,
+            _ => false,
+        };
+        match self.idle_guard.map(|guard| guard.action) {
+            Some(GCodeIdleAction::Reject) if exceeded => return Err(GCodeError::StateError),
+            Some(GCodeIdleAction::PowerOff) if exceeded => {
+                self.set_spindle(GCodeSpindle::Off)?;
+                self.write_dwell(seconds)?;
+                return self.set_spindle(spindle);
+            }
+            _ => (),
+        }
+
+        if spindle.speed() > 0.0 {
+            self.idle_time += seconds;
+        }
+        self.write_dwell(seconds)
+    }
+
+    /// Sets the target temperature of `heater` to `temp` in degrees Celsius,
+    /// 0 to turn it off. If `wait` is set, the printer blocks until the
+    /// target is reached. Only available with the Marlin dialect.
+    pub fn set_temperature(
+        &mut self,
+        heater: GCodeHeater,
+        temp: f64,
+        wait: bool,
+    ) -> Result<(), GCodeError> {
+        if self.dialect != GCodeDialect::Marlin {
+            return Err(GCodeError::UnsupportedError);
+        }
+        if !temp.is_finite() || (temp < 0.0) {
+            return Err(GCodeError::OutOfRangeError);
+        }
+
+        let code = match (heater, wait) {
+            (GCodeHeater::Hotend(_), false) => "M104",
+            (GCodeHeater::Hotend(_), true) => "M109",
+            (GCodeHeater::Bed, false) => "M140",
+            (GCodeHeater::Bed, true) => "M190",
+            (GCodeHeater::Chamber, false) => "M141",
+            (GCodeHeater::Chamber, true) => "M191",
+        };
+        write!(self.line, "{}", code)?;
+        if let GCodeHeater::Hotend(Some(tool)) = heater {
+            write!(self.line, " T{}", tool)?;
+        }
+        write!(self.line, " S")?;
+        self.write_number(temp, 0)?;
+
+        self.account(GCodeCommand::Temperature { heater, temp, wait });
+        self.end_line()?;
+
+        let temps = &mut self.state.temperatures;
+        match temps.iter_mut().find(|(h, _)| *h == heater) {
+            Some((_, t)) => *t = temp,
+            None => temps.push((heater, temp)),
+        }
+
+        Ok(())
+    }
+
+    /// Last set PWM duty cycle of fan `fan`, 0 if never set
+    pub fn fan_speed(&self, fan: u8) -> u8 {
+        self.state.fans.get(usize::from(fan)).copied().unwrap_or(0)
+    }
+
+    /// Sets the speed of fan `fan` (M106), 0 being the part cooling fan. A
+    /// speed of 0 turns the fan off (M107). Only available with the Marlin
+    /// dialect.
+    pub fn set_fan(&mut self, fan: u8, speed: GCodeFanSpeed) -> Result<(), GCodeError> {
+        if self.dialect != GCodeDialect::Marlin {
+            return Err(GCodeError::UnsupportedError);
+        }
+        let pwm = speed.pwm()?;
+
+        if pwm == 0 {
+            write!(self.line, "M107")?;
+        } else {
+            write!(self.line, "M106")?;
+        }
+        if fan != 0 {
+            write!(self.line, " P{}", fan)?;
+        }
+        if pwm != 0 {
+            write!(self.line, " S{}", pwm)?;
+        }
+
+        let idx = usize::from(fan);
+        if self.state.fans.len() <= idx {
+            self.state.fans.resize(idx + 1, 0);
+        }
+        self.state.fans[idx] = pwm;
+
+        self.end_line()
+    }
+
+    /// Sets digital or PWM output pin `pin` to `value` (M42). Only available
+    /// with the Marlin dialect.
+    pub fn set_pin(&mut self, pin: u8, value: u8) -> Result<(), GCodeError> {
+        if self.dialect != GCodeDialect::Marlin {
+            return Err(GCodeError::UnsupportedError);
+        }
+        write!(self.line, "M42 P{} S{}", pin, value)?;
+
+        self.end_line()
+    }
+
+    /// Moves rotary axis `axis` to `angle`, given in the configured units,
+    /// and wrapped according to the configured policy
+    pub fn rotate_to(
+        &mut self,
+        axis: GCodeRotaryAxis,
+        angle: f64,
+        options: Option<GCodeOptions>,
+        fast: bool,
+    ) -> Result<(), GCodeError> {
+        self.check_options(options)?;
+        /* A is driven by Y while wrapping */
+        if self.cylinder_wrap.is_some() && (axis == GCodeRotaryAxis::A) {
+            return Err(GCodeError::UnsupportedError);
+        }
+        let current = self.state.rotary[axis.index()];
+        let angle = self.rotary_config.resolve(current, angle);
+        if !angle.is_finite() {
+            return Err(GCodeError::OutOfRangeError);
+        }
+        let travel = current.map(|cur| (angle - cur).abs());
+        let feed = self.feed_word(options, travel, fast)?;
+        self.account(GCodeCommand::Rotate {
+            axis,
+            angle,
+            travel,
+            feed_rate: self.feed_rate(options),
+            rapid: fast,
+        });
+        self.update_feed_rate(options);
+        if current != Some(angle) {
+            self.idle_time = 0.0;
+        }
+        self.state.rotary[axis.index()] = Some(angle);
+
+        self.write_acceleration(options)?;
+        let code = if fast { "G00" } else { "G01" };
+        write!(self.line, "{} {}", code, axis.letter())?;
+        self.write_number(angle, 4)?;
+
+        self.write_move_words(options, feed, None)
+    }
 
     pub fn move_to(
         &mut self,
@@ -19,29 +996,543 @@ impl<'a> GCodeWriter<'a> {
         options: Option<GCodeOptions>,
         fast: bool,
     ) -> Result<(), GCodeError> {
+        /* Laser is turned down for rapids in laser mode */
+        let laser_on = self.laser_mode.is_some() && (self.state.spindle.speed() > 0.0);
+        let power = (fast && laser_on).then_some(0.0);
+        self.write_move(pos, options, fast, power)?;
+        if let (
+            Some(power),
+            GCodeSpindle::Clockwise(speed) | GCodeSpindle::CounterClockwise(speed),
+        ) = (power, &mut self.state.spindle)
+        {
+            *speed = power;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a linear move, followed by an S word with `power` if given
+    fn write_move(
+        &mut self,
+        pos: GCodePosition,
+        options: Option<GCodeOptions>,
+        fast: bool,
+        power: Option<f64>,
+    ) -> Result<(), GCodeError> {
+        self.check_options(options)?;
+        let pos = self.complete_xy(self.rotate_position(pos)?)?;
+        let cur = self.state.position;
+        let requested = options;
+        let (layer, options) = self.first_layer_options(pos.merge(&cur).z_raw(), fast, options);
+
+        if self.limit_violation(pos, options).is_some() {
+            return Err(GCodeError::OutOfRangeError);
+        }
+
+        /* Length of the move is only known if the current position is known
+         * along every axis being moved */
+        let known = [
+            (pos.x_raw(), cur.x_raw()),
+            (pos.y_raw(), cur.y_raw()),
+            (pos.z_raw(), cur.z_raw()),
+        ]
+        .iter()
+        .all(|(p, c)| p.is_none() || c.is_some());
+        let distance = known.then(|| GCodePosition::fixed_to_f64(cur.distance_to(&pos)));
+        let feed = self.feed_word(options, distance, fast)?;
+        self.account(GCodeCommand::Move {
+            from: cur,
+            to: pos.merge(&cur),
+            distance,
+            feed_rate: self.feed_rate(options),
+            rapid: fast,
+        });
+        self.update_feed_rate(requested);
+        if distance != Some(0.0) {
+            self.idle_time = 0.0;
+        }
+        self.state.position = pos.merge(&cur);
+        self.rotation_changed &= pos.x_raw().is_none();
+        if let (Some(wrap), Some(y)) = (self.cylinder_wrap, pos.y_f64()) {
+            self.state.rotary[GCodeRotaryAxis::A.index()] = Some(wrap.angle(y));
+        }
+
+        self.enter_layer(layer)?;
+
+        self.write_acceleration(options)?;
         let code = if fast { "G00" } else { "G01" };
-        let (x, y, z) = pos.as_f64();
-        write!(self.writer, "{}", code)?;
-        if let Some(val) = x {
-            write!(self.writer, " X{:.4}", val)?;
+        write!(self.line, "{}", code)?;
+        self.write_axes(pos)?;
+
+        self.write_move_words(options, feed, power)
+    }
+
+    /// Moves along an arc in the XY plane around `center` to `end`,
+    /// clockwise (G02) or counter-clockwise (G03). Z of `end` may differ,
+    /// forming a helix. The current X and Y must be known, as the center is
+    /// written relative to them. An arc ending where it starts is a full
+    /// circle.
+    pub fn arc_to(
+        &mut self,
+        end: GCodePosition,
+        center: GCodePosition,
+        clockwise: bool,
+        options: Option<GCodeOptions>,
+    ) -> Result<(), GCodeError> {
+        self.check_options(options)?;
+        if let Some(wrap) = self.cylinder_wrap {
+            return self.wrapped_arc_to(end, center, clockwise, options, wrap.tolerance);
         }
-        if let Some(val) = y {
-            write!(self.writer, " Y{:.4}", val)?;
+        let end = self.complete_xy(self.rotate_position(end)?)?;
+        let center = self.rotate_position(center)?;
+        let cur = self.state.position;
+        let target = end.merge(&cur);
+        let requested = options;
+        let (layer, options) = self.first_layer_options(target.z_raw(), false, options);
+        if self.limit_violation(end, options).is_some() {
+            return Err(GCodeError::OutOfRangeError);
         }
-        if let Some(val) = z {
-            write!(self.writer, " Z{:.4}", val)?;
+        let arc = arc_geometry(&cur, &target, &center, clockwise)?;
+        let length = arc.length();
+
+        let feed = self.feed_word(options, Some(length), false)?;
+        self.account(GCodeCommand::Arc {
+            from: cur,
+            to: target,
+            center,
+            clockwise,
+            distance: Some(length),
+            feed_rate: self.feed_rate(options),
+        });
+        self.update_feed_rate(requested);
+        self.idle_time = 0.0;
+        self.state.position = target;
+        self.rotation_changed &= end.x_raw().is_none();
+        self.enter_layer(layer)?;
+
+        self.write_acceleration(options)?;
+        let code = if clockwise { "G02" } else { "G03" };
+        write!(self.line, "{}", code)?;
+        self.write_axes(end)?;
+        /* Start is known, or arc_geometry() would have failed */
+        let (cx, cy) = arc.center;
+        write!(self.line, " I")?;
+        self.write_number(cx - cur.x_f64().unwrap_or(cx), 4)?;
+        write!(self.line, " J")?;
+        self.write_number(cy - cur.y_f64().unwrap_or(cy), 4)?;
+
+        self.write_move_words(options, feed, None)
+    }
+
+    /// Writes an arc as linear moves, for cylinder wrapping
+    fn wrapped_arc_to(
+        &mut self,
+        end: GCodePosition,
+        center: GCodePosition,
+        clockwise: bool,
+        options: Option<GCodeOptions>,
+        tolerance: f64,
+    ) -> Result<(), GCodeError> {
+        /* Linear moves would be rotated a second time */
+        if self.software_rotation().is_some() {
+            return Err(GCodeError::UnsupportedError);
         }
+        let cur = self.state.position;
+        let target = end.merge(&cur);
+        arc_geometry(&cur, &target, &center, clockwise)?;
 
-        if let Some(options) = options {
-            if let Some(feed_rate) = options.feed_rate {
-                write!(self.writer, " F{:.2}", feed_rate)?;
+        let arc = GCodeCommand::Arc {
+            from: cur,
+            to: target,
+            center,
+            clockwise,
+            distance: None,
+            feed_rate: None,
+        };
+        for command in std::iter::once(arc).linearize(tolerance) {
+            /* Arc is valid, so it is always linearized */
+            if let GCodeCommand::Move { to, .. } = command {
+                self.write_move(to, options, false, None)?;
             }
         }
+        Ok(())
+    }
 
+    /// Writes each segment of `path` as a feed move
+    pub fn follow_path(
+        &mut self,
+        path: &[GCodePathSegment],
+        options: Option<GCodeOptions>,
+    ) -> Result<(), GCodeError> {
+        for segment in path {
+            match *segment {
+                GCodePathSegment::Line(pos) => self.move_to(pos, options, false)?,
+                GCodePathSegment::Arc {
+                    end,
+                    center,
+                    clockwise,
+                } => self.arc_to(end, center, clockwise, options)?,
+            }
+        }
         Ok(())
     }
 
+    /// Same as `move_to`, but with axis values given as expressions, which
+    /// are evaluated by the controller
+    pub fn move_to_expr(
+        &mut self,
+        axes: [Option<&GCodeExpr>; 3],
+        options: Option<GCodeOptions>,
+        fast: bool,
+    ) -> Result<(), GCodeError> {
+        for expr in axes.iter().flatten() {
+            self.check_expr(expr)?;
+        }
+        self.check_options(options)?;
+        if self.software_rotation().is_some() && (axes[0].is_some() || axes[1].is_some()) {
+            return Err(GCodeError::UnsupportedError);
+        }
+        /* X and Y can not be completed by expressions */
+        if self.rotation_changed && (axes[0].is_some() != axes[1].is_some()) {
+            return Err(GCodeError::UnsupportedError);
+        }
+        if self.cylinder_wrap.is_some() && axes[1].is_some() {
+            return Err(GCodeError::UnsupportedError);
+        }
+        let feed = self.feed_word(options, None, fast)?;
+
+        /* Resulting position is only known to the controller */
+        let mut pos = self.state.position;
+        for (i, expr) in axes.iter().enumerate() {
+            if expr.is_some() {
+                pos = match i {
+                    0 => GCodePosition::from_raw(None, pos.y_raw(), pos.z_raw()),
+                    1 => GCodePosition::from_raw(pos.x_raw(), None, pos.z_raw()),
+                    _ => GCodePosition::from_raw(pos.x_raw(), pos.y_raw(), None),
+                };
+            }
+        }
+        self.account(GCodeCommand::Move {
+            from: self.state.position,
+            to: pos,
+            distance: None,
+            feed_rate: self.feed_rate(options),
+            rapid: fast,
+        });
+        self.update_feed_rate(options);
+        self.state.position = pos;
+        self.rotation_changed &= axes[0].is_none();
+        self.idle_time = 0.0;
+
+        self.write_acceleration(options)?;
+        let code = if fast { "G00" } else { "G01" };
+        write!(self.line, "{}", code)?;
+        for (axis, expr) in ['X', 'Y', 'Z'].iter().zip(axes) {
+            if let Some(expr) = expr {
+                let expr = expr.display_with(self.decimal_separator);
+                write!(self.line, " {}{}", axis, expr)?;
+            }
+        }
+
+        self.write_move_words(options, feed, None)
+    }
+
+    /// Assigns `value` to parameter `param`
+    pub fn set_parameter(
+        &mut self,
+        param: &GCodeParam,
+        value: &GCodeExpr,
+    ) -> Result<(), GCodeError> {
+        self.check_expr(&GCodeExpr::Param(param.clone()))?;
+        self.check_expr(value)?;
+
+        let value = value.display_with(self.decimal_separator);
+        write!(self.line, "{}={}", param, value)?;
+        self.end_line()
+    }
+
+    /// Sets the origin of work coordinate system `wcs` (1 = G54 through
+    /// 9 = G59.3), in machine coordinates (G10 L2)
+    pub fn set_wcs_origin(&mut self, wcs: u8, origin: GCodePosition) -> Result<(), GCodeError> {
+        Self::check_wcs(wcs)?;
+        let known = &mut self.wcs_origins[usize::from(wcs) - 1];
+        *known = origin.merge(known);
+        write!(self.line, "G10 L2 P{}", wcs)?;
+        self.write_axes(origin)?;
+        self.end_line()
+    }
+
+    /// Sets work coordinate system `wcs` (1 = G54 through 9 = G59.3) such that
+    /// the current position has coordinates `pos` (G10 L20)
+    pub fn set_wcs_position(&mut self, wcs: u8, pos: GCodePosition) -> Result<(), GCodeError> {
+        Self::check_wcs(wcs)?;
+        /* Axes given but not known in machine coordinates become unknown */
+        let origin = offset_axes(self.machine_position(), pos, -1);
+        let known = &mut self.wcs_origins[usize::from(wcs) - 1];
+        *known = GCodePosition::from_raw(
+            pick(pos.x_raw(), origin.x_raw(), known.x_raw()),
+            pick(pos.y_raw(), origin.y_raw(), known.y_raw()),
+            pick(pos.z_raw(), origin.z_raw(), known.z_raw()),
+        );
+        write!(self.line, "G10 L20 P{}", wcs)?;
+        self.write_axes(pos)?;
+        self.end_line()
+    }
+
+    /// Selects work coordinate system `wcs` (1 = G54 through 9 = G59.3)
+    pub fn select_wcs(&mut self, wcs: u8) -> Result<(), GCodeError> {
+        Self::check_wcs(wcs)?;
+        let cur = self.wcs_origins[usize::from(self.state.wcs) - 1];
+        let new = self.wcs_origins[usize::from(wcs) - 1];
+        /* Work position changes with the origin, and is only known where
+         * both origins are */
+        let machine = offset_axes(self.state.position, cur, 1);
+        self.state.position = offset_axes(machine, new, -1);
+        self.state.wcs = wcs;
+
+        match wcs {
+            1..=6 => write!(self.line, "G{}", 53 + wcs)?,
+            _ => write!(self.line, "G59.{}", wcs - 6)?,
+        }
+        self.end_line()
+    }
+
+    /// Current position in machine coordinates, where the origin of the
+    /// active work coordinate system is known
+    pub fn machine_position(&self) -> GCodePosition {
+        let origin = self.wcs_origins[usize::from(self.state.wcs) - 1];
+        offset_axes(self.state.position, origin, 1)
+    }
+
+    /// Rapidly moves to `pos` given in machine coordinates (G53), e.g. to
+    /// reach a tool change position regardless of the active work
+    /// coordinate system
+    pub fn move_to_machine(&mut self, pos: GCodePosition) -> Result<(), GCodeError> {
+        if self.profile.limits.check_travel(&pos).is_some() {
+            return Err(GCodeError::OutOfRangeError);
+        }
+        let origin = self.wcs_origins[usize::from(self.state.wcs) - 1];
+        let work = offset_axes(pos, origin, -1);
+        let cur = self.state.position;
+        let distance = (cur.is_complete() && pos.is_complete() && work.is_complete())
+            .then(|| GCodePosition::fixed_to_f64(cur.distance_to(&work)));
+        self.account(GCodeCommand::Move {
+            from: cur,
+            to: work,
+            distance,
+            feed_rate: self.state.feed_rate,
+            rapid: true,
+        });
+        self.idle_time = 0.0;
+        self.state.position = GCodePosition::from_raw(
+            pick(pos.x_raw(), work.x_raw(), cur.x_raw()),
+            pick(pos.y_raw(), work.y_raw(), cur.y_raw()),
+            pick(pos.z_raw(), work.z_raw(), cur.z_raw()),
+        );
+
+        write!(self.line, "G53 G00")?;
+        self.write_axes(pos)?;
+        self.end_line()
+    }
+
+    /// Sets work coordinate system `wcs` such that the feature located by
+    /// `probe` becomes its origin. See `GCodeProbeResult::wcs_value()` for
+    /// the meaning of `current` and `feature_offset`.
+    pub fn teach_wcs(
+        &mut self,
+        wcs: u8,
+        probe: &GCodeProbeResult,
+        current: GCodePosition,
+        feature_offset: GCodeOffset,
+    ) -> Result<(), GCodeError> {
+        let pos = probe.wcs_value(current, feature_offset)?;
+        self.set_wcs_position(wcs, pos)
+    }
+
+    /// Enables or disables controller-side cutter radius compensation
+    /// (G40/G41/G42). `tool` selects the tool table entry holding the radius
+    /// (D word), if omitted the controller uses that of the current tool.
+    pub fn cutter_compensation(
+        &mut self,
+        side: GCodeCompensation,
+        tool: Option<u32>,
+    ) -> Result<(), GCodeError> {
+        let code = match side {
+            GCodeCompensation::Off => "G40",
+            GCodeCompensation::Left => "G41",
+            GCodeCompensation::Right => "G42",
+        };
+        write!(self.line, "{}", code)?;
+
+        if side != GCodeCompensation::Off {
+            if let Some(tool) = tool {
+                write!(self.line, " D{}", tool)?;
+            }
+        }
+        self.state.compensation = side;
+
+        self.end_line()
+    }
+
+    /// Defines subprogram `number`, the contents of which are written by
+    /// `body` to the provided writer. The body is written with the settings
+    /// and modal state of this writer, but from an unknown position and feed
+    /// rate, as it may be called from anywhere.
+    ///
+    /// For LinuxCNC the definition is emitted immediately, and so must precede
+    /// any calls. For Fanuc, definitions are emitted by `write_subprograms()`
+    /// following the end of the main program. For dialects lacking
+    /// subprograms, the body is written inline by each call.
+    pub fn define_subprogram(
+        &mut self,
+        number: u32,
+        body: impl FnOnce(&mut GCodeWriter<Vec<u8>>) -> Result<(), GCodeError>,
+    ) -> Result<(), GCodeError> {
+        if (self.dialect == GCodeDialect::Fanuc) && !(1..=9999).contains(&number) {
+            return Err(GCodeError::OutOfRangeError);
+        }
+
+        /* Bodies are written as lines of their own, styled once written out */
+        let mut sub = GCodeWriterBuilder::new()
+            .dialect(self.dialect)
+            .decimal_separator(self.decimal_separator)
+            .auto_newline(true)
+            .build(vec![])?;
+        sub.rotary_config = self.rotary_config;
+        sub.laser_mode = self.laser_mode;
+        sub.profile = self.profile.clone();
+        sub.state = GCodeWriterState {
+            position: GCodePosition::from_raw(None, None, None),
+            rotary: [None; 3],
+            feed_rate: None,
+            ..self.state.clone()
+        };
+        let entry = sub.state.clone();
+
+        /* Durations of the body are estimated by the same model */
+        let model = Box::new(GCodeBasicCostModel::default());
+        sub.cost_model = mem::replace(&mut self.cost_model, model);
+        let res = body(&mut sub);
+        let model = Box::new(GCodeBasicCostModel::default());
+        self.cost_model = mem::replace(&mut sub.cost_model, model);
+        res?;
+        let (exit, estimated_time) = (sub.state.clone(), sub.estimated_time);
+        let data = sub.writer()?;
+
+        if self.dialect == GCodeDialect::LinuxCnc {
+            self.close_line()?;
+            writeln!(self.line, "o{} sub", number)?;
+            self.line.extend(&data);
+            writeln!(self.line, "o{} endsub", number)?;
+            self.emit()?;
+        }
+        let subprogram = Subprogram {
+            body: data,
+            entry,
+            exit,
+            estimated_time,
+        };
+        self.subprograms.insert(number, subprogram);
+
+        Ok(())
+    }
+
+    /// Calls subprogram `number` `repeat` times. Fails with
+    /// `OutOfRangeError` if `repeat` is 0, as not all dialects can express
+    /// a call that is skipped.
+    ///
+    /// State afterwards is as left by the body. Axes the body leaves unknown
+    /// are unknown following the call, as these may have been moved to
+    /// expressions.
+    pub fn call_subprogram(&mut self, number: u32, repeat: u32) -> Result<(), GCodeError> {
+        if repeat == 0 {
+            return Err(GCodeError::OutOfRangeError);
+        }
+        if !self.subprograms.contains_key(&number) {
+            return Err(GCodeError::NotFoundError);
+        }
+
+        match self.dialect {
+            GCodeDialect::Fanuc => {
+                write!(self.line, "M98 P{}", number)?;
+                if repeat != 1 {
+                    write!(self.line, " L{}", repeat)?;
+                }
+                self.end_line()?;
+            }
+            GCodeDialect::LinuxCnc => {
+                self.close_line()?;
+                for _ in 0..repeat {
+                    writeln!(self.line, "o{} call", number)?;
+                }
+            }
+            GCodeDialect::Generic | GCodeDialect::Marlin => {
+                self.close_line()?;
+                let body = &self.subprograms[&number].body;
+                for _ in 0..repeat {
+                    self.line.extend(body);
+                }
+            }
+        }
+        self.emit()?;
+
+        let sub = &self.subprograms[&number];
+        let (entry, exit, state) = (&sub.entry, &sub.exit, &mut self.state);
+        state.position = exit.position;
+        state.rotary = exit.rotary;
+        state.feed_rate = exit.feed_rate.or(state.feed_rate);
+        /* Modal state is only replaced where changed by the body */
+        if exit.feed_mode != entry.feed_mode {
+            state.feed_mode = exit.feed_mode;
+        }
+        if exit.spindle != entry.spindle {
+            state.spindle = exit.spindle;
+        }
+        if exit.compensation != entry.compensation {
+            state.compensation = exit.compensation;
+        }
+        if exit.wcs != entry.wcs {
+            state.wcs = exit.wcs;
+        }
+        if exit.rotation != entry.rotation {
+            state.rotation = exit.rotation;
+        }
+        if exit.temperatures != entry.temperatures {
+            state.temperatures.clone_from(&exit.temperatures);
+        }
+        if exit.fans != entry.fans {
+            state.fans.clone_from(&exit.fans);
+        }
+        self.estimated_time += sub.estimated_time * f64::from(repeat);
+
+        Ok(())
+    }
+
+    /// Writes out definitions of all subprograms for dialects where these
+    /// follow the main program, i.e. Fanuc. Does nothing for other dialects.
+    pub fn write_subprograms(&mut self) -> Result<(), GCodeError> {
+        if self.dialect != GCodeDialect::Fanuc {
+            return Ok(());
+        }
+
+        self.close_line()?;
+        for (number, sub) in &self.subprograms {
+            writeln!(self.line, "O{}", number)?;
+            self.line.extend(&sub.body);
+            writeln!(self.line, "M99")?;
+        }
+
+        self.emit()
+    }
+
+    /// Number of bytes passed on to the output so far, after styling. With
+    /// buffered output, these only reach their destination once flushed.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
     pub fn flush(&mut self) -> Result<(), GCodeError> {
+        self.emit()?;
         if self.writer.flush().is_err() {
             Err(GCodeError::IOError)
         } else {
@@ -49,17 +1540,422 @@ impl<'a> GCodeWriter<'a> {
         }
     }
 
-    /// Drops self and returns the contained writer
-    pub fn writer(self) -> Box<dyn Write + 'a> {
-        self.writer
+    /// Rotation to be applied by the writer, if any
+    fn software_rotation(&self) -> Option<GCodeRotation> {
+        self.state
+            .rotation
+            .filter(|_| !self.dialect.supports_rotation())
+    }
+
+    /// Rotates `pos` by the software rotation. Positions giving only one of
+    /// X and Y are completed from the current position, as both change.
+    fn rotate_position(&self, pos: GCodePosition) -> Result<GCodePosition, GCodeError> {
+        let rotation = match self.software_rotation() {
+            Some(rotation) if pos.x_raw().is_some() || pos.y_raw().is_some() => rotation,
+            _ => return Ok(pos),
+        };
+        let cur = self.state.position;
+        let cur = cur
+            .x_f64()
+            .zip(cur.y_f64())
+            .map(|(x, y)| rotation.apply([x, y], true));
+        let x = pos.x_f64().or(cur.map(|cur| cur[0]));
+        let y = pos.y_f64().or(cur.map(|cur| cur[1]));
+        let (x, y) = x.zip(y).ok_or(GCodeError::StateError)?;
+
+        let [x, y] = rotation.apply([x, y], false);
+        GCodePosition::from_f64(Some(x), Some(y), pos.z_f64())
+    }
+
+    /// Completes X and Y of `pos` from the current position, if it gives
+    /// either and is the first to do so since the rotation on the controller
+    /// changed
+    fn complete_xy(&self, pos: GCodePosition) -> Result<GCodePosition, GCodeError> {
+        if !self.rotation_changed || (pos.x_raw().is_none() && pos.y_raw().is_none()) {
+            return Ok(pos);
+        }
+        let cur = self.state.position;
+        match (pos.x_raw().or(cur.x_raw()), pos.y_raw().or(cur.y_raw())) {
+            (Some(x), Some(y)) => Ok(GCodePosition::from_raw(Some(x), Some(y), pos.z_raw())),
+            _ => Err(GCodeError::StateError),
+        }
+    }
+
+    fn write_axes(&mut self, pos: GCodePosition) -> Result<(), GCodeError> {
+        if let Some(raw) = pos.x_raw() {
+            self.line.extend(b" X");
+            self.write_fixed(raw, 4);
+        }
+        if let (Some(raw), None) = (pos.y_raw(), self.cylinder_wrap) {
+            self.line.extend(b" Y");
+            self.write_fixed(raw, 4);
+        }
+        if let Some(raw) = pos.z_raw() {
+            self.line.extend(b" Z");
+            self.write_fixed(raw, 4);
+        }
+        if let (Some(val), Some(wrap)) = (pos.y_f64(), self.cylinder_wrap) {
+            self.line.extend(b" A");
+            self.write_number(wrap.angle(val), 4)?;
+        }
+        Ok(())
+    }
+
+    /// Computes the F word of a move of length `distance`, as (value,
+    /// precision), converting the feed rate in `options` to the current feed
+    /// mode
+    fn feed_word(
+        &self,
+        options: Option<GCodeOptions>,
+        distance: Option<f64>,
+        fast: bool,
+    ) -> Result<Option<(f64, usize)>, GCodeError> {
+        let feed_rate = options.and_then(|options| options.feed_rate);
+        if let Some(feed_rate) = feed_rate {
+            if !feed_rate.is_finite()
+                || (feed_rate <= 0.0)
+                || self.profile.limits.check_feed_rate(feed_rate).is_some()
+            {
+                return Err(GCodeError::OutOfRangeError);
+            }
+        }
+
+        match self.state.feed_mode {
+            GCodeFeedMode::UnitsPerMinute => Ok(feed_rate.map(|feed| (feed, 2))),
+            /* Rapids do not make use of the feed rate in other modes */
+            _ if fast => Ok(None),
+            GCodeFeedMode::InverseTime => {
+                let feed_rate = feed_rate.ok_or(GCodeError::StateError)?;
+                match distance {
+                    Some(distance) if distance > 0.0 => Ok(Some((feed_rate / distance, 4))),
+                    _ => Err(GCodeError::StateError),
+                }
+            }
+            GCodeFeedMode::UnitsPerRevolution => match feed_rate {
+                Some(feed_rate) => match self.state.spindle.speed() {
+                    speed if speed > 0.0 => Ok(Some((feed_rate / speed, 4))),
+                    _ => Err(GCodeError::StateError),
+                },
+                None => Ok(None),
+            },
+        }
+    }
+
+    fn write_dwell(&mut self, seconds: f64) -> Result<(), GCodeError> {
+        self.account(GCodeCommand::Dwell(seconds));
+        match self.dialect {
+            GCodeDialect::Generic | GCodeDialect::LinuxCnc => {
+                write!(self.line, "G04 P")?;
+                self.write_number(seconds, 3)?;
+            }
+            GCodeDialect::Fanuc => {
+                write!(self.line, "G04 X")?;
+                self.write_number(seconds, 3)?;
+            }
+            /* P is in milliseconds */
+            GCodeDialect::Marlin => {
+                write!(self.line, "G04 P")?;
+                self.write_number(seconds * 1000.0, 0)?;
+            }
+        }
+        self.end_line()
+    }
+
+    /// First layer state after a move to raw Z `z`, and the factor applying
+    /// to the feed rate of the move
+    fn first_layer_transition(&self, z: Option<i64>, fast: bool) -> (FirstLayerState, f64) {
+        let factor = match self.first_layer {
+            Some(layer) if !fast => layer.feed_factor,
+            _ => return (self.layer, 1.0),
+        };
+        match (self.layer, z) {
+            (FirstLayerState::Pending, Some(z)) => (FirstLayerState::Active(z), factor),
+            (FirstLayerState::Active(layer_z), Some(z)) if z == layer_z => (self.layer, factor),
+            (FirstLayerState::Active(_), _) => (FirstLayerState::Done, 1.0),
+            (layer, _) => (layer, 1.0),
+        }
+    }
+
+    /// First layer state following a move ending at raw Z `z`, and the
+    /// options of the move with the first layer feed factor applied. Feed
+    /// rate is written explicitly within the first layer, and when leaving
+    /// it, as the modal feed rate differs from the requested one.
+    fn first_layer_options<'o>(
+        &self,
+        z: Option<i64>,
+        fast: bool,
+        options: Option<GCodeOptions<'o>>,
+    ) -> (FirstLayerState, Option<GCodeOptions<'o>>) {
+        let (layer, factor) = self.first_layer_transition(z, fast);
+        if (factor != 1.0) || (layer != self.layer) {
+            let options = GCodeOptions {
+                feed_rate: self.feed_rate(options).map(|feed| feed * factor),
+                ..options.unwrap_or_default()
+            };
+            (layer, Some(options))
+        } else {
+            (layer, options)
+        }
+    }
+
+    /// Switches power prior to a move entering or leaving the first layer
+    fn enter_layer(&mut self, layer: FirstLayerState) -> Result<(), GCodeError> {
+        if layer == self.layer {
+            return Ok(());
+        }
+        self.layer = layer;
+        let power_factor = self.first_layer.and_then(|layer| layer.power_factor);
+        if let (Some(power_factor), true) = (power_factor, self.state.spindle.speed() > 0.0) {
+            let factor = match layer {
+                FirstLayerState::Active(_) => power_factor,
+                _ => 1.0,
+            };
+            self.write_spindle(self.state.spindle, factor)?;
+        }
+        Ok(())
+    }
+
+    /// Feed rate in units per minute applying to a move with `options`
+    fn feed_rate(&self, options: Option<GCodeOptions>) -> Option<f64> {
+        options
+            .and_then(|options| options.feed_rate)
+            .or(self.state.feed_rate)
+    }
+
+    fn update_feed_rate(&mut self, options: Option<GCodeOptions>) {
+        self.state.feed_rate = self.feed_rate(options);
+    }
+
+    /// Adds the estimated duration of `command` to the total
+    fn account(&mut self, command: GCodeCommand) {
+        self.estimated_time += self.cost_model.duration(&command, &self.state);
+    }
+
+    fn write_feed(&mut self, feed: Option<(f64, usize)>) -> Result<(), GCodeError> {
+        if let Some((feed, precision)) = feed {
+            write!(self.line, " F")?;
+            self.write_number(feed, precision)?;
+        }
+        Ok(())
+    }
+
+    /// Checks `options` for validity, and for words unsupported by the
+    /// dialect
+    fn check_options(&self, options: Option<GCodeOptions>) -> Result<(), GCodeError> {
+        let Some(options) = options else {
+            return Ok(());
+        };
+        options.validate()?;
+        let marlin = self.dialect == GCodeDialect::Marlin;
+        if !marlin && (options.extrusion.is_some() || options.acceleration.is_some()) {
+            return Err(GCodeError::UnsupportedError);
+        }
+        /* Line comments would swallow following commands */
+        if marlin && options.comment.is_some() && !self.auto_newline {
+            return Err(GCodeError::UnsupportedError);
+        }
+        if let Some(power) = options.power {
+            if self.profile.limits.check_spindle_speed(power).is_some() {
+                return Err(GCodeError::OutOfRangeError);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the acceleration of `options` ahead of a move
+    fn write_acceleration(&mut self, options: Option<GCodeOptions>) -> Result<(), GCodeError> {
+        if let Some(acceleration) = options.and_then(|options| options.acceleration) {
+            write!(self.line, "M204 S")?;
+            self.write_number(acceleration, 0)?;
+            self.end_line()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the words of a move following its axes, in the order of the
+    /// dialect, and ends the line. `power` overrides that of `options`. Any
+    /// dwell of `options` follows.
+    fn write_move_words(
+        &mut self,
+        options: Option<GCodeOptions>,
+        feed: Option<(f64, usize)>,
+        power: Option<f64>,
+    ) -> Result<(), GCodeError> {
+        let options = options.unwrap_or_default();
+        let power = power.or(options.power);
+
+        if let Some(extrusion) = options.extrusion {
+            write!(self.line, " E")?;
+            self.write_number(extrusion, 5)?;
+        }
+        self.write_feed(feed)?;
+        if let Some(power) = power {
+            write!(self.line, " S")?;
+            self.write_number(power, 0)?;
+        }
+        if let Some(comment) = options.comment {
+            match self.dialect {
+                GCodeDialect::Marlin => write!(self.line, " ; {}", comment)?,
+                _ => write!(self.line, " ({})", comment)?,
+            }
+        }
+        self.end_line()?;
+
+        if let Some(power) = options.power {
+            if let GCodeSpindle::Clockwise(speed) | GCodeSpindle::CounterClockwise(speed) =
+                &mut self.state.spindle
+            {
+                *speed = power;
+            }
+        }
+        if let Some(seconds) = options.dwell {
+            self.dwell(seconds)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `val` with `precision` decimal places, using the configured
+    /// decimal separator
+    fn write_number(&mut self, val: f64, precision: usize) -> Result<(), GCodeError> {
+        let start = self.line.len();
+        write!(self.line, "{:.*}", precision, val)?;
+        if self.decimal_separator != GCodeDecimalSeparator::Point {
+            if let Some(c) = self.line[start..].iter_mut().find(|c| **c == b'.') {
+                *c = self.decimal_separator.as_char() as u8;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes fixed-point value `raw` of a position, as `write_number()`
+    /// would after conversion to f64
+    fn write_fixed(&mut self, raw: i64, precision: usize) {
+        GCodePosition::format_fixed(
+            raw,
+            precision,
+            self.decimal_separator.as_char() as u8,
+            &mut self.line,
+        );
+    }
+
+    /// Terminates the current command, with a line ending only if automatic
+    /// newlines are enabled
+    fn end_line(&mut self) -> Result<(), GCodeError> {
+        if self.auto_newline {
+            self.newline()
+        } else {
+            self.emit()
+        }
+    }
+
+    /// Ends any line left open by commands written without automatic
+    /// newlines, for output which has to start on a line of its own
+    fn close_line(&mut self) -> Result<(), GCodeError> {
+        if self.line_open || !self.line.is_empty() {
+            self.newline()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Ends the current line, for use when automatic newlines are disabled
+    pub fn newline(&mut self) -> Result<(), GCodeError> {
+        self.line.push(b'\n');
+        self.emit()
+    }
+
+    /// Passes everything written so far on to the output, styled, or to the
+    /// capture buffer if set. Separated by a space from a command left on an
+    /// open line.
+    fn emit(&mut self) -> Result<(), GCodeError> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let separator: &[u8] = if self.line_open && (self.line[0] != b'\n') {
+            b" "
+        } else {
+            b""
+        };
+        self.line_open = self.line.last() != Some(&b'\n');
+
+        if let Some(capture) = &mut self.capture {
+            capture.extend(separator);
+            capture.extend(&self.line);
+        } else if let Some(style) = &self.style {
+            self.styled.clear();
+            style_line(style, &mut self.style_state, separator, &mut self.styled);
+            style_line(style, &mut self.style_state, &self.line, &mut self.styled);
+            self.writer.write_all(&self.styled)?;
+            self.bytes_written += self.styled.len() as u64;
+            if style.auto_flush && self.line.contains(&b'\n') {
+                self.writer.flush()?;
+            }
+        } else {
+            self.writer.write_all(separator)?;
+            self.writer.write_all(&self.line)?;
+            self.bytes_written += (separator.len() + self.line.len()) as u64;
+        }
+        self.line.clear();
+        Ok(())
+    }
+
+    /// Checks that an expression can be represented in the current dialect
+    fn check_expr(&self, expr: &GCodeExpr) -> Result<(), GCodeError> {
+        if expr.has_named_params() && (self.dialect != GCodeDialect::LinuxCnc) {
+            Err(GCodeError::UnsupportedError)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_wcs(wcs: u8) -> Result<(), GCodeError> {
+        if (1..=9).contains(&wcs) {
+            Ok(())
+        } else {
+            Err(GCodeError::OutOfRangeError)
+        }
+    }
+
+    /// Drops self and returns the contained writer, after passing on any
+    /// incomplete line
+    pub fn writer(mut self) -> Result<W, GCodeError> {
+        self.emit()?;
+        Ok(self.writer)
+    }
+}
+
+/// Adds (`sign` 1) or subtracts (`sign` -1) `offset` from the axes of `pos`,
+/// axes absent from either are absent from the result
+fn offset_axes(pos: GCodePosition, offset: GCodePosition, sign: i64) -> GCodePosition {
+    let op = |l: Option<i64>, r: Option<i64>| l.zip(r).map(|(l, r)| l + sign * r);
+    GCodePosition::from_raw(
+        op(pos.x_raw(), offset.x_raw()),
+        op(pos.y_raw(), offset.y_raw()),
+        op(pos.z_raw(), offset.z_raw()),
+    )
+}
+
+/// Value of an axis being updated: `new` if the axis is `given`, else `old`
+fn pick(given: Option<i64>, new: Option<i64>, old: Option<i64>) -> Option<i64> {
+    if given.is_some() {
+        new
+    } else {
+        old
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::BufWriter;
+    use std::panic::{self, AssertUnwindSafe};
 
     use super::*;
+    use crate::{
+        fillet_path, GCodeAngleUnit, GCodeFilletOptions, GCodeLimit, GCodeParamTable,
+        GCodeRotaryWrap,
+    };
 
     #[test]
     fn move_to() -> Result<(), GCodeError> {
@@ -73,7 +1969,7 @@ mod tests {
             let mut gcw = GCodeWriter::new(bw)?;
 
             gcw.move_to(pos, options, false)?;
-            gcw.writer();
+            gcw.writer()?;
 
             assert_eq!(String::from_utf8_lossy(&data), res);
             Ok(())
@@ -88,6 +1984,7 @@ mod tests {
             GCodePosition::from_f64_full(1.1, 2.2, 3.3)?,
             Some(GCodeOptions {
                 feed_rate: Some(1200.0),
+                ..Default::default()
             }),
             "G01 X1.1000 Y2.2000 Z3.3000 F1200.00",
         )?;
@@ -99,4 +1996,1264 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn set_wcs() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::new(&mut data)?;
+
+        let probe = GCodeProbeResult::from_grbl_report("[PRB:-120.000,-80.000,-30.000:1]")?;
+        gcw.teach_wcs(
+            2,
+            &probe,
+            GCodePosition::from_f64_full(-125.0, -80.0, -30.0)?,
+            GCodeOffset::from_f64(Some(1.0), None, None)?,
+        )?;
+        assert_eq!(
+            gcw.set_wcs_origin(10, GCodePosition::from_f64_full(0.0, 0.0, 0.0)?),
+            Err(GCodeError::OutOfRangeError)
+        );
+        gcw.writer()?;
+
+        assert_eq!(String::from_utf8_lossy(&data), "G10 L20 P2 X-6.0000");
+        Ok(())
+    }
+
+    #[test]
+    fn cutter_compensation() -> Result<(), GCodeError> {
+        fn test(side: GCodeCompensation, tool: Option<u32>, res: &str) -> Result<(), GCodeError> {
+            let mut data = vec![];
+            let mut gcw = GCodeWriter::new(&mut data)?;
+            gcw.cutter_compensation(side, tool)?;
+            gcw.writer()?;
+
+            assert_eq!(String::from_utf8_lossy(&data), res);
+            Ok(())
+        }
+
+        test(GCodeCompensation::Left, Some(3), "G41 D3")?;
+        test(GCodeCompensation::Right, None, "G42")?;
+        test(GCodeCompensation::Off, Some(3), "G40")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn subprograms() -> Result<(), GCodeError> {
+        fn test(dialect: GCodeDialect, res: &str) -> Result<(), GCodeError> {
+            let mut data = vec![];
+            let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+            gcw.set_dialect(dialect);
+
+            gcw.define_subprogram(100, |w| {
+                w.move_to(
+                    GCodePosition::from_f64(None, None, Some(-1.0))?,
+                    None,
+                    false,
+                )?;
+                w.move_to(GCodePosition::from_f64(None, None, Some(1.0))?, None, true)
+            })?;
+            gcw.call_subprogram(100, 2)?;
+            assert_eq!(gcw.call_subprogram(101, 1), Err(GCodeError::NotFoundError));
+            assert_eq!(
+                gcw.call_subprogram(100, 0),
+                Err(GCodeError::OutOfRangeError)
+            );
+            gcw.write_subprograms()?;
+            gcw.writer()?;
+
+            assert_eq!(String::from_utf8_lossy(&data), res);
+            Ok(())
+        }
+
+        test(
+            GCodeDialect::Fanuc,
+            "M98 P100 L2\nO100\nG01 Z-1.0000\nG00 Z1.0000\nM99\n",
+        )?;
+        test(
+            GCodeDialect::LinuxCnc,
+            "o100 sub\nG01 Z-1.0000\nG00 Z1.0000\no100 endsub\no100 call\no100 call\n",
+        )?;
+        test(
+            GCodeDialect::Generic,
+            "G01 Z-1.0000\nG00 Z1.0000\nG01 Z-1.0000\nG00 Z1.0000\n",
+        )?;
+
+        /* Subprograms start on a line of their own, with the settings of
+         * the writer */
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder()
+            .dialect(GCodeDialect::LinuxCnc)
+            .decimal_separator(GCodeDecimalSeparator::Comma)
+            .build(&mut data)?;
+        gcw.set_machine_limits(GCodeMachineLimits {
+            max_spindle_speed: Some(1000.0),
+            ..Default::default()
+        });
+        gcw.move_to(GCodePosition::from_f64_full(0.0, 0.0, 5.0)?, None, true)?;
+        assert_eq!(
+            gcw.define_subprogram(1, |w| w.set_spindle(GCodeSpindle::Clockwise(2000.0))),
+            Err(GCodeError::OutOfRangeError)
+        );
+        gcw.define_subprogram(1, |w| {
+            w.set_spindle(GCodeSpindle::Clockwise(500.0))?;
+            w.move_to(GCodePosition::from_f64(None, None, Some(1.5))?, None, true)
+        })?;
+        gcw.call_subprogram(1, 2)?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G00 X0,0000 Y0,0000 Z5,0000\n\
+             o1 sub\n\
+             M03 S500\n\
+             G00 Z1,5000\n\
+             o1 endsub\n\
+             o1 call\n\
+             o1 call\n"
+        );
+
+        /* Calls leave the state as the body does */
+        let mut gcw = GCodeWriter::new(vec![])?;
+        gcw.move_to(GCodePosition::from_f64_full(0.0, 0.0, 5.0)?, None, true)?;
+        gcw.define_subprogram(1, |w| {
+            let options = GCodeOptions::builder().feed_rate(60.0).build()?;
+            w.set_spindle(GCodeSpindle::Clockwise(500.0))?;
+            w.move_to(
+                GCodePosition::from_f64(None, Some(0.0), Some(5.0))?,
+                None,
+                true,
+            )?;
+            w.move_to(
+                GCodePosition::from_f64(None, None, Some(1.0))?,
+                Some(options),
+                false,
+            )
+        })?;
+        let time = gcw.estimated_time();
+        gcw.call_subprogram(1, 3)?;
+        assert_eq!(
+            gcw.position(),
+            GCodePosition::from_f64(None, Some(0.0), Some(1.0))?
+        );
+        assert_eq!(gcw.state().feed_rate, Some(60.0));
+        assert_eq!(gcw.spindle(), GCodeSpindle::Clockwise(500.0));
+        assert!((gcw.estimated_time() - time - 12.0).abs() < 1e-9);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parameters() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+
+        let depth = GCodeParam::Named("depth".to_string());
+        assert_eq!(
+            gcw.set_parameter(&depth, &GCodeExpr::Number(1.5)),
+            Err(GCodeError::UnsupportedError)
+        );
+
+        gcw.set_dialect(GCodeDialect::LinuxCnc);
+        gcw.set_parameter(&depth, &GCodeExpr::parse("#100 / 2")?)?;
+        gcw.move_to_expr(
+            [
+                Some(&GCodeExpr::Number(2.0)),
+                None,
+                Some(&GCodeExpr::Neg(Box::new(depth.into()))),
+            ],
+            None,
+            false,
+        )?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "#<depth>=[#100 / 2]\nG01 X2 Z-#<depth>\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rotate_to() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        gcw.set_rotary_config(GCodeRotaryConfig {
+            units: GCodeAngleUnit::Degrees,
+            wrap: GCodeRotaryWrap::Nearest,
+        });
+
+        gcw.rotate_to(GCodeRotaryAxis::A, 350.0, None, true)?;
+        gcw.rotate_to(GCodeRotaryAxis::A, 10.0, None, false)?;
+        gcw.rotate_to(GCodeRotaryAxis::C, 10.0, None, false)?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G00 A350.0000\nG01 A370.0000\nG01 C10.0000\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decimal_separator() -> Result<(), GCodeError> {
+        let values = [0.0, 1.5, -0.0625, 123456.7891, -98765.4321, 0.0001];
+
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        gcw.set_decimal_separator(GCodeDecimalSeparator::Comma);
+        for val in values {
+            gcw.move_to(
+                GCodePosition::from_f64(Some(val), None, None)?,
+                Some(GCodeOptions {
+                    feed_rate: Some(1500.25),
+                    ..Default::default()
+                }),
+                false,
+            )?;
+        }
+        gcw.writer()?;
+
+        /* Every number parses back to exactly the position as stored */
+        let text = String::from_utf8_lossy(&data);
+        for (line, val) in text.lines().zip(values) {
+            let words: Vec<&str> = line.split(' ').collect();
+            assert_eq!(words[0], "G01");
+            assert_eq!(words[2], "F1500,25");
+            assert!(!line.contains('.'));
+
+            let x = GCodeExpr::parse(&words[1][1..])?.eval(&GCodeParamTable::new())?;
+            let stored = GCodePosition::from_f64(Some(val), None, None)?;
+            assert_eq!(GCodePosition::f64_to_fixed(x)?, stored.x_raw().unwrap());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn feed_mode() -> Result<(), GCodeError> {
+        let feed = |feed_rate| {
+            Some(GCodeOptions {
+                feed_rate: Some(feed_rate),
+                ..Default::default()
+            })
+        };
+
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+
+        gcw.move_to(GCodePosition::from_f64_full(0.0, 0.0, 5.0)?, None, true)?;
+        gcw.set_feed_mode(GCodeFeedMode::InverseTime)?;
+        /* 600 units/min over 30 units: 0.05 min */
+        gcw.move_to(
+            GCodePosition::from_f64(Some(30.0), None, None)?,
+            feed(600.0),
+            false,
+        )?;
+        /* 3-4-5 triangle: 1000 units/min over 5 units: 0.005 min */
+        gcw.move_to(
+            GCodePosition::from_f64(Some(33.0), Some(4.0), None)?,
+            feed(1000.0),
+            false,
+        )?;
+        gcw.move_to(
+            GCodePosition::from_f64(None, None, Some(10.0))?,
+            feed(1000.0),
+            true,
+        )?;
+        assert_eq!(
+            gcw.move_to(GCodePosition::from_f64(Some(0.0), None, None)?, None, false),
+            Err(GCodeError::StateError)
+        );
+
+        gcw.set_feed_mode(GCodeFeedMode::UnitsPerRevolution)?;
+        assert_eq!(
+            gcw.move_to(
+                GCodePosition::from_f64(Some(0.0), None, None)?,
+                feed(100.0),
+                false
+            ),
+            Err(GCodeError::StateError)
+        );
+        gcw.set_spindle(GCodeSpindle::Clockwise(1000.0))?;
+        gcw.move_to(
+            GCodePosition::from_f64(Some(0.0), None, None)?,
+            feed(100.0),
+            false,
+        )?;
+        gcw.set_spindle(GCodeSpindle::Off)?;
+        gcw.set_feed_mode(GCodeFeedMode::UnitsPerMinute)?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G00 X0.0000 Y0.0000 Z5.0000\n\
+             G93\n\
+             G01 X30.0000 F20.0000\n\
+             G01 X33.0000 Y4.0000 F200.0000\n\
+             G00 Z10.0000\n\
+             G95\n\
+             M03 S1000\n\
+             G01 X0.0000 F0.1000\n\
+             M05\n\
+             G94\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn block_delete() -> Result<(), GCodeError> {
+        fn test(block_delete: GCodeBlockDelete, res: &str) -> Result<(), GCodeError> {
+            let mut data = vec![];
+            let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+            gcw.set_block_delete(block_delete);
+
+            gcw.move_to(GCodePosition::from_f64_full(0.0, 0.0, 5.0)?, None, true)?;
+            gcw.optional_block(|gcw| {
+                gcw.move_to(GCodePosition::from_f64(None, None, Some(1.0))?, None, true)?;
+                gcw.optional_block(|gcw| gcw.set_spindle(GCodeSpindle::Off))?;
+                gcw.move_to(GCodePosition::from_f64(None, None, Some(5.0))?, None, true)
+            })?;
+            gcw.optional_block(|gcw| {
+                gcw.move_to(GCodePosition::from_f64(Some(1.0), None, None)?, None, true)
+            })?;
+            let pos = gcw.position();
+            gcw.writer()?;
+
+            assert_eq!(String::from_utf8_lossy(&data), res);
+            match block_delete {
+                GCodeBlockDelete::Emit => {
+                    assert_eq!(pos, GCodePosition::from_f64(None, Some(0.0), Some(5.0))?)
+                }
+                GCodeBlockDelete::Include => {
+                    assert_eq!(pos, GCodePosition::from_f64_full(1.0, 0.0, 5.0)?)
+                }
+                GCodeBlockDelete::Omit => {
+                    assert_eq!(pos, GCodePosition::from_f64_full(0.0, 0.0, 5.0)?)
+                }
+            }
+            Ok(())
+        }
+
+        test(
+            GCodeBlockDelete::Emit,
+            "G00 X0.0000 Y0.0000 Z5.0000\n\
+             /G00 Z1.0000\n\
+             /M05\n\
+             /G00 Z5.0000\n\
+             /G00 X1.0000\n",
+        )?;
+        test(
+            GCodeBlockDelete::Include,
+            "G00 X0.0000 Y0.0000 Z5.0000\n\
+             G00 Z1.0000\n\
+             M05\n\
+             G00 Z5.0000\n\
+             G00 X1.0000\n",
+        )?;
+        test(GCodeBlockDelete::Omit, "G00 X0.0000 Y0.0000 Z5.0000\n")?;
+
+        /* Omitted blocks leave no other state behind either */
+        let mut gcw = GCodeWriter::new(vec![])?;
+        gcw.set_block_delete(GCodeBlockDelete::Omit);
+        gcw.set_wcs_origin(1, GCodePosition::from_f64_full(10.0, 0.0, 0.0)?)?;
+        gcw.move_to(GCodePosition::from_f64_full(0.0, 0.0, 5.0)?, None, true)?;
+        gcw.optional_block(|gcw| {
+            gcw.set_wcs_origin(1, GCodePosition::from_f64_full(20.0, 0.0, 0.0)?)?;
+            Ok(())
+        })?;
+        assert_eq!(
+            gcw.machine_position(),
+            GCodePosition::from_f64_full(10.0, 0.0, 5.0)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn temperatures() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+
+        assert_eq!(
+            gcw.set_temperature(GCodeHeater::Bed, 60.0, false),
+            Err(GCodeError::UnsupportedError)
+        );
+        gcw.set_dialect(GCodeDialect::Marlin);
+        gcw.set_temperature(GCodeHeater::Bed, 60.0, false)?;
+        gcw.set_temperature(GCodeHeater::Chamber, 40.0, false)?;
+        gcw.set_temperature(GCodeHeater::Hotend(None), 210.0, true)?;
+        gcw.set_temperature(GCodeHeater::Hotend(Some(1)), 150.0, false)?;
+        gcw.set_temperature(GCodeHeater::Bed, 60.0, true)?;
+        gcw.set_temperature(GCodeHeater::Chamber, 0.0, true)?;
+        assert_eq!(
+            gcw.set_temperature(GCodeHeater::Bed, -1.0, false),
+            Err(GCodeError::OutOfRangeError)
+        );
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "M140 S60\n\
+             M141 S40\n\
+             M109 S210\n\
+             M104 T1 S150\n\
+             M190 S60\n\
+             M191 S0\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn fans() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+
+        assert_eq!(
+            gcw.set_fan(0, GCodeFanSpeed::Pwm(255)),
+            Err(GCodeError::UnsupportedError)
+        );
+        gcw.set_dialect(GCodeDialect::Marlin);
+        gcw.set_fan(0, GCodeFanSpeed::Pwm(255))?;
+        gcw.set_fan(1, GCodeFanSpeed::Percent(50.0))?;
+        assert_eq!(gcw.fan_speed(1), 128);
+        assert_eq!(
+            gcw.set_fan(1, GCodeFanSpeed::Percent(101.0)),
+            Err(GCodeError::OutOfRangeError)
+        );
+        gcw.set_fan(0, GCodeFanSpeed::Percent(0.0))?;
+        assert_eq!(gcw.fan_speed(0), 0);
+        assert_eq!(gcw.fan_speed(2), 0);
+        gcw.set_pin(13, 255)?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "M106 S255\n\
+             M106 P1 S128\n\
+             M107\n\
+             M42 P13 S255\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn idle_guard() -> Result<(), GCodeError> {
+        fn test(action: GCodeIdleAction) -> Result<String, GCodeError> {
+            let mut data = vec![];
+            let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+            gcw.set_idle_guard(Some(GCodeIdleGuard {
+                max_idle: 1.0,
+                action,
+            }));
+
+            gcw.move_to(GCodePosition::from_f64_full(0.0, 0.0, 0.0)?, None, true)?;
+            /* Spindle off, no limit */
+            gcw.dwell(5.0)?;
+            gcw.set_spindle(GCodeSpindle::Clockwise(1000.0))?;
+            gcw.dwell(0.5)?;
+            gcw.move_to(GCodePosition::from_f64(Some(0.0), None, None)?, None, false)?;
+            let res = gcw.dwell(0.75);
+            if action == GCodeIdleAction::Reject {
+                assert_eq!(res, Err(GCodeError::StateError));
+                gcw.move_to(GCodePosition::from_f64(Some(1.0), None, None)?, None, false)?;
+                gcw.dwell(0.75)?;
+            } else {
+                res?;
+            }
+            gcw.writer()?;
+
+            Ok(String::from_utf8_lossy(&data).into_owned())
+        }
+
+        assert_eq!(
+            test(GCodeIdleAction::Reject)?,
+            "G00 X0.0000 Y0.0000 Z0.0000\n\
+             G04 P5.000\n\
+             M03 S1000\n\
+             G04 P0.500\n\
+             G01 X0.0000\n\
+             G01 X1.0000\n\
+             G04 P0.750\n"
+        );
+        assert_eq!(
+            test(GCodeIdleAction::PowerOff)?,
+            "G00 X0.0000 Y0.0000 Z0.0000\n\
+             G04 P5.000\n\
+             M03 S1000\n\
+             G04 P0.500\n\
+             G01 X0.0000\n\
+             M05\n\
+             G04 P0.750\n\
+             M03 S1000\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn state() -> Result<(), GCodeError> {
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(vec![])?;
+        gcw.set_dialect(GCodeDialect::Marlin);
+        gcw.move_to(
+            GCodePosition::from_f64(Some(1.0), None, Some(2.0))?,
+            None,
+            true,
+        )?;
+        gcw.set_spindle(GCodeSpindle::Clockwise(1000.0))?;
+        gcw.cutter_compensation(GCodeCompensation::Left, None)?;
+        gcw.set_temperature(GCodeHeater::Bed, 50.0, false)?;
+        gcw.set_temperature(GCodeHeater::Bed, 60.0, false)?;
+        gcw.set_fan(1, GCodeFanSpeed::Pwm(10))?;
+
+        let mut expected = GCodeWriterState {
+            position: GCodePosition::from_f64(Some(1.0), None, Some(2.0))?,
+            spindle: GCodeSpindle::Clockwise(1000.0),
+            compensation: GCodeCompensation::Left,
+            temperatures: vec![(GCodeHeater::Bed, 60.0)],
+            fans: vec![0, 10],
+            ..Default::default()
+        };
+        gcw.assert_state(&expected);
+        assert_eq!(gcw.state(), &expected);
+
+        expected.spindle = GCodeSpindle::Off;
+        let res = panic::catch_unwind(AssertUnwindSafe(|| gcw.assert_state(&expected)));
+        let msg = res.unwrap_err().downcast::<String>().unwrap();
+        assert_eq!(
+            *msg,
+            "GCodeWriter state mismatch:\n  spindle: expected Off, found Clockwise(1000.0)"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn machine_limits() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        gcw.set_machine_limits(GCodeMachineLimits {
+            axes: [Some((0.0, 300.0)), Some((0.0, 200.0)), Some((-50.0, 0.0))],
+            travel: [None; 3],
+            max_feed_rate: Some(3000.0),
+            max_spindle_speed: Some(24000.0),
+        });
+        let feed = |feed_rate| {
+            Some(GCodeOptions {
+                feed_rate: Some(feed_rate),
+                ..Default::default()
+            })
+        };
+
+        gcw.move_to(GCodePosition::from_f64_full(0.0, 0.0, 0.0)?, None, true)?;
+        let pos = GCodePosition::from_f64(Some(310.0), None, None)?;
+        assert_eq!(
+            gcw.move_to(pos, None, true),
+            Err(GCodeError::OutOfRangeError)
+        );
+        assert_eq!(
+            gcw.limit_violation(pos, None),
+            Some(GCodeLimitViolation {
+                limit: GCodeLimit::X,
+                value: 310.0,
+                bound: 300.0
+            })
+        );
+        let pos = GCodePosition::from_f64(Some(10.0), None, None)?;
+        assert_eq!(
+            gcw.move_to(pos, feed(4000.0), false),
+            Err(GCodeError::OutOfRangeError)
+        );
+        assert_eq!(
+            gcw.limit_violation(pos, feed(4000.0)).map(|v| v.limit),
+            Some(GCodeLimit::FeedRate)
+        );
+        assert_eq!(
+            gcw.set_spindle(GCodeSpindle::Clockwise(30000.0)),
+            Err(GCodeError::OutOfRangeError)
+        );
+        gcw.move_to(pos, feed(3000.0), false)?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G00 X0.0000 Y0.0000 Z0.0000\n\
+             G01 X10.0000 F3000.00\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cost_model() -> Result<(), GCodeError> {
+        /* Adds a fixed time for heating up */
+        struct HeatupModel(GCodeBasicCostModel);
+        impl GCodeCostModel for HeatupModel {
+            fn duration(&self, command: &GCodeCommand, state: &GCodeWriterState) -> f64 {
+                match command {
+                    GCodeCommand::Temperature { wait: true, .. } => 120.0,
+                    _ => self.0.duration(command, state),
+                }
+            }
+        }
+
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(vec![])?;
+        gcw.set_dialect(GCodeDialect::Marlin);
+        gcw.set_cost_model(HeatupModel(GCodeBasicCostModel { rapid_rate: 6000.0 }));
+
+        gcw.set_temperature(GCodeHeater::Hotend(None), 200.0, true)?;
+        gcw.move_to(GCodePosition::from_f64_full(0.0, 0.0, 0.0)?, None, true)?;
+        /* 100 units at 6000/min: 1s */
+        gcw.move_to(
+            GCodePosition::from_f64(Some(100.0), None, None)?,
+            None,
+            true,
+        )?;
+        /* 30 units at 600/min, then 60 more at the same feed rate: 9s */
+        let options = Some(GCodeOptions {
+            feed_rate: Some(600.0),
+            ..Default::default()
+        });
+        gcw.move_to(
+            GCodePosition::from_f64(None, Some(30.0), None)?,
+            options,
+            false,
+        )?;
+        gcw.move_to(
+            GCodePosition::from_f64(None, Some(90.0), None)?,
+            None,
+            false,
+        )?;
+        gcw.dwell(0.5)?;
+
+        assert!((gcw.estimated_time() - 130.5).abs() < 1e-9);
+        assert_eq!(gcw.state().feed_rate, Some(600.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_style() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder()
+            .dialect(GCodeDialect::LinuxCnc)
+            .line_ending(GCodeLineEnding::CrLf)
+            .letter_case(GCodeLetterCase::Upper)
+            .auto_newline(false)
+            .build(&mut data)?;
+
+        gcw.set_feed_mode(GCodeFeedMode::InverseTime)?;
+        gcw.set_spindle(GCodeSpindle::Off)?;
+        gcw.newline()?;
+        gcw.define_subprogram(100, |gcw| gcw.set_spindle(GCodeSpindle::Off))?;
+        gcw.move_to(GCodePosition::from_f64(Some(1.0), None, None)?, None, true)?;
+        gcw.newline()?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G93 M05\r\n\
+             O100 SUB\r\n\
+             M05\r\n\
+             O100 ENDSUB\r\n\
+             G00 X1.0000\r\n"
+        );
+
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder()
+            .word_spacing(false)
+            .auto_newline(true)
+            .auto_flush(true)
+            .build(&mut data)?;
+        let options = Some(GCodeOptions {
+            feed_rate: Some(100.0),
+            ..Default::default()
+        });
+        gcw.move_to(
+            GCodePosition::from_f64(Some(1.0), Some(2.0), None)?,
+            options,
+            false,
+        )?;
+        gcw.writer()?;
+
+        assert_eq!(String::from_utf8_lossy(&data), "G01X1.0000Y2.0000F100.00\n");
+
+        /* Comments are copied unchanged */
+        for (dialect, res) in [
+            (GCodeDialect::Generic, "g00x1.0000(Finish Pass)\n"),
+            (GCodeDialect::Marlin, "g00x1.0000; Finish Pass\n"),
+        ] {
+            let mut data = vec![];
+            let mut gcw = GCodeWriter::builder()
+                .dialect(dialect)
+                .word_spacing(false)
+                .letter_case(GCodeLetterCase::Lower)
+                .auto_newline(true)
+                .build(&mut data)?;
+            let options = GCodeOptions::builder().comment("Finish Pass").build()?;
+            gcw.move_to(
+                GCodePosition::from_f64(Some(1.0), None, None)?,
+                Some(options),
+                true,
+            )?;
+            gcw.writer()?;
+            assert_eq!(String::from_utf8_lossy(&data), res);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn separators() -> Result<(), GCodeError> {
+        /* Commands share a line, each passed on once complete */
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::new(&mut data)?;
+        gcw.move_to(GCodePosition::from_f64(Some(1.0), None, None)?, None, true)?;
+        assert_eq!(gcw.bytes_written(), 11);
+        gcw.set_spindle(GCodeSpindle::Off)?;
+        assert_eq!(gcw.bytes_written(), 15);
+        gcw.optional_block(|gcw| gcw.set_spindle(GCodeSpindle::Off))?;
+        gcw.move_to(GCodePosition::from_f64(Some(2.0), None, None)?, None, true)?;
+        gcw.newline()?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G00 X1.0000 M05\n\
+             /M05\n\
+             G00 X2.0000\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn first_layer() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        gcw.set_first_layer(Some(GCodeFirstLayer {
+            feed_factor: 0.5,
+            power_factor: Some(0.8),
+        }));
+        let feed = Some(GCodeOptions {
+            feed_rate: Some(1200.0),
+            ..Default::default()
+        });
+
+        gcw.set_spindle(GCodeSpindle::Clockwise(1000.0))?;
+        gcw.move_to(GCodePosition::from_f64_full(0.0, 0.0, 0.2)?, None, true)?;
+        gcw.move_to(
+            GCodePosition::from_f64(Some(10.0), None, None)?,
+            feed,
+            false,
+        )?;
+        gcw.set_spindle(GCodeSpindle::Clockwise(500.0))?;
+        gcw.move_to(
+            GCodePosition::from_f64(None, Some(10.0), None)?,
+            None,
+            false,
+        )?;
+        /* Z hop does not end the layer */
+        gcw.move_to(
+            GCodePosition::from_f64(Some(0.0), None, Some(1.0))?,
+            None,
+            true,
+        )?;
+        gcw.move_to(GCodePosition::from_f64(None, None, Some(0.2))?, None, true)?;
+        gcw.move_to(GCodePosition::from_f64(None, Some(0.0), None)?, None, false)?;
+        /* Arcs are slowed down as well */
+        gcw.arc_to(
+            GCodePosition::from_f64(Some(0.0), Some(0.0), None)?,
+            GCodePosition::from_f64(Some(5.0), Some(0.0), None)?,
+            true,
+            None,
+        )?;
+        gcw.move_to(GCodePosition::from_f64(None, None, Some(0.4))?, None, false)?;
+        gcw.move_to(
+            GCodePosition::from_f64(Some(10.0), None, None)?,
+            None,
+            false,
+        )?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "M03 S1000\n\
+             G00 X0.0000 Y0.0000 Z0.2000\n\
+             M03 S800\n\
+             G01 X10.0000 F600.00\n\
+             M03 S400\n\
+             G01 Y10.0000 F600.00\n\
+             G00 X0.0000 Z1.0000\n\
+             G00 Z0.2000\n\
+             G01 Y0.0000 F600.00\n\
+             G02 X0.0000 Y0.0000 I5.0000 J0.0000 F600.00\n\
+             M03 S500\n\
+             G01 Z0.4000 F1200.00\n\
+             G01 X10.0000\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn arcs() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        let pos = |x, y| GCodePosition::from_f64(Some(x), Some(y), None);
+
+        assert_eq!(
+            gcw.arc_to(pos(10.0, 0.0)?, pos(5.0, 0.0)?, true, None),
+            Err(GCodeError::StateError)
+        );
+        gcw.move_to(GCodePosition::from_f64_full(0.0, 0.0, -1.0)?, None, true)?;
+        assert_eq!(
+            gcw.arc_to(pos(11.0, 0.0)?, pos(5.0, 0.0)?, true, None),
+            Err(GCodeError::GeometryError)
+        );
+        gcw.set_feed_mode(GCodeFeedMode::InverseTime)?;
+        /* Half circle of radius 5 at 60 * 5 * pi units/min: 1 second */
+        let options = Some(GCodeOptions {
+            feed_rate: Some(300.0 * std::f64::consts::PI),
+            ..Default::default()
+        });
+        gcw.arc_to(pos(10.0, 0.0)?, pos(5.0, 0.0)?, true, options)?;
+        gcw.set_feed_mode(GCodeFeedMode::UnitsPerMinute)?;
+        let path = fillet_path(
+            &[pos(10.0, 0.0)?, pos(20.0, 0.0)?, pos(20.0, 10.0)?],
+            &GCodeFilletOptions {
+                radius: 2.0,
+                tolerance: 1.0,
+            },
+        )?;
+        gcw.follow_path(&path[1..], None)?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G00 X0.0000 Y0.0000 Z-1.0000\n\
+             G93\n\
+             G02 X10.0000 Y0.0000 I5.0000 J0.0000 F60.0000\n\
+             G94\n\
+             G01 X18.0000 Y0.0000\n\
+             G03 X20.0000 Y2.0000 I0.0000 J2.0000\n\
+             G01 X20.0000 Y10.0000\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn named_positions() -> Result<(), GCodeError> {
+        let mut profile = GCodeMachineProfile::default();
+        profile.set_named_position("park", GCodePosition::from_f64_full(0.0, 200.0, -10.0)?);
+        profile.set_named_position("probe", GCodePosition::from_f64_full(10.0, 10.0, -50.0)?);
+        profile.set_named_position("clear", GCodePosition::from_f64(Some(-5.0), None, None)?);
+
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        gcw.set_machine_profile(profile.clone());
+        gcw.set_wcs_origin(1, GCodePosition::from_f64_full(100.0, 50.0, -80.0)?)?;
+        gcw.move_to(GCodePosition::from_f64_full(0.0, 0.0, 1.0)?, None, true)?;
+
+        assert_eq!(gcw.go_to_named("home"), Err(GCodeError::NotFoundError));
+        /* No known height to retract to */
+        assert_eq!(gcw.go_to_named("park"), Err(GCodeError::StateError));
+        gcw.set_retract(Some(GCodeRetract::SafeZ(10.0)));
+        gcw.go_to_named("park")?;
+        /* Known in work coordinates through the origin of G54 */
+        assert_eq!(
+            gcw.position(),
+            GCodePosition::from_f64_full(-100.0, 150.0, 70.0)?
+        );
+        /* Already above the retract, at Z 70 and 30 */
+        gcw.go_to_named("probe")?;
+        gcw.go_to_named("clear")?;
+        assert_eq!(
+            gcw.machine_profile().named_position("clear"),
+            Some(GCodePosition::from_f64(Some(-5.0), None, None)?)
+        );
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G10 L2 P1 X100.0000 Y50.0000 Z-80.0000\n\
+             G00 X0.0000 Y0.0000 Z1.0000\n\
+             G00 Z10.0000\n\
+             G53 G00 X0.0000 Y200.0000\n\
+             G53 G00 Z-10.0000\n\
+             G53 G00 X10.0000 Y10.0000\n\
+             G53 G00 Z-50.0000\n\
+             G53 G00 X-5.0000\n"
+        );
+
+        /* Retracting to the top of the machine travel */
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        gcw.set_machine_profile(GCodeMachineProfile {
+            limits: GCodeMachineLimits {
+                travel: [None, None, Some((-100.0, 0.0))],
+                ..Default::default()
+            },
+            ..profile
+        });
+        gcw.go_to_named("probe")?;
+        gcw.go_to_named("park")?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G53 G00 Z0.0000\n\
+             G53 G00 X10.0000 Y10.0000\n\
+             G53 G00 Z-50.0000\n\
+             G53 G00 Z0.0000\n\
+             G53 G00 X0.0000 Y200.0000\n\
+             G53 G00 Z-10.0000\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn machine_coordinates() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        gcw.set_machine_limits(GCodeMachineLimits {
+            travel: [Some((0.0, 300.0)), Some((0.0, 200.0)), Some((-100.0, 0.0))],
+            ..Default::default()
+        });
+
+        gcw.move_to_machine(GCodePosition::from_f64(None, None, Some(0.0))?)?;
+        assert!(gcw.position().is_empty());
+        gcw.set_wcs_origin(1, GCodePosition::from_f64_full(100.0, 50.0, -80.0)?)?;
+        gcw.move_to(GCodePosition::from_f64_full(0.0, 0.0, 10.0)?, None, true)?;
+        assert_eq!(
+            gcw.machine_position(),
+            GCodePosition::from_f64_full(100.0, 50.0, -70.0)?
+        );
+
+        /* Within work limits, but beyond the machine travel */
+        let pos = GCodePosition::from_f64(Some(250.0), None, None)?;
+        assert_eq!(
+            gcw.move_to(pos, None, true),
+            Err(GCodeError::OutOfRangeError)
+        );
+        assert_eq!(
+            gcw.limit_violation(pos, None),
+            Some(GCodeLimitViolation {
+                limit: GCodeLimit::TravelX,
+                value: 350.0,
+                bound: 300.0
+            })
+        );
+
+        gcw.set_wcs_position(2, GCodePosition::from_f64_full(0.0, 0.0, 0.0)?)?;
+        gcw.select_wcs(2)?;
+        assert_eq!(gcw.position(), GCodePosition::from_f64_full(0.0, 0.0, 0.0)?);
+        gcw.move_to_machine(GCodePosition::from_f64(Some(300.0), Some(200.0), None)?)?;
+        assert_eq!(
+            gcw.position(),
+            GCodePosition::from_f64_full(200.0, 150.0, 0.0)?
+        );
+        assert_eq!(
+            gcw.move_to_machine(GCodePosition::from_f64(Some(301.0), None, None)?),
+            Err(GCodeError::OutOfRangeError)
+        );
+        assert_eq!(gcw.state().wcs, 2);
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G53 G00 Z0.0000\n\
+             G10 L2 P1 X100.0000 Y50.0000 Z-80.0000\n\
+             G00 X0.0000 Y0.0000 Z10.0000\n\
+             G10 L20 P2 X0.0000 Y0.0000 Z0.0000\n\
+             G55\n\
+             G53 G00 X300.0000 Y200.0000\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn travel() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        let pos = |x, y, z| GCodePosition::from_f64(Some(x), Some(y), z);
+
+        assert_eq!(
+            gcw.travel_to(pos(0.0, 0.0, None)?, None),
+            Err(GCodeError::StateError)
+        );
+        assert_eq!(
+            gcw.travel_to(pos(0.0, 0.0, None)?, Some(GCodeRetract::SafeZ(5.0))),
+            Err(GCodeError::StateError)
+        );
+        gcw.set_retract(Some(GCodeRetract::SafeZ(5.0)));
+        /* No feed rate to plunge at */
+        assert_eq!(
+            gcw.travel_to(pos(0.0, 0.0, Some(-1.0))?, None),
+            Err(GCodeError::StateError)
+        );
+        gcw.set_plunge(Some(GCodePlunge {
+            clearance: 1.0,
+            feed_rate: 100.0,
+        }));
+        gcw.travel_to(pos(0.0, 0.0, Some(-1.0))?, None)?;
+        gcw.travel_to(pos(10.0, 0.0, None)?, None)?;
+        /* Already above the safe Z */
+        gcw.move_to(GCodePosition::from_f64(None, None, Some(8.0))?, None, true)?;
+        gcw.travel_to(pos(20.0, 0.0, Some(0.0))?, None)?;
+        gcw.travel_to(pos(30.0, 0.0, None)?, Some(GCodeRetract::Hop(0.4)))?;
+        /* Above the safe Z, the last move rises at rapid */
+        gcw.travel_to(pos(40.0, 0.0, Some(12.0))?, None)?;
+        gcw.set_feed_mode(GCodeFeedMode::InverseTime)?;
+        gcw.travel_to(pos(50.0, 0.0, Some(0.0))?, None)?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G00 Z5.0000\n\
+             G00 X0.0000 Y0.0000\n\
+             G00 Z0.0000\n\
+             G01 Z-1.0000 F100.00\n\
+             G00 Z5.0000\n\
+             G00 X10.0000 Y0.0000\n\
+             G00 Z0.0000\n\
+             G01 Z-1.0000\n\
+             G00 Z8.0000\n\
+             G00 X20.0000 Y0.0000\n\
+             G00 Z1.0000\n\
+             G01 Z0.0000\n\
+             G00 Z0.4000\n\
+             G00 X30.0000 Y0.0000\n\
+             G01 Z0.0000\n\
+             G00 Z5.0000\n\
+             G00 X40.0000 Y0.0000\n\
+             G00 Z12.0000\n\
+             G93\n\
+             G00 X50.0000 Y0.0000\n\
+             G00 Z1.0000\n\
+             G01 Z0.0000 F100.0000\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn rotation() -> Result<(), GCodeError> {
+        let rotation = GCodeRotation {
+            center: [10.0, 0.0],
+            angle: 90.0,
+        };
+        let program = |dialect| -> Result<String, GCodeError> {
+            let mut data = vec![];
+            let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+            gcw.set_dialect(dialect);
+            gcw.move_to(GCodePosition::from_f64_full(10.0, 0.0, 5.0)?, None, true)?;
+            gcw.set_rotation(Some(rotation))?;
+            gcw.move_to(
+                GCodePosition::from_f64(Some(20.0), None, None)?,
+                None,
+                false,
+            )?;
+            gcw.move_to(GCodePosition::from_f64(None, None, Some(0.0))?, None, false)?;
+            gcw.set_rotation(None)?;
+            gcw.move_to(GCodePosition::from_f64(Some(0.0), None, None)?, None, true)?;
+            gcw.writer()?;
+            Ok(String::from_utf8_lossy(&data).into_owned())
+        };
+
+        assert_eq!(
+            program(GCodeDialect::Fanuc)?,
+            "G00 X10.0000 Y0.0000 Z5.0000\n\
+             G68 X10.0000 Y0.0000 R90.0000\n\
+             G01 X20.0000 Y0.0000\n\
+             G01 Z0.0000\n\
+             G69\n\
+             G00 X0.0000 Y0.0000\n"
+        );
+        assert_eq!(
+            program(GCodeDialect::LinuxCnc)?,
+            "G00 X10.0000 Y0.0000 Z5.0000\n\
+             G01 X10.0000 Y10.0000\n\
+             G01 Z0.0000\n\
+             G00 X0.0000\n"
+        );
+
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        gcw.set_dialect(GCodeDialect::LinuxCnc);
+        let expr = GCodeExpr::Number(1.0);
+        gcw.move_to_expr([Some(&expr), None, None], None, true)?;
+        gcw.set_rotation(Some(rotation))?;
+        assert_eq!(
+            gcw.move_to(GCodePosition::from_f64(Some(1.0), None, None)?, None, true),
+            Err(GCodeError::StateError)
+        );
+        assert_eq!(
+            gcw.move_to_expr([Some(&expr), None, None], None, true),
+            Err(GCodeError::UnsupportedError)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cylinder_wrap() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        /* One degree per unit of Y */
+        let wrap = GCodeCylinderWrap {
+            diameter: 360.0 / std::f64::consts::PI,
+            tolerance: 3.0,
+        };
+        let feed = Some(GCodeOptions {
+            feed_rate: Some(600.0),
+            ..Default::default()
+        });
+
+        gcw.set_feed_mode(GCodeFeedMode::UnitsPerRevolution)?;
+        gcw.move_to(GCodePosition::from_f64_full(0.0, 0.0, 1.0)?, None, true)?;
+        assert_eq!(
+            gcw.set_cylinder_wrap(Some(GCodeCylinderWrap {
+                diameter: 0.0,
+                ..wrap
+            })),
+            Err(GCodeError::OutOfRangeError)
+        );
+        gcw.set_cylinder_wrap(Some(wrap))?;
+        gcw.move_to(
+            GCodePosition::from_f64(None, Some(30.0), None)?,
+            feed,
+            false,
+        )?;
+        gcw.arc_to(
+            GCodePosition::from_f64(None, Some(50.0), None)?,
+            GCodePosition::from_f64(Some(0.0), Some(40.0), None)?,
+            false,
+            feed,
+        )?;
+        assert_eq!(gcw.state().rotary[0], Some(50.0));
+        assert_eq!(
+            gcw.rotate_to(GCodeRotaryAxis::A, 0.0, None, true),
+            Err(GCodeError::UnsupportedError)
+        );
+        /* The feed mode prior to wrapping is restored, once */
+        gcw.set_cylinder_wrap(Some(wrap))?;
+        gcw.set_cylinder_wrap(None)?;
+        gcw.set_cylinder_wrap(None)?;
+        assert_eq!(gcw.state().feed_mode, GCodeFeedMode::UnitsPerRevolution);
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G95\n\
+             G00 X0.0000 Y0.0000 Z1.0000\n\
+             G93\n\
+             G01 A30.0000 F20.0000\n\
+             G01 X10.0000 Z1.0000 A40.0000 F42.4264\n\
+             G01 X0.0000 Z1.0000 A50.0000 F42.4264\n\
+             G95\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn laser() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        let pos = |x| GCodePosition::from_f64(Some(x), Some(0.0), None);
+
+        assert_eq!(
+            gcw.laser_to(pos(1.0)?, 100.0, None),
+            Err(GCodeError::StateError)
+        );
+        gcw.set_laser_mode(Some(GCodeLaserMode::Dynamic));
+        gcw.move_to(pos(0.0)?, None, true)?;
+        let feed = Some(GCodeOptions {
+            feed_rate: Some(1000.0),
+            ..Default::default()
+        });
+        gcw.laser_to(pos(1.0)?, 500.0, feed)?;
+        gcw.laser_to(pos(2.0)?, 500.0, None)?;
+        gcw.laser_to(pos(3.0)?, 0.0, None)?;
+        gcw.laser_to(pos(4.0)?, 250.0, None)?;
+        gcw.move_to(pos(10.0)?, None, true)?;
+        gcw.laser_to(pos(11.0)?, 250.0, None)?;
+        gcw.set_laser_mode(Some(GCodeLaserMode::Constant));
+        gcw.laser_to(pos(12.0)?, 250.0, None)?;
+        gcw.set_spindle(GCodeSpindle::Off)?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "G00 X0.0000 Y0.0000\n\
+             M04 S0\n\
+             G01 X1.0000 Y0.0000 F1000.00 S500\n\
+             G01 X2.0000 Y0.0000\n\
+             G01 X3.0000 Y0.0000 S0\n\
+             G01 X4.0000 Y0.0000 S250\n\
+             G00 X10.0000 Y0.0000 S0\n\
+             G01 X11.0000 Y0.0000 S250\n\
+             M03 S0\n\
+             G01 X12.0000 Y0.0000 S250\n\
+             M05\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn move_options() -> Result<(), GCodeError> {
+        let mut data = vec![];
+        let mut gcw = GCodeWriter::builder().auto_newline(true).build(&mut data)?;
+        let pos = |x| GCodePosition::from_f64(Some(x), None, None);
+
+        assert_eq!(
+            GCodeOptions::builder().feed_rate(-1.0).build(),
+            Err(GCodeError::OutOfRangeError)
+        );
+        assert_eq!(
+            GCodeOptions::builder().comment("(nested)").build(),
+            Err(GCodeError::ParseError)
+        );
+        let options = GCodeOptions::builder()
+            .feed_rate(600.0)
+            .power(8000.0)
+            .dwell(0.5)
+            .comment("finish")
+            .build()?;
+        gcw.set_spindle(GCodeSpindle::Clockwise(10000.0))?;
+        gcw.move_to(pos(10.0)?, Some(options), false)?;
+        assert_eq!(gcw.spindle(), GCodeSpindle::Clockwise(8000.0));
+        let extrude = GCodeOptions::builder().extrusion(1.5).build()?;
+        assert_eq!(
+            gcw.move_to(pos(20.0)?, Some(extrude), false),
+            Err(GCodeError::UnsupportedError)
+        );
+
+        gcw.set_dialect(GCodeDialect::Marlin);
+        /* Comments built at runtime */
+        let comment = format!("perimeter {}", 2);
+        let options = GCodeOptions::builder()
+            .feed_rate(1800.0)
+            .extrusion(1.5)
+            .acceleration(500.0)
+            .comment(&comment)
+            .build()?;
+        gcw.move_to(pos(20.0)?, Some(options), false)?;
+        gcw.writer()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "M03 S10000\n\
+             G01 X10.0000 F600.00 S8000 (finish)\n\
+             G04 P0.500\n\
+             M204 S500\n\
+             G01 X20.0000 E1.50000 F1800.00 ; perimeter 2\n"
+        );
+
+        Ok(())
+    }
 }